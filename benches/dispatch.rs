@@ -0,0 +1,195 @@
+//! Costs of the dispatch strategies that a node can choose between: concrete versus dyn handle
+//! iteration, casts between handle types, reading through a view and acquiring guards.
+//!
+//! ```text
+//! cargo bench --bench dispatch
+//! cargo bench --bench dispatch -- iteration
+//! ```
+//
+// # Internal Design
+//
+// - Every storage holds the same LEN items so the groups can be compared with each other.
+// - Handles are built once outside of the measured closures, only the operation being compared is
+//   timed. Casts consume their handle so the handle clone is part of every cast measurement, the
+//   clone baseline in the casts group shows how much of it that is.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ngenate_flex_storage::{
+    casting,
+    storage_handle::{builder, StorageHandle},
+    storage_traits::{ItemSliceStorage, KeyItemStorage, Storage},
+    storage_types::{KeyItemViewStorage, VecStorage},
+    Arw, Rw,
+};
+
+const LEN: usize = 10_000;
+
+type Items = VecStorage<usize, f32>;
+
+fn items() -> Items
+{
+    VecStorage::from_vec((0..LEN).map(|index| index as f32).collect())
+}
+
+fn iteration(c: &mut Criterion)
+{
+    let handle: StorageHandle<dyn Storage> = builder(items()).build();
+    let concrete: StorageHandle<Items> = handle.clone().cast_to_sized_storage().unwrap();
+    let key_item: StorageHandle<dyn KeyItemStorage<Key = usize, Item = f32>> =
+        handle.clone().cast_to_getitem_storage().unwrap();
+    let slice: StorageHandle<dyn ItemSliceStorage<Item = f32>> =
+        handle.cast_to_slice_storage::<usize, f32>().unwrap();
+
+    let mut group = c.benchmark_group("iteration");
+
+    group.bench_function("concrete_slice", |b| {
+        b.iter(|| concrete.try_read().unwrap().as_item_slice().iter().sum::<f32>())
+    });
+
+    group.bench_function("concrete_for_each_item", |b| {
+        b.iter(|| {
+            let mut sum = 0.0;
+            concrete.for_each_item(|item| sum += item).unwrap();
+            sum
+        })
+    });
+
+    group.bench_function("concrete_for_each_key_item", |b| {
+        b.iter(|| {
+            let mut sum = 0.0;
+            concrete.for_each_key_item(|key, item| sum += key as f32 * item).unwrap();
+            sum
+        })
+    });
+
+    group.bench_function("dyn_slice", |b| {
+        b.iter(|| slice.try_read().unwrap().as_item_slice().iter().sum::<f32>())
+    });
+
+    group.bench_function("dyn_item_iter", |b| {
+        b.iter(|| key_item.try_read().unwrap().item_iter().sum::<f32>())
+    });
+
+    group.bench_function("dyn_key_item_iter", |b| {
+        b.iter(|| {
+            let guard = key_item.try_read().unwrap();
+            guard.key_item_iter().map(|(key, item)| key as f32 * item).sum::<f32>()
+        })
+    });
+
+    group.bench_function("dyn_get", |b| {
+        b.iter(|| {
+            let guard = key_item.try_read().unwrap();
+            (0..LEN).filter_map(|key| guard.get(key)).sum::<f32>()
+        })
+    });
+
+    group.finish();
+}
+
+fn casts(c: &mut Criterion)
+{
+    let handle: StorageHandle<dyn Storage> = builder(items()).build();
+    let storage: Arw<dyn Storage> = Arc::new(Rw::new(items()));
+
+    let mut group = c.benchmark_group("casts");
+
+    group.bench_function("handle_clone", |b| b.iter(|| black_box(handle.clone())));
+
+    group.bench_function("handle_to_sized", |b| {
+        b.iter(|| handle.clone().cast_to_sized_storage::<Items>().unwrap())
+    });
+
+    group.bench_function("handle_to_key_item", |b| {
+        b.iter(|| handle.clone().cast_to_getitem_storage::<usize, f32>().unwrap())
+    });
+
+    group.bench_function("handle_to_key_item_miss", |b| {
+        b.iter(|| handle.clone().cast_to_getitem_storage::<usize, i32>().is_err())
+    });
+
+    group.bench_function("arw_to_sized", |b| {
+        b.iter(|| casting::dyn_storage_into_sized::<dyn Storage, Items>(&storage).unwrap())
+    });
+
+    group.bench_function("arw_to_key_item", |b| {
+        b.iter(|| {
+            casting::cast_to_dyn_getkeyitemstorage::<dyn Storage, usize, f32>(&storage).unwrap()
+        })
+    });
+
+    group.bench_function("arw_to_key_item_miss", |b| {
+        b.iter(|| {
+            casting::cast_to_dyn_getkeyitemstorage::<dyn Storage, usize, i32>(&storage).is_err()
+        })
+    });
+
+    group.finish();
+}
+
+fn views(c: &mut Criterion)
+{
+    let input: StorageHandle<dyn Storage> = builder(items()).build();
+    let input_key_item: StorageHandle<dyn KeyItemStorage<Key = usize, Item = f32>> =
+        input.clone().cast_to_getitem_storage().unwrap();
+
+    let mut view_builder = builder(KeyItemViewStorage::<Items, usize, f32>::new());
+    view_builder.add_view_controller();
+    let mut view = view_builder.build();
+
+    let controller = view.view_storage_controller_mut().unwrap();
+    controller.set_input::<usize, f32>(input).unwrap();
+    controller.create_read_view::<usize, f32>(0..LEN).unwrap();
+
+    let view_key_item: StorageHandle<dyn KeyItemStorage<Key = usize, Item = f32>> =
+        view.cast_to_getitem_storage().unwrap();
+
+    let mut group = c.benchmark_group("views");
+
+    group.bench_function("input_key_item_iter", |b| {
+        b.iter(|| input_key_item.try_read().unwrap().item_iter().sum::<f32>())
+    });
+
+    group.bench_function("view_key_item_iter", |b| {
+        b.iter(|| view_key_item.try_read().unwrap().item_iter().sum::<f32>())
+    });
+
+    group.bench_function("input_get", |b| {
+        b.iter(|| {
+            let guard = input_key_item.try_read().unwrap();
+            (0..LEN).filter_map(|key| guard.get(key)).sum::<f32>()
+        })
+    });
+
+    group.bench_function("view_get", |b| {
+        b.iter(|| {
+            let guard = view_key_item.try_read().unwrap();
+            (0..LEN).filter_map(|key| guard.get(key)).sum::<f32>()
+        })
+    });
+
+    group.finish();
+}
+
+fn locks(c: &mut Criterion)
+{
+    let handle: StorageHandle<Items> = builder(items()).build().cast_to_sized_storage().unwrap();
+    let raw: Arw<Items> = Arc::new(Rw::new(items()));
+
+    let mut group = c.benchmark_group("locks");
+
+    group.bench_function("raw_read", |b| b.iter(|| raw.try_read().unwrap().len()));
+
+    group.bench_function("raw_write", |b| b.iter(|| raw.try_write().unwrap().len()));
+
+    group.bench_function("handle_read", |b| b.iter(|| handle.try_read().unwrap().len()));
+
+    group.bench_function("handle_write", |b| b.iter(|| handle.try_write().unwrap().len()));
+
+    group.finish();
+}
+
+criterion_group!(benches, iteration, casts, views, locks);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+/*
+ * C API of ngenate_flex_storage, available when the crate is built with the `ffi` feature.
+ *
+ * Handles are opaque and reference counted. Every handle received from the host or from
+ * flex_storage_handle_clone must be released with flex_storage_handle_release.
+ *
+ * Storage locks are only held for the duration of a call. Calls fail with
+ * FLEX_STORAGE_LOCK_FAILED rather than wait when a storage is locked elsewhere.
+ */
+
+#ifndef FLEX_STORAGE_H
+#define FLEX_STORAGE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct FlexStorageHandle FlexStorageHandle;
+
+typedef enum FlexStorageStatus
+{
+    FLEX_STORAGE_OK = 0,
+    FLEX_STORAGE_NULL_POINTER = 1,
+    FLEX_STORAGE_TYPE_MISMATCH = 2,
+    FLEX_STORAGE_LOCK_FAILED = 3,
+    FLEX_STORAGE_NOT_FOUND = 4,
+    FLEX_STORAGE_PANICKED = 5,
+} FlexStorageStatus;
+
+void flex_storage_handle_release(FlexStorageHandle *handle);
+FlexStorageStatus flex_storage_handle_clone(
+    const FlexStorageHandle *handle, FlexStorageHandle **out);
+FlexStorageStatus flex_storage_len(const FlexStorageHandle *handle, size_t *out);
+
+/* Items are iterated until the callback returns false */
+FlexStorageStatus flex_storage_get_f32(
+    const FlexStorageHandle *handle, size_t key, float *out);
+FlexStorageStatus flex_storage_insert_f32(
+    const FlexStorageHandle *handle, size_t key, float item);
+FlexStorageStatus flex_storage_iterate_f32(
+    const FlexStorageHandle *handle,
+    bool (*callback)(size_t key, float item, void *user_data),
+    void *user_data);
+
+FlexStorageStatus flex_storage_get_f64(
+    const FlexStorageHandle *handle, size_t key, double *out);
+FlexStorageStatus flex_storage_insert_f64(
+    const FlexStorageHandle *handle, size_t key, double item);
+FlexStorageStatus flex_storage_iterate_f64(
+    const FlexStorageHandle *handle,
+    bool (*callback)(size_t key, double item, void *user_data),
+    void *user_data);
+
+FlexStorageStatus flex_storage_get_i32(
+    const FlexStorageHandle *handle, size_t key, int32_t *out);
+FlexStorageStatus flex_storage_insert_i32(
+    const FlexStorageHandle *handle, size_t key, int32_t item);
+FlexStorageStatus flex_storage_iterate_i32(
+    const FlexStorageHandle *handle,
+    bool (*callback)(size_t key, int32_t item, void *user_data),
+    void *user_data);
+
+FlexStorageStatus flex_storage_get_i64(
+    const FlexStorageHandle *handle, size_t key, int64_t *out);
+FlexStorageStatus flex_storage_insert_i64(
+    const FlexStorageHandle *handle, size_t key, int64_t item);
+FlexStorageStatus flex_storage_iterate_i64(
+    const FlexStorageHandle *handle,
+    bool (*callback)(size_t key, int64_t item, void *user_data),
+    void *user_data);
+
+FlexStorageStatus flex_storage_get_u8(
+    const FlexStorageHandle *handle, size_t key, uint8_t *out);
+FlexStorageStatus flex_storage_insert_u8(
+    const FlexStorageHandle *handle, size_t key, uint8_t item);
+FlexStorageStatus flex_storage_iterate_u8(
+    const FlexStorageHandle *handle,
+    bool (*callback)(size_t key, uint8_t item, void *user_data),
+    void *user_data);
+
+FlexStorageStatus flex_storage_get_u32(
+    const FlexStorageHandle *handle, size_t key, uint32_t *out);
+FlexStorageStatus flex_storage_insert_u32(
+    const FlexStorageHandle *handle, size_t key, uint32_t item);
+FlexStorageStatus flex_storage_iterate_u32(
+    const FlexStorageHandle *handle,
+    bool (*callback)(size_t key, uint32_t item, void *user_data),
+    void *user_data);
+
+FlexStorageStatus flex_storage_get_u64(
+    const FlexStorageHandle *handle, size_t key, uint64_t *out);
+FlexStorageStatus flex_storage_insert_u64(
+    const FlexStorageHandle *handle, size_t key, uint64_t item);
+FlexStorageStatus flex_storage_iterate_u64(
+    const FlexStorageHandle *handle,
+    bool (*callback)(size_t key, uint64_t item, void *user_data),
+    void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,426 @@
+//! Background saving of storages that have changed since they were last saved.
+//!
+//! Handles are registered with an [Autosave] along with the file they are saved to. On every
+//! interval the storages whose [StorageHandle::write_version] moved on are written out, and
+//! [load_autosave] restores them on the next startup:
+//!
+//! ```ignore
+//! let mut config = AutosaveConfig::default();
+//! config.lock_budget = Duration::from_millis(2);
+//!
+//! let mut autosave = Autosave::new(config);
+//! autosave.watch::<_, u64, Node>(&nodes, "session/nodes.autosave")?;
+//! autosave.start();
+//!
+//! // On the next startup
+//! load_autosave::<_, u64, Node>(&nodes, "session/nodes.autosave")?;
+//! ```
+//
+// # Internal Design
+//
+// - A save holds a read lock which makes writers fail, so storages are saved in chunks. Each chunk
+//   holds the read lock for at most [AutosaveConfig::lock_budget] (plus the time to write one item)
+//   before releasing it and yielding to writers.
+// - A write between two chunks would make the save a mix of two versions. The write version is
+//   checked every time the lock is retaken and the save is abandoned if it changed, the storage is
+//   then saved on a later pass.
+// - Saves are written to a temporary file that replaces the previous save once complete, so a crash
+//   during a save leaves the previous save intact.
+// - Items are written as a sequence of key item pairs rather than the serialized storage so that a
+//   save can be resumed at any item. The sequence ends with a marker so that a truncated file is
+//   detected.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    snapshot::{options, read_header, write_header, SnapshotKind},
+    storage_handle::StorageHandle,
+    storage_traits::{ItemTrait, KeyItemStorage, KeyTrait, Storage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+#[derive(Clone)]
+pub struct AutosaveConfig
+{
+    /// Time between save passes
+    pub interval: Duration,
+
+    /// Longest time a save holds a storage's read lock in one go
+    pub lock_budget: Duration,
+
+    /// Called with the save path when saving a storage fails
+    pub error_hook: fn(&Path, &StorageError),
+}
+
+impl Default for AutosaveConfig
+{
+    fn default() -> Self
+    {
+        Self {
+            interval: Duration::from_secs(30),
+            lock_budget: Duration::from_millis(5),
+            error_hook: |path, error| eprintln!("Autosave of {} failed: {error}", path.display()),
+        }
+    }
+}
+
+pub struct Autosave
+{
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+struct Shared
+{
+    config: AutosaveConfig,
+    watched: Mutex<Vec<Box<dyn Watched>>>,
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Autosave
+{
+    pub fn new(config: AutosaveConfig) -> Self
+    {
+        Self {
+            shared: Arc::new(Shared {
+                config,
+                watched: <_>::default(),
+                stopped: Mutex::new(false),
+                wake: Condvar::new(),
+            }),
+            worker: None,
+        }
+    }
+
+    /// Save the storage of `handle` to `path` whenever it changes. Fails if the storage can't be
+    /// cast to a [KeyItemStorage] with the given Key and Item.
+    pub fn watch<S, Key, Item>(
+        &self,
+        handle: &StorageHandle<S>,
+        path: impl Into<PathBuf>,
+    ) -> SimpleResult<()>
+    where
+        S: Storage + ?Sized,
+        Key: KeyTrait + Serialize,
+        Item: ItemTrait + Serialize,
+    {
+        let handle = handle.clone().cast_to_getitem_storage::<Key, Item>()?;
+
+        self.shared
+            .watched
+            .lock()
+            .unwrap()
+            .push(Box::new(WatchedStorage {
+                handle,
+                path: path.into(),
+                saved_version: None,
+            }));
+
+        Ok(())
+    }
+
+    /// Save every changed storage now and return how many were saved
+    pub fn save_changed(&self) -> usize
+    {
+        self.shared.save_changed()
+    }
+
+    /// Start saving changed storages on a background thread every [AutosaveConfig::interval]
+    pub fn start(&mut self)
+    {
+        if self.worker.is_some()
+        {
+            return;
+        }
+
+        *self.shared.stopped.lock().unwrap() = false;
+
+        let shared = self.shared.clone();
+        self.worker = Some(std::thread::spawn(move || {
+            let mut stopped = shared.stopped.lock().unwrap();
+
+            loop
+            {
+                stopped = shared
+                    .wake
+                    .wait_timeout(stopped, shared.config.interval)
+                    .unwrap()
+                    .0;
+
+                if *stopped
+                {
+                    return;
+                }
+
+                drop(stopped);
+                shared.save_changed();
+                stopped = shared.stopped.lock().unwrap();
+            }
+        }));
+    }
+
+    /// Stop the background thread, waiting for a save in progress to finish
+    pub fn stop(&mut self)
+    {
+        let Some(worker) = self.worker.take()
+        else
+        {
+            return;
+        };
+
+        *self.shared.stopped.lock().unwrap() = true;
+        self.shared.wake.notify_all();
+
+        let _ = worker.join();
+    }
+}
+
+impl Drop for Autosave
+{
+    fn drop(&mut self)
+    {
+        self.stop();
+    }
+}
+
+impl Shared
+{
+    fn save_changed(&self) -> usize
+    {
+        let mut watched = self.watched.lock().unwrap();
+        let mut saved = 0;
+
+        for storage in watched.iter_mut()
+        {
+            match storage.save_if_changed(self.config.lock_budget)
+            {
+                Ok(true) => saved += 1,
+                Ok(false) => (),
+                Err(error) => (self.config.error_hook)(storage.path(), &error),
+            }
+        }
+
+        saved
+    }
+}
+
+trait Watched: Send
+{
+    fn path(&self) -> &Path;
+
+    /// Returns whether the storage was saved. Storages that are unchanged, or that were written
+    /// to while being saved, are not.
+    fn save_if_changed(&mut self, lock_budget: Duration) -> SimpleResult<bool>;
+}
+
+struct WatchedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    handle: StorageHandle<dyn KeyItemStorage<Key = Key, Item = Item>>,
+    path: PathBuf,
+    saved_version: Option<u64>,
+}
+
+impl<Key, Item> Watched for WatchedStorage<Key, Item>
+where
+    Key: KeyTrait + Serialize,
+    Item: ItemTrait + Serialize,
+{
+    fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    fn save_if_changed(&mut self, lock_budget: Duration) -> SimpleResult<bool>
+    {
+        let version = self.handle.write_version();
+        if self.saved_version == Some(version)
+        {
+            return Ok(false);
+        }
+
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let io_error = |error: std::io::Error| {
+            StorageError::with_source(
+                ErrorKind::Io,
+                format!("{}: {error}", temp_path.display()),
+                error,
+            )
+        };
+        let encode_error = |error: bincode::Error| {
+            StorageError::with_source(
+                ErrorKind::InvalidData,
+                format!("Failed to write item: {error}"),
+                error,
+            )
+        };
+
+        let mut writer = BufWriter::new(File::create(&temp_path).map_err(io_error)?);
+        write_header(&mut writer, SnapshotKind::Items)?;
+
+        let mut written = 0;
+        loop
+        {
+            let guard = self.handle.try_read()?;
+
+            if self.handle.write_version() != version
+            {
+                drop(guard);
+                let _ = std::fs::remove_file(&temp_path);
+
+                return Ok(false);
+            }
+
+            let started = Instant::now();
+            let mut finished = true;
+
+            for (key, item) in guard.key_item_iter().skip(written)
+            {
+                options()
+                    .serialize_into(&mut writer, &Some((key, item)))
+                    .map_err(encode_error)?;
+                written += 1;
+
+                if started.elapsed() >= lock_budget
+                {
+                    finished = false;
+                    break;
+                }
+            }
+
+            drop(guard);
+
+            if finished
+            {
+                break;
+            }
+
+            std::thread::yield_now();
+        }
+
+        options()
+            .serialize_into(&mut writer, &None::<(Key, &Item)>)
+            .map_err(encode_error)?;
+        writer.flush().map_err(io_error)?;
+        drop(writer);
+
+        std::fs::rename(&temp_path, &self.path).map_err(io_error)?;
+        self.saved_version = Some(version);
+
+        Ok(true)
+    }
+}
+
+/// Replace the contents of the storage of `handle` with the items saved to `path` by an
+/// [Autosave]. Fails if the storage can't be cast to a
+/// [crate::storage_traits::MutKeyItemStorage] with the given Key and Item.
+pub fn load_autosave<S, Key, Item>(
+    handle: &StorageHandle<S>,
+    path: impl AsRef<Path>,
+) -> SimpleResult<()>
+where
+    S: Storage + ?Sized,
+    Key: KeyTrait + DeserializeOwned,
+    Item: ItemTrait + DeserializeOwned,
+{
+    let path = path.as_ref();
+    let handle = handle.clone().cast_to_mut_getitem_storage::<Key, Item>()?;
+
+    let file = File::open(path).map_err(|error| {
+        StorageError::with_source(ErrorKind::Io, format!("{}: {error}", path.display()), error)
+    })?;
+    let mut reader = BufReader::new(file);
+    read_header(&mut reader, SnapshotKind::Items)?;
+
+    // Read everything before locking so that a failed load leaves the storage untouched
+    let mut items = Vec::new();
+    while let Some((key, item)) = options()
+        .deserialize_from::<_, Option<(Key, Item)>>(&mut reader)
+        .map_err(|error| {
+            StorageError::with_source(
+                ErrorKind::InvalidData,
+                format!("Failed to read autosave {}: {error}", path.display()),
+                error,
+            )
+        })?
+    {
+        items.push((key, item));
+    }
+
+    let mut guard = handle.try_write()?;
+    guard.clear();
+
+    for (key, item) in items
+    {
+        guard.insert(key, item);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::time::Duration;
+
+    use super::{load_autosave, Autosave, AutosaveConfig};
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{ItemSliceStorage, MutItemSliceStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn save_changed_test()
+    {
+        let path =
+            std::env::temp_dir().join(format!("flex_storage_{}.autosave", std::process::id()));
+
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3])).build();
+
+        // A zero budget releases the lock after every item
+        let mut config = AutosaveConfig::default();
+        config.lock_budget = Duration::ZERO;
+
+        let mut autosave = Autosave::new(config);
+        autosave.watch::<_, usize, i32>(&handle, &path).unwrap();
+        assert!(autosave.watch::<_, usize, f32>(&handle, &path).is_err());
+
+        assert_eq!(autosave.save_changed(), 1);
+        assert_eq!(autosave.save_changed(), 0);
+
+        let sized: StorageHandle<VecStorage<usize, i32>> =
+            handle.clone().cast_to_sized_storage().unwrap();
+        sized.try_write().unwrap().as_mut_slice()[0] = 10;
+        assert_eq!(autosave.save_changed(), 1);
+
+        autosave.start();
+        autosave.stop();
+
+        let restored: StorageHandle<dyn Storage> = builder(VecStorage::<usize, i32>::new()).build();
+        load_autosave::<_, usize, i32>(&restored, &path).unwrap();
+
+        let restored: StorageHandle<VecStorage<usize, i32>> =
+            restored.cast_to_sized_storage().unwrap();
+        assert_eq!(restored.try_read().unwrap().as_item_slice(), &[10, 2, 3]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -11,6 +11,22 @@
 //! This module deals only with Arw pointers however there is another higher level
 //! pointer type that wraps that one and adds that its own meta data for runtime type
 //! inspection. See: [crate::storage_handle::StorageHandle] for details.
+//!
+//! The cast functions borrow the source pointer and only clone it when the cast succeeds, so
+//! probing a storage against several target types costs no reference count updates on a miss.
+//!
+//! The casts to trait objects know the storage types of this crate. Storage types of other crates
+//! are registered with their target traits through [register_pointer_cast], [register_cast] or
+//! [crate::register_casts]:
+//!
+//! ```ignore
+//! register_casts!(RingStorage<u32, f32> => [
+//!     dyn KeyItemStorage<Key = u32, Item = f32>,
+//!     dyn MutKeyItemStorage<Key = u32, Item = f32>,
+//! ]);
+//!
+//! let reader = handle.cast_to_getitem_storage::<u32, f32>()?;
+//! ```
 
 // # Internal Design
 //
@@ -21,6 +37,29 @@
 // followed by implicit coerce unsize based casting thats currently
 // built into rust.
 //
+// ## Code size
+//
+// Every cast function is instantiated for each Key and Item combination that an application casts
+// with, and each instantiation checks against its whole list of storage types. To keep that
+// generated code small:
+// - Only the TypeId comparisons and pointer casts of the lists are generic. Errors are built by
+//   shared non generic functions that are marked cold so they aren't inlined into every
+//   instantiation.
+// - [crate::storage_handle::StorageHandle] casts from its base `Arw<dyn Storage>` whatever its own
+//   storage type is, so the cast lists are instantiated once per Key and Item rather than once per
+//   Key, Item and source storage type.
+//
+// ## Registered casts
+//
+// Casts registered at runtime are looked up by the source and target TypeIds once a storage matches
+// none of the listed types, so the built in types pay nothing for them. Each entry holds the
+// registering crate's own unsizing function behind a closure that rebuilds the concrete Arc from
+// the erased data pointer, which is only called after the source TypeId matched.
+//
+// Casts registered as pointer casts also record the pointer metadata of the target, which is all
+// that a cast needs besides the data pointer whatever lock the storage is held in. This is what
+// lets the casts of [AsyncArw] pointers use them.
+//
 // # Limitations
 // The cast functions only work with the base storage trait: Arw<dyn Storage>, because upcast
 // coercion has not been completed in rust. An attempted workaround using generics and the Unsize
@@ -29,24 +68,30 @@
 // won't accept supertraits of Storage like Arw<dyn [KeyItemStorage<Key=Key, Item=Item>]>
 
 use std::{
-    any::{type_name, TypeId},
-    ptr::Pointee,
-    sync::{Arc, RwLock},
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    ptr::{self, Pointee},
+    sync::{Arc, LazyLock, RwLock, TryLockError},
 };
 
 use crate::{
 
     storage_traits::{
         ItemSliceStorage, ItemTrait, KeyItemStorage, KeyStorage, KeyTrait,
-        MutItemSliceStorage, MutKeyItemStorage, Storage,
+        MutItemSliceStorage, MutKeyItemStorage, RemovableStorage, Storage,
         ViewStorageSetup,
     },
     storage_types::{
         HashMapStorage, VecStorage, KeyItemViewStorage, SparseSetVecStorage, ValStorage,
+        ShardedHashMapStorage, DirtyTracked, UndoableStorage, CowStorage,
+        ProvenanceTracked, ComputedStorage, ArenaStorage, KeyMappedStorage, ExternalVecStorage,
     },
-    Arw, SimpleResult,
+    Arw, ErrorDetail, ErrorKind, Hint, Rw, SimpleResult, StorageError,
 };
 
+#[cfg(feature = "tokio")]
+use crate::AsyncArw;
+
 /// Casts [Arw<SourceStorage>] to [Arw]<dyn [TargetStorageTrait]>
 //
 // # Internal Design
@@ -56,38 +101,96 @@ use crate::{
 // by first downcasting and then upcasting. And to downcast we need the full concrete type
 // signature which of course involves keys and items as all of our types use keys and items
 // even ValStorage for compatibility reasons.
+//
+// Given a second fn name it also defines the cast of [AsyncArw] pointers with the same list.
 macro_rules! define_cast_to_dyn_fn {
 
     ($fn_name:ident, $target_trait:ty, [$($related_type:ty),*]) => {
 
         pub fn $fn_name<SourceStorage, Key, Item>(
-            source_storage: Arw<SourceStorage>,
+            source_storage: &Arw<SourceStorage>,
         ) -> SimpleResult<Arw<$target_trait>>
         where
             SourceStorage: Storage + ?Sized,
             Key: KeyTrait,
             Item: ItemTrait,
         {
+            let (source_type_id, source_type_name) = storage_type(source_storage)?;
+
             $(
-                if let Ok(target_type) =
-                    dyn_storage_into_sized::<SourceStorage, $related_type>(source_storage.clone())
+                if source_type_id == TypeId::of::<$related_type>()
                 {
+                    // Safety: The concrete type was checked just above
+                    let target_type = unsafe {
+                        into_sized_unchecked::<SourceStorage, $related_type>(
+                            source_storage.clone(),
+                        )
+                    };
                     let storage: Arw<$target_trait> = target_type;
                     return Ok(storage);
                 };
             )*
 
-            Err(format!(
-                "Invalid cast from '{}' into '{}'",
-                type_name::<SourceStorage>(),
-                type_name::<$target_trait>()
-            ))
+            if let Some(storage) =
+                registered_cast::<SourceStorage, $target_trait>(source_storage, source_type_id)
+            {
+                return Ok(storage);
+            }
+
+            Err(cast_to_dyn_error(source_type_name, type_name::<$target_trait>()))
         }
 
     };
+
+    ($fn_name:ident, $async_fn_name:ident, $target_trait:ty, [$($related_type:ty),*]) => {
+
+        define_cast_to_dyn_fn!($fn_name, $target_trait, [$($related_type),*]);
+
+        // The same cast for the tokio locks of [crate::storage_handle::AsyncStorageHandle]. The
+        // concrete type is passed in rather than read through a lock so that the cast never waits.
+        #[cfg(feature = "tokio")]
+        pub fn $async_fn_name<SourceStorage, Key, Item>(
+            source_storage: &AsyncArw<SourceStorage>,
+            source_type_id: TypeId,
+            source_type_name: &'static str,
+        ) -> SimpleResult<AsyncArw<$target_trait>>
+        where
+            SourceStorage: Storage + ?Sized,
+            Key: KeyTrait,
+            Item: ItemTrait,
+        {
+            $(
+                if source_type_id == TypeId::of::<$related_type>()
+                {
+                    // Safety: The caller passes the concrete type of the storage
+                    let target_type = unsafe {
+                        async_into_sized_unchecked::<SourceStorage, $related_type>(
+                            source_storage.clone(),
+                        )
+                    };
+                    let storage: AsyncArw<$target_trait> = target_type;
+                    return Ok(storage);
+                };
+            )*
+
+            if let Some(storage) = async_registered_cast::<SourceStorage, $target_trait>(
+                source_storage,
+                source_type_id,
+            )
+            {
+                return Ok(storage);
+            }
+
+            Err(cast_to_dyn_error(source_type_name, type_name::<$target_trait>()))
+        }
+    };
 }
 
 /// Cast [`Arw<SourceStorage>`] to [`Arw<TargetStorageType>`]
+///
+/// The concrete type of the source is checked through a brief read lock, so the cast fails rather
+/// than blocking if a writer holds the lock, and fails if the lock is poisoned. The same goes for
+/// every cast to a dyn trait.
 // ------------------------------------------------------
 //
 // # Internal Design
@@ -124,7 +227,7 @@ macro_rules! define_cast_to_dyn_fn {
 // - Wait for improvements to trait objects, trait object casting, etc. There is a lot of incomplete
 //   work going on in this space though its taking time.
 pub fn dyn_storage_into_sized<SourceStorage, TargetStorageType>(
-    source_storage: Arw<SourceStorage>,
+    source_storage: &Arw<SourceStorage>,
 ) -> SimpleResult<Arw<TargetStorageType>>
 where
     SourceStorage: Storage + ?Sized,
@@ -132,40 +235,336 @@ where
 {
     // Safety: Before doing any pointer work - confirm that the source storage trait object
     // points to type data that is of the expected type
+    let (source_type_id, source_type_name) = storage_type(source_storage)?;
+
+    if TypeId::of::<TargetStorageType>() != source_type_id
     {
-        // TODO: #HIGH return an error instead of unwrapping
-        let borrow = source_storage.try_read().unwrap();
+        return Err(cast_to_sized_error(
+            source_type_name,
+            type_name::<TargetStorageType>(),
+        ));
+    }
 
-        // To avoid getting the type id of the RefCell or the RC,
-        // as_any() is required to get the correct &Any object to
-        // perform the type_id call on.
-        let any = borrow.as_any();
+    // Safety: The concrete type was checked just above
+    Ok(unsafe { into_sized_unchecked(source_storage.clone()) })
+}
 
-        if TypeId::of::<TargetStorageType>() != any.type_id()
-        {
-            return Err(format!(
-                "Invalid cast to sized from '{}' into '{}'",
-                type_name::<SourceStorage>(),
-                type_name::<TargetStorageType>()
-            ));
-        }
+/// Type id and name of the concrete storage behind `source_storage`. Takes a brief read lock as the
+/// type is read through the storage's vtable.
+fn storage_type<SourceStorage>(
+    source_storage: &Arw<SourceStorage>,
+) -> SimpleResult<(TypeId, &'static str)>
+where
+    SourceStorage: Storage + ?Sized,
+{
+    let borrow = source_storage.try_read().map_err(|error| {
+        let would_block = matches!(error, TryLockError::WouldBlock);
+        type_check_lock_error(would_block, type_name::<SourceStorage>())
+    })?;
+
+    // To avoid getting the type id of the RefCell or the RC,
+    // as_any() is required to get the correct &Any object to
+    // perform the type_id call on.
+    let any = borrow.as_any();
+
+    Ok((any.type_id(), borrow.type_name()))
+}
+
+#[cold]
+#[inline(never)]
+fn type_check_lock_error(would_block: bool, source_type_name: &str) -> StorageError
+{
+    if would_block
+    {
+        return StorageError::new(
+            ErrorKind::WouldBlock,
+            format!(
+                "Failed to aquire read lock to check the type of '{source_type_name}' as it is \
+                 write locked"
+            ),
+        );
     }
 
-    let raw_ptr: *const RwLock<SourceStorage> = Arc::into_raw(source_storage);
+    StorageError::new(
+        ErrorKind::Poisoned,
+        format!(
+            "Failed to aquire read lock to check the type of '{source_type_name}' as it is poisoned"
+        ),
+    )
+}
+
+#[cold]
+#[inline(never)]
+pub(crate) fn cast_to_dyn_error(
+    source_type_name: &'static str,
+    target_type_name: &'static str,
+) -> StorageError
+{
+    StorageError::new(
+        ErrorKind::TypeMismatch,
+        format!("Invalid cast from '{source_type_name}' into '{target_type_name}'"),
+    )
+    .with_hint(
+        Hint::CastTypeMismatch,
+        format!(
+            "The storage is a '{source_type_name}'. Cast with its Key and Item types to a trait \
+             that it implements"
+        ),
+    )
+    .with_detail(ErrorDetail::InvalidCast {
+        from: source_type_name,
+        to: target_type_name,
+    })
+}
+
+#[cold]
+#[inline(never)]
+fn cast_to_sized_error(
+    source_type_name: &'static str,
+    target_type_name: &'static str,
+) -> StorageError
+{
+    StorageError::new(
+        ErrorKind::TypeMismatch,
+        format!("Invalid cast to sized from '{source_type_name}' into '{target_type_name}'"),
+    )
+    .with_hint(
+        Hint::CastTypeMismatch,
+        format!("The storage is a '{source_type_name}'. Cast into that type instead"),
+    )
+    .with_detail(ErrorDetail::InvalidCast {
+        from: source_type_name,
+        to: target_type_name,
+    })
+}
+
+/// # Safety
+/// The concrete type of the storage behind `source_storage` must be TargetStorageType
+unsafe fn into_sized_unchecked<SourceStorage, TargetStorageType>(
+    source_storage: Arw<SourceStorage>,
+) -> Arw<TargetStorageType>
+where
+    SourceStorage: Storage + ?Sized,
+    TargetStorageType: Storage,
+{
+    let raw_ptr: *const Rw<SourceStorage> = Arc::into_raw(source_storage);
 
-    let (type_erased_ptr, _): (*const (), <RwLock<SourceStorage> as Pointee>::Metadata) =
+    let (type_erased_ptr, _): (*const (), <Rw<SourceStorage> as Pointee>::Metadata) =
         raw_ptr.to_raw_parts();
 
-    let typed_data_ptr = type_erased_ptr as *const RwLock<TargetStorageType>;
-    let arc = unsafe { Arc::from_raw(typed_data_ptr) };
+    let typed_data_ptr = type_erased_ptr as *const Rw<TargetStorageType>;
+
+    unsafe { Arc::from_raw(typed_data_ptr) }
+}
+
+/// Like [dyn_storage_into_sized] for the tokio locks of
+/// [crate::storage_handle::AsyncStorageHandle], given the concrete type of the storage instead of
+/// reading it through a lock
+#[cfg(feature = "tokio")]
+pub fn async_dyn_storage_into_sized<SourceStorage, TargetStorageType>(
+    source_storage: &AsyncArw<SourceStorage>,
+    source_type_id: TypeId,
+    source_type_name: &'static str,
+) -> SimpleResult<AsyncArw<TargetStorageType>>
+where
+    SourceStorage: Storage + ?Sized,
+    TargetStorageType: Storage,
+{
+    if TypeId::of::<TargetStorageType>() != source_type_id
+    {
+        return Err(cast_to_sized_error(
+            source_type_name,
+            type_name::<TargetStorageType>(),
+        ));
+    }
+
+    // Safety: The concrete type was checked just above
+    Ok(unsafe { async_into_sized_unchecked(source_storage.clone()) })
+}
+
+/// # Safety
+/// The concrete type of the storage behind `source_storage` must be TargetStorageType
+#[cfg(feature = "tokio")]
+unsafe fn async_into_sized_unchecked<SourceStorage, TargetStorageType>(
+    source_storage: AsyncArw<SourceStorage>,
+) -> AsyncArw<TargetStorageType>
+where
+    SourceStorage: Storage + ?Sized,
+    TargetStorageType: Storage,
+{
+    let raw_ptr: *const tokio::sync::RwLock<SourceStorage> = Arc::into_raw(source_storage);
+    let (type_erased_ptr, _) = raw_ptr.to_raw_parts();
+
+    unsafe { Arc::from_raw(type_erased_ptr as *const tokio::sync::RwLock<TargetStorageType>) }
+}
+
+// ---------------------------------------------------------------
+// Registered casts
+// ---------------------------------------------------------------
+
+/// Rebuilds the source Arc from its erased data pointer and unsizes it. Only called with pointers
+/// to storages of the registered source type.
+type RegisteredCast<Target> = Box<dyn Fn(*const ()) -> Arw<Target> + Send + Sync>;
+
+type RegisteredCasts = HashMap<(TypeId, TypeId), Arc<dyn Any + Send + Sync>>;
+
+static REGISTERED_CASTS: LazyLock<RwLock<RegisteredCasts>> = LazyLock::new(<_>::default);
+
+/// The `<Target as Pointee>::Metadata` of pointer casts, by source and target TypeId
+static REGISTERED_METADATA: LazyLock<RwLock<RegisteredCasts>> = LazyLock::new(<_>::default);
+
+/// Make the casts of this module to the `Target` trait object accept storages of type
+/// `SourceStorage`, for storage types defined outside of this crate. `cast` unsizes the storage,
+/// which [crate::register_casts] writes as `|storage| storage`.
+///
+/// Register casts before building handles to the type, as handles record the casts they support
+/// when they are built, see [crate::storage_handle::StorageHandle::info]. Registering a pair again
+/// replaces its cast.
+///
+/// Casts registered this way don't apply to [crate::storage_handle::AsyncStorageHandle], see
+/// [register_pointer_cast].
+pub fn register_cast<SourceStorage, Target>(cast: fn(Arw<SourceStorage>) -> Arw<Target>)
+where
+    SourceStorage: Storage,
+    Target: Storage + ?Sized,
+{
+    let erased: RegisteredCast<Target> = Box::new(move |data_ptr| {
+        // Safety: Only called once the source type id matched SourceStorage
+        let storage = unsafe { Arc::from_raw(data_ptr as *const Rw<SourceStorage>) };
+        cast(storage)
+    });
+
+    REGISTERED_CASTS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(
+            (TypeId::of::<SourceStorage>(), TypeId::of::<Target>()),
+            Arc::new(erased),
+        );
+}
+
+/// Like [register_cast] but given the unsizing of a pointer, which [crate::register_casts] writes
+/// as `|storage| storage`. The cast then also applies to storages held in other locks, such as
+/// those of [crate::storage_handle::AsyncStorageHandle].
+///
+/// # Safety
+///
+/// `cast` must return the pointer it is given unsized to `Target`, as `|storage| storage` does.
+/// The metadata of the returned pointer is later attached to pointers of `SourceStorage`, so a
+/// pointer cast through another type, eg `|storage| storage as *const OtherStorage as *const _`,
+/// gives them a vtable of the wrong type.
+pub unsafe fn register_pointer_cast<SourceStorage, Target>(
+    cast: fn(*const SourceStorage) -> *const Target,
+) where
+    SourceStorage: Storage,
+    Target: Storage + ?Sized,
+    <Target as Pointee>::Metadata: 'static,
+{
+    // Unsizing a pointer never reads through it, so a null pointer gives the metadata
+    let (_, metadata) = cast(ptr::null()).to_raw_parts();
+
+    let erased: RegisteredCast<Target> = Box::new(move |data_ptr| {
+        // Safety: Only called once the source type id matched SourceStorage, which the metadata
+        // is for
+        unsafe { Arc::from_raw(ptr::from_raw_parts::<Rw<Target>>(data_ptr, metadata)) }
+    });
+
+    let key = (TypeId::of::<SourceStorage>(), TypeId::of::<Target>());
+
+    REGISTERED_CASTS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(key, Arc::new(erased));
+    REGISTERED_METADATA
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(key, Arc::new(metadata));
+}
+
+/// Whether a cast from `SourceStorage` to `Target` was registered with [register_cast] or
+/// [register_pointer_cast]
+pub fn is_cast_registered<SourceStorage, Target>() -> bool
+where
+    SourceStorage: Storage,
+    Target: Storage + ?Sized,
+{
+    REGISTERED_CASTS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .contains_key(&(TypeId::of::<SourceStorage>(), TypeId::of::<Target>()))
+}
+
+fn registered_cast<SourceStorage, Target>(
+    source_storage: &Arw<SourceStorage>,
+    source_type_id: TypeId,
+) -> Option<Arw<Target>>
+where
+    SourceStorage: Storage + ?Sized,
+    Target: Storage + ?Sized,
+{
+    let cast = REGISTERED_CASTS
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&(source_type_id, TypeId::of::<Target>()))?
+        .clone();
+    let cast = cast.downcast_ref::<RegisteredCast<Target>>()?;
+
+    let (data_ptr, _) = Arc::into_raw(source_storage.clone()).to_raw_parts();
+
+    Some(cast(data_ptr))
+}
+
+/// [registered_cast] of the tokio locks of [crate::storage_handle::AsyncStorageHandle], which
+/// only finds casts registered with [register_pointer_cast]
+#[cfg(feature = "tokio")]
+fn async_registered_cast<SourceStorage, Target>(
+    source_storage: &AsyncArw<SourceStorage>,
+    source_type_id: TypeId,
+) -> Option<AsyncArw<Target>>
+where
+    SourceStorage: Storage + ?Sized,
+    Target: Storage + ?Sized,
+    <Target as Pointee>::Metadata: 'static,
+{
+    let metadata: <Target as Pointee>::Metadata = *REGISTERED_METADATA
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&(source_type_id, TypeId::of::<Target>()))?
+        .downcast_ref()?;
+
+    let (data_ptr, _) = Arc::into_raw(source_storage.clone()).to_raw_parts();
+    let storage = ptr::from_raw_parts::<tokio::sync::RwLock<Target>>(data_ptr, metadata);
+
+    // Safety: The metadata was registered for the concrete type of the storage
+    Some(unsafe { Arc::from_raw(storage) })
+}
 
-    Ok(arc)
+/// Register casts of a storage type defined outside of this crate to trait objects of the
+/// [crate::storage_traits] family, see [crate::casting::register_cast]:
+///
+/// ```ignore
+/// register_casts!(RingStorage<u32, f32> => [
+///     dyn KeyItemStorage<Key = u32, Item = f32>,
+///     dyn ItemSliceStorage<Item = f32>,
+/// ]);
+/// ```
+#[macro_export]
+macro_rules! register_casts {
+    ($storage:ty => [ $($target:ty),* $(,)? ]) => {
+        $(
+            // Safety: The compiler does the unsizing of the cast
+            unsafe {
+                $crate::casting::register_pointer_cast::<$storage, $target>(|storage| storage);
+            }
+        )*
+    };
 }
 
 // Cast [Arw<SourceStorage>] to [Arw]<dyn [KeyItemStorage<Key=Key, Item=Item>]>
 #[rustfmt::skip]
 define_cast_to_dyn_fn!( 
     cast_to_dyn_getkeyitemstorage,              // fn name
+    async_cast_to_dyn_getkeyitemstorage,        // async fn name
     dyn KeyItemStorage<Key = Key, Item = Item>, // target trait
 
     // Storage types that can be cast to the target trait
@@ -174,12 +573,45 @@ define_cast_to_dyn_fn!(
         SparseSetVecStorage<Key, Item>,
         HashMapStorage<Key, Item>,
         ValStorage<Key, Item>,
+        ExternalVecStorage<Key, Item>,
 
         // Repetition of above with views
         KeyItemViewStorage<VecStorage<Key, Item>, Key, Item>,
         KeyItemViewStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
         KeyItemViewStorage<HashMapStorage<Key, Item>, Key, Item>,
-        KeyItemViewStorage<ValStorage<Key, Item>, Key, Item>
+        KeyItemViewStorage<ValStorage<Key, Item>, Key, Item>,
+
+        // Dirty tracking wrappers
+        DirtyTracked<VecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Provenance wrappers
+        ProvenanceTracked<VecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Undo wrappers, of storages whose keys stay put only, see [UndoableStorage]
+        UndoableStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        UndoableStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Copy on write wrappers
+        CowStorage<VecStorage<Key, Item>, Key, Item>,
+        CowStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        CowStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Arena wrappers
+        ArenaStorage<VecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Key mapped adapters over local indices
+        KeyMappedStorage<VecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<SparseSetVecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<HashMapStorage<usize, Item>, Key, Item>,
+
+        // Computed storages
+        ComputedStorage<Key, Item>
     ]
 );
 
@@ -187,6 +619,7 @@ define_cast_to_dyn_fn!(
 #[rustfmt::skip]
 define_cast_to_dyn_fn!( 
     cast_to_dyn_mutitemstorage,
+    async_cast_to_dyn_mutitemstorage,              // async fn name
     dyn MutKeyItemStorage<Key = Key, Item = Item>, // target trait
 
     // Storage types that can be cast to the target trait
@@ -195,15 +628,86 @@ define_cast_to_dyn_fn!(
         SparseSetVecStorage<Key, Item>,
         HashMapStorage<Key, Item>,
 
-        // TODO: These don't have an implementation of GetItemMut yet or at least they cause compiler errors 
-        // when uncommented - needs investigation
-        // ValStorage<Key, Item>,
-        // KeyItemViewStorage<ValStorage<Key, Item>, Key, Item>
+        ValStorage<Key, Item>,
+        KeyItemViewStorage<ValStorage<Key, Item>, Key, Item>,
 
         // Repetition of above with views
         KeyItemViewStorage<VecStorage<Key, Item>, Key, Item>,
         KeyItemViewStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
-        KeyItemViewStorage<HashMapStorage<Key, Item>, Key, Item>
+        KeyItemViewStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Dirty tracking wrappers
+        DirtyTracked<VecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Provenance wrappers
+        ProvenanceTracked<VecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Undo wrappers, of storages whose keys stay put only, see [UndoableStorage]
+        UndoableStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        UndoableStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Copy on write wrappers
+        CowStorage<VecStorage<Key, Item>, Key, Item>,
+        CowStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        CowStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Arena wrappers
+        ArenaStorage<VecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Key mapped adapters over local indices
+        KeyMappedStorage<VecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<SparseSetVecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<HashMapStorage<usize, Item>, Key, Item>
+    ]
+);
+
+// Cast [Arw<SourceStorage>] to [Arw]<dyn [RemovableStorage<Key=Key, Item=Item>]>
+#[rustfmt::skip]
+define_cast_to_dyn_fn!( 
+    cast_to_dyn_removablestorage,
+    async_cast_to_dyn_removablestorage,           // async fn name
+    dyn RemovableStorage<Key = Key, Item = Item>, // target trait
+
+    // Storage types that can be cast to the target trait
+    [
+        VecStorage<Key, Item>,
+        SparseSetVecStorage<Key, Item>,
+        HashMapStorage<Key, Item>,
+
+        // Dirty tracking wrappers
+        DirtyTracked<VecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Provenance wrappers
+        ProvenanceTracked<VecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Undo wrappers, of storages whose keys stay put only, see [UndoableStorage]
+        UndoableStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        UndoableStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Copy on write wrappers
+        CowStorage<VecStorage<Key, Item>, Key, Item>,
+        CowStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        CowStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Arena wrappers
+        ArenaStorage<VecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Key mapped adapters over local indices
+        KeyMappedStorage<VecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<SparseSetVecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<HashMapStorage<usize, Item>, Key, Item>
     ]
 );
 
@@ -211,6 +715,7 @@ define_cast_to_dyn_fn!(
 #[rustfmt::skip]
 define_cast_to_dyn_fn!( 
     cast_to_key_storage,       // fn name
+    async_cast_to_key_storage, // async fn name
     dyn KeyStorage<Key = Key>, // target trait
 
     // Storage types that can be cast to the target trait
@@ -219,12 +724,46 @@ define_cast_to_dyn_fn!(
         SparseSetVecStorage<Key, Item>,
         HashMapStorage<Key, Item>,
         ValStorage<Key, Item>,
+        ExternalVecStorage<Key, Item>,
+        ShardedHashMapStorage<Key, Item>,
 
         // Repetition of above with views
         KeyItemViewStorage<VecStorage<Key, Item>, Key, Item>,
         KeyItemViewStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
         KeyItemViewStorage<HashMapStorage<Key, Item>, Key, Item>,
-        KeyItemViewStorage<ValStorage<Key, Item>, Key, Item>
+        KeyItemViewStorage<ValStorage<Key, Item>, Key, Item>,
+
+        // Dirty tracking wrappers
+        DirtyTracked<VecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Provenance wrappers
+        ProvenanceTracked<VecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Undo wrappers, of storages whose keys stay put only, see [UndoableStorage]
+        UndoableStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        UndoableStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Copy on write wrappers
+        CowStorage<VecStorage<Key, Item>, Key, Item>,
+        CowStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        CowStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Arena wrappers
+        ArenaStorage<VecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<HashMapStorage<Key, Item>, Key, Item>,
+
+        // Key mapped adapters over local indices
+        KeyMappedStorage<VecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<SparseSetVecStorage<usize, Item>, Key, Item>,
+        KeyMappedStorage<HashMapStorage<usize, Item>, Key, Item>,
+
+        // Computed storages
+        ComputedStorage<Key, Item>
     ]
 );
 
@@ -247,13 +786,32 @@ define_cast_to_dyn_fn!(
 #[rustfmt::skip]
 define_cast_to_dyn_fn!( 
     cast_to_dyn_sliceitemstorage,        // fn name
+    async_cast_to_dyn_sliceitemstorage,  // async fn name
     dyn ItemSliceStorage<Item = Item>,   // target trait
 
     // Storage types that can be cast to the target trait
     [
         VecStorage<Key, Item>,
         SparseSetVecStorage<Key, Item>,
-        ValStorage<Key, Item>
+        ValStorage<Key, Item>,
+        ExternalVecStorage<Key, Item>,
+        DirtyTracked<VecStorage<Key, Item>, Key, Item>,
+        DirtyTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+
+        // Provenance wrappers
+        ProvenanceTracked<VecStorage<Key, Item>, Key, Item>,
+        ProvenanceTracked<SparseSetVecStorage<Key, Item>, Key, Item>,
+
+        // Copy on write wrappers
+        CowStorage<VecStorage<Key, Item>, Key, Item>,
+        CowStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+
+        // Arena wrappers
+        ArenaStorage<VecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+
+        // Computed storages
+        ComputedStorage<Key, Item>
 
         // ViewStorage types are excluded as there is no contiguous Item data that they can 
         // return due to these kinds of views being able to filter using sparse items locations
@@ -264,13 +822,18 @@ define_cast_to_dyn_fn!(
 #[rustfmt::skip]
 define_cast_to_dyn_fn!( 
     cast_to_dyn_mutsliceitemstorage,        // fn name
+    async_cast_to_dyn_mutsliceitemstorage,  // async fn name
     dyn MutItemSliceStorage<Item = Item>,   // target trait
 
     // Storage types that can be cast to the target trait
     [
         VecStorage<Key, Item>,
         SparseSetVecStorage<Key, Item>,
-        ValStorage<Key, Item>
+        ValStorage<Key, Item>,
+        CowStorage<VecStorage<Key, Item>, Key, Item>,
+        CowStorage<SparseSetVecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<VecStorage<Key, Item>, Key, Item>,
+        ArenaStorage<SparseSetVecStorage<Key, Item>, Key, Item>
 
         // ViewStorage types are excluded as there is no contiguous Item data that they can 
         // return due to these kinds of views being able to filter using sparse items locations
@@ -281,12 +844,13 @@ define_cast_to_dyn_fn!(
 #[cfg(test)]
 mod tests
 {
-    use std::sync::{Arc, RwLock};
+    use std::sync::Arc;
 
     use crate::{
         casting::{cast_to_dyn_sliceitemstorage, dyn_storage_into_sized},
         storage_types::{VecStorage, SparseSetVecStorage},
-        Arw, Rw, storage_traits::{Storage, KeyItemStorage, ItemSliceStorage, MutKeyItemStorage},
+        Arw, ErrorKind, Rw,
+        storage_traits::{Storage, KeyItemStorage, ItemSliceStorage, MutKeyItemStorage},
     };
 
     use crate::casting::cast_to_dyn_getkeyitemstorage;
@@ -299,16 +863,17 @@ mod tests
     /// functions.
     #[test]
     #[should_panic]
+    #[cfg(not(feature = "no_panic"))]
     fn key_supports_index_test()
     {
         let vec_storage: VecStorage<u128, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
 
         // Prepare the source
-        let storage: Arw<VecStorage<u128, i32>> = Arc::new(RwLock::new(vec_storage.clone()));
+        let storage: Arw<VecStorage<u128, i32>> = Arc::new(Rw::new(vec_storage.clone()));
         let storage: Arw<dyn Storage> = storage;
 
         let _: Arw<dyn KeyItemStorage<Key = u128, Item = i32>> =
-            cast_to_dyn_getkeyitemstorage(storage).unwrap();
+            cast_to_dyn_getkeyitemstorage(&storage).unwrap();
     }
 
     /// Upcast a variety of storage types to the Storage trait and a range of Storage supertraits
@@ -398,6 +963,7 @@ mod tests
         }
 
         // Arc<dyn Storage> -> Arc<VecStorage<usize, i32>>
+        #[cfg(not(feature = "local"))]
         {
             // prepare the source
             let storage: Arc<VecStorage<usize, i32>> = Arc::new(vec_storage.clone());
@@ -421,30 +987,97 @@ mod tests
 
         {
             // Prepare the source
-            let storage: Arw<VecStorage<usize, i32>> = Arc::new(RwLock::new(vec_storage.clone()));
+            let storage: Arw<VecStorage<usize, i32>> = Arc::new(Rw::new(vec_storage.clone()));
             let storage: Arw<dyn Storage> = storage;
 
             let storage: Arw<VecStorage<usize, i32>> =
-                dyn_storage_into_sized::<dyn Storage, VecStorage<usize, i32>>(storage).unwrap();
+                dyn_storage_into_sized::<dyn Storage, VecStorage<usize, i32>>(&storage).unwrap();
 
-            let guard: std::sync::RwLockReadGuard<VecStorage<usize, i32>> =
+            let guard: crate::sync::StorageLockReadGuard<VecStorage<usize, i32>> =
                 storage.try_read().unwrap();
             assert_eq!(guard.len(), 3);
         }
     }
 
+    /// Casts of a locked or poisoned source fail instead of panicking
+    #[test]
+    fn dyn_storage_into_sized_locked_test()
+    {
+        let storage: Arw<VecStorage<usize, i32>> =
+            Arc::new(Rw::new(VecStorage::new_from_iter(vec![1, 2, 3])));
+        let storage: Arw<dyn Storage> = storage;
+
+        {
+            let _guard = storage.try_write().unwrap();
+            assert!(dyn_storage_into_sized::<dyn Storage, VecStorage<usize, i32>>(&storage)
+                .is_err());
+            let error =
+                cast_to_dyn_getkeyitemstorage::<dyn Storage, usize, i32>(&storage).err();
+            assert_eq!(error.unwrap().kind(), ErrorKind::WouldBlock);
+        }
+
+        // A wrong target type is a type mismatch rather than a lock failure
+        let error = dyn_storage_into_sized::<dyn Storage, VecStorage<usize, f32>>(&storage);
+        assert_eq!(error.err().unwrap().kind(), ErrorKind::TypeMismatch);
+
+        // Errors name the concrete storage rather than the trait object it was cast from
+        let error = cast_to_dyn_getkeyitemstorage::<dyn Storage, usize, f32>(&storage)
+            .err()
+            .unwrap();
+        assert!(error.message().contains("VecStorage<usize, i32>"));
+
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = storage.try_write().unwrap();
+            panic!("Poison the lock");
+        }));
+
+        let result = dyn_storage_into_sized::<dyn Storage, VecStorage<usize, i32>>(&storage);
+
+        #[cfg(not(feature = "parking_lot"))]
+        {
+            let error = result.err().unwrap();
+            assert!(error.message().contains("poisoned"));
+            assert_eq!(error.kind(), ErrorKind::Poisoned);
+        }
+
+        // Parking lot locks aren't poisoned by a panic
+        #[cfg(feature = "parking_lot")]
+        assert!(result.is_ok());
+    }
+
+    /// Casts borrow their source and only clone it on success
+    #[test]
+    fn cast_clones_only_on_success_test()
+    {
+        let storage: Arw<VecStorage<usize, i32>> =
+            Arc::new(Rw::new(VecStorage::new_from_iter(vec![1, 2, 3])));
+        let storage: Arw<dyn Storage> = storage;
+
+        assert!(cast_to_dyn_getkeyitemstorage::<dyn Storage, usize, f32>(&storage).is_err());
+        assert!(dyn_storage_into_sized::<dyn Storage, VecStorage<usize, f32>>(&storage).is_err());
+        assert_eq!(Arc::strong_count(&storage), 1);
+
+        let sized =
+            dyn_storage_into_sized::<dyn Storage, VecStorage<usize, i32>>(&storage).unwrap();
+        let slice = cast_to_dyn_sliceitemstorage::<dyn Storage, usize, i32>(&storage).unwrap();
+        assert_eq!(Arc::strong_count(&storage), 3);
+
+        drop((sized, slice));
+        assert_eq!(Arc::strong_count(&storage), 1);
+    }
+
     #[test]
     fn cast_to_dyn_itemslice_test()
     {
         let vec_storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
 
         // Prepare the source
-        let storage: Arw<VecStorage<usize, i32>> = Arc::new(RwLock::new(vec_storage.clone()));
+        let storage: Arw<VecStorage<usize, i32>> = Arc::new(Rw::new(vec_storage.clone()));
         let storage: Arw<dyn Storage> = storage;
 
         // Cast from A
         let slice_storage: Arw<dyn ItemSliceStorage<Item = i32>> =
-            cast_to_dyn_sliceitemstorage::<dyn Storage, usize, i32>(storage).unwrap();
+            cast_to_dyn_sliceitemstorage::<dyn Storage, usize, i32>(&storage).unwrap();
 
         let guard = slice_storage.try_read().unwrap();
         assert_eq!(guard.as_item_slice().len(), 3);
@@ -458,12 +1091,12 @@ mod tests
         let vec_storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
 
         // Prepare the source
-        let storage: Arw<VecStorage<usize, i32>> = Arc::new(RwLock::new(vec_storage.clone()));
+        let storage: Arw<VecStorage<usize, i32>> = Arc::new(Rw::new(vec_storage.clone()));
         let storage: Arw<dyn Storage> = storage;
 
         // Cast
         let slice_storage: Arw<dyn KeyItemStorage<Key = usize, Item = i32>> =
-            cast_to_dyn_getkeyitemstorage::<dyn Storage, usize, i32>(storage).unwrap();
+            cast_to_dyn_getkeyitemstorage::<dyn Storage, usize, i32>(&storage).unwrap();
 
         let guard = slice_storage.try_read().unwrap();
         assert_eq!(guard.get(0).unwrap(), &1);
@@ -0,0 +1,322 @@
+//! Item level differences between storages as values that can be stored, sent and inverted.
+//!
+//! [diff] compares two storages of the same Key and Item types through [KeyItemStorage] and
+//! returns the [ChangeSet] that turns the first into the second. [apply] plays a change set onto
+//! any storage, so the same primitive serves network sync and undo:
+//!
+//! ```ignore
+//! let changes = diff(&*before.try_read()?, &*after.try_read()?);
+//! send(&changes);
+//!
+//! // On the receiving side
+//! apply_removable(&changes, &mut *replica.try_write()?)?;
+//!
+//! // Undo
+//! apply_removable(&changes.inverse(), &mut *replica.try_write()?)?;
+//! ```
+//
+// # Internal Design
+//
+// - Every change records the item before and after so that a change set can be inverted without the
+//   storage it was taken from, and so that [apply] can tell when the target has diverged from the
+//   storage the change set was made against.
+// - [apply] checks every change before writing anything so a conflicting change set leaves the
+//   target untouched.
+// - Removals need a [RemovableStorage]. As there is no way to tell from a [MutKeyItemStorage] trait
+//   object whether the storage underneath can remove, [apply] rejects change sets with removals and
+//   [apply_removable] is used for storages that support them.
+
+use crate::{
+    storage_traits::{
+        ItemTrait, KeyItemStorage, KeyTrait, MutKeyItemStorage, StableRemovableStorage,
+    },
+    SimpleResult,
+};
+
+/// The item at a key before and after a change, where None is no item
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItemChange<Key, Item>
+{
+    pub key: Key,
+    pub before: Option<Item>,
+    pub after: Option<Item>,
+}
+
+impl<Key, Item> ItemChange<Key, Item>
+{
+    pub fn is_removal(&self) -> bool
+    {
+        self.after.is_none()
+    }
+}
+
+/// Changes to the items of a storage, see the [module docs](self)
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangeSet<Key, Item>
+{
+    changes: Vec<ItemChange<Key, Item>>,
+}
+
+impl<Key, Item> Default for ChangeSet<Key, Item>
+{
+    fn default() -> Self
+    {
+        Self {
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl<Key, Item> ChangeSet<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait + PartialEq,
+{
+    pub fn new() -> Self
+    {
+        <_>::default()
+    }
+
+    /// Record a change. Changes are applied in the order they were pushed.
+    pub fn push(&mut self, key: Key, before: Option<Item>, after: Option<Item>)
+    {
+        self.changes.push(ItemChange { key, before, after });
+    }
+
+    pub fn changes(&self) -> &[ItemChange<Key, Item>]
+    {
+        &self.changes
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.changes.is_empty()
+    }
+
+    pub fn has_removals(&self) -> bool
+    {
+        self.changes.iter().any(ItemChange::is_removal)
+    }
+
+    /// The change set that undoes this one
+    pub fn inverse(&self) -> Self
+    {
+        Self {
+            changes: self
+                .changes
+                .iter()
+                .rev()
+                .map(|change| ItemChange {
+                    key: change.key,
+                    before: change.after.clone(),
+                    after: change.before.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The changes that turn `from` into `to`: items of `to` that are new or differ from `from` in the
+/// order of `to`, followed by removals of keys that are only in `from`
+pub fn diff<Key, Item>(
+    from: &dyn KeyItemStorage<Key = Key, Item = Item>,
+    to: &dyn KeyItemStorage<Key = Key, Item = Item>,
+) -> ChangeSet<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait + PartialEq,
+{
+    let mut change_set = ChangeSet::new();
+
+    for (key, item) in to.key_item_iter()
+    {
+        let before = from.get(key);
+
+        if before != Some(item)
+        {
+            change_set.push(key, before.cloned(), Some(item.clone()));
+        }
+    }
+
+    for (key, item) in from.key_item_iter()
+    {
+        if !to.contains(key)
+        {
+            change_set.push(key, Some(item.clone()), None);
+        }
+    }
+
+    change_set
+}
+
+/// Apply a change set without removals. Fails without changing `target` if the change set has
+/// removals or if an item in `target` doesn't match the item a change expects to replace.
+pub fn apply<Key, Item>(
+    change_set: &ChangeSet<Key, Item>,
+    target: &mut dyn MutKeyItemStorage<Key = Key, Item = Item>,
+) -> SimpleResult<()>
+where
+    Key: KeyTrait,
+    Item: ItemTrait + PartialEq,
+{
+    if let Some(change) = change_set.changes.iter().find(|change| change.is_removal())
+    {
+        return Err(format!(
+            "Cannot remove key {:?} from a storage that is not removable, see apply_removable",
+            change.key
+        )
+        .into());
+    }
+
+    check_conflicts(change_set, &*target)?;
+
+    for change in &change_set.changes
+    {
+        if let Some(item) = &change.after
+        {
+            set(target, change.key, item.clone());
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply a change set that may have removals. Fails without changing `target` if an item in
+/// `target` doesn't match the item a change expects to replace. The target must leave other keys
+/// unchanged on a remove, as the changes are made per key.
+pub fn apply_removable<Key, Item>(
+    change_set: &ChangeSet<Key, Item>,
+    target: &mut dyn StableRemovableStorage<Key = Key, Item = Item>,
+) -> SimpleResult<()>
+where
+    Key: KeyTrait,
+    Item: ItemTrait + PartialEq,
+{
+    check_conflicts(change_set, &*target)?;
+
+    for change in &change_set.changes
+    {
+        match &change.after
+        {
+            Some(item) => set(target, change.key, item.clone()),
+            None =>
+            {
+                target.remove(change.key);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check each change against the item that the changes before it leave at its key
+fn check_conflicts<Key, Item>(
+    change_set: &ChangeSet<Key, Item>,
+    target: &dyn KeyItemStorage<Key = Key, Item = Item>,
+) -> SimpleResult<()>
+where
+    Key: KeyTrait,
+    Item: ItemTrait + PartialEq,
+{
+    let mut pending: std::collections::HashMap<Key, Option<&Item>> = <_>::default();
+
+    for change in &change_set.changes
+    {
+        let current = match pending.get(&change.key)
+        {
+            Some(item) => *item,
+            None => target.get(change.key),
+        };
+
+        if current != change.before.as_ref()
+        {
+            return Err(format!(
+                "Change set conflicts with the target storage at key {:?}",
+                change.key
+            )
+            .into());
+        }
+
+        pending.insert(change.key, change.after.as_ref());
+    }
+
+    Ok(())
+}
+
+fn set<Key, Item>(
+    target: &mut (impl MutKeyItemStorage<Key = Key, Item = Item> + ?Sized),
+    key: Key,
+    item: Item,
+) where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    // Existing items are replaced in place as insert shifts items in vec like storages
+    match target.get_mut(key)
+    {
+        Some(existing) => *existing = item,
+        None => target.insert(key, item),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{apply, apply_removable, diff};
+    use crate::{
+        storage_traits::{KeyItemStorage, MutKeyItemStorage, RemovableStorage, Storage},
+        storage_types::{HashMapStorage, VecStorage},
+    };
+
+    #[test]
+    fn diff_apply_test()
+    {
+        let mut from = HashMapStorage::<u32, i32>::new();
+        from.insert(1, 10);
+        from.insert(2, 20);
+
+        let mut to = from.clone();
+        *to.get_mut(1).unwrap() = 11;
+        to.insert(3, 30);
+        to.remove(2);
+
+        let changes = diff(&from, &to);
+        assert_eq!(changes.len(), 3);
+        assert!(changes.has_removals());
+
+        let mut replica = from.clone();
+        apply_removable(&changes, &mut replica).unwrap();
+        assert_eq!(diff(&replica, &to).len(), 0);
+
+        // Applying twice conflicts
+        assert!(apply_removable(&changes, &mut replica).is_err());
+
+        apply_removable(&changes.inverse(), &mut replica).unwrap();
+        assert_eq!(diff(&replica, &from).len(), 0);
+    }
+
+    #[test]
+    fn apply_vec_test()
+    {
+        let from: VecStorage<usize, i32> = VecStorage::from_vec(vec![1, 2]);
+        let to: VecStorage<usize, i32> = VecStorage::from_vec(vec![1, 5, 3]);
+
+        let mut replica = from.clone();
+        apply(&diff(&from, &to), &mut replica).unwrap();
+        assert_eq!(
+            replica.item_iter().copied().collect::<Vec<_>>(),
+            vec![1, 5, 3]
+        );
+
+        // Shrinking a vec needs removals
+        assert!(apply(&diff(&to, &from), &mut replica).is_err());
+        assert_eq!(replica.len(), 3);
+    }
+}
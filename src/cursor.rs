@@ -0,0 +1,306 @@
+//! Cursors that walk the items of an ordered storage in ascending key order and seek to keys.
+//!
+//! A [StorageCursor] keeps its position between steps, so algorithms that walk two storages side by
+//! side, such as a merge join, step or seek each cursor instead of looking every key up again. They
+//! are made by [OrderedStorage::cursor], which is also callable on trait objects:
+//!
+//! ```ignore
+//! type Ordered<'a> = &'a dyn OrderedStorage<Key = u32, Item = f32>;
+//!
+//! fn join(left: Ordered, right: Ordered)
+//! {
+//!     let (mut left, mut right) = (left.cursor(), right.cursor());
+//!     let (mut a, mut b) = (left.next(), right.next());
+//!
+//!     while let (Some((left_key, left_item)), Some((right_key, right_item))) = (a, b)
+//!     {
+//!         match left_key.cmp(&right_key)
+//!         {
+//!             Ordering::Less => a = left.seek(right_key),
+//!             Ordering::Greater => b = right.seek(left_key),
+//!             Ordering::Equal =>
+//!             {
+//!                 println!("{left_key}: {left_item} {right_item}");
+//!                 (a, b) = (left.next(), right.next());
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! [OrderedStorage]: crate::storage_traits::OrderedStorage
+//! [OrderedStorage::cursor]: crate::storage_traits::OrderedStorage::cursor
+//
+// # Internal Design
+//
+// - One cursor type serves every storage so that [OrderedStorage] stays object safe without boxing
+//   the cursor. Storages differ only in where keys and items come from, see [CursorKeys] and
+//   [CursorItems].
+// - Storages whose stored keys aren't in ascending order, such as an unsorted sparse set, give the
+//   cursor the sorted order of their positions. It is computed once per cursor and only when the
+//   keys are out of order.
+// - Positions whose item can't be found, such as view keys that are missing from the view's input,
+//   are stepped over.
+//
+// [OrderedStorage]: crate::storage_traits::OrderedStorage
+
+use std::cmp::Ordering;
+
+use crate::{
+    storage_traits::{ItemTrait, KeyItemStorage, KeyTrait},
+    storage_types::{try_index_to_key, try_key_to_index},
+};
+
+/// Where a cursor gets the key at each position
+pub enum CursorKeys<'a, Key>
+{
+    /// The positions are the keys, as in [crate::storage_types::VecStorage]
+    Indices,
+
+    /// Stored keys with their order
+    Stored
+    {
+        keys: &'a [Key],
+        compare: fn(&Key, &Key) -> Ordering,
+    },
+}
+
+/// Where a cursor gets the item of each key
+pub enum CursorItems<'a, Key, Item>
+{
+    /// The item at each position
+    Slice(&'a [Item]),
+
+    /// Looked up by key, as for views
+    Lookup(&'a dyn KeyItemStorage<Key = Key, Item = Item>),
+
+    /// No items, such as for a view without view data
+    Empty,
+}
+
+/// A position in the items of an ordered storage, see the [module docs](self).
+///
+/// A new cursor is before the first item. [Self::next] and [Self::prev] step to the adjacent item
+/// and return it, or None once they step past either end, where the cursor then stays until it is
+/// stepped back or seeks.
+pub struct StorageCursor<'a, Key, Item>
+{
+    keys: CursorKeys<'a, Key>,
+    items: CursorItems<'a, Key, Item>,
+
+    /// Positions in ascending key order when the stored keys aren't
+    order: Option<Vec<usize>>,
+    len: usize,
+
+    /// 0 is before the first position, `len + 1` after the last and `i + 1` at position i
+    position: usize,
+}
+
+impl<'a, Key, Item> StorageCursor<'a, Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// A cursor over `len` positions, which are sorted by key first if needed
+    pub fn new(keys: CursorKeys<'a, Key>, items: CursorItems<'a, Key, Item>, len: usize) -> Self
+    {
+        let order = match keys
+        {
+            CursorKeys::Stored { keys, compare }
+                if !keys.is_sorted_by(|a, b| compare(a, b) != Ordering::Greater) =>
+            {
+                let mut order: Vec<usize> = (0..len).collect();
+                order.sort_by(|&a, &b| compare(&keys[a], &keys[b]));
+                Some(order)
+            }
+            _ => None,
+        };
+
+        Self {
+            keys,
+            items,
+            order,
+            len,
+            position: 0,
+        }
+    }
+
+    /// The key and item the cursor is at. None before the first or after the last item.
+    pub fn current(&self) -> Option<(Key, &'a Item)>
+    {
+        let position = self.position.checked_sub(1).filter(|&p| p < self.len)?;
+
+        self.entry(position)
+    }
+
+    /// Step to the next item and return it
+    // Not an Iterator as the cursor also steps back and can return to an end it has passed
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(Key, &'a Item)>
+    {
+        while self.position <= self.len
+        {
+            self.position += 1;
+
+            if let Some(entry) = self.current()
+            {
+                return Some(entry);
+            }
+        }
+
+        None
+    }
+
+    /// Step to the previous item and return it
+    pub fn prev(&mut self) -> Option<(Key, &'a Item)>
+    {
+        while self.position > 0
+        {
+            self.position -= 1;
+
+            if let Some(entry) = self.current()
+            {
+                return Some(entry);
+            }
+        }
+
+        None
+    }
+
+    /// Move to the first item whose key is not less than `key` and return it. None if there is no
+    /// such item, leaving the cursor after the last item.
+    pub fn seek(&mut self, key: Key) -> Option<(Key, &'a Item)>
+    {
+        let position = match &self.keys
+        {
+            CursorKeys::Indices =>
+            {
+                try_key_to_index(key).map_or(self.len, |index| index.min(self.len))
+            }
+            CursorKeys::Stored { keys, compare } => match &self.order
+            {
+                Some(order) =>
+                {
+                    order.partition_point(|&p| compare(&keys[p], &key) == Ordering::Less)
+                }
+                None => keys[..self.len].partition_point(|k| compare(k, &key) == Ordering::Less),
+            },
+        };
+
+        // Step onto the position, or past it if its item is missing
+        self.position = position;
+        self.next()
+    }
+
+    /// Move before the first item
+    pub fn reset(&mut self)
+    {
+        self.position = 0;
+    }
+
+    fn entry(&self, position: usize) -> Option<(Key, &'a Item)>
+    {
+        let index = self
+            .order
+            .as_ref()
+            .map_or(position, |order| order[position]);
+
+        let key = match &self.keys
+        {
+            CursorKeys::Indices => try_index_to_key(index).ok()?,
+            CursorKeys::Stored { keys, .. } => keys[index],
+        };
+
+        let item = match &self.items
+        {
+            CursorItems::Slice(items) => items.get(index)?,
+            CursorItems::Lookup(storage) => storage.get(key)?,
+            CursorItems::Empty => return None,
+        };
+
+        Some((key, item))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use crate::{
+        storage_traits::{MutKeyItemStorage, OrderedStorage, Storage, ViewStorageSetup},
+        storage_types::{KeyItemViewStorage, SparseSetVecStorage, VecStorage},
+        Arw, Rw,
+    };
+
+    #[test]
+    fn step_and_seek_test()
+    {
+        let storage = VecStorage::<usize, char>::from_vec(vec!['a', 'b', 'c']);
+        let ordered: &dyn OrderedStorage<Key = usize, Item = char> = &storage;
+
+        let mut cursor = ordered.cursor();
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.next(), Some((0, &'a')));
+        assert_eq!(cursor.seek(2), Some((2, &'c')));
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.prev(), Some((2, &'c')));
+        assert_eq!(cursor.seek(10), None);
+        assert_eq!(cursor.prev(), Some((2, &'c')));
+
+        cursor.reset();
+        assert_eq!(cursor.prev(), None);
+        assert_eq!(cursor.next(), Some((0, &'a')));
+    }
+
+    #[test]
+    fn merge_join_test()
+    {
+        // Inserted out of order so that the cursor sorts the dense keys
+        let mut left = SparseSetVecStorage::<u32, i32>::new();
+        for key in [9, 2, 5, 7]
+        {
+            left.insert(key, key as i32 * 10);
+        }
+        let right = VecStorage::<u32, i32>::from_vec(vec![0, 1, 2, 3, 4, 5, 6]);
+
+        let (mut left, mut right) = (left.cursor(), right.cursor());
+        let (mut a, mut b) = (left.next(), right.next());
+        let mut joined = Vec::new();
+
+        while let (Some((left_key, left_item)), Some((right_key, right_item))) = (a, b)
+        {
+            match left_key.cmp(&right_key)
+            {
+                std::cmp::Ordering::Less => a = left.seek(right_key),
+                std::cmp::Ordering::Greater => b = right.seek(left_key),
+                std::cmp::Ordering::Equal =>
+                {
+                    joined.push((left_key, left_item + right_item));
+                    (a, b) = (left.next(), right.next());
+                }
+            }
+        }
+
+        assert_eq!(joined, [(2, 22), (5, 55)]);
+    }
+
+    #[test]
+    fn view_cursor_test()
+    {
+        let input: Arw<VecStorage<usize, i32>> =
+            Arc::new(Rw::new(VecStorage::from_vec(vec![10, 11, 12, 13])));
+
+        let input: Arw<dyn Storage> = input;
+
+        let mut view: KeyItemViewStorage<VecStorage<usize, i32>, usize, i32> =
+            KeyItemViewStorage::new();
+        view.set_input_storage(&input).unwrap();
+        view.create_read_view([3, 1].into()).unwrap();
+
+        let mut cursor = view.cursor();
+        assert_eq!(cursor.next(), Some((1, &11)));
+        assert_eq!(cursor.seek(2), Some((3, &13)));
+    }
+}
@@ -0,0 +1,553 @@
+//! Opt-in deadlock detection diagnostics.
+//!
+//! When enabled, every guard handed out by a [crate::storage_handle::StorageHandle] and every
+//! input lock taken out by a view records which thread (or view) holds which storage. When a
+//! thread keeps failing to acquire a lock, or a blocking read or write has waited too long, the
+//! detector walks the wait-for graph and reports the probable cycle through the configured report
+//! hook. By default reports are logged as warnings with the `tracing` feature and dropped without
+//! it.
+//!
+//! Storages are identified by [StorageId] and reported using their handle label when one was
+//! given via [crate::storage_handle::StorageHandleBuilder::label].
+//
+// # Internal Design
+//
+// - The lock table is global rather than per handle as a cycle always spans several storages.
+// - Since the handle API is try based the detector can't know that a thread is truly blocked, only
+//   that it keeps failing to acquire. This is why reports are probable cycles. Blocking reads and
+//   writes retry try_* too, so they are seen the same way and caught by how long they have waited.
+// - Nothing tells the detector that a thread has given up on a lock, so a thread stops counting as
+//   waiting once it hasn't failed for [DetectorConfig::wait_expiry]. Otherwise the stale wait would
+//   link into cycles that don't exist.
+// - A view holds a lock on its input storage until the view is cleared and clearing requires a
+//   write lock on the view storage itself. So in the wait-for graph a view "waits" on whoever holds
+//   its own storage. This is what links view guards and plain handle guards into one graph.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::{LazyLock, Mutex, MutexGuard},
+    thread::ThreadId,
+    time::{Duration, Instant},
+};
+
+pub use crate::storage_handle::LockAccess;
+use crate::storage_handle::StorageId;
+
+/// The owner of a storage lock
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockHolder
+{
+    Thread(ThreadId),
+
+    /// A view storage (identified by its own storage id) holding a lock on its input storage
+    View(StorageId),
+}
+
+#[derive(Clone, Debug)]
+pub struct HeldLock
+{
+    pub storage: StorageId,
+    pub access: LockAccess,
+    pub holder: LockHolder,
+}
+
+/// One step of a probable deadlock cycle: `waiter` is waiting on `storage` which is held by
+/// `holder`
+#[derive(Clone, Debug)]
+pub struct WaitEdge
+{
+    pub waiter: LockHolder,
+    pub storage: StorageId,
+    pub storage_label: Option<String>,
+    pub holder: LockHolder,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeadlockReport
+{
+    pub cycle: Vec<WaitEdge>,
+}
+
+impl Display for DeadlockReport
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        writeln!(f, "Probable storage deadlock:")?;
+
+        for edge in &self.cycle
+        {
+            let storage = match &edge.storage_label
+            {
+                Some(label) => format!("'{label}' ({})", edge.storage),
+                None => format!("{}", edge.storage),
+            };
+
+            writeln!(
+                f,
+                "  {:?} waits for {storage} held by {:?}",
+                edge.waiter, edge.holder
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct DetectorConfig
+{
+    /// Consecutive failed try_read / try_write attempts on the same storage from one thread after
+    /// which the wait-for graph is checked
+    pub failed_attempt_threshold: u32,
+
+    /// Time a thread has been failing to acquire the same storage after which the wait-for graph
+    /// is checked, whatever the number of attempts. This is what catches a blocking read or write
+    /// that waits too long.
+    pub wait_threshold: Duration,
+
+    /// Time since its last failed attempt after which a thread is taken to have given up on the
+    /// storage and no longer counts as waiting
+    pub wait_expiry: Duration,
+
+    /// Called once per wait episode when a cycle is found
+    pub report_hook: fn(&DeadlockReport),
+}
+
+impl Default for DetectorConfig
+{
+    fn default() -> Self
+    {
+        Self {
+            failed_attempt_threshold: 1000,
+            wait_threshold: Duration::from_millis(100),
+            wait_expiry: Duration::from_secs(1),
+            report_hook: default_report_hook,
+        }
+    }
+}
+
+/// Logs the report as a warning with the `tracing` feature, and drops it otherwise as library code
+/// shouldn't write to stderr
+fn default_report_hook(_report: &DeadlockReport)
+{
+    #[cfg(feature = "tracing")]
+    tracing::warn!("{_report}");
+}
+
+/// Replace the detector configuration
+pub fn configure(config: DetectorConfig)
+{
+    lock_table().config = config;
+}
+
+/// Snapshot of all currently recorded locks
+pub fn held_locks() -> Vec<HeldLock>
+{
+    lock_table().held.values().cloned().collect()
+}
+
+/// Check whether the given thread is currently part of a probable deadlock cycle
+pub fn find_probable_deadlock(thread: ThreadId) -> Option<DeadlockReport>
+{
+    lock_table().find_cycle(LockHolder::Thread(thread))
+}
+
+////////////////////////////////////////////////
+// Lock Record
+////////////////////////////////////////////////
+
+/// RAII record of a held lock. Removed from the lock table on drop.
+pub(crate) struct LockRecord
+{
+    id: u64,
+}
+
+impl Drop for LockRecord
+{
+    fn drop(&mut self)
+    {
+        lock_table().held.remove(&self.id);
+    }
+}
+
+////////////////////////////////////////////////
+// Hooks
+////////////////////////////////////////////////
+
+pub(crate) fn on_acquired(storage: StorageId, label: Option<&str>, access: LockAccess)
+    -> LockRecord
+{
+    let thread = std::thread::current().id();
+    let mut table = lock_table();
+
+    table.register_label(storage, label);
+    table.waiting.remove(&thread);
+
+    let id = table.next_record_id;
+    table.next_record_id += 1;
+
+    table.held.insert(
+        id,
+        HeldLock {
+            storage,
+            access,
+            holder: LockHolder::Thread(thread),
+        },
+    );
+
+    LockRecord { id }
+}
+
+pub(crate) fn on_try_failed(storage: StorageId, label: Option<&str>, access: LockAccess)
+{
+    let thread = std::thread::current().id();
+    let now = Instant::now();
+    let mut table = lock_table();
+
+    table.register_label(storage, label);
+
+    let failed_attempt_threshold = table.config.failed_attempt_threshold;
+    let wait_threshold = table.config.wait_threshold;
+    let wait_expiry = table.config.wait_expiry;
+
+    // Also drops the waits of threads that gave up, or have exited, so they don't pile up
+    table
+        .waiting
+        .retain(|_, wait| !wait.is_expired(now, wait_expiry));
+
+    let wait = table
+        .waiting
+        .entry(thread)
+        .or_insert_with(|| WaitState::new(storage, access, now));
+
+    // Waiting on a different storage starts a new wait episode
+    if wait.storage != storage || wait.access != access
+    {
+        *wait = WaitState::new(storage, access, now);
+    }
+
+    wait.failed_attempts += 1;
+    wait.last_failed = now;
+
+    let waited_too_long = now.duration_since(wait.since) >= wait_threshold;
+
+    if wait.reported || (wait.failed_attempts < failed_attempt_threshold && !waited_too_long)
+    {
+        return;
+    }
+
+    if let Some(report) = table.find_cycle(LockHolder::Thread(thread))
+    {
+        if let Some(wait) = table.waiting.get_mut(&thread)
+        {
+            wait.reported = true;
+        }
+
+        let report_hook = table.config.report_hook;
+
+        // Release the table before calling out so that the hook can query the detector
+        drop(table);
+
+        report_hook(&report);
+    }
+}
+
+pub(crate) fn on_view_created(view: StorageId, input: StorageId, access: LockAccess)
+{
+    let mut table = lock_table();
+
+    let id = table.next_record_id;
+    table.next_record_id += 1;
+
+    table.held.insert(
+        id,
+        HeldLock {
+            storage: input,
+            access,
+            holder: LockHolder::View(view),
+        },
+    );
+}
+
+pub(crate) fn on_view_cleared(view: StorageId)
+{
+    lock_table()
+        .held
+        .retain(|_, held| held.holder != LockHolder::View(view));
+}
+
+pub(crate) fn register_label(storage: StorageId, label: Option<&str>)
+{
+    lock_table().register_label(storage, label);
+}
+
+////////////////////////////////////////////////
+// Lock Table
+////////////////////////////////////////////////
+
+struct WaitState
+{
+    storage: StorageId,
+    access: LockAccess,
+    failed_attempts: u32,
+    reported: bool,
+
+    /// First failed attempt of the wait episode
+    since: Instant,
+    last_failed: Instant,
+}
+
+impl WaitState
+{
+    fn new(storage: StorageId, access: LockAccess, now: Instant) -> Self
+    {
+        Self {
+            storage,
+            access,
+            failed_attempts: 0,
+            reported: false,
+            since: now,
+            last_failed: now,
+        }
+    }
+
+    fn is_expired(&self, now: Instant, wait_expiry: Duration) -> bool
+    {
+        now.duration_since(self.last_failed) >= wait_expiry
+    }
+}
+
+#[derive(Default)]
+struct LockTable
+{
+    config: DetectorConfig,
+    next_record_id: u64,
+    held: HashMap<u64, HeldLock>,
+    waiting: HashMap<ThreadId, WaitState>,
+    labels: HashMap<StorageId, String>,
+}
+
+static LOCK_TABLE: LazyLock<Mutex<LockTable>> = LazyLock::new(<_>::default);
+
+fn lock_table() -> MutexGuard<'static, LockTable>
+{
+    // Diagnostics should never take the host down so a poisoned table is still used
+    LOCK_TABLE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl LockTable
+{
+    fn register_label(&mut self, storage: StorageId, label: Option<&str>)
+    {
+        if let Some(label) = label
+        {
+            self.labels
+                .entry(storage)
+                .or_insert_with(|| label.to_string());
+        }
+    }
+
+    fn waits_on(&self, node: LockHolder) -> Option<StorageId>
+    {
+        match node
+        {
+            LockHolder::Thread(thread) => self
+                .waiting
+                .get(&thread)
+                .filter(|wait| !wait.is_expired(Instant::now(), self.config.wait_expiry))
+                .map(|wait| wait.storage),
+            LockHolder::View(view) => Some(view),
+        }
+    }
+
+    fn holders_of(&self, storage: StorageId) -> Vec<LockHolder>
+    {
+        // A thread can hold several read guards of the same storage. Thread ids aren't ordered so
+        // duplicates are dropped as they are seen rather than sorted out.
+        let mut seen: HashSet<LockHolder> = HashSet::new();
+
+        self.held
+            .values()
+            .filter(|held| held.storage == storage)
+            .map(|held| held.holder)
+            .filter(|holder| seen.insert(*holder))
+            .collect()
+    }
+
+    fn find_cycle(&self, start: LockHolder) -> Option<DeadlockReport>
+    {
+        let mut path: Vec<WaitEdge> = Vec::new();
+        let mut visited: HashSet<LockHolder> = HashSet::new();
+
+        if self.visit(start, start, &mut path, &mut visited)
+        {
+            Some(DeadlockReport { cycle: path })
+        }
+        else
+        {
+            None
+        }
+    }
+
+    fn visit(
+        &self,
+        start: LockHolder,
+        node: LockHolder,
+        path: &mut Vec<WaitEdge>,
+        visited: &mut HashSet<LockHolder>,
+    ) -> bool
+    {
+        let Some(storage) = self.waits_on(node)
+        else
+        {
+            return false;
+        };
+
+        for holder in self.holders_of(storage)
+        {
+            path.push(WaitEdge {
+                waiter: node,
+                storage,
+                storage_label: self.labels.get(&storage).cloned(),
+                holder,
+            });
+
+            if holder == start
+            {
+                return true;
+            }
+
+            if visited.insert(holder) && self.visit(start, holder, path, visited)
+            {
+                return true;
+            }
+
+            path.pop();
+        }
+
+        false
+    }
+}
+
+// Needs handles that can be sent between threads
+#[cfg(all(test, not(feature = "local")))]
+mod tests
+{
+    use std::{
+        sync::{Arc, Barrier},
+        time::Instant,
+    };
+
+    use super::{
+        configure, find_probable_deadlock, DetectorConfig, HeldLock, LockAccess, LockHolder,
+        LockTable, WaitState,
+    };
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::VecStorage,
+    };
+
+    fn labelled_handle(label: &str) -> StorageHandle<dyn Storage>
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let mut builder = builder(storage);
+        builder.label(label);
+        builder.build()
+    }
+
+    /// Two threads each hold one storage and spin on the other
+    #[test]
+    fn detects_two_thread_cycle_test()
+    {
+        configure(DetectorConfig {
+            failed_attempt_threshold: 10,
+            ..Default::default()
+        });
+
+        let handle_a = labelled_handle("a");
+        let handle_b = labelled_handle("b");
+
+        let barrier = Arc::new(Barrier::new(2));
+
+        let spawn = |first: StorageHandle<dyn Storage>, second: StorageHandle<dyn Storage>| {
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                let _guard = first.try_write().unwrap();
+                barrier.wait();
+
+                for _ in 0..10
+                {
+                    assert!(second.try_write().is_err());
+                }
+
+                // Both threads need to be waiting before the cycle exists
+                barrier.wait();
+
+                let report = find_probable_deadlock(std::thread::current().id());
+
+                // Keep holding the lock until the other thread has also checked
+                barrier.wait();
+
+                report
+            })
+        };
+
+        let thread_1 = spawn(handle_a.clone(), handle_b.clone());
+        let thread_2 = spawn(handle_b, handle_a);
+
+        let report_1 = thread_1
+            .join()
+            .unwrap()
+            .expect("Cycle should have been detected");
+        let report_2 = thread_2
+            .join()
+            .unwrap()
+            .expect("Cycle should have been detected");
+
+        assert_eq!(report_1.cycle.len(), 2);
+        assert_eq!(report_2.cycle.len(), 2);
+        assert!(format!("{report_1}").contains("'b'"));
+    }
+
+    /// A thread that gave up on a lock doesn't link into a cycle
+    #[test]
+    fn abandoned_wait_test()
+    {
+        let (handle_a, handle_b) = (labelled_handle("a"), labelled_handle("b"));
+        let (a, b) = (handle_a.storage_id(), handle_b.storage_id());
+
+        let this = std::thread::current().id();
+        let other = std::thread::spawn(|| std::thread::current().id())
+            .join()
+            .unwrap();
+
+        // Each thread holds one storage and has been failing to acquire the other
+        let mut table = LockTable::default();
+        let now = Instant::now();
+
+        for (id, (held, waited_on, thread)) in [(a, b, this), (b, a, other)].into_iter().enumerate()
+        {
+            table.held.insert(
+                id as u64,
+                HeldLock {
+                    storage: held,
+                    access: LockAccess::Write,
+                    holder: LockHolder::Thread(thread),
+                },
+            );
+            table
+                .waiting
+                .insert(thread, WaitState::new(waited_on, LockAccess::Write, now));
+        }
+
+        let start = LockHolder::Thread(this);
+        assert_eq!(table.find_cycle(start).unwrap().cycle.len(), 2);
+
+        let gave_up = now.checked_sub(table.config.wait_expiry * 2).unwrap();
+        table.waiting.get_mut(&other).unwrap().last_failed = gave_up;
+        assert!(table.find_cycle(start).is_none());
+    }
+}
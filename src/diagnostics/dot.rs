@@ -0,0 +1,315 @@
+//! Graphviz DOT export of how storages and views are wired together.
+//!
+//! Handles are added one by one, eg from whatever registry a node graph keeps them in. View
+//! handles are added with their key and item types so that the edge from their input storage can
+//! be found through the view controller:
+//!
+//! ```ignore
+//! let mut graph = DotGraph::new();
+//! graph.storage(&positions).storage(&velocities);
+//! graph.view::<usize, f32, _>(&moving_positions)?;
+//!
+//! std::fs::write("storages.dot", graph.to_dot())?;
+//! ```
+//
+// # Internal Design
+//
+// - Lock states are probed with try locks on the base storage at the time a handle is added. This
+//   never blocks, including when the calling thread holds a guard itself, but it is only a snapshot
+//   and is not recorded through the handle so it doesn't show up in metrics or deadlock
+//   diagnostics.
+// - The concrete type name is read from the storage while probing, so it is unknown for storages
+//   that are write locked at the time.
+
+use std::{
+    fmt::{Display, Write},
+    sync::TryLockError,
+};
+
+use crate::{
+    storage_handle::{InputStorageLockStatus, LockAccess, StorageHandle, StorageId},
+    storage_traits::{ItemTrait, KeyTrait, Storage},
+    Arw, SimpleResult,
+};
+
+struct DotNode
+{
+    id: StorageId,
+    label: Option<String>,
+    type_name: Option<&'static str>,
+    lock: Option<LockAccess>,
+    is_view: bool,
+}
+
+struct DotEdge
+{
+    input: StorageId,
+    view: StorageId,
+    status: InputStorageLockStatus,
+}
+
+/// A snapshot of storages, views and their lock states that renders as a DOT graph
+#[derive(Default)]
+pub struct DotGraph
+{
+    nodes: Vec<DotNode>,
+    edges: Vec<DotEdge>,
+}
+
+impl DotGraph
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Add the storage of `handle`. Adding a storage that is already in the graph, eg through a
+    /// clone of its handle, has no effect.
+    pub fn storage<S>(&mut self, handle: &StorageHandle<S>) -> &mut Self
+    where
+        S: Storage + ?Sized,
+    {
+        self.add_node(handle, false);
+
+        self
+    }
+
+    /// Add the view storage of `handle` along with an edge from its input storage. The input
+    /// storage is expected to be added separately.
+    pub fn view<Key, Item, S>(&mut self, handle: &StorageHandle<S>) -> SimpleResult<&mut Self>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+        S: Storage + ?Sized,
+    {
+        let Some(view_controller) = handle.view_storage_controller()
+        else
+        {
+            return Err("Handle has no view storage controller".into());
+        };
+
+        let input = view_controller.input_storage_id::<Key, Item>()?;
+        let status = view_controller.status()?;
+
+        self.add_node(handle, true);
+
+        if let Some(input) = input
+        {
+            self.edges.push(DotEdge {
+                input,
+                view: handle.storage_id(),
+                status,
+            });
+        }
+
+        Ok(self)
+    }
+
+    pub fn to_dot(&self) -> String
+    {
+        self.to_string()
+    }
+
+    fn add_node<S>(&mut self, handle: &StorageHandle<S>, is_view: bool)
+    where
+        S: Storage + ?Sized,
+    {
+        let id = handle.storage_id();
+
+        if self.nodes.iter().any(|node| node.id == id)
+        {
+            return;
+        }
+
+        let (lock, type_name) = probe(handle.base_storage());
+
+        self.nodes.push(DotNode {
+            id,
+            label: handle.label().map(str::to_owned),
+            type_name,
+            lock,
+            is_view,
+        });
+    }
+}
+
+impl Display for DotGraph
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        writeln!(f, "digraph storages {{")?;
+        writeln!(f, "    node [shape=box];")?;
+
+        for node in &self.nodes
+        {
+            let mut label = node.label.clone().unwrap_or_else(|| node.id.to_string());
+
+            match node.type_name
+            {
+                Some(type_name) => write!(label, "\n{}", short_type_name(type_name))?,
+                None => label.push_str("\n(type unknown)"),
+            }
+
+            let lock = match node.lock
+            {
+                Some(LockAccess::Read) => "read locked",
+                Some(LockAccess::Write) => "write locked",
+                None => "unlocked",
+            };
+            write!(label, "\n{lock}")?;
+
+            let mut attributes = vec![format!("label=\"{}\"", escape(&label))];
+
+            if node.is_view
+            {
+                attributes.push("style=\"rounded,dashed\"".into());
+            }
+
+            match node.lock
+            {
+                Some(LockAccess::Read) => attributes.push("color=orange".into()),
+                Some(LockAccess::Write) => attributes.push("color=red".into()),
+                None => (),
+            }
+
+            writeln!(f, "    \"{}\" [{}];", node.id, attributes.join(", "))?;
+        }
+
+        for edge in &self.edges
+        {
+            let label = match edge.status
+            {
+                InputStorageLockStatus::None => "input",
+                InputStorageLockStatus::Readable => "read view",
+                InputStorageLockStatus::Writable => "write view",
+            };
+
+            writeln!(
+                f,
+                "    \"{}\" -> \"{}\" [label=\"{label}\"];",
+                edge.input, edge.view
+            )?;
+        }
+
+        writeln!(f, "}}")
+    }
+}
+
+/// The lock currently held on `storage` and its type name if it could be read
+fn probe(storage: &Arw<dyn Storage>) -> (Option<LockAccess>, Option<&'static str>)
+{
+    match storage.try_write()
+    {
+        Ok(guard) => (None, Some(guard.type_name())),
+        Err(TryLockError::Poisoned(poisoned)) => (None, Some(poisoned.get_ref().type_name())),
+        Err(TryLockError::WouldBlock) => match storage.try_read()
+        {
+            Ok(guard) => (Some(LockAccess::Read), Some(guard.type_name())),
+            Err(TryLockError::Poisoned(poisoned)) =>
+            {
+                (Some(LockAccess::Read), Some(poisoned.get_ref().type_name()))
+            }
+            Err(TryLockError::WouldBlock) => (Some(LockAccess::Write), None),
+        },
+    }
+}
+
+/// Strip module paths from a type name, eg `a::b::Vec<a::C>` becomes `Vec<C>`
+fn short_type_name(type_name: &str) -> String
+{
+    let mut short = String::with_capacity(type_name.len());
+
+    // Start of the path segment being written
+    let mut segment_start = 0;
+
+    let mut chars = type_name.chars().peekable();
+    while let Some(c) = chars.next()
+    {
+        if c == ':' && chars.peek() == Some(&':')
+        {
+            chars.next();
+            short.truncate(segment_start);
+        }
+        else if c.is_alphanumeric() || c == '_'
+        {
+            short.push(c);
+        }
+        else
+        {
+            short.push(c);
+            segment_start = short.len();
+        }
+    }
+
+    short
+}
+
+fn escape(label: &str) -> String
+{
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{any::TypeId, sync::Arc};
+
+    use super::{short_type_name, DotGraph};
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::{KeyItemViewStorage, VecStorage},
+        Rw,
+    };
+
+    type PositionsView = KeyItemViewStorage<VecStorage<usize, f32>, usize, f32>;
+
+    #[test]
+    fn dot_graph_test()
+    {
+        let mut positions = builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0]));
+        positions.label("positions");
+        let positions = positions.build();
+
+        let view = Arc::new(Rw::new(PositionsView::new()));
+        let mut view: StorageHandle<dyn Storage> = StorageHandle::new_with_view_controller(
+            view.clone(),
+            view,
+            TypeId::of::<usize>(),
+            TypeId::of::<f32>(),
+        );
+
+        let controller = view.view_storage_controller_mut().unwrap();
+        controller
+            .set_input::<usize, f32>(positions.clone())
+            .unwrap();
+        controller.create_read_view::<usize, f32>(vec![1]).unwrap();
+
+        let mut graph = DotGraph::new();
+        graph.storage(&positions).storage(&positions.clone());
+        graph.view::<usize, f32, _>(&view).unwrap();
+        let dot = graph.to_dot();
+
+        assert_eq!(dot.matches("label=\"positions").count(), 1);
+        assert!(dot.contains("VecStorage<usize, f32>\\nread locked"));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\" [label=\"read view\"]",
+            positions.storage_id(),
+            view.storage_id()
+        )));
+
+        assert!(DotGraph::new().view::<usize, f32, _>(&positions).is_err());
+    }
+
+    #[test]
+    fn short_type_name_test()
+    {
+        assert_eq!(
+            short_type_name("a::b::Storage<a::K, (c::D, u8)>"),
+            "Storage<K, (D, u8)>"
+        );
+    }
+}
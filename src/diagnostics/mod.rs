@@ -0,0 +1,9 @@
+//! Opt-in tooling for debugging how storages are wired together and locked at runtime.
+
+#[cfg(feature = "deadlock_detection")]
+pub mod deadlock;
+
+pub mod dot;
+
+#[cfg(feature = "tracing")]
+pub mod trace;
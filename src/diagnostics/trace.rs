@@ -0,0 +1,151 @@
+//! Opt-in [tracing](https://docs.rs/tracing) instrumentation of handles and views.
+//!
+//! When enabled, handle construction, guard acquisition and release, failed lock attempts and view
+//! state transitions are emitted as structured events under the `ngenate_flex_storage` target.
+//! Every event carries the storage id, the handle label when one was given via
+//! [crate::storage_handle::StorageHandleBuilder::label] and the current thread. Misuse that is
+//! reported back as an error, such as locking a view before it was created, is also emitted as a
+//! warning so it shows up in post-mortem logs. Guard events are at trace level and everything else
+//! at debug level:
+//!
+//! ```ignore
+//! tracing_subscriber::fmt()
+//!     .with_env_filter("ngenate_flex_storage=debug")
+//!     .init();
+//! ```
+//
+// # Internal Design
+//
+// - Storage types are only known for handles built through a builder, see
+//   [crate::storage_handle::StorageHandleBuilder]. Handles made with the plain constructors leave
+//   the field out.
+// - The release event is emitted by a record stored in the guard's hooks, the same way as the other
+//   guard bookkeeping, so it fires after the lock itself has been released.
+
+use std::{sync::Arc, thread, time::Instant};
+
+use crate::storage_handle::{HandleState, InputStorageLockStatus, LockAccess, StorageId};
+
+const TARGET: &str = "ngenate_flex_storage";
+
+////////////////////////////////////////////////
+// Handles and guards
+////////////////////////////////////////////////
+
+pub(crate) fn on_handle_built(storage: StorageId, state: &HandleState)
+{
+    tracing::debug!(
+        target: TARGET,
+        storage = %storage,
+        label = state.label.as_deref(),
+        storage_type = state.type_info.map(|info| info.storage_type),
+        thread = ?thread::current().id(),
+        "Storage handle built"
+    );
+}
+
+/// Emits the release event when dropped
+pub(crate) struct ReleaseRecord
+{
+    storage: StorageId,
+    state: Arc<HandleState>,
+    access: LockAccess,
+    acquired: Instant,
+}
+
+impl Drop for ReleaseRecord
+{
+    fn drop(&mut self)
+    {
+        tracing::trace!(
+            target: TARGET,
+            storage = %self.storage,
+            label = self.state.label.as_deref(),
+            access = ?self.access,
+            held_micros = self.acquired.elapsed().as_micros() as u64,
+            thread = ?thread::current().id(),
+            "Storage guard released"
+        );
+    }
+}
+
+pub(crate) fn on_acquired(
+    storage: StorageId,
+    state: &Arc<HandleState>,
+    access: LockAccess,
+) -> ReleaseRecord
+{
+    tracing::trace!(
+        target: TARGET,
+        storage = %storage,
+        label = state.label.as_deref(),
+        storage_type = state.type_info.map(|info| info.storage_type),
+        access = ?access,
+        thread = ?thread::current().id(),
+        "Storage guard acquired"
+    );
+
+    ReleaseRecord {
+        storage,
+        state: state.clone(),
+        access,
+        acquired: Instant::now(),
+    }
+}
+
+pub(crate) fn on_try_failed(storage: StorageId, state: &HandleState, access: LockAccess)
+{
+    tracing::debug!(
+        target: TARGET,
+        storage = %storage,
+        label = state.label.as_deref(),
+        access = ?access,
+        thread = ?thread::current().id(),
+        "Failed to acquire storage guard"
+    );
+}
+
+////////////////////////////////////////////////
+// Views
+////////////////////////////////////////////////
+
+pub(crate) fn on_view_input_set(view: StorageId, input: StorageId, input_label: Option<&str>)
+{
+    tracing::debug!(
+        target: TARGET,
+        view = %view,
+        input = %input,
+        input_label,
+        thread = ?thread::current().id(),
+        "View input set"
+    );
+}
+
+pub(crate) fn on_view_status_changed(
+    view: StorageId,
+    from: InputStorageLockStatus,
+    to: InputStorageLockStatus,
+)
+{
+    tracing::debug!(
+        target: TARGET,
+        view = %view,
+        from = ?from,
+        to = ?to,
+        thread = ?thread::current().id(),
+        "View status changed"
+    );
+}
+
+/// A view operation that was refused because of the status of the view
+pub(crate) fn on_view_misuse(view: StorageId, status: InputStorageLockStatus, attempted: &str)
+{
+    tracing::warn!(
+        target: TARGET,
+        view = %view,
+        status = ?status,
+        attempted,
+        thread = ?thread::current().id(),
+        "View used in the wrong state"
+    );
+}
@@ -0,0 +1,464 @@
+//! The error returned by fallible operations throughout the crate, see [crate::SimpleResult].
+//!
+//! [StorageError] implements [std::error::Error] and is Send + Sync + 'static so it converts into
+//! host application errors such as `anyhow::Error` or `eyre::Report` with `?`. Errors caused by
+//! another error, such as an IO or decoding failure, keep it as their [Error::source] so the whole
+//! chain is reported. Every error also has an [ErrorKind] for handling failures programmatically,
+//! and failed casts and key conversions have an [ErrorDetail] naming the types involved. Errors
+//! caused by common misuse of the API carry a [Hint] and a suggested fix that tools can show to end
+//! users:
+//!
+//! ```ignore
+//! fn load_session(registry: &StorageTypeRegistry, path: &Path)
+//!     -> anyhow::Result<Vec<StorageHandle<dyn Storage>>>
+//! {
+//!     Ok(registry.load_snapshot(File::open(path)?)?)
+//! }
+//!
+//! match handle.try_write()
+//! {
+//!     Ok(mut guard) => guard.insert(key, item),
+//!     Err(error) if error.kind() == ErrorKind::WouldBlock => retry_next_frame(),
+//!     Err(error) => return Err(error),
+//! }
+//!
+//! if let (Some(hint), Some(fix)) = (error.hint(), error.fix())
+//! {
+//!     editor.show_quick_fix(hint, fix);
+//! }
+//!
+//! if let Some(ErrorDetail::InvalidCast { from, to }) = error.detail()
+//! {
+//!     log::warn!("{from} can't be used as {to}");
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - Messages are still plain strings so the many `Err("...".into())` and `format!` call sites
+//   convert through [From] without change. Those errors are of [ErrorKind::Other], so failures that
+//   callers are expected to handle differently construct their error with a kind.
+// - Messages of errors with a source still include the source's message, as they did when errors
+//   were plain strings, so that printing an error alone stays informative. Reporters that walk the
+//   chain show it twice.
+// - Sources are boxed trait objects rather than a generic parameter so that every fallible function
+//   shares one result type.
+// - The [Hint] identifies the misuse while the fix is free text, so that it can name the types
+//   involved. [StorageError::context] copies both to the outer error so they aren't lost in the
+//   chain.
+// - Details are kept apart from the kind rather than as data of [ErrorKind] variants so that kinds
+//   stay Copy and comparable with `==`, and so that a kind can gain details without breaking
+//   matches on it.
+
+use std::{error::Error, fmt, sync::TryLockError};
+
+type BoxedSource = Box<dyn Error + Send + Sync + 'static>;
+
+/// The category of a [StorageError], for deciding how to handle it without matching on messages
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind
+{
+    /// A lock is held elsewhere. Trying again later may succeed.
+    WouldBlock,
+
+    /// A lock was poisoned by a panic while it was held
+    Poisoned,
+
+    /// A cast, lookup or import expected a different storage, key or item type
+    TypeMismatch,
+
+    /// A view was locked before it was created, or set up again while created
+    ViewNotReady,
+
+    /// A key can't address the storage, eg because it doesn't fit an index
+    KeyInvalid,
+
+    /// A storage type, storage or checkpoint that was asked for isn't known
+    NotRegistered,
+
+    /// Reading or writing external data failed, see the source of the error
+    Io,
+
+    /// Data is malformed, of the wrong shape or of an unsupported version, or can't be encoded
+    InvalidData,
+
+    /// Any other failure
+    Other,
+}
+
+/// A common misuse of the API that an error was caused by, see [StorageError::fix] for how to
+/// resolve it
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Hint
+{
+    /// A view was locked before its view data was created
+    ViewNotCreated,
+
+    /// A view's input or keys were changed while it still holds a guard on its input
+    ViewInUse,
+
+    /// A view was created before its input storage was set
+    ViewInputNotSet,
+
+    /// A storage was cast with Key or Item type parameters that don't match its own
+    CastTypeMismatch,
+
+    /// An index based storage was used with a Key type that can't be converted to an index
+    NonIndexKey,
+}
+
+/// Structured data about a failure, for tools that report or react to the types involved without
+/// parsing messages
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorDetail
+{
+    /// A storage of type `from` can't be cast to `to`, which is a storage type or trait object.
+    /// Errors of [ErrorKind::TypeMismatch].
+    InvalidCast
+    {
+        from: &'static str,
+        to: &'static str,
+    },
+
+    /// A key or index, written in `value`, can't be converted for use with a storage. Errors of
+    /// [ErrorKind::KeyInvalid].
+    KeyConversion
+    {
+        value: String,
+        key_type: &'static str,
+    },
+}
+
+/// An error with a readable message, an [ErrorKind] and, when it was caused by another error, its
+/// source
+#[derive(Debug)]
+pub struct StorageError
+{
+    kind: ErrorKind,
+    message: String,
+    hint: Option<(Hint, String)>,
+    detail: Option<ErrorDetail>,
+    source: Option<BoxedSource>,
+}
+
+impl StorageError
+{
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self
+    {
+        Self {
+            kind,
+            message: message.into(),
+            hint: None,
+            detail: None,
+            source: None,
+        }
+    }
+
+    /// An error caused by `source`, which is kept as the [Error::source] of the new error
+    pub fn with_source(
+        kind: ErrorKind,
+        message: impl Into<String>,
+        source: impl Error + Send + Sync + 'static,
+    ) -> Self
+    {
+        Self {
+            kind,
+            message: message.into(),
+            hint: None,
+            detail: None,
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// Mark the error as caused by the misuse `hint`, with a suggestion for how to fix it
+    pub fn with_hint(mut self, hint: Hint, fix: impl Into<String>) -> Self
+    {
+        self.hint = Some((hint, fix.into()));
+        self
+    }
+
+    /// Attach structured data about the failure
+    pub fn with_detail(mut self, detail: ErrorDetail) -> Self
+    {
+        self.detail = Some(detail);
+        self
+    }
+
+    /// Wrap this error in an error of the same kind, hint and detail with a message describing what
+    /// was being done when it happened
+    pub fn context(self, message: impl Into<String>) -> Self
+    {
+        let hint = self.hint.clone();
+        let detail = self.detail.clone();
+
+        Self {
+            hint,
+            detail,
+            ..Self::with_source(self.kind, message, self)
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind
+    {
+        self.kind
+    }
+
+    /// The message of this error alone, without the messages of its sources
+    pub fn message(&self) -> &str
+    {
+        &self.message
+    }
+
+    /// The misuse that caused this error, if it is a known one
+    pub fn hint(&self) -> Option<Hint>
+    {
+        self.hint.as_ref().map(|(hint, _)| *hint)
+    }
+
+    /// A suggestion for how to fix the misuse that caused this error, for showing to end users
+    pub fn fix(&self) -> Option<&str>
+    {
+        self.hint.as_ref().map(|(_, fix)| fix.as_str())
+    }
+
+    /// Structured data about the failure, if the error has any
+    pub fn detail(&self) -> Option<&ErrorDetail>
+    {
+        self.detail.as_ref()
+    }
+}
+
+/// An error of kind [ErrorKind::WouldBlock] or [ErrorKind::Poisoned] for a failed try lock
+pub(crate) fn lock_error<Guard>(error: &TryLockError<Guard>, message: &str) -> StorageError
+{
+    match error
+    {
+        TryLockError::WouldBlock => StorageError::new(ErrorKind::WouldBlock, message),
+        TryLockError::Poisoned(_) => StorageError::new(
+            ErrorKind::Poisoned,
+            format!("{message} as the lock is poisoned"),
+        ),
+    }
+}
+
+/// For infallible methods that wrap a try version. Panics with the error, or with the `no_panic`
+/// feature skips the failed operation.
+pub(crate) fn panic_or_skip(result: Result<(), StorageError>)
+{
+    #[cfg(not(feature = "no_panic"))]
+    if let Err(error) = result
+    {
+        panic!("{error}");
+    }
+
+    #[cfg(feature = "no_panic")]
+    let _ = result;
+}
+
+impl fmt::Display for StorageError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for StorageError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn Error + 'static))
+    }
+}
+
+impl From<String> for StorageError
+{
+    fn from(message: String) -> Self
+    {
+        Self::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<&str> for StorageError
+{
+    fn from(message: &str) -> Self
+    {
+        Self::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<StorageError> for String
+{
+    fn from(error: StorageError) -> Self
+    {
+        error.to_string()
+    }
+}
+
+impl PartialEq<str> for StorageError
+{
+    fn eq(&self, other: &str) -> bool
+    {
+        self.message == other
+    }
+}
+
+impl PartialEq<&str> for StorageError
+{
+    fn eq(&self, other: &&str) -> bool
+    {
+        self.message == *other
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::error::Error;
+
+    use super::{ErrorKind, StorageError};
+    use crate::SimpleResult;
+
+    fn assert_host_compatible<E: Error + Send + Sync + 'static>() {}
+
+    #[test]
+    fn source_chain_test()
+    {
+        assert_host_compatible::<StorageError>();
+
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "missing.bin");
+        let error = StorageError::with_source(ErrorKind::Io, "Failed to read snapshot", io_error)
+            .context("Failed to restore session");
+
+        assert_eq!(error.to_string(), "Failed to restore session");
+        assert_eq!(error.kind(), ErrorKind::Io);
+
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "Failed to read snapshot");
+        assert_eq!(source.source().unwrap().to_string(), "missing.bin");
+
+        // Converts into boxed errors as used by host applications
+        let boxed: Box<dyn Error + Send + Sync> = StorageError::from("Locked").into();
+        assert_eq!(boxed.to_string(), "Locked");
+
+        assert_eq!(StorageError::from("Locked").kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn misuse_hints_test()
+    {
+        use crate::{
+            storage_handle::{builder, StorageHandle},
+            storage_traits::Storage,
+            storage_types::{KeyItemViewStorage, VecStorage},
+            Hint,
+        };
+
+        fn assert_hint<T>(result: SimpleResult<T>, hint: Hint)
+        {
+            let error = result.err().unwrap();
+            assert_eq!(error.hint(), Some(hint));
+            assert!(!error.fix().unwrap().is_empty());
+        }
+
+        let input: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0])).build();
+
+        let mut view_builder =
+            builder(KeyItemViewStorage::<VecStorage<usize, f32>, usize, f32>::new());
+        view_builder.add_view_controller();
+        let mut view = view_builder.build();
+
+        // Reading a view before creating it
+        assert_hint(view.try_read(), Hint::ViewNotCreated);
+
+        // Creating a view before setting its input
+        let controller = view.view_storage_controller_mut().unwrap();
+        assert_hint(
+            controller.create_read_view::<usize, f32>(vec![0]),
+            Hint::ViewInputNotSet,
+        );
+
+        // Changing the input of a view that is in use
+        controller.set_input::<usize, f32>(input.clone()).unwrap();
+        controller.create_read_view::<usize, f32>(vec![0]).unwrap();
+        assert_hint(
+            controller.set_input::<usize, f32>(input.clone()),
+            Hint::ViewInUse,
+        );
+        controller.clear_view::<usize, f32>().unwrap();
+
+        // Casting with the wrong Item type
+        assert_hint(
+            input.clone().cast_to_getitem_storage::<usize, i32>(),
+            Hint::CastTypeMismatch,
+        );
+        assert_hint(
+            input
+                .clone()
+                .cast_to_sized_storage::<VecStorage<usize, i32>>(),
+            Hint::CastTypeMismatch,
+        );
+
+        // Index storages with keys that aren't indices
+        assert_hint(VecStorage::<u128, i32>::try_new(), Hint::NonIndexKey);
+
+        // Hints survive added context
+        let error = VecStorage::<u128, i32>::try_new()
+            .err()
+            .unwrap()
+            .context("Failed to load");
+        assert_eq!(error.hint(), Some(Hint::NonIndexKey));
+    }
+
+    #[test]
+    fn error_detail_test()
+    {
+        use crate::{
+            storage_handle::{builder, StorageHandle},
+            storage_traits::{KeyItemStorage, Storage},
+            storage_types::{try_key_to_index, VecStorage},
+            ErrorDetail,
+        };
+
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, f32>::from_vec(vec![1.0])).build();
+
+        let error = handle
+            .clone()
+            .cast_to_getitem_storage::<usize, i32>()
+            .err()
+            .unwrap()
+            .context("Failed to connect node input");
+        assert_eq!(
+            error.detail(),
+            Some(&ErrorDetail::InvalidCast {
+                from: std::any::type_name::<VecStorage<usize, f32>>(),
+                to: std::any::type_name::<dyn KeyItemStorage<Key = usize, Item = i32>>(),
+            })
+        );
+
+        let error = handle
+            .cast_to_sized_storage::<VecStorage<u32, f32>>()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.detail(),
+            Some(ErrorDetail::InvalidCast { to, .. }) if to.contains("VecStorage<u32, f32>")
+        ));
+
+        let error = try_key_to_index(u128::MAX).unwrap_err();
+        assert_eq!(
+            error.detail(),
+            Some(&ErrorDetail::KeyConversion {
+                value: u128::MAX.to_string(),
+                key_type: "u128",
+            })
+        );
+    }
+}
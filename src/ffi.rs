@@ -0,0 +1,410 @@
+//! C ABI over storage handles so that plugins written in C or C++ can read and write storages.
+//!
+//! The host hands plugins opaque [FfiStorageHandle] pointers created with [into_ffi_handle]. Keys
+//! are `size_t` and items are one of the primitive number types, each with its own set of
+//! functions, eg for `float` items:
+//!
+//! ```c
+//! size_t len;
+//! flex_storage_len(handle, &len);
+//!
+//! float weight;
+//! if (flex_storage_get_f32(handle, 3, &weight) == FLEX_STORAGE_OK)
+//!     flex_storage_insert_f32(handle, 3, weight * 2.0f);
+//!
+//! flex_storage_handle_release(handle);
+//! ```
+//!
+//! The matching declarations are in `include/flex_storage.h`.
+//
+// # Internal Design
+//
+// - Handles cross the ABI as `StorageHandle<dyn Storage>` so that one opaque type covers every
+//   storage. Each call casts to the trait object it needs, which is also where a mismatched item
+//   type is detected and reported as [FfiStatus::TypeMismatch].
+// - Storage locks are only held for the duration of a call, so C code never holds a guard and a
+//   plugin can't leave a storage locked. Iteration holds a read lock while the callback runs.
+// - Panics are caught at the boundary and reported as [FfiStatus::Panicked] as unwinding into C is
+//   undefined behavior.
+
+use std::{
+    ffi::c_void,
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+use crate::{storage_handle::StorageHandle, storage_traits::Storage};
+
+/// An opaque, reference counted handle to a storage
+pub struct FfiStorageHandle
+{
+    handle: StorageHandle<dyn Storage>,
+}
+
+/// Result of every C function
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FfiStatus
+{
+    Ok = 0,
+    NullPointer = 1,
+
+    /// The storage's key or item type doesn't match the function, or the storage doesn't support
+    /// the operation
+    TypeMismatch = 2,
+
+    /// The storage is locked elsewhere
+    LockFailed = 3,
+
+    NotFound = 4,
+    Panicked = 5,
+}
+
+/// Box `handle` so that it can be passed to C. It must be released with
+/// [flex_storage_handle_release].
+pub fn into_ffi_handle(handle: StorageHandle<dyn Storage>) -> *mut FfiStorageHandle
+{
+    Box::into_raw(Box::new(FfiStorageHandle { handle }))
+}
+
+/// Run `f` with the handle behind `handle`, catching panics
+///
+/// # Safety
+///
+/// `handle` must be null or a live pointer from [into_ffi_handle]
+unsafe fn with_handle(
+    handle: *const FfiStorageHandle,
+    f: impl FnOnce(&StorageHandle<dyn Storage>) -> FfiStatus,
+) -> FfiStatus
+{
+    let Some(handle) = handle.as_ref()
+    else
+    {
+        return FfiStatus::NullPointer;
+    };
+
+    catch_unwind(AssertUnwindSafe(|| f(&handle.handle))).unwrap_or(FfiStatus::Panicked)
+}
+
+/// Release a handle. The storage is dropped once its last handle is released.
+///
+/// # Safety
+///
+/// `handle` must be null or a live pointer from [into_ffi_handle] or [flex_storage_handle_clone]
+/// and must not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn flex_storage_handle_release(handle: *mut FfiStorageHandle)
+{
+    if !handle.is_null()
+    {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Create another handle to the same storage
+///
+/// # Safety
+///
+/// `handle` must be a live handle pointer and `out` a valid pointer to write to
+#[no_mangle]
+pub unsafe extern "C" fn flex_storage_handle_clone(
+    handle: *const FfiStorageHandle,
+    out: *mut *mut FfiStorageHandle,
+) -> FfiStatus
+{
+    if out.is_null()
+    {
+        return FfiStatus::NullPointer;
+    }
+
+    with_handle(handle, |handle| {
+        *out = into_ffi_handle(handle.clone());
+        FfiStatus::Ok
+    })
+}
+
+/// Number of items in the storage
+///
+/// # Safety
+///
+/// `handle` must be a live handle pointer and `out` a valid pointer to write to
+#[no_mangle]
+pub unsafe extern "C" fn flex_storage_len(
+    handle: *const FfiStorageHandle,
+    out: *mut usize,
+) -> FfiStatus
+{
+    if out.is_null()
+    {
+        return FfiStatus::NullPointer;
+    }
+
+    with_handle(handle, |handle| match handle.try_read()
+    {
+        Ok(guard) =>
+        {
+            *out = guard.len();
+            FfiStatus::Ok
+        }
+        Err(_) => FfiStatus::LockFailed,
+    })
+}
+
+/// Defines get, insert and iterate functions for an item type
+macro_rules! define_ffi_item_fns {
+    ($item:ty, $get_fn:ident, $insert_fn:ident, $iterate_fn:ident) => {
+        /// Copy the item at `key` into `out`
+        ///
+        /// # Safety
+        ///
+        /// `handle` must be a live handle pointer and `out` a valid pointer to write to
+        #[no_mangle]
+        pub unsafe extern "C" fn $get_fn(
+            handle: *const FfiStorageHandle,
+            key: usize,
+            out: *mut $item,
+        ) -> FfiStatus
+        {
+            if out.is_null()
+            {
+                return FfiStatus::NullPointer;
+            }
+
+            with_handle(handle, |handle| {
+                let Ok(storage) = handle.clone().cast_to_getitem_storage::<usize, $item>()
+                else
+                {
+                    return FfiStatus::TypeMismatch;
+                };
+
+                let Ok(guard) = storage.try_read()
+                else
+                {
+                    return FfiStatus::LockFailed;
+                };
+
+                match guard.get(key)
+                {
+                    Some(item) =>
+                    {
+                        *out = *item;
+                        FfiStatus::Ok
+                    }
+                    None => FfiStatus::NotFound,
+                }
+            })
+        }
+
+        /// Insert or overwrite the item at `key`
+        ///
+        /// # Safety
+        ///
+        /// `handle` must be a live handle pointer
+        #[no_mangle]
+        pub unsafe extern "C" fn $insert_fn(
+            handle: *const FfiStorageHandle,
+            key: usize,
+            item: $item,
+        ) -> FfiStatus
+        {
+            with_handle(handle, |handle| {
+                let Ok(storage) = handle.clone().cast_to_mut_getitem_storage::<usize, $item>()
+                else
+                {
+                    return FfiStatus::TypeMismatch;
+                };
+
+                let Ok(mut guard) = storage.try_write()
+                else
+                {
+                    return FfiStatus::LockFailed;
+                };
+
+                // Existing items are replaced in place as insert shifts items in vec like storages
+                match guard.get_mut(key)
+                {
+                    Some(existing) => *existing = item,
+                    None => guard.insert(key, item),
+                }
+                FfiStatus::Ok
+            })
+        }
+
+        /// Call `callback` with every key and item until it returns false. The storage is read
+        /// locked while iterating so `callback` must not write to it.
+        ///
+        /// # Safety
+        ///
+        /// `handle` must be a live handle pointer. `user_data` is passed through to `callback`
+        /// untouched.
+        #[no_mangle]
+        pub unsafe extern "C" fn $iterate_fn(
+            handle: *const FfiStorageHandle,
+            callback: Option<extern "C" fn(usize, $item, *mut c_void) -> bool>,
+            user_data: *mut c_void,
+        ) -> FfiStatus
+        {
+            let Some(callback) = callback
+            else
+            {
+                return FfiStatus::NullPointer;
+            };
+
+            with_handle(handle, |handle| {
+                let Ok(storage) = handle.clone().cast_to_getitem_storage::<usize, $item>()
+                else
+                {
+                    return FfiStatus::TypeMismatch;
+                };
+
+                let Ok(guard) = storage.try_read()
+                else
+                {
+                    return FfiStatus::LockFailed;
+                };
+
+                for (key, item) in guard.key_item_iter()
+                {
+                    if !callback(key, *item, user_data)
+                    {
+                        break;
+                    }
+                }
+
+                FfiStatus::Ok
+            })
+        }
+    };
+}
+
+define_ffi_item_fns!(
+    f32,
+    flex_storage_get_f32,
+    flex_storage_insert_f32,
+    flex_storage_iterate_f32
+);
+define_ffi_item_fns!(
+    f64,
+    flex_storage_get_f64,
+    flex_storage_insert_f64,
+    flex_storage_iterate_f64
+);
+define_ffi_item_fns!(
+    i32,
+    flex_storage_get_i32,
+    flex_storage_insert_i32,
+    flex_storage_iterate_i32
+);
+define_ffi_item_fns!(
+    i64,
+    flex_storage_get_i64,
+    flex_storage_insert_i64,
+    flex_storage_iterate_i64
+);
+define_ffi_item_fns!(
+    u8,
+    flex_storage_get_u8,
+    flex_storage_insert_u8,
+    flex_storage_iterate_u8
+);
+define_ffi_item_fns!(
+    u32,
+    flex_storage_get_u32,
+    flex_storage_insert_u32,
+    flex_storage_iterate_u32
+);
+define_ffi_item_fns!(
+    u64,
+    flex_storage_get_u64,
+    flex_storage_insert_u64,
+    flex_storage_iterate_u64
+);
+
+#[cfg(test)]
+mod tests
+{
+    use std::{ffi::c_void, ptr};
+
+    use super::{
+        flex_storage_get_f32, flex_storage_get_i32, flex_storage_handle_clone,
+        flex_storage_handle_release, flex_storage_insert_f32, flex_storage_iterate_f32,
+        flex_storage_len, into_ffi_handle, FfiStatus,
+    };
+    use crate::{storage_handle::builder, storage_types::VecStorage};
+
+    extern "C" fn sum(_key: usize, item: f32, user_data: *mut c_void) -> bool
+    {
+        unsafe { *(user_data as *mut f32) += item };
+        true
+    }
+
+    #[test]
+    fn ffi_test()
+    {
+        let storage: VecStorage<usize, f32> = VecStorage::from_vec(vec![1.0, 2.0]);
+        let handle = into_ffi_handle(builder(storage).build());
+
+        unsafe {
+            let mut clone = ptr::null_mut();
+            assert_eq!(flex_storage_handle_clone(handle, &mut clone), FfiStatus::Ok);
+            flex_storage_handle_release(handle);
+
+            assert_eq!(flex_storage_insert_f32(clone, 2, 3.0), FfiStatus::Ok);
+
+            let mut len = 0;
+            assert_eq!(flex_storage_len(clone, &mut len), FfiStatus::Ok);
+            assert_eq!(len, 3);
+
+            let mut item = 0.0;
+            assert_eq!(flex_storage_get_f32(clone, 1, &mut item), FfiStatus::Ok);
+            assert_eq!(item, 2.0);
+            assert_eq!(
+                flex_storage_get_f32(clone, 9, &mut item),
+                FfiStatus::NotFound
+            );
+
+            let mut int_item = 0;
+            assert_eq!(
+                flex_storage_get_i32(clone, 0, &mut int_item),
+                FfiStatus::TypeMismatch
+            );
+
+            let mut total = 0.0f32;
+            let user_data = &mut total as *mut f32 as *mut c_void;
+            assert_eq!(
+                flex_storage_iterate_f32(clone, Some(sum), user_data),
+                FfiStatus::Ok
+            );
+            assert_eq!(total, 6.0);
+
+            assert_eq!(
+                flex_storage_len(ptr::null(), &mut len),
+                FfiStatus::NullPointer
+            );
+
+            flex_storage_handle_release(clone);
+        }
+    }
+
+    #[test]
+    fn ffi_overwrite_test()
+    {
+        let storage: VecStorage<usize, f32> = VecStorage::from_vec(vec![1.0, 2.0, 3.0]);
+        let handle = into_ffi_handle(builder(storage).build());
+
+        unsafe {
+            assert_eq!(flex_storage_insert_f32(handle, 1, 20.0), FfiStatus::Ok);
+
+            let mut len = 0;
+            assert_eq!(flex_storage_len(handle, &mut len), FfiStatus::Ok);
+            assert_eq!(len, 3);
+
+            let mut item = 0.0;
+            assert_eq!(flex_storage_get_f32(handle, 1, &mut item), FfiStatus::Ok);
+            assert_eq!(item, 20.0);
+            assert_eq!(flex_storage_get_f32(handle, 2, &mut item), FfiStatus::Ok);
+            assert_eq!(item, 3.0);
+
+            flex_storage_handle_release(handle);
+        }
+    }
+}
@@ -0,0 +1,673 @@
+//! A dependency DAG of storage handles for dataflow processing.
+//!
+//! [StorageGraph] owns a handle per storage and records which storages are computed from which.
+//! Edges point in the direction data flows, from an input storage to the storages that depend on
+//! it, so topological order is the order in which storages can be processed:
+//!
+//! ```ignore
+//! let mut graph = StorageGraph::new();
+//! let positions = graph.add(positions_handle);
+//! let velocities = graph.add(velocities_handle);
+//! let next_positions = graph.add(next_positions_handle);
+//!
+//! graph.add_dependency(positions, next_positions)?;
+//! graph.add_dependency(velocities, next_positions)?;
+//!
+//! // Views get an edge from their input automatically
+//! let moving = graph.add_view::<usize, f32>(moving_view_handle)?;
+//!
+//! for (id, handle) in graph.topological_iter()
+//! {
+//!     process(id, handle);
+//! }
+//! ```
+//!
+//! For incremental recomputation only the storages whose inputs were written to since they were
+//! last processed need processing:
+//!
+//! ```ignore
+//! for id in graph.stale()
+//! {
+//!     process(id, graph.handle(id).unwrap(), graph.changed_inputs(id));
+//!     graph.mark_clean(id);
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - Nodes are keyed by [StorageId] so clones and casts of a handle map to the same node. The graph
+//   holds a `StorageHandle<dyn Storage>` per node as that is the one cast every other cast can be
+//   made from.
+// - Cycles are rejected when an edge is added rather than when iterating, so every graph is a DAG
+//   and topological order always exists. Topological order breaks ties by insertion order so that
+//   iteration is deterministic.
+// - Staleness is tracked with the [StorageHandle::write_version] of each input, recorded when a
+//   storage is marked clean, so writes made through any clone of an input handle are seen without
+//   the graph being told about them. A storage is also stale when one of its inputs is stale as
+//   that input will be written to when it is recomputed.
+// - The input of a view is recorded when the view is added. [StorageGraph::validate] re-reads it
+//   through a monomorphized fn pointer, so that the graph stays free of Key and Item generics, to
+//   catch views whose input was changed afterwards.
+// - [StorageGraph::collect] finds unreachable storages by the strong counts of their handles rather
+//   than by holding weak handles, so a node holds the same handle whether or not it is collectable
+//   and nothing has to be upgraded to read it.
+
+use std::collections::HashMap;
+
+use crate::{
+    storage_handle::{StorageHandle, StorageId},
+    storage_traits::{ItemTrait, KeyTrait, Storage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+type InputIdFn = fn(&StorageHandle<dyn Storage>) -> SimpleResult<Option<StorageId>>;
+
+struct GraphNode
+{
+    handle: StorageHandle<dyn Storage>,
+
+    /// Storages this one is computed from
+    inputs: Vec<StorageId>,
+
+    /// Storages computed from this one
+    outputs: Vec<StorageId>,
+
+    /// Reads the current input of a view node
+    view_input: Option<InputIdFn>,
+
+    /// Write versions of the inputs when the storage was last marked clean, None if it never was
+    clean_input_versions: Option<Vec<(StorageId, u64)>>,
+}
+
+/// Storage handles linked by the direction data flows between them, see the
+/// [module docs](self)
+#[derive(Default)]
+pub struct StorageGraph
+{
+    nodes: HashMap<StorageId, GraphNode>,
+
+    /// Node ids in insertion order
+    order: Vec<StorageId>,
+}
+
+impl StorageGraph
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Add the storage of `handle` and return its id. Adding a storage that is already in the
+    /// graph, eg through a clone of its handle, keeps the existing node.
+    pub fn add(&mut self, handle: StorageHandle<dyn Storage>) -> StorageId
+    {
+        let id = handle.storage_id();
+
+        if !self.nodes.contains_key(&id)
+        {
+            self.nodes.insert(
+                id,
+                GraphNode {
+                    handle,
+                    inputs: Vec::new(),
+                    outputs: Vec::new(),
+                    view_input: None,
+                    clean_input_versions: None,
+                },
+            );
+            self.order.push(id);
+        }
+
+        id
+    }
+
+    /// Add a view storage along with an edge from its input storage, which must already be in
+    /// the graph. Fails if the handle has no view controller or no input has been set.
+    pub fn add_view<Key, Item>(
+        &mut self,
+        handle: StorageHandle<dyn Storage>,
+    ) -> SimpleResult<StorageId>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let input_id: InputIdFn = |handle| {
+            handle
+                .view_storage_controller()
+                .ok_or("Handle has no view storage controller")?
+                .input_storage_id::<Key, Item>()
+        };
+
+        let input = input_id(&handle)?.ok_or("View has no input storage set")?;
+
+        if !self.nodes.contains_key(&input)
+        {
+            return Err(format!(
+                "Input storage {input} of view {} must be added to the graph first",
+                handle.storage_id()
+            )
+            .into());
+        }
+
+        let id = self.add(handle);
+        self.add_dependency(input, id)?;
+        self.nodes.get_mut(&id).unwrap().view_input = Some(input_id);
+
+        Ok(id)
+    }
+
+    /// Record that `dependent` is computed from `input`. Fails if either isn't in the graph or if
+    /// the edge would create a cycle. Adding an existing edge has no effect.
+    pub fn add_dependency(&mut self, input: StorageId, dependent: StorageId) -> SimpleResult<()>
+    {
+        for id in [input, dependent]
+        {
+            if !self.nodes.contains_key(&id)
+            {
+                return Err(StorageError::new(
+                    ErrorKind::NotRegistered,
+                    format!("Storage {id} is not in the graph"),
+                ));
+            }
+        }
+
+        if self.nodes[&input].outputs.contains(&dependent)
+        {
+            return Ok(());
+        }
+
+        if input == dependent || self.is_downstream(dependent, input)
+        {
+            return Err(format!(
+                "Dependency of {dependent} on {input} would create a cycle"
+            )
+            .into());
+        }
+
+        self.nodes.get_mut(&input).unwrap().outputs.push(dependent);
+        self.nodes.get_mut(&dependent).unwrap().inputs.push(input);
+
+        Ok(())
+    }
+
+    pub fn remove_dependency(&mut self, input: StorageId, dependent: StorageId)
+    {
+        if let Some(node) = self.nodes.get_mut(&input)
+        {
+            node.outputs.retain(|id| *id != dependent);
+        }
+
+        if let Some(node) = self.nodes.get_mut(&dependent)
+        {
+            node.inputs.retain(|id| *id != input);
+        }
+    }
+
+    /// Remove a storage and every edge to and from it, returning its handle
+    pub fn remove(&mut self, id: StorageId) -> Option<StorageHandle<dyn Storage>>
+    {
+        let node = self.nodes.remove(&id)?;
+        self.order.retain(|other| *other != id);
+
+        for input in &node.inputs
+        {
+            if let Some(input) = self.nodes.get_mut(input)
+            {
+                input.outputs.retain(|other| *other != id);
+            }
+        }
+
+        for output in &node.outputs
+        {
+            if let Some(output) = self.nodes.get_mut(output)
+            {
+                output.inputs.retain(|other| *other != id);
+            }
+        }
+
+        Some(node.handle)
+    }
+
+    pub fn contains(&self, id: StorageId) -> bool
+    {
+        self.nodes.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.nodes.is_empty()
+    }
+
+    pub fn handle(&self, id: StorageId) -> Option<&StorageHandle<dyn Storage>>
+    {
+        self.nodes.get(&id).map(|node| &node.handle)
+    }
+
+    /// Storages that `id` is computed from
+    pub fn inputs(&self, id: StorageId) -> &[StorageId]
+    {
+        self.nodes.get(&id).map_or(&[], |node| &node.inputs)
+    }
+
+    /// Storages computed from `id`
+    pub fn dependents(&self, id: StorageId) -> &[StorageId]
+    {
+        self.nodes.get(&id).map_or(&[], |node| &node.outputs)
+    }
+
+    /// Whether data flows from `from` to `to` through one or more edges
+    pub fn is_downstream(&self, from: StorageId, to: StorageId) -> bool
+    {
+        let mut stack = vec![from];
+        let mut visited = vec![from];
+
+        while let Some(id) = stack.pop()
+        {
+            for output in self.dependents(id)
+            {
+                if *output == to
+                {
+                    return true;
+                }
+
+                if !visited.contains(output)
+                {
+                    visited.push(*output);
+                    stack.push(*output);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Every storage ordered so that inputs come before the storages computed from them
+    pub fn topological_order(&self) -> Vec<StorageId>
+    {
+        let mut remaining_inputs: HashMap<StorageId, usize> = self
+            .nodes
+            .iter()
+            .map(|(id, node)| (*id, node.inputs.len()))
+            .collect();
+
+        let mut ordered = Vec::with_capacity(self.nodes.len());
+
+        // Repeated passes in insertion order keep ties deterministic. Graphs are small enough
+        // that this beats maintaining a priority queue.
+        while ordered.len() < self.nodes.len()
+        {
+            for id in &self.order
+            {
+                if remaining_inputs.get(id) != Some(&0)
+                {
+                    continue;
+                }
+
+                remaining_inputs.remove(id);
+                ordered.push(*id);
+
+                for output in &self.nodes[id].outputs
+                {
+                    if let Some(count) = remaining_inputs.get_mut(output)
+                    {
+                        *count -= 1;
+                    }
+                }
+            }
+        }
+
+        ordered
+    }
+
+    /// Handles in [StorageGraph::topological_order]
+    pub fn topological_iter(
+        &self,
+    ) -> impl Iterator<Item = (StorageId, &StorageHandle<dyn Storage>)> + '_
+    {
+        self.topological_order()
+            .into_iter()
+            .map(|id| (id, &self.nodes[&id].handle))
+    }
+
+    /// Check that a process that produces `writer` may write to `target`, ie that `target` is
+    /// `writer` itself or downstream of it. Writing upstream would change an input of the writer
+    /// after it has been read.
+    pub fn check_write(&self, writer: StorageId, target: StorageId) -> SimpleResult<()>
+    {
+        if !self.contains(writer) || !self.contains(target)
+        {
+            return Err(StorageError::new(
+                ErrorKind::NotRegistered,
+                format!(
+                    "Write from {writer} to {target} involves a storage that is not in the graph"
+                ),
+            ));
+        }
+
+        if writer == target || self.is_downstream(writer, target)
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(format!(
+                "Write from {writer} to {target} goes against the direction of the graph"
+            )
+            .into())
+        }
+    }
+
+    // ----------------------------------------------------------
+    // Incremental recomputation
+    // ----------------------------------------------------------
+
+    /// Storages that need recomputing in topological order. A storage is stale if it has inputs
+    /// and has never been marked clean, if an input was written to since it was marked clean, or
+    /// if an input is itself stale.
+    pub fn stale(&self) -> Vec<StorageId>
+    {
+        let mut stale: Vec<StorageId> = Vec::new();
+
+        for id in self.topological_order()
+        {
+            let node = &self.nodes[&id];
+
+            let is_stale = !self.changed_inputs(id).is_empty()
+                || node.inputs.iter().any(|input| stale.contains(input));
+
+            if is_stale
+            {
+                stale.push(id);
+            }
+        }
+
+        stale
+    }
+
+    /// Storages downstream of any of `changed` in topological order, whether or not they are
+    /// marked clean. For when changes are known without looking at write versions, eg from
+    /// [crate::storage_handle::WriteNotification]s.
+    pub fn stale_from(&self, changed: &[StorageId]) -> Vec<StorageId>
+    {
+        self.topological_order()
+            .into_iter()
+            .filter(|id| {
+                changed
+                    .iter()
+                    .any(|changed| self.is_downstream(*changed, *id))
+            })
+            .collect()
+    }
+
+    /// Inputs of `id` that were written to since it was last marked clean, eg to fetch their
+    /// [crate::storage_types::DirtyTracked] changes. Every input if it was never marked clean.
+    pub fn changed_inputs(&self, id: StorageId) -> Vec<StorageId>
+    {
+        let Some(node) = self.nodes.get(&id)
+        else
+        {
+            return Vec::new();
+        };
+
+        node.inputs
+            .iter()
+            .copied()
+            .filter(|input| {
+                let version = self.nodes[input].handle.write_version();
+
+                !node
+                    .clean_input_versions
+                    .as_ref()
+                    .is_some_and(|clean| clean.contains(&(*input, version)))
+            })
+            .collect()
+    }
+
+    /// Record that `id` is up to date with the current contents of its inputs
+    pub fn mark_clean(&mut self, id: StorageId)
+    {
+        let Some(node) = self.nodes.get(&id)
+        else
+        {
+            return;
+        };
+
+        let versions = node
+            .inputs
+            .iter()
+            .map(|input| (*input, self.nodes[input].handle.write_version()))
+            .collect();
+
+        self.nodes.get_mut(&id).unwrap().clean_input_versions = Some(versions);
+    }
+
+    /// Check that every view still has the input it was added with
+    pub fn validate(&self) -> SimpleResult<()>
+    {
+        for id in &self.order
+        {
+            let node = &self.nodes[id];
+
+            let Some(view_input) = node.view_input
+            else
+            {
+                continue;
+            };
+
+            match view_input(&node.handle)?
+            {
+                Some(input) if node.inputs.contains(&input) => (),
+                Some(input) =>
+                {
+                    return Err(format!(
+                        "View {id} has input {input} which is not one of its graph inputs"
+                    )
+                    .into())
+                }
+                None => return Err(format!("View {id} no longer has an input storage").into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the storages that are only kept alive by the graph: storages with no handle outside
+    /// of the graph and no dependents left in it. Removing a storage can leave its inputs without
+    /// dependents so this repeats until nothing more can be removed.
+    ///
+    /// Storages that are only reached through [Self::handle] count as unreachable, so keep a
+    /// handle to every storage whose result is still wanted. Storages that are locked are kept.
+    pub fn collect(&mut self) -> CollectReport
+    {
+        let mut report = CollectReport::default();
+
+        loop
+        {
+            let garbage: Vec<StorageId> = self
+                .order
+                .iter()
+                .copied()
+                .filter(|id| {
+                    let node = &self.nodes[id];
+                    node.outputs.is_empty() && node.handle.is_unique()
+                })
+                .collect();
+
+            let collected_before = report.collected.len();
+
+            for id in garbage
+            {
+                let Ok(memory_size) = self.nodes[&id]
+                    .handle
+                    .base_storage()
+                    .try_read()
+                    .map(|storage| storage.memory_size())
+                else
+                {
+                    continue;
+                };
+
+                self.remove(id);
+
+                report.collected.push(id);
+                report.freed_bytes += memory_size;
+            }
+
+            if report.collected.len() == collected_before
+            {
+                return report;
+            }
+        }
+    }
+}
+
+/// Storages removed by [StorageGraph::collect]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CollectReport
+{
+    /// In the order they were removed, dependents before their inputs
+    pub collected: Vec<StorageId>,
+
+    /// Sum of the [Storage::memory_size] of the removed storages
+    pub freed_bytes: usize,
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{any::TypeId, sync::Arc};
+
+    use super::StorageGraph;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::{KeyItemViewStorage, VecStorage},
+        Rw,
+    };
+
+    type PositionsView = KeyItemViewStorage<VecStorage<usize, f32>, usize, f32>;
+
+    fn storage() -> StorageHandle<dyn Storage>
+    {
+        builder(VecStorage::<usize, f32>::new()).build()
+    }
+
+    #[test]
+    fn topological_order_test()
+    {
+        let mut graph = StorageGraph::new();
+        let c = graph.add(storage());
+        let a = graph.add(storage());
+        let b = graph.add(storage());
+
+        graph.add_dependency(a, b).unwrap();
+        graph.add_dependency(b, c).unwrap();
+        graph.add_dependency(a, c).unwrap();
+
+        assert_eq!(graph.topological_order(), vec![a, b, c]);
+        assert!(graph.add_dependency(c, a).is_err());
+        assert!(graph.add_dependency(a, a).is_err());
+
+        assert!(graph.check_write(a, c).is_ok());
+        assert!(graph.check_write(c, a).is_err());
+
+        graph.remove(b);
+        assert_eq!(graph.inputs(c), &[a]);
+        assert_eq!(graph.topological_order(), vec![a, c]);
+    }
+
+    #[test]
+    fn stale_test()
+    {
+        let mut graph = StorageGraph::new();
+        let a = graph.add(storage());
+        let b = graph.add(storage());
+        let c = graph.add(storage());
+
+        graph.add_dependency(a, b).unwrap();
+        graph.add_dependency(b, c).unwrap();
+
+        assert_eq!(graph.stale(), vec![b, c]);
+
+        graph.mark_clean(b);
+        graph.mark_clean(c);
+        assert!(graph.stale().is_empty());
+
+        let _ = graph.handle(a).unwrap().try_write().unwrap();
+        assert_eq!(graph.changed_inputs(b), vec![a]);
+        assert!(graph.changed_inputs(c).is_empty());
+
+        // c is stale through b even though b hasn't been written to yet
+        assert_eq!(graph.stale(), vec![b, c]);
+
+        // Recompute b
+        let _ = graph.handle(b).unwrap().try_write().unwrap();
+        graph.mark_clean(b);
+        assert_eq!(graph.stale(), vec![c]);
+
+        assert_eq!(graph.stale_from(&[a]), vec![b, c]);
+        assert_eq!(graph.stale_from(&[c]), vec![]);
+    }
+
+    #[test]
+    fn collect_test()
+    {
+        let mut graph = StorageGraph::new();
+
+        let kept = storage();
+        let kept_id = graph.add(kept.clone());
+        let input = graph.add(storage());
+        let dependent = graph.add(storage());
+        graph.add_dependency(input, dependent).unwrap();
+
+        let abandoned = graph.add(storage());
+
+        // The input is kept alive by its dependent until the dependent is collected
+        let report = graph.collect();
+        assert_eq!(report.collected, vec![dependent, abandoned, input]);
+        assert!(report.freed_bytes > 0);
+        assert_eq!(graph.topological_order(), vec![kept_id]);
+
+        drop(kept);
+        assert_eq!(graph.collect().collected, vec![kept_id]);
+        assert!(graph.topological_order().is_empty());
+    }
+
+    #[test]
+    fn view_edge_test()
+    {
+        let mut graph = StorageGraph::new();
+        let positions = storage();
+        let input = graph.add(positions.clone());
+
+        let view = Arc::new(Rw::new(PositionsView::new()));
+        let mut view: StorageHandle<dyn Storage> = StorageHandle::new_with_view_controller(
+            view.clone(),
+            view,
+            TypeId::of::<usize>(),
+            TypeId::of::<f32>(),
+        );
+
+        assert!(graph.add_view::<usize, f32>(view.clone()).is_err());
+
+        view.view_storage_controller_mut()
+            .unwrap()
+            .set_input::<usize, f32>(positions)
+            .unwrap();
+
+        let view_id = graph.add_view::<usize, f32>(view.clone()).unwrap();
+        assert_eq!(graph.dependents(input), &[view_id]);
+        assert!(graph.validate().is_ok());
+
+        view.view_storage_controller_mut()
+            .unwrap()
+            .set_input::<usize, f32>(storage())
+            .unwrap();
+        assert!(graph.validate().is_err());
+    }
+}
@@ -0,0 +1,483 @@
+//! Storages with their lock on the inside, cast with safe code only. Enabled with the
+//! `interior_lock` feature.
+//!
+//! Handles keep the [std::sync::RwLock] outside of the storage, as in `Arc<RwLock<dyn Storage>>`,
+//! and cast through the unsafe [crate::casting::dyn_storage_into_sized]. This module is the
+//! alternative for consumers that must avoid that path: [InteriorLock] puts a
+//! [parking_lot::RwLock] inside the storage so that `Arc<dyn InteriorStorage>` is downcast with
+//! [downcast_rs] and the lock hands out mapped guards of the usual storage traits.
+//!
+//! Each lock trait pairs with the storage trait that its guards give access to:
+//!
+//! | Lock trait                    | Guard target                  |
+//! |-------------------------------|-------------------------------|
+//! | [InteriorStorage]             | [Storage]                     |
+//! | [InteriorKeyItemStorage]      | [KeyItemStorage]              |
+//! | [InteriorMutKeyItemStorage]   | [MutKeyItemStorage]           |
+//! | [InteriorSliceStorage]        | [ItemSliceStorage]            |
+//! | [InteriorMutSliceStorage]     | [MutItemSliceStorage]         |
+//!
+//! ```ignore
+//! let storage: Arc<dyn InteriorStorage> =
+//!     Arc::new(InteriorLock::new(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0])));
+//!
+//! let weights = interior::cast_to_mut_key_item::<usize, f32>(storage.clone())?;
+//! weights.try_write()?.insert(2, 3.0);
+//!
+//! let slice = interior::cast_to_slice::<usize, f32>(storage)?;
+//! assert_eq!(slice.read_slice().as_item_slice(), &[1.0, 2.0, 3.0]);
+//! ```
+//
+// # Internal Design
+//
+// - This is the approach of tests/experiments/interior_guardcell_3_pass.rs with parking_lot's
+//   mapped guards in place of RefCell, so it works across threads. The companion "interior" traits
+//   of the experiment are the crate's own storage traits, which keeps the extra traits to the lock
+//   traits above.
+// - Casts go through the same kind of list of concrete types as [crate::casting], so the storage
+//   types that can be cast here are the built in ones. Wrappers can still be locked directly
+//   through an `Arc<InteriorLock<S>>`.
+// - Not available with the `local` feature as safe downcasts of an Arc need Send + Sync.
+// - Handles, views and the rest of the handle tooling stay with the outer lock design.
+
+#![forbid(unsafe_code)]
+
+use std::{
+    any::{type_name, TypeId},
+    fmt,
+    sync::Arc,
+};
+
+use downcast_rs::{impl_downcast, DowncastSync};
+use parking_lot::{
+    MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+use crate::{
+    casting::cast_to_dyn_error,
+    storage_traits::{
+        ItemSliceStorage, ItemTrait, ItemTypeIdNoSelf, KeyItemStorage, KeyTrait, KeyTypeIdNoSelf,
+        MutItemSliceStorage, MutKeyItemStorage, Storage,
+    },
+    storage_types::{HashMapStorage, SparseSetVecStorage, ValStorage, VecStorage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+/// Read guard of a storage trait object inside an [InteriorLock]
+pub type InteriorReadGuard<'a, T> = MappedRwLockReadGuard<'a, T>;
+
+/// Write guard of a storage trait object inside an [InteriorLock]
+pub type InteriorWriteGuard<'a, T> = MappedRwLockWriteGuard<'a, T>;
+
+/// A storage that locks itself. The base of the lock traits and the type that is downcast.
+pub trait InteriorStorage: DowncastSync
+{
+    fn key_type_id(&self) -> TypeId;
+
+    fn item_type_id(&self) -> TypeId;
+
+    /// Name of the storage type inside the lock
+    fn storage_type_name(&self) -> &'static str;
+
+    fn read_storage(&self) -> InteriorReadGuard<'_, dyn Storage>;
+}
+
+impl_downcast!(sync InteriorStorage);
+
+pub trait InteriorKeyItemStorage: InteriorStorage
+{
+    type Key: KeyTrait;
+    type Item: ItemTrait;
+
+    /// Blocks until the lock is free
+    fn read(&self)
+        -> InteriorReadGuard<'_, dyn KeyItemStorage<Key = Self::Key, Item = Self::Item>>;
+
+    /// Fails with [ErrorKind::WouldBlock] if a writer holds the lock
+    fn try_read(
+        &self,
+    ) -> SimpleResult<
+        InteriorReadGuard<'_, dyn KeyItemStorage<Key = Self::Key, Item = Self::Item>>,
+    >;
+}
+
+pub trait InteriorMutKeyItemStorage: InteriorKeyItemStorage
+{
+    /// Blocks until the lock is free
+    fn write(
+        &self,
+    ) -> InteriorWriteGuard<'_, dyn MutKeyItemStorage<Key = Self::Key, Item = Self::Item>>;
+
+    /// Fails with [ErrorKind::WouldBlock] if the lock is held
+    fn try_write(
+        &self,
+    ) -> SimpleResult<
+        InteriorWriteGuard<'_, dyn MutKeyItemStorage<Key = Self::Key, Item = Self::Item>>,
+    >;
+}
+
+pub trait InteriorSliceStorage: InteriorStorage
+{
+    type Item: ItemTrait;
+
+    /// Blocks until the lock is free
+    fn read_slice(&self) -> InteriorReadGuard<'_, dyn ItemSliceStorage<Item = Self::Item>>;
+
+    /// Fails with [ErrorKind::WouldBlock] if a writer holds the lock
+    fn try_read_slice(
+        &self,
+    ) -> SimpleResult<InteriorReadGuard<'_, dyn ItemSliceStorage<Item = Self::Item>>>;
+}
+
+pub trait InteriorMutSliceStorage: InteriorSliceStorage
+{
+    /// Blocks until the lock is free
+    fn write_slice(&self) -> InteriorWriteGuard<'_, dyn MutItemSliceStorage<Item = Self::Item>>;
+
+    /// Fails with [ErrorKind::WouldBlock] if the lock is held
+    fn try_write_slice(
+        &self,
+    ) -> SimpleResult<InteriorWriteGuard<'_, dyn MutItemSliceStorage<Item = Self::Item>>>;
+}
+
+/// Any storage of the crate with a [parking_lot::RwLock] inside, see the [module docs](self)
+#[derive(Default)]
+pub struct InteriorLock<S>
+{
+    storage: RwLock<S>,
+}
+
+impl<S> InteriorLock<S>
+{
+    pub fn new(storage: S) -> Self
+    {
+        Self {
+            storage: RwLock::new(storage),
+        }
+    }
+
+    pub fn into_inner(self) -> S
+    {
+        self.storage.into_inner()
+    }
+
+    /// The storage without locking, as the lock is exclusively borrowed
+    pub fn get_mut(&mut self) -> &mut S
+    {
+        self.storage.get_mut()
+    }
+
+    /// Lock the storage as its concrete type
+    pub fn read_inner(&self) -> RwLockReadGuard<'_, S>
+    {
+        self.storage.read()
+    }
+
+    /// Lock the storage as its concrete type
+    pub fn write_inner(&self) -> RwLockWriteGuard<'_, S>
+    {
+        self.storage.write()
+    }
+
+    fn try_read_inner(&self) -> SimpleResult<RwLockReadGuard<'_, S>>
+    {
+        self.storage.try_read().ok_or_else(would_block::<S>)
+    }
+
+    fn try_write_inner(&self) -> SimpleResult<RwLockWriteGuard<'_, S>>
+    {
+        self.storage.try_write().ok_or_else(would_block::<S>)
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for InteriorLock<S>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_tuple("InteriorLock").field(&self.storage).finish()
+    }
+}
+
+#[cold]
+fn would_block<S>() -> StorageError
+{
+    StorageError::new(
+        ErrorKind::WouldBlock,
+        format!("The lock of '{}' is held elsewhere", type_name::<S>()),
+    )
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Lock trait impls
+////////////////////////////////////////////////////////////////////////////////
+
+impl<S> InteriorStorage for InteriorLock<S>
+where
+    S: Storage + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+{
+    fn key_type_id(&self) -> TypeId
+    {
+        S::key_type_id()
+    }
+
+    fn item_type_id(&self) -> TypeId
+    {
+        S::item_type_id()
+    }
+
+    fn storage_type_name(&self) -> &'static str
+    {
+        type_name::<S>()
+    }
+
+    fn read_storage(&self) -> InteriorReadGuard<'_, dyn Storage>
+    {
+        RwLockReadGuard::map(self.storage.read(), |storage| storage as &dyn Storage)
+    }
+}
+
+impl<S> InteriorKeyItemStorage for InteriorLock<S>
+where
+    S: KeyItemStorage + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    S::Key: KeyTrait,
+    S::Item: ItemTrait,
+{
+    type Key = S::Key;
+    type Item = S::Item;
+
+    fn read(&self) -> InteriorReadGuard<'_, dyn KeyItemStorage<Key = S::Key, Item = S::Item>>
+    {
+        RwLockReadGuard::map(self.storage.read(), |storage| {
+            storage as &dyn KeyItemStorage<Key = _, Item = _>
+        })
+    }
+
+    fn try_read(
+        &self,
+    ) -> SimpleResult<InteriorReadGuard<'_, dyn KeyItemStorage<Key = S::Key, Item = S::Item>>>
+    {
+        Ok(RwLockReadGuard::map(self.try_read_inner()?, |storage| {
+            storage as &dyn KeyItemStorage<Key = _, Item = _>
+        }))
+    }
+}
+
+impl<S> InteriorMutKeyItemStorage for InteriorLock<S>
+where
+    S: MutKeyItemStorage + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    S::Key: KeyTrait,
+    S::Item: ItemTrait,
+{
+    fn write(&self) -> InteriorWriteGuard<'_, dyn MutKeyItemStorage<Key = S::Key, Item = S::Item>>
+    {
+        RwLockWriteGuard::map(self.storage.write(), |storage| {
+            storage as &mut dyn MutKeyItemStorage<Key = _, Item = _>
+        })
+    }
+
+    fn try_write(
+        &self,
+    ) -> SimpleResult<InteriorWriteGuard<'_, dyn MutKeyItemStorage<Key = S::Key, Item = S::Item>>>
+    {
+        Ok(RwLockWriteGuard::map(self.try_write_inner()?, |storage| {
+            storage as &mut dyn MutKeyItemStorage<Key = _, Item = _>
+        }))
+    }
+}
+
+impl<S> InteriorSliceStorage for InteriorLock<S>
+where
+    S: ItemSliceStorage + Storage + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    S::Item: ItemTrait,
+{
+    type Item = S::Item;
+
+    fn read_slice(&self) -> InteriorReadGuard<'_, dyn ItemSliceStorage<Item = S::Item>>
+    {
+        RwLockReadGuard::map(self.storage.read(), |storage| {
+            storage as &dyn ItemSliceStorage<Item = _>
+        })
+    }
+
+    fn try_read_slice(
+        &self,
+    ) -> SimpleResult<InteriorReadGuard<'_, dyn ItemSliceStorage<Item = S::Item>>>
+    {
+        Ok(RwLockReadGuard::map(self.try_read_inner()?, |storage| {
+            storage as &dyn ItemSliceStorage<Item = _>
+        }))
+    }
+}
+
+impl<S> InteriorMutSliceStorage for InteriorLock<S>
+where
+    S: MutItemSliceStorage + Storage + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    S::Item: ItemTrait,
+{
+    fn write_slice(&self) -> InteriorWriteGuard<'_, dyn MutItemSliceStorage<Item = S::Item>>
+    {
+        RwLockWriteGuard::map(self.storage.write(), |storage| {
+            storage as &mut dyn MutItemSliceStorage<Item = _>
+        })
+    }
+
+    fn try_write_slice(
+        &self,
+    ) -> SimpleResult<InteriorWriteGuard<'_, dyn MutItemSliceStorage<Item = S::Item>>>
+    {
+        Ok(RwLockWriteGuard::map(self.try_write_inner()?, |storage| {
+            storage as &mut dyn MutItemSliceStorage<Item = _>
+        }))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Casting
+////////////////////////////////////////////////////////////////////////////////
+
+// Defines a function that casts an Arc<dyn InteriorStorage> to an Arc of one of the lock traits by
+// trying each listed storage type in turn. Unlike [crate::casting] each attempt is a safe downcast.
+macro_rules! define_interior_cast_fn {
+
+    ($fn_name:ident, $target_trait:ty, [$($storage_type:ty),* $(,)?]) => {
+
+        pub fn $fn_name<Key, Item>(
+            storage: Arc<dyn InteriorStorage>,
+        ) -> SimpleResult<Arc<$target_trait>>
+        where
+            Key: KeyTrait,
+            Item: ItemTrait,
+        {
+            $(
+                let storage = match storage.downcast_arc::<InteriorLock<$storage_type>>()
+                {
+                    Ok(storage) => return Ok(storage),
+                    Err(storage) => storage,
+                };
+            )*
+
+            Err(cast_to_dyn_error(storage.storage_type_name(), type_name::<$target_trait>()))
+        }
+    };
+}
+
+define_interior_cast_fn!(
+    cast_to_key_item,
+    dyn InteriorKeyItemStorage<Key = Key, Item = Item>,
+    [
+        VecStorage<Key, Item>,
+        SparseSetVecStorage<Key, Item>,
+        HashMapStorage<Key, Item>,
+        ValStorage<Key, Item>,
+    ]
+);
+
+define_interior_cast_fn!(
+    cast_to_mut_key_item,
+    dyn InteriorMutKeyItemStorage<Key = Key, Item = Item>,
+    [
+        VecStorage<Key, Item>,
+        SparseSetVecStorage<Key, Item>,
+        HashMapStorage<Key, Item>,
+        ValStorage<Key, Item>,
+    ]
+);
+
+define_interior_cast_fn!(
+    cast_to_slice,
+    dyn InteriorSliceStorage<Item = Item>,
+    [
+        VecStorage<Key, Item>,
+        SparseSetVecStorage<Key, Item>,
+        ValStorage<Key, Item>,
+    ]
+);
+
+define_interior_cast_fn!(
+    cast_to_mut_slice,
+    dyn InteriorMutSliceStorage<Item = Item>,
+    [
+        VecStorage<Key, Item>,
+        SparseSetVecStorage<Key, Item>,
+        ValStorage<Key, Item>,
+    ]
+);
+
+/// Downcast to the lock of a concrete storage type
+pub fn cast_to_sized<S>(storage: Arc<dyn InteriorStorage>) -> SimpleResult<Arc<InteriorLock<S>>>
+where
+    InteriorLock<S>: InteriorStorage,
+{
+    storage
+        .downcast_arc::<InteriorLock<S>>()
+        .map_err(|storage| cast_to_dyn_error(storage.storage_type_name(), type_name::<S>()))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use super::{
+        cast_to_key_item, cast_to_mut_key_item, cast_to_mut_slice, cast_to_sized, cast_to_slice,
+        InteriorLock, InteriorStorage,
+    };
+    use crate::{
+        storage_traits::KeyItemStorage,
+        storage_types::{HashMapStorage, VecStorage},
+        ErrorKind,
+    };
+
+    #[test]
+    fn interior_cast_test()
+    {
+        let weights = VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0]);
+        let storage: Arc<dyn InteriorStorage> = Arc::new(InteriorLock::new(weights));
+
+        let weights = cast_to_mut_key_item::<usize, f32>(storage.clone()).unwrap();
+        weights.try_write().unwrap().insert(2, 3.0);
+
+        let slice = cast_to_slice::<usize, f32>(storage.clone()).unwrap();
+        assert_eq!(slice.read_slice().as_item_slice(), &[1.0, 2.0, 3.0]);
+
+        cast_to_mut_slice::<usize, f32>(storage.clone())
+            .unwrap()
+            .write_slice()
+            .as_mut_slice()
+            .sort_by(|a, b| b.total_cmp(a));
+
+        let reader = cast_to_key_item::<usize, f32>(storage.clone()).unwrap();
+        assert_eq!(reader.read().get(0), Some(&3.0));
+        assert_eq!(storage.read_storage().len(), 3);
+
+        // Held guards are reported rather than waited on by the try methods
+        let guard = reader.read();
+        assert_eq!(
+            weights.try_write().err().unwrap().kind(),
+            ErrorKind::WouldBlock
+        );
+        drop(guard);
+
+        let error = cast_to_key_item::<usize, i32>(storage.clone())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::TypeMismatch);
+        assert!(cast_to_sized::<VecStorage<usize, f32>>(storage).is_ok());
+    }
+
+    #[test]
+    fn interior_map_test()
+    {
+        let names: Arc<dyn InteriorStorage> =
+            Arc::new(InteriorLock::new(HashMapStorage::<u32, &str>::new()));
+
+        let writer = cast_to_mut_key_item::<u32, &str>(names.clone()).unwrap();
+        writer.write().insert(7, "blur");
+
+        // Maps have no item slice
+        assert!(cast_to_slice::<u32, &str>(names.clone()).is_err());
+
+        let names = cast_to_sized::<HashMapStorage<u32, &str>>(names).unwrap();
+        assert_eq!(names.read_inner().get(7), Some(&"blur"));
+    }
+}
@@ -0,0 +1,219 @@
+//! Conversions between [VecStorage] and Apache Arrow arrays and record batches.
+//!
+//! Consuming conversions hand the Vec of a storage over to an Arrow buffer and back without
+//! copying:
+//!
+//! ```ignore
+//! let weights: VecStorage<usize, f32> = ...;
+//! let batch = record_batch([("weights", weights.into_array_ref())])?;
+//!
+//! // Run the batch through DataFusion etc
+//!
+//! let weights = VecStorage::<usize, f32>::try_from_array_ref(batch.column(0).clone())?;
+//! ```
+//!
+//! Borrowing conversions such as [VecStorage::to_arrow] copy, which is what is needed when the
+//! storage is behind a handle read guard.
+//
+// # Internal Design
+//
+// - A Vec can only be taken back out of an Arrow buffer when nothing else shares the buffer and the
+//   buffer was allocated by a Vec of the same type. When that isn't the case the values are copied
+//   instead so that conversions back into storages never fail because of how the array was made.
+// - Arrays with nulls are rejected as storages don't have a notion of a missing item.
+
+use std::sync::Arc;
+
+use arrow_array::{
+    types::{
+        Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type,
+        UInt32Type, UInt64Type, UInt8Type,
+    },
+    Array, ArrayRef, ArrowPrimitiveType, PrimitiveArray, RecordBatch,
+};
+use arrow_buffer::{ArrowNativeType, ScalarBuffer};
+
+use crate::{
+    storage_traits::{ItemSliceStorage, ItemTrait, KeyTrait},
+    storage_types::VecStorage,
+    ErrorKind, SimpleResult, StorageError,
+};
+
+/// Items that have an Arrow primitive array type with the same memory layout
+pub trait ArrowItem: ItemTrait + ArrowNativeType
+{
+    type ArrowType: ArrowPrimitiveType<Native = Self>;
+}
+
+/// Implements [ArrowItem] for the list of given (item type => arrow type) pairs
+macro_rules! impl_arrow_item {
+
+    ( $($item:ty => $arrow_type:ty),* ) => {
+
+        $( impl ArrowItem for $item
+        {
+            type ArrowType = $arrow_type;
+        } )*
+    };
+}
+
+impl_arrow_item!(
+    i8 => Int8Type,
+    i16 => Int16Type,
+    i32 => Int32Type,
+    i64 => Int64Type,
+    u8 => UInt8Type,
+    u16 => UInt16Type,
+    u32 => UInt32Type,
+    u64 => UInt64Type,
+    f32 => Float32Type,
+    f64 => Float64Type
+);
+
+impl<Key, Item> VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ArrowItem,
+{
+    /// Move the items into an Arrow array without copying
+    pub fn into_arrow(self) -> PrimitiveArray<Item::ArrowType>
+    {
+        PrimitiveArray::new(ScalarBuffer::from(self.into_vec()), None)
+    }
+
+    pub fn into_array_ref(self) -> ArrayRef
+    {
+        Arc::new(self.into_arrow())
+    }
+
+    /// Copy the items into a new Arrow array
+    pub fn to_arrow(&self) -> PrimitiveArray<Item::ArrowType>
+    {
+        PrimitiveArray::from_iter_values(self.as_item_slice().iter().copied())
+    }
+
+    /// Take the values of `array` without copying when it is the only owner of its buffer,
+    /// otherwise copy them. Fails if the array has nulls.
+    pub fn try_from_arrow(array: PrimitiveArray<Item::ArrowType>) -> SimpleResult<Self>
+    {
+        if array.null_count() > 0
+        {
+            return Err(StorageError::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Arrow array with {} nulls can't be converted into a storage",
+                    array.null_count()
+                ),
+            ));
+        }
+
+        let (_, values, _) = array.into_parts();
+
+        let data = match values.into_inner().into_vec::<Item>()
+        {
+            Ok(data) => data,
+            Err(buffer) => buffer.typed_data::<Item>().to_vec(),
+        };
+
+        Ok(Self::from_vec(data))
+    }
+
+    /// Like [VecStorage::try_from_arrow] for a type erased array, eg a column of a
+    /// [RecordBatch]. The values are only taken without copying if `array` is the last reference to
+    /// the array.
+    pub fn try_from_array_ref(array: ArrayRef) -> SimpleResult<Self>
+    {
+        let typed = array
+            .as_any()
+            .downcast_ref::<PrimitiveArray<Item::ArrowType>>()
+            .ok_or_else(|| {
+                StorageError::new(
+                    ErrorKind::TypeMismatch,
+                    format!(
+                        "Arrow array of type {} can't be converted into a storage of {}",
+                        array.data_type(),
+                        std::any::type_name::<Item>()
+                    ),
+                )
+            })?
+            .clone();
+
+        // Release the shared reference to the buffer so that the clone can take it over
+        drop(array);
+
+        Self::try_from_arrow(typed)
+    }
+}
+
+/// Build a record batch out of named columns, eg from [VecStorage::into_array_ref]
+pub fn record_batch<Name>(
+    columns: impl IntoIterator<Item = (Name, ArrayRef)>,
+) -> SimpleResult<RecordBatch>
+where
+    Name: AsRef<str>,
+{
+    RecordBatch::try_from_iter(columns).map_err(|error| {
+        StorageError::with_source(ErrorKind::InvalidData, error.to_string(), error)
+    })
+}
+
+/// Copy or take the named column of `batch` into a storage, see [VecStorage::try_from_array_ref]
+pub fn column_into_storage<Key, Item>(
+    batch: &RecordBatch,
+    name: &str,
+) -> SimpleResult<VecStorage<Key, Item>>
+where
+    Key: KeyTrait,
+    Item: ArrowItem,
+{
+    let column = batch
+        .column_by_name(name)
+        .ok_or_else(|| {
+            StorageError::new(
+                ErrorKind::InvalidData,
+                format!("Record batch has no column named {name}"),
+            )
+        })?;
+
+    VecStorage::try_from_array_ref(column.clone())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{column_into_storage, record_batch};
+    use crate::{storage_traits::ItemSliceStorage, storage_types::VecStorage};
+
+    #[test]
+    fn arrow_round_trip_test()
+    {
+        let storage: VecStorage<usize, f32> = VecStorage::from_vec(vec![1.0, 2.0, 3.0]);
+        let ptr = storage.as_item_slice().as_ptr();
+
+        let array = storage.into_arrow();
+        assert_eq!(array.values().as_ref(), &[1.0, 2.0, 3.0]);
+
+        // Round trips without copying
+        let storage = VecStorage::<usize, f32>::try_from_arrow(array).unwrap();
+        assert_eq!(storage.as_item_slice().as_ptr(), ptr);
+    }
+
+    #[test]
+    fn record_batch_test()
+    {
+        let ids: VecStorage<usize, u32> = VecStorage::from_vec(vec![10, 11]);
+        let weights: VecStorage<usize, f64> = VecStorage::from_vec(vec![0.5, 0.25]);
+
+        let batch = record_batch([
+            ("ids", ids.into_array_ref()),
+            ("weights", weights.into_array_ref()),
+        ])
+        .unwrap();
+
+        let weights = column_into_storage::<usize, f64>(&batch, "weights").unwrap();
+        assert_eq!(weights.as_item_slice(), &[0.5, 0.25]);
+
+        assert!(column_into_storage::<usize, f32>(&batch, "ids").is_err());
+        assert!(column_into_storage::<usize, u32>(&batch, "missing").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Bridge between storages and a bevy_ecs [World].
+//!
+//! - [StorageResource] exposes a [StorageHandle] as a bevy [Resource] so systems can share storages
+//!   with the rest of a node graph.
+//! - [components_to_storage] and [storage_to_components] copy a component to and from a storage
+//!   keyed by entity index, eg a [SparseSetVecStorage] or [VecStorage].
+//!
+//! ```ignore
+//! fn export_positions(world: &mut World, positions: &StorageHandle<Positions>) -> SimpleResult<()>
+//! {
+//!     components_to_storage(world, &mut *positions.try_write()?);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [SparseSetVecStorage]: crate::storage_types::SparseSetVecStorage
+//! [VecStorage]: crate::storage_types::VecStorage
+//
+// # Internal Design
+//
+// - Storages are keyed by [Entity::index] only. Generations are not stored so a storage should be
+//   synced from the world again after entities are despawned rather than kept across frames.
+// - Components are inserted in ascending key order as the trait based insert of index backed
+//   storages such as VecStorage fills gaps with default items and shifts when inserting below the
+//   current length.
+
+use bevy_ecs::{component::Component, entity::Entity, system::Resource, world::World};
+
+use crate::{
+    storage_handle::StorageHandle,
+    storage_traits::{ClearableStorage, ItemTrait, KeyItemStorage, MutKeyItemStorage, Storage},
+};
+
+/// A [StorageHandle] that can be inserted into a bevy [World] as a resource
+pub struct StorageResource<S>(pub StorageHandle<S>)
+where
+    S: Storage + ?Sized;
+
+impl<S> Resource for StorageResource<S> where S: Storage + ?Sized {}
+
+impl<S> std::ops::Deref for StorageResource<S>
+where
+    S: Storage + ?Sized,
+{
+    type Target = StorageHandle<S>;
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.0
+    }
+}
+
+/// Storage key of an entity
+pub fn entity_key(entity: Entity) -> usize
+{
+    entity.index() as usize
+}
+
+/// Replace the contents of `storage` with the `C` component of every entity that has one
+pub fn components_to_storage<C, S>(world: &mut World, storage: &mut S)
+where
+    C: Component + ItemTrait,
+    S: MutKeyItemStorage<Key = usize, Item = C> + ClearableStorage + ?Sized,
+{
+    let mut components: Vec<(usize, C)> = world
+        .query::<(Entity, &C)>()
+        .iter(world)
+        .map(|(entity, component)| (entity_key(entity), component.clone()))
+        .collect();
+
+    components.sort_by_key(|(key, _)| *key);
+
+    storage.clear();
+
+    for (key, component) in components
+    {
+        storage.insert(key, component);
+    }
+}
+
+/// Overwrite the `C` component of every entity that has one with the item at its key in
+/// `storage`. Entities without an item are left unchanged.
+pub fn storage_to_components<C, S>(storage: &S, world: &mut World)
+where
+    C: Component + ItemTrait,
+    S: KeyItemStorage<Key = usize, Item = C> + ?Sized,
+{
+    for (entity, mut component) in world.query::<(Entity, &mut C)>().iter_mut(world)
+    {
+        if let Some(item) = storage.get(entity_key(entity))
+        {
+            *component = item.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bevy_ecs::{
+        component::Component,
+        world::{Mut, World},
+    };
+
+    use super::{components_to_storage, entity_key, storage_to_components, StorageResource};
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{KeyItemStorage, MutKeyItemStorage, Storage},
+        storage_types::{SparseSetVecStorage, VecStorage},
+    };
+
+    #[derive(Component, Clone, Debug, Default, PartialEq)]
+    struct Position(f32);
+
+    type Positions = VecStorage<usize, Position>;
+
+    #[test]
+    fn component_sync_test()
+    {
+        let mut world = World::new();
+        let a = world.spawn(Position(1.0)).id();
+        let _ = world.spawn_empty().id();
+        let b = world.spawn(Position(2.0)).id();
+
+        let mut storage: SparseSetVecStorage<usize, Position> = SparseSetVecStorage::new();
+        components_to_storage(&mut world, &mut storage);
+
+        assert_eq!(storage.len(), 2);
+        assert_eq!(storage.get(entity_key(b)), Some(&Position(2.0)));
+
+        storage.get_mut(entity_key(a)).unwrap().0 = 10.0;
+        storage_to_components(&storage, &mut world);
+
+        assert_eq!(world.get::<Position>(a), Some(&Position(10.0)));
+        assert_eq!(world.get::<Position>(b), Some(&Position(2.0)));
+    }
+
+    #[test]
+    fn storage_resource_test()
+    {
+        let mut world = World::new();
+        let entity = world.spawn(Position(3.0)).id();
+
+        let handle: StorageHandle<Positions> = builder(Positions::new())
+            .build()
+            .cast_to_sized_storage()
+            .unwrap();
+        world.insert_resource(StorageResource(handle));
+
+        world.resource_scope(|world, resource: Mut<StorageResource<Positions>>| {
+            components_to_storage(world, &mut *resource.try_write().unwrap());
+        });
+
+        let resource = world.resource::<StorageResource<Positions>>();
+        let guard = resource.try_read().unwrap();
+        assert_eq!(guard.get(entity_key(entity)), Some(&Position(3.0)));
+    }
+}
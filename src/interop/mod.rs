@@ -0,0 +1,22 @@
+//! Feature gated conversions between storages and the data types of other crates.
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+
+#[cfg(feature = "parquet")]
+pub mod parquet;
+
+#[cfg(feature = "polars")]
+pub mod polars;
+
+#[cfg(feature = "rkyv")]
+pub mod rkyv;
+
+#[cfg(feature = "ndarray")]
+pub mod ndarray;
+
+#[cfg(feature = "wgpu")]
+pub mod wgpu;
+
+#[cfg(feature = "bevy")]
+pub mod bevy;
@@ -0,0 +1,171 @@
+//! ndarray views over slice backed storages so numeric nodes can use ndarray operations in place.
+//!
+//! [NdArrayStorage] and [NdArrayStorageMut] are implemented for every [ItemSliceStorage] and
+//! [MutItemSliceStorage] including trait objects, so they also work through handle guards:
+//!
+//! ```ignore
+//! let mut guard = handle.try_write()?;
+//! let mut image = guard.as_array_view_mut2((height, width))?;
+//! image *= 0.5;
+//! ```
+//
+// # Internal Design
+//
+// - Storages are flat so the 2D views take their shape from the caller and interpret the items in
+//   row major order.
+// - [VecStorage::from_array] only copies when the array's elements are not already a contiguous row
+//   major Vec.
+
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut1, ArrayViewMut2};
+
+use crate::{
+    storage_traits::{ItemSliceStorage, ItemTrait, KeyTrait, MutItemSliceStorage},
+    storage_types::VecStorage,
+    ErrorKind, SimpleResult, StorageError,
+};
+
+pub trait NdArrayStorage: ItemSliceStorage
+{
+    fn as_array_view(&self) -> ArrayView1<'_, Self::Item>
+    {
+        ArrayView1::from(self.as_item_slice())
+    }
+
+    /// View the items as a row major 2D array with `shape` of (rows, columns)
+    fn as_array_view2(&self, shape: (usize, usize)) -> SimpleResult<ArrayView2<'_, Self::Item>>
+    {
+        ArrayView2::from_shape(shape, self.as_item_slice()).map_err(|error| {
+            StorageError::with_source(
+                ErrorKind::InvalidData,
+                format!("Can't view {} items with shape {shape:?}: {error}", self.len()),
+                error,
+            )
+        })
+    }
+}
+
+impl<S> NdArrayStorage for S where S: ItemSliceStorage + ?Sized {}
+
+pub trait NdArrayStorageMut: MutItemSliceStorage
+{
+    fn as_array_view_mut(&mut self) -> ArrayViewMut1<'_, Self::Item>
+    {
+        ArrayViewMut1::from(self.as_mut_slice())
+    }
+
+    /// Mutable version of [NdArrayStorage::as_array_view2]
+    fn as_array_view_mut2(
+        &mut self,
+        shape: (usize, usize),
+    ) -> SimpleResult<ArrayViewMut2<'_, Self::Item>>
+    {
+        let len = self.len();
+
+        ArrayViewMut2::from_shape(shape, self.as_mut_slice())
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Can't view {len} items with shape {shape:?}: {error}"),
+                    error,
+                )
+            })
+    }
+}
+
+impl<S> NdArrayStorageMut for S where S: MutItemSliceStorage + ?Sized {}
+
+impl<Key, Item> VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Take the elements of `array`, copying only if they are not contiguous
+    pub fn from_array(array: Array1<Item>) -> Self
+    {
+        Self::from_vec(into_row_major_vec(array))
+    }
+
+    /// Take the elements of `array` in row major order, copying only if they are not contiguous
+    /// and in row major order already
+    pub fn from_array2(array: Array2<Item>) -> Self
+    {
+        Self::from_vec(into_row_major_vec(array))
+    }
+}
+
+fn into_row_major_vec<Item, D>(array: ndarray::Array<Item, D>) -> Vec<Item>
+where
+    Item: ItemTrait,
+    D: ndarray::Dimension,
+{
+    let len = array.len();
+
+    let array = if array.is_standard_layout()
+    {
+        array
+    }
+    else
+    {
+        array.as_standard_layout().into_owned()
+    };
+
+    let (mut data, offset) = array.into_raw_vec_and_offset();
+
+    match offset
+    {
+        None | Some(0) =>
+        {
+            data.truncate(len);
+            data
+        }
+        Some(offset) => data.drain(offset..offset + len).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use ndarray::{array, s};
+
+    use super::{NdArrayStorage, NdArrayStorageMut};
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::ItemSliceStorage,
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn array_view_test()
+    {
+        let storage: VecStorage<usize, f32> = VecStorage::from_vec(vec![1.0, 2.0, 3.0, 4.0]);
+        let handle: StorageHandle<VecStorage<usize, f32>> =
+            builder(storage).build().cast_to_sized_storage().unwrap();
+
+        {
+            let mut guard = handle.try_write().unwrap();
+            let mut view = guard.as_array_view_mut2((2, 2)).unwrap();
+            view.row_mut(1).map_inplace(|value| *value *= 10.0);
+        }
+
+        let guard = handle.try_read().unwrap();
+        assert_eq!(guard.as_array_view().sum(), 73.0);
+        assert_eq!(guard.as_array_view2((2, 2)).unwrap()[[1, 0]], 30.0);
+        assert!(guard.as_array_view2((3, 2)).is_err());
+    }
+
+    #[test]
+    fn from_array_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::from_array(array![1, 2, 3]);
+        assert_eq!(storage.as_item_slice(), &[1, 2, 3]);
+
+        // Column major and sliced arrays are copied into row major order
+        let transposed = array![[1, 2], [3, 4]].reversed_axes();
+        let storage: VecStorage<usize, i32> = VecStorage::from_array2(transposed);
+        assert_eq!(storage.as_item_slice(), &[1, 3, 2, 4]);
+
+        let sliced = array![1, 2, 3, 4].slice_move(s![1..3]);
+        let storage: VecStorage<usize, i32> = VecStorage::from_array(sliced);
+        assert_eq!(storage.as_item_slice(), &[2, 3]);
+    }
+}
@@ -0,0 +1,315 @@
+//! Parquet files of slice backed storages for exchanging datasets with data science pipelines.
+//!
+//! Builds on the [arrow](super::arrow) conversions. Index keyed storages such as [VecStorage] map
+//! keys to row indices so several same keyed columns can share one file, while sparse storages
+//! write their keys to a column of their own:
+//!
+//! ```ignore
+//! write_parquet(File::create("particles.parquet")?, [
+//!     ("mass", masses.to_arrow_ref()),
+//!     ("charge", charges.to_arrow_ref()),
+//! ])?;
+//!
+//! let batches = read_parquet(File::open("particles.parquet")?)?;
+//! let masses = parquet_column_into_storage::<usize, f32>(&batches, "mass")?;
+//!
+//! // Sparse storages
+//! selected.write_parquet(File::create("selected.parquet")?, "entity", "weight")?;
+//! let file = File::open("selected.parquet")?;
+//! let selected = SparseSetVecStorage::<usize, f32>::read_parquet(file, "entity", "weight")?;
+//! ```
+//
+// # Internal Design
+//
+// - Key columns are written as UInt64 whatever the key type so that files read the same in tools
+//   that know nothing about the key types of this crate. Keys that don't fit the key type when read
+//   back are an error rather than being wrapped.
+// - A file is read into one record batch per row group and columns are copied out of all of them in
+//   order. Taking buffers over without copying as [VecStorage::try_from_arrow] does only works for
+//   a single batch and the copy is small next to decoding the file.
+
+use std::{io::Write, sync::Arc};
+
+use arrow_array::{Array, ArrayRef, PrimitiveArray, RecordBatch, UInt64Array};
+use ::parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    file::reader::ChunkReader,
+};
+
+use super::arrow::{record_batch, ArrowItem};
+use crate::{
+    storage_traits::{ItemSliceStorage, KeyItemStorage, KeyTrait, MutKeyItemStorage},
+    storage_types::{SparseSetVecStorage, VecStorage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+/// Write named columns of the same length, eg from [VecStorage::to_arrow_ref], as a Parquet file
+pub fn write_parquet<Name>(
+    writer: impl Write + Send,
+    columns: impl IntoIterator<Item = (Name, ArrayRef)>,
+) -> SimpleResult<()>
+where
+    Name: AsRef<str>,
+{
+    let batch = record_batch(columns)?;
+
+    let mut writer = ArrowWriter::try_new(writer, batch.schema(), None)
+        .map_err(|error| {
+            StorageError::with_source(
+                ErrorKind::Io,
+                format!("Failed to create Parquet writer: {error}"),
+                error,
+            )
+        })?;
+
+    writer
+        .write(&batch)
+        .map_err(|error| {
+            StorageError::with_source(
+                ErrorKind::Io,
+                format!("Failed to write Parquet file: {error}"),
+                error,
+            )
+        })?;
+    writer
+        .close()
+        .map_err(|error| {
+            StorageError::with_source(
+                ErrorKind::Io,
+                format!("Failed to write Parquet file: {error}"),
+                error,
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Read every row group of a Parquet file, eg a [std::fs::File], as record batches
+pub fn read_parquet(reader: impl ChunkReader + 'static) -> SimpleResult<Vec<RecordBatch>>
+{
+    ParquetRecordBatchReaderBuilder::try_new(reader)
+        .and_then(|builder| builder.build())
+        .map_err(read_error)?
+        .map(|batch| batch.map_err(read_error))
+        .collect()
+}
+
+fn read_error(error: impl std::error::Error + Send + Sync + 'static) -> StorageError
+{
+    StorageError::with_source(
+        ErrorKind::Io,
+        format!("Failed to read Parquet file: {error}"),
+        error,
+    )
+}
+
+/// Copy the named column of every batch into one storage with a key per row
+pub fn parquet_column_into_storage<Key, Item>(
+    batches: &[RecordBatch],
+    name: &str,
+) -> SimpleResult<VecStorage<Key, Item>>
+where
+    Key: KeyTrait,
+    Item: ArrowItem,
+{
+    Ok(VecStorage::from_vec(column_values::<Item>(batches, name)?))
+}
+
+impl<Key, Item> VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ArrowItem,
+{
+    /// Copy the items into a new type erased Arrow array, eg for [write_parquet]
+    pub fn to_arrow_ref(&self) -> ArrayRef
+    {
+        Arc::new(self.to_arrow())
+    }
+
+    /// Write the items to a Parquet file with a single column. Keys are row indices.
+    pub fn write_parquet(&self, writer: impl Write + Send, column: &str) -> SimpleResult<()>
+    {
+        write_parquet(writer, [(column, self.to_arrow_ref())])
+    }
+
+    /// Read the named column of a Parquet file into a new storage
+    pub fn read_parquet(reader: impl ChunkReader + 'static, column: &str) -> SimpleResult<Self>
+    {
+        parquet_column_into_storage(&read_parquet(reader)?, column)
+    }
+}
+
+impl<Key, Item> SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ArrowItem,
+{
+    /// Write the keys and items to a Parquet file with a key column and an item column
+    pub fn write_parquet(
+        &self,
+        writer: impl Write + Send,
+        key_column: &str,
+        item_column: &str,
+    ) -> SimpleResult<()>
+    {
+        let keys = self
+            .key_item_iter()
+            .map(|(key, _)| {
+                key.try_to_index()
+                    .map(|index| index as u64)
+                    .ok_or_else(|| format!("Key {key:?} can't be written as an index").into())
+            })
+            .collect::<SimpleResult<Vec<u64>>>()?;
+
+        let items = PrimitiveArray::<Item::ArrowType>::from_iter_values(
+            self.as_item_slice().iter().copied(),
+        );
+
+        write_parquet(
+            writer,
+            [
+                (key_column, Arc::new(UInt64Array::from(keys)) as ArrayRef),
+                (item_column, Arc::new(items) as ArrayRef),
+            ],
+        )
+    }
+
+    /// Read the key and item columns of a Parquet file into a new storage
+    pub fn read_parquet(
+        reader: impl ChunkReader + 'static,
+        key_column: &str,
+        item_column: &str,
+    ) -> SimpleResult<Self>
+    {
+        let batches = read_parquet(reader)?;
+        let keys = column_values::<u64>(&batches, key_column)?;
+        let items = column_values::<Item>(&batches, item_column)?;
+
+        let mut storage = Self::new();
+
+        for (key, item) in keys.into_iter().zip(items)
+        {
+            let key = usize::try_from(key)
+                .ok()
+                .and_then(Key::try_from_index)
+                .ok_or_else(|| {
+                    StorageError::new(
+                        ErrorKind::KeyInvalid,
+                        format!(
+                            "Key {key} in column {key_column} doesn't fit {}",
+                            std::any::type_name::<Key>()
+                        ),
+                    )
+                })?;
+
+            storage.insert(key, item);
+        }
+
+        Ok(storage)
+    }
+}
+
+/// Values of the named column of every batch in order. Fails if a batch has no such column, the
+/// column has another type or it has nulls.
+fn column_values<Item>(batches: &[RecordBatch], name: &str) -> SimpleResult<Vec<Item>>
+where
+    Item: ArrowItem,
+{
+    let mut values = Vec::with_capacity(batches.iter().map(RecordBatch::num_rows).sum());
+
+    for batch in batches
+    {
+        let column = batch
+            .column_by_name(name)
+            .ok_or_else(|| {
+                StorageError::new(
+                    ErrorKind::InvalidData,
+                    format!("Parquet file has no column named {name}"),
+                )
+            })?;
+
+        let typed = column
+            .as_any()
+            .downcast_ref::<PrimitiveArray<Item::ArrowType>>()
+            .ok_or_else(|| {
+                StorageError::new(
+                    ErrorKind::TypeMismatch,
+                    format!(
+                        "Parquet column {name} of type {} can't be read as {}",
+                        column.data_type(),
+                        std::any::type_name::<Item>()
+                    ),
+                )
+            })?;
+
+        if typed.null_count() > 0
+        {
+            return Err(StorageError::new(
+                ErrorKind::InvalidData,
+                format!("Parquet column {name} has {} nulls", typed.null_count()),
+            ));
+        }
+
+        values.extend_from_slice(typed.values());
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bytes::Bytes;
+
+    use super::{parquet_column_into_storage, read_parquet, write_parquet};
+    use crate::{
+        storage_traits::{ItemSliceStorage, KeyItemStorage, MutKeyItemStorage},
+        storage_types::{SparseSetVecStorage, VecStorage},
+    };
+
+    #[test]
+    fn parquet_columns_test()
+    {
+        let masses: VecStorage<usize, f32> = VecStorage::from_vec(vec![1.0, 2.0]);
+        let charges: VecStorage<usize, i64> = VecStorage::from_vec(vec![-1, 1]);
+
+        let mut file = Vec::new();
+        write_parquet(
+            &mut file,
+            [
+                ("mass", masses.to_arrow_ref()),
+                ("charge", charges.to_arrow_ref()),
+            ],
+        )
+        .unwrap();
+
+        let batches = read_parquet(Bytes::from(file)).unwrap();
+
+        let charges = parquet_column_into_storage::<usize, i64>(&batches, "charge").unwrap();
+        assert_eq!(charges.as_item_slice(), &[-1, 1]);
+
+        assert!(parquet_column_into_storage::<usize, f64>(&batches, "mass").is_err());
+        assert!(parquet_column_into_storage::<usize, f32>(&batches, "spin").is_err());
+    }
+
+    #[test]
+    fn parquet_sparse_test()
+    {
+        let mut selected: SparseSetVecStorage<usize, f64> = SparseSetVecStorage::new();
+        selected.insert(7, 0.5);
+        selected.insert(2, 0.25);
+
+        let mut file = Vec::new();
+        selected
+            .write_parquet(&mut file, "entity", "weight")
+            .unwrap();
+
+        let selected =
+            SparseSetVecStorage::<usize, f64>::read_parquet(Bytes::from(file), "entity", "weight")
+                .unwrap();
+
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected.get(7), Some(&0.5));
+        assert_eq!(selected.get(2), Some(&0.25));
+    }
+}
@@ -0,0 +1,229 @@
+//! Conversions between same keyed [VecStorage] columns and a polars [DataFrame].
+//!
+//! Row `n` of a data frame corresponds to the key of index `n` in each column storage, so a
+//! boolean mask computed in polars can also be used to select keys for a [KeyItemViewStorage]
+//! without copying any items:
+//!
+//! ```ignore
+//! let frame = dataframe([weights.to_series("weights"), ids.to_series("ids")])?;
+//! let mask = frame.column("weights")?.gt(0.5)?;
+//!
+//! view_handle
+//!     .view_storage_controller_mut()
+//!     .unwrap()
+//!     .create_read_view_from_mask::<usize, f32>(&mask)?;
+//! ```
+//!
+//! [KeyItemViewStorage]: crate::storage_types::KeyItemViewStorage
+//
+// # Internal Design
+//
+// - Item types are mapped to polars types by [PolarsItem] via a macro over the numeric types that
+//   polars stores natively, the same way [KeyTrait] is implemented for key types.
+// - Columns with nulls are rejected as storages don't have a notion of a missing item. Null entries
+//   in a mask are treated as false so that a mask never selects a row it has no value for.
+
+use ::polars::prelude::{BooleanChunked, DataFrame, NamedFrom, PolarsResult, Series};
+
+use crate::{
+    storage_handle::ViewStorageController,
+    storage_traits::{ItemSliceStorage, ItemTrait, KeyTrait},
+    storage_types::{try_index_to_key, VecStorage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+/// Items that polars can hold in a [Series] without conversion
+pub trait PolarsItem: ItemTrait
+{
+    fn from_series(series: &Series) -> PolarsResult<Vec<Self>>;
+}
+
+/// Implements [PolarsItem] for the list of given (item type => series accessor) pairs
+macro_rules! impl_polars_item {
+
+    ( $($item:ty => $accessor:ident),* ) => {
+
+        $( impl PolarsItem for $item
+        {
+            fn from_series(series: &Series) -> PolarsResult<Vec<Self>>
+            {
+                Ok(series.$accessor()?.into_no_null_iter().collect())
+            }
+        } )*
+    };
+}
+
+impl_polars_item!(
+    i32 => i32,
+    i64 => i64,
+    u32 => u32,
+    u64 => u64,
+    f32 => f32,
+    f64 => f64
+);
+
+impl<Key, Item> VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: PolarsItem,
+    Series: NamedFrom<Vec<Item>, [Item]>,
+{
+    /// Copy the items into a new named [Series]
+    pub fn to_series(&self, name: &str) -> Series
+    {
+        Series::new(name.into(), self.as_item_slice().to_vec())
+    }
+
+    /// Copy the items of `series` into a new storage. Fails if the series has nulls or is of a
+    /// different item type.
+    pub fn try_from_series(series: &Series) -> SimpleResult<Self>
+    {
+        if series.null_count() > 0
+        {
+            return Err(StorageError::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Series {} with {} nulls can't be converted into a storage",
+                    series.name(),
+                    series.null_count()
+                ),
+            ));
+        }
+
+        let data = Item::from_series(series).map_err(|error| {
+            StorageError::with_source(ErrorKind::TypeMismatch, error.to_string(), error)
+        })?;
+
+        Ok(Self::from_vec(data))
+    }
+}
+
+/// Build a data frame out of equal length columns, eg from [VecStorage::to_series]
+pub fn dataframe(columns: impl IntoIterator<Item = Series>) -> SimpleResult<DataFrame>
+{
+    DataFrame::new(columns.into_iter().collect()).map_err(|error| {
+        StorageError::with_source(ErrorKind::InvalidData, error.to_string(), error)
+    })
+}
+
+/// Copy the named column of `frame` into a storage, see [VecStorage::try_from_series]
+pub fn column_into_storage<Key, Item>(
+    frame: &DataFrame,
+    name: &str,
+) -> SimpleResult<VecStorage<Key, Item>>
+where
+    Key: KeyTrait,
+    Item: PolarsItem,
+    Series: NamedFrom<Vec<Item>, [Item]>,
+{
+    let column = frame.column(name).map_err(|error| {
+        StorageError::with_source(ErrorKind::InvalidData, error.to_string(), error)
+    })?;
+
+    VecStorage::try_from_series(column)
+}
+
+/// Keys of the rows for which `mask` is true. Fails if a row index doesn't fit in the Key type.
+pub fn mask_keys<Key>(mask: &BooleanChunked) -> SimpleResult<Vec<Key>>
+where
+    Key: KeyTrait,
+{
+    mask.into_iter()
+        .enumerate()
+        .filter(|(_, selected)| *selected == Some(true))
+        .map(|(index, _)| try_index_to_key(index))
+        .collect()
+}
+
+impl ViewStorageController
+{
+    /// Create a read view of the rows for which `mask` is true, see [mask_keys]
+    pub fn create_read_view_from_mask<Key, Item>(
+        &mut self,
+        mask: &BooleanChunked,
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        self.create_read_view::<Key, Item>(mask_keys::<Key>(mask)?)
+    }
+
+    /// Create a write view of the rows for which `mask` is true, see [mask_keys]
+    pub fn create_write_view_from_mask<Key, Item>(
+        &mut self,
+        mask: &BooleanChunked,
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        self.create_write_view::<Key, Item>(mask_keys::<Key>(mask)?)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{any::TypeId, sync::Arc};
+
+    use ::polars::prelude::ChunkCompareIneq;
+
+    use super::{column_into_storage, dataframe};
+    use crate::{
+        storage_handle::StorageHandle,
+        storage_traits::{ItemSliceStorage, KeyItemStorage, Storage},
+        storage_types::{KeyItemViewStorage, VecStorage},
+        Rw,
+    };
+
+    #[test]
+    fn dataframe_round_trip_test()
+    {
+        let ids: VecStorage<usize, u32> = VecStorage::from_vec(vec![10, 11, 12]);
+        let weights: VecStorage<usize, f64> = VecStorage::from_vec(vec![0.5, 0.25, 1.0]);
+
+        let frame = dataframe([ids.to_series("ids"), weights.to_series("weights")]).unwrap();
+        assert_eq!(frame.shape(), (3, 2));
+
+        let weights = column_into_storage::<usize, f64>(&frame, "weights").unwrap();
+        assert_eq!(weights.as_item_slice(), &[0.5, 0.25, 1.0]);
+
+        assert!(column_into_storage::<usize, f32>(&frame, "ids").is_err());
+    }
+
+    type WeightsView = KeyItemViewStorage<VecStorage<usize, f64>, usize, f64>;
+
+    #[test]
+    fn view_from_mask_test()
+    {
+        let weights: VecStorage<usize, f64> = VecStorage::from_vec(vec![0.5, 0.25, 1.0]);
+        let mask = weights.to_series("weights").gt(0.4).unwrap();
+
+        let input = Arc::new(Rw::new(weights));
+        let input: StorageHandle<dyn Storage> = StorageHandle::new(
+            input.clone(),
+            input,
+            TypeId::of::<usize>(),
+            TypeId::of::<f64>(),
+        );
+
+        let view = Arc::new(Rw::new(WeightsView::new()));
+        let mut view: StorageHandle<dyn Storage> = StorageHandle::new_with_view_controller(
+            view.clone(),
+            view,
+            TypeId::of::<usize>(),
+            TypeId::of::<f64>(),
+        );
+
+        let controller = view.view_storage_controller_mut().unwrap();
+        controller.set_input::<usize, f64>(input).unwrap();
+        controller
+            .create_read_view_from_mask::<usize, f64>(&mask)
+            .unwrap();
+
+        let view: StorageHandle<WeightsView> = view.cast_to_sized_storage().unwrap();
+        let guard = view.try_read().unwrap();
+        assert_eq!(guard.item_iter().collect::<Vec<_>>(), vec![&0.5, &1.0]);
+    }
+}
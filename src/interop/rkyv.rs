@@ -0,0 +1,112 @@
+//! Zero copy archives of [VecStorage] via rkyv.
+//!
+//! An archive can be read in place without deserializing it, which makes it a good fit for large
+//! cached intermediate results that are written once and loaded at every startup. The bytes only
+//! need to be suitably aligned, eg from an [AlignedVec] or a memory mapped file:
+//!
+//! ```ignore
+//! std::fs::write(&path, weights.to_archive()?)?;
+//!
+//! // Later, eg at startup
+//! let file = std::fs::File::open(&path)?;
+//! let bytes = unsafe { memmap2::Mmap::map(&file)? };
+//! let archived = VecStorage::<usize, f32>::access_archive(&bytes)?;
+//! let total: f32 = archived.as_item_slice().iter().map(|weight| weight.to_native()).sum();
+//! ```
+//
+// # Internal Design
+//
+// - Archives are validated on access so that a truncated or foreign file is reported as an error
+//   rather than read as garbage. Validation is a linear scan that is still far cheaper than
+//   deserializing.
+// - Read access to the archived storage lives next to [VecStorage] as the archived type has the
+//   same private fields as the storage it was derived from.
+
+use rkyv::{
+    api::high::{HighDeserializer, HighSerializer, HighValidator},
+    bytecheck::CheckBytes,
+    rancor,
+    ser::allocator::ArenaHandle,
+    util::AlignedVec,
+    Archive, Deserialize, Serialize,
+};
+
+use crate::{
+    storage_traits::{ItemTrait, KeyTrait},
+    storage_types::{ArchivedVecStorage, VecStorage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+impl<Key, Item> VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait + Archive,
+{
+    /// Write the storage to a new archive
+    pub fn to_archive(&self) -> SimpleResult<AlignedVec>
+    where
+        Self: for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>,
+    {
+        rkyv::to_bytes::<rancor::Error>(self)
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Failed to archive storage: {error}"),
+                    error,
+                )
+            })
+    }
+
+    /// Validate `bytes` and read the archived storage in place
+    pub fn access_archive(bytes: &[u8]) -> SimpleResult<&ArchivedVecStorage<Key, Item>>
+    where
+        ArchivedVecStorage<Key, Item>: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>,
+    {
+        rkyv::access::<ArchivedVecStorage<Key, Item>, rancor::Error>(bytes)
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Invalid storage archive: {error}"),
+                    error,
+                )
+            })
+    }
+
+    /// Validate `bytes` and deserialize them into a new storage
+    pub fn from_archive(bytes: &[u8]) -> SimpleResult<Self>
+    where
+        ArchivedVecStorage<Key, Item>: for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+            + Deserialize<Self, HighDeserializer<rancor::Error>>,
+    {
+        rkyv::from_bytes::<Self, rancor::Error>(bytes)
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Invalid storage archive: {error}"),
+                    error,
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{storage_traits::ItemSliceStorage, storage_types::VecStorage};
+
+    #[test]
+    fn archive_test()
+    {
+        let storage: VecStorage<usize, u32> = VecStorage::from_vec(vec![1, 2, 3]);
+        let bytes = storage.to_archive().unwrap();
+
+        let archived = VecStorage::<usize, u32>::access_archive(&bytes).unwrap();
+        assert_eq!(archived.len(), 3);
+        assert_eq!(archived.as_item_slice()[2].to_native(), 3);
+
+        let restored = VecStorage::<usize, u32>::from_archive(&bytes).unwrap();
+        assert_eq!(restored.as_item_slice(), &[1, 2, 3]);
+
+        assert!(VecStorage::<usize, u32>::access_archive(&bytes[..2]).is_err());
+    }
+}
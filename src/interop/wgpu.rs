@@ -0,0 +1,147 @@
+//! Uploading the bytes of a storage into a [wgpu::Buffer].
+//!
+//! [GpuUpload] builds on [AsBytesBorrowed] so any storage with a contiguous item layout can be
+//! written straight from a read guard without an intermediate copy:
+//!
+//! ```ignore
+//! let guard = positions.try_read()?;
+//! guard.upload(&queue, &vertex_buffer)?;
+//!
+//! // Or only the items that changed since the last upload
+//! guard.upload_ranges(&queue, &vertex_buffer, [10..12, 40..41])?;
+//! ```
+//
+// # Internal Design
+//
+// - wgpu requires buffer writes to start and end on [wgpu::COPY_BUFFER_ALIGNMENT]. Item ranges are
+//   widened to the surrounding aligned bytes which is always valid to upload as the storage bytes
+//   mirror the buffer contents.
+// - Adjacent or overlapping ranges are merged after widening so that many small dirty ranges don't
+//   turn into as many queue writes.
+
+use std::{mem::size_of, ops::Range};
+
+use crate::{
+    storage_traits::{AsBytesBorrowed, ItemStorage},
+    SimpleResult,
+};
+
+pub trait GpuUpload: AsBytesBorrowed + ItemStorage
+{
+    /// Write every byte of the storage to the start of `buffer`
+    fn upload(&self, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> SimpleResult<()>
+    {
+        self.upload_ranges(queue, buffer, [0..self.len()])
+    }
+
+    /// Write the bytes of the given item ranges to the same offsets in `buffer`
+    fn upload_ranges(
+        &self,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        item_ranges: impl IntoIterator<Item = Range<usize>>,
+    ) -> SimpleResult<()>
+    {
+        let bytes = self.byte_slice();
+
+        if !buffer.usage().contains(wgpu::BufferUsages::COPY_DST)
+        {
+            return Err("Buffer needs COPY_DST usage to be uploaded to".into());
+        }
+
+        if buffer.size() < bytes.len() as u64
+        {
+            return Err(format!(
+                "Buffer of {} bytes is too small for a storage of {} bytes",
+                buffer.size(),
+                bytes.len()
+            )
+            .into());
+        }
+
+        for range in upload_byte_ranges::<Self::Item>(item_ranges, bytes.len())?
+        {
+            queue.write_buffer(buffer, range.start as u64, &bytes[range]);
+        }
+
+        Ok(())
+    }
+}
+
+impl<S> GpuUpload for S where S: AsBytesBorrowed + ItemStorage + ?Sized {}
+
+/// Byte ranges to upload for the given item ranges, aligned to [wgpu::COPY_BUFFER_ALIGNMENT],
+/// sorted and merged
+fn upload_byte_ranges<Item>(
+    item_ranges: impl IntoIterator<Item = Range<usize>>,
+    byte_len: usize,
+) -> SimpleResult<Vec<Range<usize>>>
+{
+    let alignment = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+    let item_size = size_of::<Item>();
+
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+
+    for items in item_ranges
+    {
+        if items.is_empty()
+        {
+            continue;
+        }
+
+        let start = items.start * item_size / alignment * alignment;
+        let end = (items.end * item_size).div_ceil(alignment) * alignment;
+
+        if items.end * item_size > byte_len
+        {
+            return Err(format!(
+                "Item range {items:?} is out of bounds for a storage of {byte_len} bytes"
+            )
+            .into());
+        }
+
+        if end > byte_len
+        {
+            return Err(format!(
+                "Storage of {byte_len} bytes is not a multiple of the {alignment} byte alignment"
+            )
+            .into());
+        }
+
+        ranges.push(start..end);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges
+    {
+        match merged.last_mut()
+        {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::upload_byte_ranges;
+
+    #[test]
+    fn upload_byte_ranges_test()
+    {
+        // 2 byte items so single items have to be widened to 4 byte alignment
+        let ranges = upload_byte_ranges::<u16>([5..6, 0..1, 1..2, 9..10], 20).unwrap();
+        assert_eq!(ranges, vec![0..4, 8..12, 16..20]);
+
+        assert!(upload_byte_ranges::<u16>([0..11], 20).is_err());
+
+        // A 6 byte storage can't be padded to the 4 byte alignment
+        assert!(upload_byte_ranges::<u16>([2..3], 6).is_err());
+        assert_eq!(upload_byte_ranges::<u16>([0..2], 6).unwrap(), vec![0..4]);
+    }
+}
@@ -0,0 +1,274 @@
+//! Item types beyond plain values, for use with any storage through [ItemTrait].
+//!
+//! [ItemTrait] needs [Default] and [Clone] so that storages can fill gaps and copy their items,
+//! which boxed trait objects such as `Box<dyn NodeData>` are neither. [DynItem] holds a trait
+//! object behind an [Arc] instead. It is empty by default and clones by sharing the object, so
+//! payloads of different types can live in one storage:
+//!
+//! ```ignore
+//! trait NodeData: Send + Sync
+//! {
+//!     fn describe(&self) -> String;
+//! }
+//!
+//! let mut payloads = VecStorage::<usize, DynItem<dyn NodeData>>::new();
+//! payloads.insert(0, DynItem::from(Box::new(BlurData { radius: 2.0 }) as Box<dyn NodeData>));
+//! payloads.insert(1, DynItem::from(Arc::new(NoiseData::default()) as Arc<dyn NodeData>));
+//!
+//! for payload in payloads.item_iter().filter_map(DynItem::get)
+//! {
+//!     println!("{}", payload.describe());
+//! }
+//! ```
+//!
+//! `DynItem<dyn Any + Send + Sync>` payloads can be downcast back to their concrete type with
+//! [DynItem::downcast_ref].
+//!
+//! # Shared buffer items
+//!
+//! Reference counted items such as `Arc<str>`, `Arc<[f32]>` or `bytes::Bytes` are items like any
+//! other. Whether an API copies their buffers depends on how it copies items:
+//!
+//! - Cloning a storage, [checkpoints](crate::storage_handle::StorageHandle::checkpoint) and copy on
+//!   write storages clone each item, which shares its buffer.
+//! - Serde, snapshots and write ahead logs encode the contents of each buffer, and loading them
+//!   allocates a buffer per item.
+//! - The byte and GPU paths such as [AsBytesBorrowed] hand out the bytes of the items themselves,
+//!   which would be pointers for these items, so they are only implemented for storages of
+//!   [ZeroCopyItem]s and don't apply.
+//
+// # Internal Design
+//
+// - Clones share the object rather than copying it, as trait objects can't be cloned without a
+//   clone method on the trait. [DynItem::get_mut] only hands out the object while no clone shares
+//   it, and [DynItem::make_mut] copies sized objects on write.
+// - The empty state exists for [Default], which index storages use to fill gaps.
+// - Send and Sync come from the object type, so trait objects need them as supertraits or in the
+//   type, eg `DynItem<dyn NodeData + Send + Sync>`, unless the `local` feature is enabled.
+//
+// [AsBytesBorrowed]: crate::storage_traits::AsBytesBorrowed
+// [ItemTrait]: crate::storage_traits::ItemTrait
+// [ZeroCopyItem]: crate::storage_traits::ZeroCopyItem
+
+use std::{any::Any, fmt, sync::Arc};
+
+/// A shared trait object as a storage item, see the [module docs](self)
+pub struct DynItem<T: ?Sized>(Option<Arc<T>>);
+
+impl<T: ?Sized> DynItem<T>
+{
+    pub fn new(object: Arc<T>) -> Self
+    {
+        Self(Some(object))
+    }
+
+    /// None if the item is empty
+    pub fn get(&self) -> Option<&T>
+    {
+        self.0.as_deref()
+    }
+
+    /// None if the item is empty or its object is shared with a clone
+    pub fn get_mut(&mut self) -> Option<&mut T>
+    {
+        self.0.as_mut().and_then(Arc::get_mut)
+    }
+
+    pub fn arc(&self) -> Option<&Arc<T>>
+    {
+        self.0.as_ref()
+    }
+
+    /// Take the object out, leaving the item empty
+    pub fn take(&mut self) -> Option<Arc<T>>
+    {
+        self.0.take()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.0.is_none()
+    }
+
+    /// Whether both items hold the same object
+    pub fn ptr_eq(&self, other: &Self) -> bool
+    {
+        match (&self.0, &other.0)
+        {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl<T: Clone> DynItem<T>
+{
+    /// The object, copied first if it is shared with a clone. None if the item is empty.
+    pub fn make_mut(&mut self) -> Option<&mut T>
+    {
+        self.0.as_mut().map(Arc::make_mut)
+    }
+}
+
+impl DynItem<dyn Any + Send + Sync>
+{
+    /// None if the item is empty or holds another type
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T>
+    {
+        self.get()?.downcast_ref()
+    }
+}
+
+impl<T: ?Sized> Default for DynItem<T>
+{
+    fn default() -> Self
+    {
+        Self(None)
+    }
+}
+
+/// Shares the object rather than cloning it
+impl<T: ?Sized> Clone for DynItem<T>
+{
+    fn clone(&self) -> Self
+    {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for DynItem<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_tuple("DynItem").field(&self.get()).finish()
+    }
+}
+
+impl<T: ?Sized> From<Arc<T>> for DynItem<T>
+{
+    fn from(object: Arc<T>) -> Self
+    {
+        Self::new(object)
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for DynItem<T>
+{
+    fn from(object: Box<T>) -> Self
+    {
+        Self::new(object.into())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{any::Any, sync::Arc};
+
+    use super::DynItem;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{AsBytesBorrowed, KeyItemStorage, MutKeyItemStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    trait NodeData: Send + Sync
+    {
+        fn describe(&self) -> String;
+    }
+
+    #[derive(Clone)]
+    struct Blur(f32);
+
+    impl NodeData for Blur
+    {
+        fn describe(&self) -> String
+        {
+            format!("blur {}", self.0)
+        }
+    }
+
+    struct Noise;
+
+    impl NodeData for Noise
+    {
+        fn describe(&self) -> String
+        {
+            "noise".into()
+        }
+    }
+
+    #[test]
+    fn trait_object_items_test()
+    {
+        let mut payloads: VecStorage<usize, DynItem<dyn NodeData>> = VecStorage::new();
+        payloads.insert(0, DynItem::from(Box::new(Blur(2.0)) as Box<dyn NodeData>));
+        payloads.insert(1, DynItem::from(Arc::new(Noise) as Arc<dyn NodeData>));
+
+        let descriptions: Vec<String> = payloads
+            .item_iter()
+            .filter_map(DynItem::get)
+            .map(NodeData::describe)
+            .collect();
+        assert_eq!(descriptions, ["blur 2", "noise"]);
+
+        // Gaps are empty items
+        payloads.insert(3, DynItem::from(Arc::new(Noise) as Arc<dyn NodeData>));
+        assert!(payloads.get(2).unwrap().is_empty());
+
+        // Clones share the object, which is then no longer mutable in place
+        let mut copy = payloads.get(0).unwrap().clone();
+        assert!(copy.ptr_eq(payloads.get(0).unwrap()));
+        assert!(copy.get_mut().is_none());
+        assert!(payloads.get_mut(3).unwrap().get_mut().is_some());
+
+        // Casts work as for any other item type
+        let handle: StorageHandle<dyn Storage> = builder(payloads).build();
+        assert!(handle
+            .cast_to_getitem_storage::<usize, DynItem<dyn NodeData>>()
+            .is_ok());
+    }
+
+    #[test]
+    fn sized_and_any_items_test()
+    {
+        let mut blur = DynItem::new(Arc::new(Blur(1.0)));
+        let shared = blur.clone();
+        blur.make_mut().unwrap().0 = 3.0;
+        assert_eq!(shared.get().unwrap().0, 1.0);
+        assert_eq!(blur.get().unwrap().0, 3.0);
+
+        let any: DynItem<dyn Any + Send + Sync> = DynItem::new(Arc::new(5_i32));
+        assert_eq!(any.downcast_ref::<i32>(), Some(&5));
+        assert_eq!(any.downcast_ref::<f32>(), None);
+        assert_eq!(
+            DynItem::<dyn Any + Send + Sync>::default().downcast_ref::<i32>(),
+            None
+        );
+    }
+
+    #[test]
+    fn shared_buffer_items_test()
+    {
+        let names: VecStorage<usize, Arc<str>> =
+            VecStorage::from_vec(vec!["position".into(), "normal".into()]);
+
+        // Checkpoints clone the items, which shares their buffers
+        let handle: StorageHandle<VecStorage<usize, Arc<str>>> = builder(names.clone())
+            .build()
+            .cast_to_sized_storage()
+            .unwrap();
+        let checkpoint = handle.checkpoint().unwrap();
+        handle.try_write().unwrap().insert(2, "uv".into());
+        handle.rollback(checkpoint).unwrap();
+
+        let restored = handle.try_read().unwrap();
+        assert_eq!(restored.len(), 2);
+        assert!(Arc::ptr_eq(restored.get(0).unwrap(), names.get(0).unwrap()));
+
+        // Zero copy items are available as bytes
+        let points: VecStorage<usize, [f32; 2]> = VecStorage::from_vec(vec![[1.0, 2.0]]);
+        assert_eq!(points.byte_slice().len(), 8);
+        assert_eq!(&points.byte_slice()[4..], &2.0_f32.to_ne_bytes());
+    }
+}
@@ -0,0 +1,824 @@
+//! Key types beyond the plain integers, for use with any storage through [KeyTrait].
+//!
+//! [GenKey] is a generational key of the shape used by node and entity systems. Its index part
+//! addresses index based storages, and its generation lets a slot be reused without old keys
+//! reaching the new item. [SparseSetVecStorage] stores whole keys so it checks generations:
+//!
+//! ```ignore
+//! let mut nodes = SparseSetVecStorage::<GenKey, Node>::new();
+//!
+//! let first = GenKey::new(3, 0);
+//! nodes.insert(first, Node::default());
+//! nodes.remove(first);
+//!
+//! // The index is reused by a newer generation
+//! let second = first.next_generation();
+//! nodes.insert(second, Node::default());
+//!
+//! assert!(nodes.get(first).is_none());
+//! assert!(nodes.try_insert(first, Node::default()).is_err());
+//! ```
+//!
+//! [StrKey] keys storages by name, such as the names of ports and parameters in user graphs. Names
+//! are interned into a global [StrInterner] by default, or into one owned by a registry or graph
+//! when its names shouldn't be shared:
+//!
+//! ```ignore
+//! let mut params = HashMapStorage::<StrKey, f32>::new();
+//! params.insert(StrKey::intern("gain"), 0.5);
+//!
+//! assert_eq!(params.get(StrKey::intern("gain")), Some(&0.5));
+//! assert_eq!(&*StrKey::intern("gain").name().unwrap(), "gain");
+//!
+//! let mut interner = StrInterner::new();
+//! let local = interner.intern("gain");
+//! assert_eq!(interner.resolve(local), Some("gain"));
+//! ```
+//!
+//! Downstream crates key storages with their own id types through [crate::impl_key_type], which
+//! implements [KeyTrait] and its index conversions for newtypes of an existing key type. They are
+//! then usable in every storage, view, cast and controller like the built in keys:
+//!
+//! ```ignore
+//! #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+//! struct NodeId(u32);
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+//! struct AssetId(u128);
+//!
+//! impl_key_type!(NodeId(u32), AssetId(u128));
+//!
+//! let positions = builder(VecStorage::<NodeId, Vec3>::new()).build();
+//! let positions = positions.cast_to_getitem_storage::<NodeId, Vec3>()?;
+//! ```
+//!
+//! [NonZeroKey] is an index key without a zero, so that an `Option<NonZeroKey>` is no larger than
+//! the key itself wherever optional keys are kept. Its first value addresses index 0, and the same
+//! goes for [std::num::NonZeroUsize] which keys storages directly:
+//!
+//! ```ignore
+//! let mut parents = VecStorage::<NodeId, Option<NonZeroKey>>::new();
+//! parents.insert(child, NonZeroKey::new(1));
+//!
+//! assert_eq!(size_of::<Option<NonZeroKey>>(), size_of::<u32>());
+//! ```
+//!
+//! [ScopedKey] pairs a key with the namespace of its owner, such as the id of the graph node that
+//! wrote it, so that several owners can share one storage without their keys colliding. Storages
+//! and views of scoped keys get [ScopedStorage] helpers to work with one namespace at a time:
+//!
+//! ```ignore
+//! let mut outputs = HashMapStorage::<ScopedKey<NodeId, u32>, f32>::new();
+//! outputs.insert(ScopedKey::new(blur_node, 0), 0.5);
+//! outputs.insert(ScopedKey::new(sharpen_node, 0), 1.5);
+//!
+//! let blurred: Vec<(u32, &f32)> = outputs.scope_iter(blur_node).collect();
+//!
+//! // A view of one node's outputs
+//! let keys = outputs.scope_keys(blur_node).collect();
+//! controller.create_read_view::<ScopedKey<NodeId, u32>, f32>(keys)?;
+//!
+//! // Deleting a node
+//! outputs.remove_scope(sharpen_node);
+//! ```
+//!
+//! With the `uuid` feature, [UuidKey] keys map storages such as
+//! [HashMapStorage](crate::storage_types::HashMapStorage) with globally unique asset ids. It isn't
+//! an index, so index based storages reject it like any other 128 bit key:
+//!
+//! ```ignore
+//! let mut assets = HashMapStorage::<UuidKey, Mesh>::new();
+//! assets.insert(UuidKey::from(asset_id), mesh);
+//! ```
+//
+// # Internal Design
+//
+// - Storages that only keep items by index, such as [VecStorage], can't tell generations apart. A
+//   [GenKey] addresses them by its index alone.
+// - Converting an index back into a [GenKey], as index storages do when they list their keys, gives
+//   generation 0.
+// - The derived ordering is by index and then generation, so sorting a sparse set by key restores
+//   index order.
+// - A [StrKey] is the id of its name in an interner, which keeps it Copy and makes comparing and
+//   hashing it as cheap as for a u32. Ids are handed out from 0 in interning order, so they are
+//   also indices and index storages can be keyed by name. Keys order by id rather than by name.
+// - A key doesn't know which interner it came from. Keys of different interners compare equal when
+//   their ids do, so a storage should only be keyed from one interner.
+// - Interned names are never freed, as keys may still be stored anywhere. Names are shared as
+//   Arc<str> so that the global interner can hand them out past its lock.
+// - A [NonZeroKey] converts to the index one below its value, so that index storages are dense from
+//   0 as with other keys. NonZeroUsize maps the same way through [KeyTrait::try_to_index] as its
+//   std conversions keep its value and can't be replaced. Sparse sets index by the std conversion,
+//   which only leaves slot 0 of their sparse array unused.
+// - NonZeroU32 has no std conversion from usize, which the orphan rule doesn't allow to be added
+//   in this crate, so it can't be a [KeyTrait] and is wrapped in [NonZeroKey] instead.
+// - Casts, views and controllers are generic over [KeyTrait] and compare key TypeIds, so user keys
+//   need nothing beyond the trait. [crate::impl_key_type] only saves writing the conversions, and
+//   keys that convert differently, like [GenKey], implement the trait by hand.
+// - The namespace of a [ScopedKey] is a value rather than a const parameter, so that keys of all
+//   namespaces are of one type and can share a storage. Scoped keys aren't indices and only key map
+//   storages. The [ScopedStorage] helpers scan every key, as map storages aren't grouped by
+//   namespace.
+// - [KeyTrait] needs conversions between the key and usize, which the orphan rule doesn't allow to
+//   be implemented for uuid::Uuid in this crate, so uuids are wrapped in [UuidKey].
+//
+// [SparseSetVecStorage]: crate::storage_types::SparseSetVecStorage
+// [VecStorage]: crate::storage_types::VecStorage
+
+use std::{
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroUsize, TryFromIntError},
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use crate::{
+    storage_traits::{KeyItemStorage, KeyTrait, RemovableStorage},
+    ErrorKind, StorageError,
+};
+
+/// A key of an index and the generation of the item at that index, see the [module docs](self)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GenKey
+{
+    pub index: u32,
+    pub generation: u32,
+}
+
+impl GenKey
+{
+    pub fn new(index: u32, generation: u32) -> Self
+    {
+        Self { index, generation }
+    }
+
+    /// The key of the next item to reuse this key's index. Wraps after [u32::MAX] generations.
+    pub fn next_generation(self) -> Self
+    {
+        Self {
+            generation: self.generation.wrapping_add(1),
+            ..self
+        }
+    }
+}
+
+impl KeyTrait for GenKey
+{
+    fn supports_index() -> bool
+    {
+        u32::supports_index()
+    }
+
+    fn generation(&self) -> u32
+    {
+        self.generation
+    }
+}
+
+impl TryFrom<GenKey> for usize
+{
+    type Error = TryFromIntError;
+
+    fn try_from(key: GenKey) -> Result<Self, Self::Error>
+    {
+        usize::try_from(key.index)
+    }
+}
+
+/// The key of generation 0 at the index
+impl TryFrom<usize> for GenKey
+{
+    type Error = TryFromIntError;
+
+    fn try_from(index: usize) -> Result<Self, Self::Error>
+    {
+        Ok(Self::new(u32::try_from(index)?, 0))
+    }
+}
+
+/// An interned name as a key, see the [module docs](self)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StrKey(u32);
+
+static GLOBAL_INTERNER: LazyLock<RwLock<StrInterner>> = LazyLock::new(<_>::default);
+
+impl StrKey
+{
+    /// The key of `name` in the global interner, interning it if it is new
+    pub fn intern(name: &str) -> Self
+    {
+        if let Some(key) = Self::lookup(name)
+        {
+            return key;
+        }
+
+        GLOBAL_INTERNER
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .intern(name)
+    }
+
+    /// The key of `name` in the global interner if it was interned before
+    pub fn lookup(name: &str) -> Option<Self>
+    {
+        GLOBAL_INTERNER
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+    }
+
+    /// The name of this key in the global interner. None for keys of other interners with ids that
+    /// the global interner hasn't handed out.
+    pub fn name(self) -> Option<Arc<str>>
+    {
+        GLOBAL_INTERNER
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .names
+            .get(self.0 as usize)
+            .cloned()
+    }
+
+    pub fn id(self) -> u32
+    {
+        self.0
+    }
+}
+
+impl KeyTrait for StrKey
+{
+    fn supports_index() -> bool
+    {
+        u32::supports_index()
+    }
+}
+
+impl TryFrom<StrKey> for usize
+{
+    type Error = TryFromIntError;
+
+    fn try_from(key: StrKey) -> Result<Self, Self::Error>
+    {
+        usize::try_from(key.0)
+    }
+}
+
+/// The key of the id `index`, whether or not a name was interned for it
+impl TryFrom<usize> for StrKey
+{
+    type Error = TryFromIntError;
+
+    fn try_from(index: usize) -> Result<Self, Self::Error>
+    {
+        Ok(Self(u32::try_from(index)?))
+    }
+}
+
+/// Hands out a [StrKey] per distinct name, see the [module docs](self)
+#[derive(Debug, Default)]
+pub struct StrInterner
+{
+    names: Vec<Arc<str>>,
+    ids: HashMap<Arc<str>, StrKey>,
+}
+
+impl StrInterner
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// The key of `name`, interning it if it is new. Panics if more than [u32::MAX] names are
+    /// interned.
+    pub fn intern(&mut self, name: &str) -> StrKey
+    {
+        if let Some(key) = self.get(name)
+        {
+            return key;
+        }
+
+        let key = StrKey(u32::try_from(self.names.len()).expect("Too many names interned"));
+        let name: Arc<str> = name.into();
+
+        self.names.push(name.clone());
+        self.ids.insert(name, key);
+
+        key
+    }
+
+    /// The key of `name` if it was interned before
+    pub fn get(&self, name: &str) -> Option<StrKey>
+    {
+        self.ids.get(name).copied()
+    }
+
+    pub fn resolve(&self, key: StrKey) -> Option<&str>
+    {
+        self.names.get(key.0 as usize).map(|name| &**name)
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.names.is_empty()
+    }
+}
+
+/// Implement [KeyTrait] for newtypes of existing key types, see the [module docs](self). The
+/// newtypes must derive Clone, Copy, Debug, PartialEq, Eq and Hash, and also PartialOrd and Ord to
+/// be a [crate::storage_traits::SortableKey]. They are index keys when the wrapped type is.
+///
+/// ```ignore
+/// impl_key_type!(NodeId(u32), AssetId(u128));
+/// ```
+#[macro_export]
+macro_rules! impl_key_type {
+    ($($key:ident($inner:ty)),* $(,)?) => {
+        $(
+            impl $crate::storage_traits::KeyTrait for $key
+            {
+                fn supports_index() -> bool
+                {
+                    <$inner as $crate::storage_traits::KeyTrait>::supports_index()
+                }
+            }
+
+            // Infallible for wrapped types that are no wider than usize
+            #[allow(clippy::infallible_try_from)]
+            impl ::std::convert::TryFrom<$key> for usize
+            {
+                type Error = <usize as ::std::convert::TryFrom<$inner>>::Error;
+
+                fn try_from(key: $key) -> ::std::result::Result<Self, Self::Error>
+                {
+                    <usize as ::std::convert::TryFrom<$inner>>::try_from(key.0)
+                }
+            }
+
+            impl ::std::convert::TryFrom<usize> for $key
+            {
+                type Error = <$inner as ::std::convert::TryFrom<usize>>::Error;
+
+                fn try_from(index: usize) -> ::std::result::Result<Self, Self::Error>
+                {
+                    <$inner as ::std::convert::TryFrom<usize>>::try_from(index).map($key)
+                }
+            }
+        )*
+    };
+}
+
+/// An index key with a niche for `Option`, see the [module docs](self)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NonZeroKey(pub NonZeroU32);
+
+impl NonZeroKey
+{
+    /// None if `value` is 0
+    pub fn new(value: u32) -> Option<Self>
+    {
+        NonZeroU32::new(value).map(Self)
+    }
+
+    pub fn get(self) -> u32
+    {
+        self.0.get()
+    }
+}
+
+impl KeyTrait for NonZeroKey
+{
+    fn supports_index() -> bool
+    {
+        u32::supports_index()
+    }
+}
+
+/// The index one below the key's value
+impl TryFrom<NonZeroKey> for usize
+{
+    type Error = TryFromIntError;
+
+    fn try_from(key: NonZeroKey) -> Result<Self, Self::Error>
+    {
+        usize::try_from(key.get() - 1)
+    }
+}
+
+/// The key one above the index. Fails for [u32::MAX] and above.
+impl TryFrom<usize> for NonZeroKey
+{
+    type Error = TryFromIntError;
+
+    fn try_from(index: usize) -> Result<Self, Self::Error>
+    {
+        let value = u32::try_from(index)?.checked_add(1);
+
+        // Overflow is reported through a failed conversion as TryFromIntError can't be built
+        NonZeroU32::try_from(value.unwrap_or(0)).map(Self)
+    }
+}
+
+/// Addresses the index one below its value like [NonZeroKey]
+impl KeyTrait for NonZeroUsize
+{
+    fn supports_index() -> bool
+    {
+        true
+    }
+
+    fn try_to_index(self) -> Option<usize>
+    {
+        Some(self.get() - 1)
+    }
+
+    fn try_from_index(index: usize) -> Option<Self>
+    {
+        NonZeroUsize::new(index.checked_add(1)?)
+    }
+}
+
+/// A key in the namespace of its owner, see the [module docs](self). Orders by namespace first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopedKey<NS, K>
+{
+    pub namespace: NS,
+    pub key: K,
+}
+
+impl<NS, K> ScopedKey<NS, K>
+{
+    pub fn new(namespace: NS, key: K) -> Self
+    {
+        Self { namespace, key }
+    }
+}
+
+impl<NS: KeyTrait, K: KeyTrait> KeyTrait for ScopedKey<NS, K>
+{
+    fn supports_index() -> bool
+    {
+        false
+    }
+
+    fn generation(&self) -> u32
+    {
+        self.key.generation()
+    }
+}
+
+/// Always fails as scoped keys aren't indices
+impl<NS, K> TryFrom<ScopedKey<NS, K>> for usize
+{
+    type Error = StorageError;
+
+    fn try_from(_: ScopedKey<NS, K>) -> Result<Self, Self::Error>
+    {
+        Err(scoped_index_error())
+    }
+}
+
+/// Always fails as scoped keys aren't indices
+impl<NS, K> TryFrom<usize> for ScopedKey<NS, K>
+{
+    type Error = StorageError;
+
+    fn try_from(_: usize) -> Result<Self, Self::Error>
+    {
+        Err(scoped_index_error())
+    }
+}
+
+fn scoped_index_error() -> StorageError
+{
+    StorageError::new(
+        ErrorKind::KeyInvalid,
+        "A ScopedKey can't be used as an index",
+    )
+}
+
+/// Work with the keys and items of one namespace of a storage or view keyed by [ScopedKey], see
+/// the [module docs](self)
+pub trait ScopedStorage<NS, K>: KeyItemStorage<Key = ScopedKey<NS, K>>
+where
+    NS: KeyTrait,
+    K: KeyTrait,
+{
+    /// The whole scoped keys in `namespace`, eg for creating a view of the namespace
+    fn scope_keys(&self, namespace: NS) -> impl Iterator<Item = ScopedKey<NS, K>> + '_
+    {
+        self.keys_iter()
+            .filter(move |key| key.namespace == namespace)
+    }
+
+    /// The keys within `namespace` along with their items
+    fn scope_iter(&self, namespace: NS) -> impl Iterator<Item = (K, &Self::Item)> + '_
+    {
+        self.key_item_iter()
+            .filter(move |(key, _)| key.namespace == namespace)
+            .map(|(key, item)| (key.key, item))
+    }
+
+    fn scope_len(&self, namespace: NS) -> usize
+    {
+        self.scope_keys(namespace).count()
+    }
+
+    /// Remove every item in `namespace`, eg when its owner is deleted. Returns how many were
+    /// removed.
+    fn remove_scope(&mut self, namespace: NS) -> usize
+    where
+        Self: RemovableStorage,
+    {
+        let keys: Vec<_> = self.scope_keys(namespace).collect();
+
+        keys.into_iter()
+            .filter(|key| self.remove(*key).is_some())
+            .count()
+    }
+}
+
+impl<S, NS, K> ScopedStorage<NS, K> for S
+where
+    S: KeyItemStorage<Key = ScopedKey<NS, K>> + ?Sized,
+    NS: KeyTrait,
+    K: KeyTrait,
+{
+}
+
+/// A [uuid::Uuid] as a non index key for map storages, see the [module docs](self)
+#[cfg(feature = "uuid")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UuidKey(pub uuid::Uuid);
+
+#[cfg(feature = "uuid")]
+impl KeyTrait for UuidKey
+{
+    fn supports_index() -> bool
+    {
+        false
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for UuidKey
+{
+    fn from(uuid: uuid::Uuid) -> Self
+    {
+        Self(uuid)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<UuidKey> for uuid::Uuid
+{
+    fn from(key: UuidKey) -> Self
+    {
+        key.0
+    }
+}
+
+/// Only uuids of values that fit in a usize convert, as for [u128] keys
+#[cfg(feature = "uuid")]
+impl TryFrom<UuidKey> for usize
+{
+    type Error = TryFromIntError;
+
+    fn try_from(key: UuidKey) -> Result<Self, Self::Error>
+    {
+        usize::try_from(key.0.as_u128())
+    }
+}
+
+/// Every index is a valid uuid value, which gives [TryFrom] as well
+#[cfg(feature = "uuid")]
+impl From<usize> for UuidKey
+{
+    fn from(index: usize) -> Self
+    {
+        Self(uuid::Uuid::from_u128(index as u128))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::num::NonZeroUsize;
+
+    use super::{GenKey, NonZeroKey, ScopedKey, ScopedStorage, StrInterner, StrKey};
+    use crate::storage_handle::{builder, StorageHandle};
+    use crate::{
+        storage_traits::{
+            ItemSliceStorage, KeyItemStorage, KeyStorage, KeyTrait, MutKeyItemStorage,
+            RemovableStorage, Storage,
+        },
+        storage_types::{HashMapStorage, SparseSetVecStorage, VecStorage},
+        ErrorKind,
+    };
+
+    #[test]
+    fn sparse_generation_test()
+    {
+        let mut storage: SparseSetVecStorage<GenKey, &str> = SparseSetVecStorage::new();
+
+        let first = GenKey::new(3, 0);
+        storage.insert(first, "first");
+        storage.insert(GenKey::new(5, 0), "other");
+
+        // A newer generation replaces the item and the stored key
+        let second = first.next_generation();
+        storage.try_insert(second, "second").unwrap();
+        assert_eq!(storage.get(second), Some(&"second"));
+        assert_eq!(storage.get(first), None);
+        assert!(!storage.contains(first));
+        assert_eq!(storage.len(), 2);
+
+        // Stale keys can't write or remove the newer item
+        let error = storage.try_insert(first, "stale").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::KeyInvalid);
+        assert!(storage.get_mut(first).is_none());
+        assert_eq!(storage.remove(first), None);
+        assert_eq!(storage.remove(second), Some("second"));
+        assert!(storage.keys_iter().eq([GenKey::new(5, 0)]));
+    }
+
+    #[test]
+    fn index_storage_test()
+    {
+        // Index storages only see the index part
+        let mut storage: VecStorage<GenKey, i32> = VecStorage::try_new().unwrap();
+        storage.try_insert(GenKey::new(0, 4), 10).unwrap();
+        assert_eq!(storage.get(GenKey::new(0, 0)), Some(&10));
+        assert!(storage.keys_iter().eq([GenKey::new(0, 0)]));
+    }
+
+    #[test]
+    fn str_key_test()
+    {
+        let gain = StrKey::intern("str_key_test.gain");
+        assert_eq!(StrKey::intern("str_key_test.gain"), gain);
+        assert_eq!(StrKey::lookup("str_key_test.gain"), Some(gain));
+        assert_eq!(StrKey::lookup("str_key_test.missing"), None);
+        assert_eq!(&*gain.name().unwrap(), "str_key_test.gain");
+
+        let mut params: HashMapStorage<StrKey, f32> = HashMapStorage::new();
+        params.insert(gain, 0.5);
+        params.insert(StrKey::intern("str_key_test.bias"), 1.0);
+        assert_eq!(params.get(StrKey::intern("str_key_test.gain")), Some(&0.5));
+
+        // Interners hand out dense ids from 0 that index storages can use
+        let mut interner = StrInterner::new();
+        let names = ["x", "y", "x", "z"].map(|name| interner.intern(name));
+        assert_eq!(names.map(StrKey::id), [0, 1, 0, 2]);
+        assert_eq!(interner.len(), 3);
+        assert_eq!(interner.resolve(names[3]), Some("z"));
+        assert_eq!(interner.get("w"), None);
+
+        let mut columns: VecStorage<StrKey, i32> = VecStorage::try_new().unwrap();
+        columns.try_insert(names[0], 10).unwrap();
+        columns.try_insert(names[1], 20).unwrap();
+        assert_eq!(columns.get(interner.intern("y")), Some(&20));
+        assert!(columns.keys_iter().eq([names[0], names[1]]));
+    }
+
+    #[test]
+    fn non_zero_key_test()
+    {
+        assert_eq!(size_of::<Option<NonZeroKey>>(), size_of::<u32>());
+        assert_eq!(size_of::<Option<NonZeroUsize>>(), size_of::<usize>());
+
+        let [first, second] = [1, 2].map(|value| NonZeroKey::new(value).unwrap());
+        assert_eq!(NonZeroKey::new(0), None);
+        assert_eq!(usize::try_from(first), Ok(0));
+        assert_eq!(NonZeroKey::try_from(1), Ok(second));
+        assert!(NonZeroKey::try_from(u32::MAX as usize).is_err());
+
+        let mut storage: VecStorage<NonZeroKey, &str> = VecStorage::try_new().unwrap();
+        storage.try_insert(first, "first").unwrap();
+        storage.try_insert(second, "second").unwrap();
+        assert_eq!(storage.get(second), Some(&"second"));
+        assert!(storage.keys_iter().eq([first, second]));
+
+        let mut sparse: SparseSetVecStorage<NonZeroKey, &str> = SparseSetVecStorage::new();
+        sparse.insert(second, "second");
+        assert!(sparse.contains(second) && !sparse.contains(first));
+
+        // NonZeroUsize keys index the same way
+        let [first, second] = [1, 2].map(|value| NonZeroUsize::new(value).unwrap());
+        assert_eq!(first.try_to_index(), Some(0));
+        assert_eq!(NonZeroUsize::try_from_index(1), Some(second));
+        assert_eq!(NonZeroUsize::try_from_index(usize::MAX), None);
+
+        let mut storage: VecStorage<NonZeroUsize, &str> = VecStorage::try_new().unwrap();
+        storage.try_insert(first, "first").unwrap();
+        storage.try_insert(second, "second").unwrap();
+        assert_eq!(storage.as_item_slice(), &["first", "second"]);
+        assert!(storage.keys_iter().eq([first, second]));
+
+        let mut sparse: SparseSetVecStorage<NonZeroUsize, &str> = SparseSetVecStorage::new();
+        sparse.insert(second, "second");
+        assert!(sparse.contains(second) && !sparse.contains(first));
+
+        let mut map: HashMapStorage<NonZeroUsize, &str> = HashMapStorage::new();
+        map.insert(second, "second");
+        assert_eq!(map.get(second), Some(&"second"));
+    }
+
+    #[test]
+    fn unordered_key_test()
+    {
+        // Keys with only Eq and Hash
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        struct Tag(u16);
+
+        crate::impl_key_type!(Tag(u16));
+
+        let mut tags: HashMapStorage<Tag, &str> = HashMapStorage::new();
+        tags.insert(Tag(4), "four");
+
+        let handle: StorageHandle<dyn Storage> = builder(tags).build();
+        let tags = handle.cast_to_getitem_storage::<Tag, &str>().unwrap();
+        assert_eq!(tags.try_read().unwrap().get(Tag(4)), Some(&"four"));
+
+        let mut sparse: SparseSetVecStorage<Tag, i32> = SparseSetVecStorage::new();
+        sparse.insert(Tag(2), 20);
+        assert_eq!(sparse.get(Tag(2)), Some(&20));
+    }
+
+    #[test]
+    fn scoped_key_test()
+    {
+        use crate::{storage_handle::ViewStorageController, storage_types::KeyItemViewStorage};
+
+        type Key = ScopedKey<u16, u32>;
+
+        let mut outputs: HashMapStorage<Key, f32> = HashMapStorage::new();
+        outputs.insert(ScopedKey::new(1, 0), 0.5);
+        outputs.insert(ScopedKey::new(1, 1), 1.0);
+        outputs.insert(ScopedKey::new(2, 0), 1.5);
+
+        // The same raw key in two namespaces
+        assert_eq!(outputs.get(ScopedKey::new(2, 0)), Some(&1.5));
+        assert_eq!(outputs.scope_len(1), 2);
+
+        let mut first: Vec<(u32, f32)> = outputs
+            .scope_iter(1)
+            .map(|(key, item)| (key, *item))
+            .collect();
+        first.sort_by_key(|(key, _)| *key);
+        assert_eq!(first, [(0, 0.5), (1, 1.0)]);
+
+        // A view of one namespace
+        let mut keys: Vec<Key> = outputs.scope_keys(2).collect();
+        keys.sort();
+
+        let input: StorageHandle<dyn Storage> = builder(outputs.clone()).build();
+        let mut view_builder =
+            builder(KeyItemViewStorage::<HashMapStorage<Key, f32>, Key, f32>::new());
+        view_builder.add_view_controller();
+        let mut view = view_builder.build();
+
+        let controller: &mut ViewStorageController = view.view_storage_controller_mut().unwrap();
+        controller.set_input::<Key, f32>(input).unwrap();
+        controller.create_read_view::<Key, f32>(keys).unwrap();
+        assert_eq!(view.try_read().unwrap().len(), 1);
+
+        assert_eq!(outputs.remove_scope(1), 2);
+        assert!(outputs.keys_iter().eq([ScopedKey::new(2, 0)]));
+        assert!(VecStorage::<Key, f32>::try_new().is_err());
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn uuid_key_test()
+    {
+        use super::UuidKey;
+
+        let key = UuidKey::from(uuid::Uuid::from_u128(u128::MAX - 7));
+
+        let mut storage: HashMapStorage<UuidKey, f32> = HashMapStorage::new();
+        storage.insert(key, 1.5);
+        let handle: StorageHandle<dyn Storage> = builder(storage).build();
+
+        // Casts compare type ids so keys that aren't indices go through the same tables
+        let items = handle
+            .clone()
+            .cast_to_getitem_storage::<UuidKey, f32>()
+            .unwrap();
+        assert_eq!(items.try_read().unwrap().get(key), Some(&1.5));
+        assert!(handle.clone().cast_to_key_storage::<UuidKey, f32>().is_ok());
+
+        let error = handle.cast_to_slice_storage::<UuidKey, f32>().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TypeMismatch);
+
+        // Index storages reject the key type instead of truncating it
+        assert!(SparseSetVecStorage::<UuidKey, f32>::try_new().is_err());
+        assert!(usize::try_from(key).is_err());
+    }
+}
@@ -21,6 +21,20 @@
 //!   graph based data processing.
 //! * Primary use case is multithreaded so all storage types and handles are Send + Sync and use
 //!   Arc<RwLock<StorageType>> internally within StorageHandles
+//! * The `local` feature drops the Send + Sync requirements for single threaded targets such as
+//!   wasm32-unknown-unknown so that keys and items like [std::rc::Rc] can be stored
+//! * The `no_panic` feature turns the remaining panics in library code paths into returned errors,
+//!   or into skipped operations for infallible methods, for hosts that can't tolerate a panic
+//! * The `simd` feature adds SIMD sums, minimums, maximums, dot products and scaling of slice
+//!   storages of f32, f64 and i32 items, see [reduce]
+//! * The `uuid` feature adds [keys::UuidKey] for keying map storages with [uuid::Uuid] asset ids
+//! * The `interior_lock` feature adds storages with their lock inside that are cast with safe code
+//!   only, for consumers that must avoid the unsafe cast path of handles, see [interior]
+//! * The `parking_lot` feature holds storages in parking_lot RwLocks, which aren't poisoned by
+//!   panics and behave better under contention, and lets guards be mapped to part of a storage, see
+//!   [sync::StorageLock]. [Rw] keeps the same API either way
+//! * The `tokio` feature adds [storage_handle::AsyncStorageHandle], which holds storages in tokio
+//!   RwLocks so that async tasks wait for them without blocking their executor
 
 // ----------------------------------------------------------------------------------------------
 //
@@ -59,29 +73,65 @@
 
 #![allow(dead_code)]
 #![feature(ptr_metadata)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
 // -------------------------------------------------------
 
+#[cfg(feature = "autosave")]
+pub mod autosave;
 pub mod casting;
+pub mod change_set;
+pub mod cursor;
+pub mod diagnostics;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod graph;
+#[cfg(all(feature = "interior_lock", not(feature = "local")))]
+pub mod interior;
+pub mod interop;
+pub mod items;
+pub mod keys;
+pub mod loaders;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod query;
+#[cfg(feature = "simd")]
+pub mod reduce;
+pub mod registry;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
 pub mod storage_handle;
 pub mod storage_traits;
 pub mod storage_types;
+pub mod sync;
+#[cfg(feature = "wal")]
+pub mod wal;
+pub mod world;
 
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
+
+use sync::StorageLock;
 
 // ------------------------
 // Type Aliases
 // ------------------------
 
-pub type Rw<T> = RwLock<T>;
+/// The lock storages are held in, a std or parking_lot RwLock, see [sync::StorageLock]
+pub type Rw<T> = StorageLock<T>;
 
 /// Arc Read Write lock pointer
-pub type Arw<T> = Arc<RwLock<T>>;
+pub type Arw<T> = Arc<StorageLock<T>>;
 
 /// Optional Arc Read Write lock pointer
-pub type OArw<T> = Option<Arc<RwLock<T>>>;
+pub type OArw<T> = Option<Arc<StorageLock<T>>>;
+
+/// Arc tokio Read Write lock pointer, which [storage_handle::AsyncStorageHandle] holds storages in
+#[cfg(feature = "tokio")]
+pub type AsyncArw<T> = Arc<tokio::sync::RwLock<T>>;
 
 // -------------------------
 
-// TODO: #LOW Consider replacing this with anyhow, etc
-pub type SimpleResult<T> = Result<T, String>;
+pub use error::{ErrorDetail, ErrorKind, Hint, StorageError};
+
+pub type SimpleResult<T> = Result<T, StorageError>;
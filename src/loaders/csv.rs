@@ -0,0 +1,372 @@
+//! Streams CSV data into one [VecStorage] per column.
+//!
+//! Column types are either given up front with a [CsvSchema] or inferred from the first rows of
+//! the data:
+//!
+//! ```ignore
+//! let mut loader = CsvLoader::new();
+//! loader.infer_rows(100);
+//!
+//! for (name, column) in loader.load_path("points.csv")?
+//! {
+//!     let handle: StorageHandle<dyn Storage> = column.into_handle(&name);
+//!     // ...
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - Rows are parsed straight into the column storages as they are read so only the rows used for
+//   inference are ever buffered.
+// - Inference picks the narrowest of [ColumnType::I64], [ColumnType::F64], [ColumnType::Bool] and
+//   [ColumnType::String] that every inference row parses as. Storages have no notion of a missing
+//   item so a column with empty values is only inferred as a string column.
+
+use std::{fs::File, io::Read, path::Path};
+
+use ::csv::{ReaderBuilder, StringRecord};
+
+use crate::{
+    storage_handle::{builder, StorageHandle},
+    storage_traits::Storage,
+    storage_types::VecStorage,
+    ErrorKind, SimpleResult, StorageError,
+};
+
+/// Rows used for inference by default
+pub const DEFAULT_INFER_ROWS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType
+{
+    I64,
+    F64,
+    Bool,
+    String,
+}
+
+impl ColumnType
+{
+    fn parses(self, value: &str) -> bool
+    {
+        match self
+        {
+            ColumnType::I64 => value.parse::<i64>().is_ok(),
+            ColumnType::F64 => value.parse::<f64>().is_ok(),
+            ColumnType::Bool => value.parse::<bool>().is_ok(),
+            ColumnType::String => true,
+        }
+    }
+
+    fn infer<'a>(values: impl Iterator<Item = &'a str> + Clone) -> Self
+    {
+        [ColumnType::I64, ColumnType::F64, ColumnType::Bool]
+            .into_iter()
+            .find(|column_type| values.clone().all(|value| column_type.parses(value)))
+            .unwrap_or(ColumnType::String)
+    }
+}
+
+/// Names and types of the columns to load. Columns of the data that are not in the schema are
+/// skipped.
+#[derive(Clone, Debug, Default)]
+pub struct CsvSchema
+{
+    columns: Vec<(String, ColumnType)>,
+}
+
+impl CsvSchema
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn column(&mut self, name: impl Into<String>, column_type: ColumnType) -> &mut Self
+    {
+        self.columns.push((name.into(), column_type));
+
+        self
+    }
+}
+
+/// A loaded column
+#[derive(Clone, Debug)]
+pub enum CsvColumn
+{
+    I64(VecStorage<usize, i64>),
+    F64(VecStorage<usize, f64>),
+    Bool(VecStorage<usize, bool>),
+    String(VecStorage<usize, String>),
+}
+
+impl CsvColumn
+{
+    fn new(column_type: ColumnType) -> Self
+    {
+        match column_type
+        {
+            ColumnType::I64 => CsvColumn::I64(VecStorage::new()),
+            ColumnType::F64 => CsvColumn::F64(VecStorage::new()),
+            ColumnType::Bool => CsvColumn::Bool(VecStorage::new()),
+            ColumnType::String => CsvColumn::String(VecStorage::new()),
+        }
+    }
+
+    pub fn column_type(&self) -> ColumnType
+    {
+        match self
+        {
+            CsvColumn::I64(_) => ColumnType::I64,
+            CsvColumn::F64(_) => ColumnType::F64,
+            CsvColumn::Bool(_) => ColumnType::Bool,
+            CsvColumn::String(_) => ColumnType::String,
+        }
+    }
+
+    fn push(&mut self, value: &str) -> Result<(), String>
+    {
+        let invalid = |error: &dyn std::fmt::Display| format!("Invalid value {value:?}: {error}");
+
+        match self
+        {
+            CsvColumn::I64(storage) => storage.push(value.parse().map_err(|e| invalid(&e))?),
+            CsvColumn::F64(storage) => storage.push(value.parse().map_err(|e| invalid(&e))?),
+            CsvColumn::Bool(storage) => storage.push(value.parse().map_err(|e| invalid(&e))?),
+            CsvColumn::String(storage) => storage.push(value.to_owned()),
+        }
+
+        Ok(())
+    }
+
+    /// Build a handle to the column's storage labelled with `name`
+    pub fn into_handle(self, name: &str) -> StorageHandle<dyn Storage>
+    {
+        let mut builder = match self
+        {
+            CsvColumn::I64(storage) => builder(storage),
+            CsvColumn::F64(storage) => builder(storage),
+            CsvColumn::Bool(storage) => builder(storage),
+            CsvColumn::String(storage) => builder(storage),
+        };
+
+        builder.label(name);
+        builder.build()
+    }
+}
+
+pub struct CsvLoader
+{
+    schema: Option<CsvSchema>,
+    delimiter: u8,
+    infer_rows: usize,
+}
+
+impl Default for CsvLoader
+{
+    fn default() -> Self
+    {
+        Self {
+            schema: None,
+            delimiter: b',',
+            infer_rows: DEFAULT_INFER_ROWS,
+        }
+    }
+}
+
+impl CsvLoader
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Load only the columns of `schema` with the given types instead of inferring them
+    pub fn schema(&mut self, schema: CsvSchema) -> &mut Self
+    {
+        self.schema = Some(schema);
+
+        self
+    }
+
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut Self
+    {
+        self.delimiter = delimiter;
+
+        self
+    }
+
+    /// How many rows are used to infer column types when there is no schema
+    pub fn infer_rows(&mut self, rows: usize) -> &mut Self
+    {
+        self.infer_rows = rows;
+
+        self
+    }
+
+    pub fn load_path(&self, path: impl AsRef<Path>) -> SimpleResult<Vec<(String, CsvColumn)>>
+    {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|error| {
+            StorageError::with_source(
+                ErrorKind::Io,
+                format!("Failed to open {}: {error}", path.display()),
+                error,
+            )
+        })?;
+
+        self.load(file)
+    }
+
+    /// Load every column of the CSV data read from `reader`, which must start with a header row.
+    /// Columns are returned in the order of the schema, or of the data when inferring.
+    pub fn load(&self, reader: impl Read) -> SimpleResult<Vec<(String, CsvColumn)>>
+    {
+        let mut reader = ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .from_reader(reader);
+
+        let headers = reader
+            .headers()
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Failed to read CSV header: {error}"),
+                    error,
+                )
+            })?
+            .clone();
+
+        let mut records = reader.into_records().enumerate().map(|(row, record)| {
+            record.map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Failed to read CSV row {}: {error}", row + 1),
+                    error,
+                )
+            })
+        });
+
+        // Buffer the rows needed to infer column types
+        let mut buffered = Vec::new();
+        if self.schema.is_none()
+        {
+            for record in records.by_ref().take(self.infer_rows)
+            {
+                buffered.push(record?);
+            }
+        }
+
+        // (index in record, name, column)
+        let mut columns: Vec<(usize, String, CsvColumn)> = match &self.schema
+        {
+            Some(schema) => schema
+                .columns
+                .iter()
+                .map(|(name, column_type)| {
+                    headers
+                        .iter()
+                        .position(|header| header == name)
+                        .map(|index| (index, name.clone(), CsvColumn::new(*column_type)))
+                        .ok_or_else(|| format!("CSV data has no column named {name}").into())
+                })
+                .collect::<SimpleResult<_>>()?,
+            None => headers
+                .iter()
+                .enumerate()
+                .map(|(index, name)| {
+                    let values = buffered
+                        .iter()
+                        .map(|record| record.get(index).unwrap_or(""));
+                    (
+                        index,
+                        name.to_owned(),
+                        CsvColumn::new(ColumnType::infer(values)),
+                    )
+                })
+                .collect(),
+        };
+
+        let mut push_record = |row: usize, record: &StringRecord| -> SimpleResult<()> {
+            for (index, name, column) in &mut columns
+            {
+                let value = record.get(*index).unwrap_or("");
+                column
+                    .push(value)
+                    .map_err(|error| format!("Column {name} row {}: {error}", row + 1))?;
+            }
+
+            Ok(())
+        };
+
+        for (row, record) in buffered.iter().enumerate()
+        {
+            push_record(row, record)?;
+        }
+
+        for (row, record) in records.enumerate()
+        {
+            push_record(buffered.len() + row, &record?)?;
+        }
+
+        Ok(columns
+            .into_iter()
+            .map(|(_, name, column)| (name, column))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{ColumnType, CsvColumn, CsvLoader, CsvSchema};
+    use crate::storage_traits::ItemSliceStorage;
+
+    const DATA: &str = "id,weight,active,name\n1,0.5,true,a\n2,1,false,b\n";
+
+    #[test]
+    fn infer_test()
+    {
+        let columns = CsvLoader::new().load(DATA.as_bytes()).unwrap();
+
+        let types: Vec<_> = columns
+            .iter()
+            .map(|(name, column)| (name.as_str(), column.column_type()))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("id", ColumnType::I64),
+                ("weight", ColumnType::F64),
+                ("active", ColumnType::Bool),
+                ("name", ColumnType::String),
+            ]
+        );
+
+        let CsvColumn::F64(weights) = &columns[1].1
+        else
+        {
+            panic!("Expected a float column");
+        };
+        assert_eq!(weights.as_item_slice(), &[0.5, 1.0]);
+    }
+
+    #[test]
+    fn schema_test()
+    {
+        let mut schema = CsvSchema::new();
+        schema.column("weight", ColumnType::String);
+
+        let mut loader = CsvLoader::new();
+        loader.schema(schema);
+        let columns = loader.load(DATA.as_bytes()).unwrap();
+
+        assert_eq!(columns.len(), 1);
+        assert_eq!(columns[0].1.column_type(), ColumnType::String);
+
+        let mut schema = CsvSchema::new();
+        schema.column("name", ColumnType::I64);
+        loader.schema(schema);
+        assert!(loader.load(DATA.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,4 @@
+//! Loaders that stream data from common file formats into storages.
+
+#[cfg(feature = "csv")]
+pub mod csv;
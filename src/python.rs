@@ -0,0 +1,422 @@
+//! Python classes for reading and writing storages from scripting nodes.
+//!
+//! The host registers the classes with [register] and passes handles to scripts as
+//! [PyStorageHandle]s, which Python sees as `StorageHandle`. Scripts can also build their own
+//! storages through `StorageBuilder`:
+//!
+//! ```python
+//! weights = StorageBuilder("f32").label("weights").build()
+//! weights[0] = 1.5
+//! weights.insert(1, 2.5)
+//!
+//! for key, weight in weights.items():
+//!     print(key, weight)
+//! ```
+//!
+//! Storages are keyed by `int` and items are one of the primitive number types, `bool` or `str`.
+//! Scripts work with any storage the host passes them as long as it can be cast to
+//! [KeyItemStorage] for its key and item types, the same as on the Rust side.
+//!
+//! [KeyItemStorage]: crate::storage_traits::KeyItemStorage
+//
+// # Internal Design
+//
+// - pyo3 classes can't be generic so a single class wraps `StorageHandle<dyn Storage>` and the item
+//   type is recovered at call time from [StorageHandle::item_type_id]. The supported item types are
+//   listed once in [dispatch_item_type].
+// - Guards are never handed to Python. Every method locks for the duration of the call only, so a
+//   script can't leave a storage locked, and `items` copies out rather than iterating lazily.
+// - Lock failures are raised as `RuntimeError` and casts the storage doesn't support as
+//   `TypeError`, in line with how Python reports operations on the wrong type.
+
+use std::any::TypeId;
+
+use pyo3::{
+    exceptions::{PyKeyError, PyRuntimeError, PyTypeError, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    storage_handle::{builder, StorageHandle},
+    storage_traits::{ItemTrait, Storage},
+    storage_types::VecStorage,
+};
+
+/// Call `$function::<Item>$args` with the item type matching `$item_type_id`
+macro_rules! dispatch_item_type {
+    ($item_type_id:expr, $function:ident $args:tt) => {
+        dispatch_item_type!(
+            @types $item_type_id, $function $args,
+            f32, f64, i32, i64, u8, u32, u64, usize, bool, String
+        )
+    };
+    (@types $item_type_id:expr, $function:ident $args:tt, $($item:ty),*) => {{
+        let item_type_id = $item_type_id;
+
+        $(
+            if item_type_id == TypeId::of::<$item>()
+            {
+                $function::<$item> $args
+            }
+            else
+        )*
+        {
+            Err(PyTypeError::new_err("Storage item type is not supported from Python"))
+        }
+    }};
+}
+
+/// Items that can be converted to and from Python objects
+trait PyItem: ItemTrait + IntoPy<PyObject> + for<'py> FromPyObject<'py> {}
+
+impl<T> PyItem for T where T: ItemTrait + IntoPy<PyObject> + for<'py> FromPyObject<'py> {}
+
+/// A handle to a storage keyed by `int`
+#[pyclass(name = "StorageHandle")]
+#[derive(Clone)]
+pub struct PyStorageHandle
+{
+    handle: StorageHandle<dyn Storage>,
+}
+
+impl PyStorageHandle
+{
+    pub fn handle(&self) -> &StorageHandle<dyn Storage>
+    {
+        &self.handle
+    }
+}
+
+impl From<StorageHandle<dyn Storage>> for PyStorageHandle
+{
+    fn from(handle: StorageHandle<dyn Storage>) -> Self
+    {
+        Self { handle }
+    }
+}
+
+#[pymethods]
+impl PyStorageHandle
+{
+    #[getter]
+    fn label(&self) -> Option<&str>
+    {
+        self.handle.label()
+    }
+
+    #[getter]
+    fn write_version(&self) -> u64
+    {
+        self.handle.write_version()
+    }
+
+    fn __len__(&self) -> PyResult<usize>
+    {
+        let guard = self
+            .handle
+            .try_read()
+            .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+
+        Ok(guard.len())
+    }
+
+    /// The item at `key`, or `default` if there is none
+    #[pyo3(signature = (key, default = None))]
+    fn get(
+        &self,
+        py: Python<'_>,
+        key: usize,
+        default: Option<PyObject>,
+    ) -> PyResult<Option<PyObject>>
+    {
+        let item =
+            dispatch_item_type!(self.handle.item_type_id(), get_item(py, &self.handle, key))?;
+
+        Ok(item.or(default))
+    }
+
+    fn __getitem__(&self, py: Python<'_>, key: usize) -> PyResult<PyObject>
+    {
+        self.get(py, key, None)?
+            .ok_or_else(|| PyKeyError::new_err(key))
+    }
+
+    /// Insert or overwrite the item at `key`
+    fn insert(&self, key: usize, item: &Bound<'_, PyAny>) -> PyResult<()>
+    {
+        dispatch_item_type!(
+            self.handle.item_type_id(),
+            insert_item(&self.handle, key, item)
+        )
+    }
+
+    fn __setitem__(&self, key: usize, item: &Bound<'_, PyAny>) -> PyResult<()>
+    {
+        self.insert(key, item)
+    }
+
+    fn keys(&self, py: Python<'_>) -> PyResult<Vec<usize>>
+    {
+        let items = self.items(py)?;
+
+        Ok(items.into_iter().map(|(key, _)| key).collect())
+    }
+
+    /// A list of `(key, item)` tuples copied out of the storage
+    fn items(&self, py: Python<'_>) -> PyResult<Vec<(usize, PyObject)>>
+    {
+        dispatch_item_type!(self.handle.item_type_id(), items(py, &self.handle))
+    }
+
+    fn __repr__(&self) -> String
+    {
+        match self.handle.label()
+        {
+            Some(label) => format!("StorageHandle({label:?})"),
+            None => format!("StorageHandle({})", self.handle.storage_id()),
+        }
+    }
+}
+
+/// Builds a handle to a new vec backed storage of the given item type
+#[pyclass(name = "StorageBuilder")]
+pub struct PyStorageBuilder
+{
+    item_type: String,
+    label: Option<String>,
+    metrics: bool,
+    read_cache: bool,
+}
+
+#[pymethods]
+impl PyStorageBuilder
+{
+    /// `item_type` is the Rust name of the item type, eg `"f32"` or `"String"`
+    #[new]
+    fn new(item_type: String) -> Self
+    {
+        Self {
+            item_type,
+            label: None,
+            metrics: false,
+            read_cache: false,
+        }
+    }
+
+    fn label(mut slf: PyRefMut<'_, Self>, label: String) -> PyRefMut<'_, Self>
+    {
+        slf.label = Some(label);
+        slf
+    }
+
+    fn metrics(mut slf: PyRefMut<'_, Self>) -> PyRefMut<'_, Self>
+    {
+        slf.metrics = true;
+        slf
+    }
+
+    fn read_cache(mut slf: PyRefMut<'_, Self>) -> PyRefMut<'_, Self>
+    {
+        slf.read_cache = true;
+        slf
+    }
+
+    fn build(&self) -> PyResult<PyStorageHandle>
+    {
+        let item_type_id = match self.item_type.as_str()
+        {
+            "f32" => TypeId::of::<f32>(),
+            "f64" => TypeId::of::<f64>(),
+            "i32" => TypeId::of::<i32>(),
+            "i64" => TypeId::of::<i64>(),
+            "u8" => TypeId::of::<u8>(),
+            "u32" => TypeId::of::<u32>(),
+            "u64" => TypeId::of::<u64>(),
+            "usize" => TypeId::of::<usize>(),
+            "bool" => TypeId::of::<bool>(),
+            "String" | "str" => TypeId::of::<String>(),
+            other => return Err(PyValueError::new_err(format!("Unknown item type {other}"))),
+        };
+
+        let handle = dispatch_item_type!(item_type_id, build_vec_storage(self))?;
+
+        Ok(handle.into())
+    }
+}
+
+/// Add the `StorageHandle` and `StorageBuilder` classes to `module`
+pub fn register(module: &Bound<'_, PyModule>) -> PyResult<()>
+{
+    module.add_class::<PyStorageHandle>()?;
+    module.add_class::<PyStorageBuilder>()?;
+
+    Ok(())
+}
+
+fn get_item<Item>(
+    py: Python<'_>,
+    handle: &StorageHandle<dyn Storage>,
+    key: usize,
+) -> PyResult<Option<PyObject>>
+where
+    Item: PyItem,
+{
+    let storage = handle
+        .clone()
+        .cast_to_getitem_storage::<usize, Item>()
+        .map_err(|error| PyTypeError::new_err(error.to_string()))?;
+    let guard = storage.try_read().map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+
+    Ok(guard.get(key).map(|item| item.clone().into_py(py)))
+}
+
+fn insert_item<Item>(
+    handle: &StorageHandle<dyn Storage>,
+    key: usize,
+    item: &Bound<'_, PyAny>,
+) -> PyResult<()>
+where
+    Item: PyItem,
+{
+    // Convert before locking so that a bad item doesn't take the lock
+    let item: Item = item.extract()?;
+
+    let storage = handle
+        .clone()
+        .cast_to_mut_getitem_storage::<usize, Item>()
+        .map_err(|error| PyTypeError::new_err(error.to_string()))?;
+    let mut guard = storage
+        .try_write()
+        .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+
+    // Existing items are replaced in place as insert shifts items in vec like storages
+    match guard.get_mut(key)
+    {
+        Some(existing) => *existing = item,
+        None => guard.insert(key, item),
+    }
+
+    Ok(())
+}
+
+fn items<Item>(
+    py: Python<'_>,
+    handle: &StorageHandle<dyn Storage>,
+) -> PyResult<Vec<(usize, PyObject)>>
+where
+    Item: PyItem,
+{
+    let storage = handle
+        .clone()
+        .cast_to_getitem_storage::<usize, Item>()
+        .map_err(|error| PyTypeError::new_err(error.to_string()))?;
+    let guard = storage.try_read().map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+
+    Ok(guard
+        .key_item_iter()
+        .map(|(key, item)| (key, item.clone().into_py(py)))
+        .collect())
+}
+
+fn build_vec_storage<Item>(options: &PyStorageBuilder) -> PyResult<StorageHandle<dyn Storage>>
+where
+    Item: PyItem,
+{
+    let mut storage_builder = builder(VecStorage::<usize, Item>::new());
+
+    if let Some(label) = &options.label
+    {
+        storage_builder.label(label.clone());
+    }
+
+    if options.metrics
+    {
+        storage_builder.metrics();
+    }
+
+    if options.read_cache
+    {
+        storage_builder.read_cache();
+    }
+
+    Ok(storage_builder.build())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use pyo3::{prelude::*, types::PyDict};
+
+    use super::{register, PyStorageHandle};
+    use crate::{storage_handle::builder, storage_types::VecStorage};
+
+    #[test]
+    fn python_test()
+    {
+        pyo3::prepare_freethreaded_python();
+
+        let handle = builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0])).build();
+
+        Python::with_gil(|py| {
+            let module = PyModule::new_bound(py, "flex_storage").unwrap();
+            register(&module).unwrap();
+
+            let globals = PyDict::new_bound(py);
+            globals.set_item("flex_storage", module).unwrap();
+            globals
+                .set_item("weights", PyStorageHandle::from(handle.clone()).into_py(py))
+                .unwrap();
+
+            py.run_bound(
+                r#"
+weights[2] = weights[0] + weights[1]
+assert len(weights) == 3
+assert weights.get(9) is None
+
+try:
+    weights[0] = "heavy"
+    raise AssertionError("expected TypeError")
+except TypeError:
+    pass
+
+names = flex_storage.StorageBuilder("String").label("names").build()
+names.insert(0, "a")
+assert names.items() == [(0, "a")]
+"#,
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+
+        let weights = handle.cast_to_getitem_storage::<usize, f32>().unwrap();
+        assert_eq!(weights.try_read().unwrap().get(2), Some(&3.0));
+    }
+
+    #[test]
+    fn assign_existing_test()
+    {
+        pyo3::prepare_freethreaded_python();
+
+        let handle = builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0, 3.0])).build();
+
+        Python::with_gil(|py| {
+            let globals = PyDict::new_bound(py);
+            globals
+                .set_item("weights", PyStorageHandle::from(handle).into_py(py))
+                .unwrap();
+
+            py.run_bound(
+                r#"
+weights[0] = 1.5
+weights.insert(1, 2.5)
+assert len(weights) == 3
+assert weights.items() == [(0, 1.5), (1, 2.5), (2, 3.0)]
+"#,
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
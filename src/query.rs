@@ -0,0 +1,236 @@
+//! Filter the items of a storage down to the keys that match, and feed those keys straight into a
+//! view.
+//!
+//! A [Query] is a set of predicates on items that all have to hold. It can be evaluated against
+//! any [KeyItemStorage] to produce the matching keys, or used to set up a read or write view of
+//! just the matching items of an input storage:
+//!
+//! ```ignore
+//! let mut query = Query::new();
+//! query.range(0.5..=1.0).filter(|weight: &f32| weight.is_finite());
+//!
+//! let heavy: Vec<usize> = query.keys(&*weights.try_read()?);
+//!
+//! // Or have the view only show the matching items of the weights storage
+//! query.create_read_view::<usize>(&weights, &mut heavy_weights_view)?;
+//! ```
+//
+// # Internal Design
+//
+// - Predicates are boxed closures over the Item type alone so that one query can be evaluated
+//   against storages with different key types.
+// - Keys are collected while holding the read lock on the input, which is then released before the
+//   view is created. The view takes its own lock on the input so an input that is written in
+//   between can end up with a view of keys that no longer match. Views are normally created by the
+//   same node evaluation that owns the input so this is not guarded against.
+
+use std::ops::{Bound, RangeBounds};
+
+use crate::{
+    storage_handle::{StorageHandle, ViewStorageController},
+    storage_traits::{ItemTrait, KeyItemStorage, KeyTrait, Storage},
+    SimpleResult,
+};
+
+type Predicate<Item> = Box<dyn Fn(&Item) -> bool>;
+
+/// Predicates on items that all have to hold for an item to match, see the [module docs](self)
+pub struct Query<Item>
+{
+    predicates: Vec<Predicate<Item>>,
+}
+
+impl<Item> Default for Query<Item>
+{
+    fn default() -> Self
+    {
+        Self {
+            predicates: Vec::new(),
+        }
+    }
+}
+
+impl<Item> Query<Item>
+where
+    Item: ItemTrait,
+{
+    /// A query that matches every item
+    pub fn new() -> Self
+    {
+        <_>::default()
+    }
+
+    /// Match items equal to `value`
+    pub fn eq(&mut self, value: Item) -> &mut Self
+    where
+        Item: PartialEq,
+    {
+        self.filter(move |item| *item == value)
+    }
+
+    /// Match items not equal to `value`
+    pub fn ne(&mut self, value: Item) -> &mut Self
+    where
+        Item: PartialEq,
+    {
+        self.filter(move |item| *item != value)
+    }
+
+    /// Match items within `range`
+    pub fn range(&mut self, range: impl RangeBounds<Item>) -> &mut Self
+    where
+        Item: PartialOrd,
+    {
+        let bounds: (Bound<Item>, Bound<Item>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+
+        self.filter(move |item| bounds.contains(item))
+    }
+
+    /// Match items for which `predicate` returns true
+    pub fn filter(&mut self, predicate: impl Fn(&Item) -> bool + 'static) -> &mut Self
+    {
+        self.predicates.push(Box::new(predicate));
+
+        self
+    }
+
+    /// Match items that match at least one of `queries`
+    pub fn any_of(&mut self, queries: Vec<Query<Item>>) -> &mut Self
+    {
+        self.filter(move |item| queries.iter().any(|query| query.matches(item)))
+    }
+
+    pub fn matches(&self, item: &Item) -> bool
+    {
+        self.predicates.iter().all(|predicate| predicate(item))
+    }
+
+    /// Keys of the matching items in the iteration order of `storage`
+    pub fn keys<Key>(&self, storage: &dyn KeyItemStorage<Key = Key, Item = Item>) -> Vec<Key>
+    where
+        Key: KeyTrait,
+    {
+        storage
+            .key_item_iter()
+            .filter(|(_, item)| self.matches(item))
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Keys of the matching items of the storage behind `handle`. Briefly takes a read lock.
+    pub fn keys_of<S, Key>(&self, handle: &StorageHandle<S>) -> SimpleResult<Vec<Key>>
+    where
+        S: Storage + ?Sized,
+        Key: KeyTrait,
+    {
+        let handle = handle.clone().cast_to_getitem_storage::<Key, Item>()?;
+        let guard = handle.try_read()?;
+
+        Ok(self.keys(&*guard))
+    }
+
+    /// Set `input` as the input of `view` and create a read view of its matching items
+    pub fn create_read_view<Key>(
+        &self,
+        input: &StorageHandle<dyn Storage>,
+        view: &mut StorageHandle<dyn Storage>,
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+    {
+        let keys: Vec<Key> = self.keys_of(input)?;
+        let controller = view_controller(view)?;
+
+        controller.set_input::<Key, Item>(input.clone())?;
+        controller.create_read_view::<Key, Item>(keys)
+    }
+
+    /// Set `input` as the input of `view` and create a write view of its matching items
+    pub fn create_write_view<Key>(
+        &self,
+        input: &StorageHandle<dyn Storage>,
+        view: &mut StorageHandle<dyn Storage>,
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+    {
+        let keys: Vec<Key> = self.keys_of(input)?;
+        let controller = view_controller(view)?;
+
+        controller.set_input::<Key, Item>(input.clone())?;
+        controller.create_write_view::<Key, Item>(keys)
+    }
+}
+
+fn view_controller(
+    view: &mut StorageHandle<dyn Storage>,
+) -> SimpleResult<&mut ViewStorageController>
+{
+    view.view_storage_controller_mut()
+        .ok_or_else(|| "Cannot create a view on a storage handle without a view controller".into())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{any::TypeId, sync::Arc};
+
+    use super::Query;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::{KeyItemViewStorage, VecStorage},
+        Rw,
+    };
+
+    #[test]
+    fn query_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::from_vec(vec![5, -2, 8, 3, 10, 3]);
+
+        let mut query = Query::new();
+        query.range(0..=8).ne(5);
+        assert_eq!(query.keys(&storage), vec![2, 3, 5]);
+
+        let mut low = Query::new();
+        low.range(..0);
+        let mut high = Query::new();
+        high.range(9..);
+        let mut outliers = Query::new();
+        outliers.any_of(vec![low, high]);
+        assert_eq!(outliers.keys(&storage), vec![1, 4]);
+
+        let mut threes = Query::new();
+        threes.eq(3);
+        assert_eq!(threes.keys(&storage), vec![3, 5]);
+    }
+
+    #[test]
+    fn query_view_test()
+    {
+        let input: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 20, 3, 40])).build();
+
+        let mut view: StorageHandle<dyn Storage> = {
+            let storage: KeyItemViewStorage<VecStorage<usize, i32>, usize, i32> =
+                KeyItemViewStorage::new();
+            let storage = Arc::new(Rw::new(storage));
+
+            StorageHandle::new_with_view_controller(
+                storage.clone(),
+                storage,
+                TypeId::of::<usize>(),
+                TypeId::of::<i32>(),
+            )
+        };
+
+        let mut query = Query::new();
+        query.filter(|item: &i32| *item >= 10);
+        query.create_read_view::<usize>(&input, &mut view).unwrap();
+
+        let view = view.cast_to_getitem_storage::<usize, i32>().unwrap();
+        let guard = view.try_read().unwrap();
+        assert_eq!(guard.item_iter().copied().collect::<Vec<_>>(), vec![20, 40]);
+    }
+}
@@ -0,0 +1,347 @@
+//! SIMD reductions over the items of slice backed storages, enabled with the `simd` feature.
+//!
+//! [SimdReduce] adds sums, minimums, maximums and dot products to every [ItemSliceStorage] of
+//! f32, f64 or i32 items, and [SimdScale] scales the items of a [MutItemSliceStorage] in place.
+//! Handles run them under a single guard:
+//!
+//! ```ignore
+//! let total: f32 = weights.simd_sum()?;
+//! let range = (weights.simd_min()?, weights.simd_max()?);
+//! let similarity = weights.simd_dot(&other_weights)?;
+//!
+//! weights.simd_scale(1.0 / total)?;
+//! ```
+//
+// # Internal Design
+//
+// - Built on std::simd, which like ptr_metadata needs nightly, so the portable_simd feature is only
+//   turned on along with the `simd` feature.
+// - Slices are read in chunks of a fixed lane count with a scalar loop over the remainder rather
+//   than through [slice::as_simd], so that the two slices of a dot product don't need to share an
+//   alignment.
+// - Float sums are accumulated per lane, so the result can differ from a sequential sum in the last
+//   bits. Integer sums, products and scales wrap on overflow in every build as SIMD lanes do.
+// - Minimums and maximums of floats ignore NaN like [f32::min], unless every item is NaN.
+
+use std::{
+    ops::{Add, Mul},
+    simd::{
+        cmp::SimdOrd,
+        num::{SimdFloat, SimdInt},
+        Simd,
+    },
+};
+
+use crate::{
+    storage_handle::StorageHandle,
+    storage_traits::{ItemSliceStorage, MutItemSliceStorage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+/// Item types that [SimdReduce] and [SimdScale] support
+pub trait SimdItem: Copy + sealed::Sealed
+{
+    fn slice_sum(items: &[Self]) -> Self;
+
+    fn slice_min(items: &[Self]) -> Option<Self>;
+
+    fn slice_max(items: &[Self]) -> Option<Self>;
+
+    /// `a` and `b` are of the same length
+    fn slice_dot(a: &[Self], b: &[Self]) -> Self;
+
+    fn slice_scale(items: &mut [Self], factor: Self);
+}
+
+mod sealed
+{
+    pub trait Sealed {}
+
+    impl Sealed for f32 {}
+    impl Sealed for f64 {}
+    impl Sealed for i32 {}
+}
+
+macro_rules! impl_simd_item {
+    ($item:ty, $lanes:literal, $zero:expr, $add:ident, $mul:ident, $min:ident, $max:ident) => {
+        impl SimdItem for $item
+        {
+            fn slice_sum(items: &[Self]) -> Self
+            {
+                let chunks = items.chunks_exact($lanes);
+                let tail = chunks
+                    .remainder()
+                    .iter()
+                    .fold($zero, |sum, item| sum.$add(*item));
+
+                chunks
+                    .fold(Simd::<$item, $lanes>::splat($zero), |sum, chunk| {
+                        sum + Simd::from_slice(chunk)
+                    })
+                    .reduce_sum()
+                    .$add(tail)
+            }
+
+            fn slice_min(items: &[Self]) -> Option<Self>
+            {
+                let first = *items.first()?;
+                let chunks = items.chunks_exact($lanes);
+                let tail = chunks
+                    .remainder()
+                    .iter()
+                    .fold(first, |min, item| min.$min(*item));
+
+                let min = chunks
+                    .fold(Simd::<$item, $lanes>::splat(first), |min, chunk| {
+                        min.simd_min(Simd::from_slice(chunk))
+                    })
+                    .reduce_min();
+
+                Some(min.$min(tail))
+            }
+
+            fn slice_max(items: &[Self]) -> Option<Self>
+            {
+                let first = *items.first()?;
+                let chunks = items.chunks_exact($lanes);
+                let tail = chunks
+                    .remainder()
+                    .iter()
+                    .fold(first, |max, item| max.$max(*item));
+
+                let max = chunks
+                    .fold(Simd::<$item, $lanes>::splat(first), |max, chunk| {
+                        max.simd_max(Simd::from_slice(chunk))
+                    })
+                    .reduce_max();
+
+                Some(max.$max(tail))
+            }
+
+            fn slice_dot(a: &[Self], b: &[Self]) -> Self
+            {
+                let a_chunks = a.chunks_exact($lanes);
+                let b_chunks = b.chunks_exact($lanes);
+
+                let tail = std::iter::zip(a_chunks.remainder(), b_chunks.remainder())
+                    .fold($zero, |sum, (a, b)| sum.$add(a.$mul(*b)));
+
+                std::iter::zip(a_chunks, b_chunks)
+                    .fold(Simd::<$item, $lanes>::splat($zero), |sum, (a, b)| {
+                        sum + Simd::from_slice(a) * Simd::from_slice(b)
+                    })
+                    .reduce_sum()
+                    .$add(tail)
+            }
+
+            fn slice_scale(items: &mut [Self], factor: Self)
+            {
+                let factors = Simd::<$item, $lanes>::splat(factor);
+                let mut chunks = items.chunks_exact_mut($lanes);
+
+                for chunk in &mut chunks
+                {
+                    (Simd::from_slice(chunk) * factors).copy_to_slice(chunk);
+                }
+
+                for item in chunks.into_remainder()
+                {
+                    *item = item.$mul(factor);
+                }
+            }
+        }
+    };
+}
+
+impl_simd_item!(f32, 8, 0.0_f32, add, mul, min, max);
+impl_simd_item!(f64, 4, 0.0_f64, add, mul, min, max);
+impl_simd_item!(i32, 8, 0_i32, wrapping_add, wrapping_mul, min, max);
+
+/// SIMD reductions over the items of a slice storage, see the [module docs](self)
+pub trait SimdReduce: ItemSliceStorage
+where
+    Self::Item: SimdItem,
+{
+    fn simd_sum(&self) -> Self::Item
+    {
+        SimdItem::slice_sum(self.as_item_slice())
+    }
+
+    /// None if the storage is empty
+    fn simd_min(&self) -> Option<Self::Item>
+    {
+        SimdItem::slice_min(self.as_item_slice())
+    }
+
+    /// None if the storage is empty
+    fn simd_max(&self) -> Option<Self::Item>
+    {
+        SimdItem::slice_max(self.as_item_slice())
+    }
+
+    /// Fails if `other` has a different number of items
+    fn simd_dot(&self, other: &[Self::Item]) -> SimpleResult<Self::Item>
+    {
+        let items = self.as_item_slice();
+
+        if items.len() != other.len()
+        {
+            return Err(StorageError::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Failed to compute a dot product of {} items with {} items",
+                    items.len(),
+                    other.len()
+                ),
+            ));
+        }
+
+        Ok(SimdItem::slice_dot(items, other))
+    }
+}
+
+impl<S> SimdReduce for S
+where
+    S: ItemSliceStorage + ?Sized,
+    S::Item: SimdItem,
+{
+}
+
+/// Scale the items of a mutable slice storage in place, see the [module docs](self)
+pub trait SimdScale: MutItemSliceStorage
+where
+    Self::Item: SimdItem,
+{
+    fn simd_scale(&mut self, factor: Self::Item)
+    {
+        SimdItem::slice_scale(self.as_mut_slice(), factor);
+    }
+}
+
+impl<S> SimdScale for S
+where
+    S: MutItemSliceStorage + ?Sized,
+    S::Item: SimdItem,
+{
+}
+
+impl<S> StorageHandle<S>
+where
+    S: ItemSliceStorage + ?Sized,
+    S::Item: SimdItem,
+{
+    /// [SimdReduce::simd_sum] under a read guard. Fails rather than waits if the lock is contended.
+    pub fn simd_sum(&self) -> SimpleResult<S::Item>
+    {
+        Ok(self.try_read()?.simd_sum())
+    }
+
+    /// [SimdReduce::simd_min] under a read guard
+    pub fn simd_min(&self) -> SimpleResult<Option<S::Item>>
+    {
+        Ok(self.try_read()?.simd_min())
+    }
+
+    /// [SimdReduce::simd_max] under a read guard
+    pub fn simd_max(&self) -> SimpleResult<Option<S::Item>>
+    {
+        Ok(self.try_read()?.simd_max())
+    }
+
+    /// [SimdReduce::simd_dot] of this storage's items with the items of `other`, under a read guard
+    /// on each
+    pub fn simd_dot<Other>(&self, other: &StorageHandle<Other>) -> SimpleResult<S::Item>
+    where
+        Other: ItemSliceStorage<Item = S::Item> + ?Sized,
+    {
+        let guard = self.try_read()?;
+        let other_guard = other.try_read()?;
+
+        guard.simd_dot(other_guard.as_item_slice())
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: MutItemSliceStorage + ?Sized,
+    S::Item: SimdItem,
+{
+    /// [SimdScale::simd_scale] under a write guard. Fails rather than waits if the lock is
+    /// contended.
+    pub fn simd_scale(&self, factor: S::Item) -> SimpleResult<()>
+    {
+        self.try_write()?.simd_scale(factor);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{SimdItem, SimdReduce};
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::ItemSliceStorage,
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn reductions_match_scalar_test()
+    {
+        // Long enough for full chunks and a remainder
+        let floats: Vec<f32> = (0..21).map(|index| (index as f32 - 10.0) * 0.5).collect();
+        let ints: Vec<i32> = (0..21).map(|index| index * 3 - 30).collect();
+
+        assert_eq!(f32::slice_sum(&floats), floats.iter().sum::<f32>());
+        assert_eq!(f32::slice_min(&floats), Some(-5.0));
+        assert_eq!(f32::slice_max(&floats), Some(5.0));
+        assert_eq!(
+            f32::slice_dot(&floats, &floats),
+            floats.iter().map(|x| x * x).sum::<f32>()
+        );
+        assert_eq!(f32::slice_min(&[]), None);
+
+        assert_eq!(i32::slice_sum(&ints), ints.iter().sum::<i32>());
+        assert_eq!(i32::slice_min(&ints), Some(-30));
+        assert_eq!(i32::slice_max(&ints), Some(30));
+        assert_eq!(i32::slice_sum(&[i32::MAX, 1]), i32::MIN);
+
+        let doubles: Vec<f64> = vec![1.0, f64::NAN, 3.0, -2.0, 0.5];
+        assert_eq!(f64::slice_min(&doubles), Some(-2.0));
+        assert_eq!(f64::slice_max(&doubles), Some(3.0));
+
+        let mut scaled = ints.clone();
+        i32::slice_scale(&mut scaled, 2);
+        assert_eq!(scaled, ints.iter().map(|x| x * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn handle_reductions_test()
+    {
+        let weights: StorageHandle<dyn ItemSliceStorage<Item = f32>> =
+            builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0, 3.0]))
+                .build()
+                .cast_to_slice_storage::<usize, f32>()
+                .unwrap();
+
+        assert_eq!(weights.simd_sum().unwrap(), 6.0);
+        assert_eq!(weights.simd_max().unwrap(), Some(3.0));
+        assert_eq!(weights.simd_dot(&weights).unwrap(), 14.0);
+
+        let other = VecStorage::<usize, f32>::from_vec(vec![1.0]);
+        assert!(weights
+            .try_read()
+            .unwrap()
+            .simd_dot(other.as_item_slice())
+            .is_err());
+
+        let scaled: StorageHandle<VecStorage<usize, f32>> =
+            builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0]))
+                .build()
+                .cast_to_sized_storage()
+                .unwrap();
+        scaled.simd_scale(0.5).unwrap();
+        assert_eq!(scaled.try_read().unwrap().as_item_slice(), &[0.5, 1.0]);
+    }
+}
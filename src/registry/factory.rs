@@ -0,0 +1,212 @@
+//! Construct storages from type names that are only known at runtime, eg read from a saved graph
+//! file.
+//!
+//! A [StorageFactoryRegistry] maps names such as `"vec<f32>"` or `"hashmap<u64,f32>"` to
+//! constructors of empty storages. [StorageFactoryRegistry::with_builtins] registers the built in
+//! storage types for common key and item types and [crate::register_storage_types] adds user types:
+//!
+//! ```ignore
+//! let mut factories = StorageFactoryRegistry::with_builtins();
+//! register_storage_types!(factories, {
+//!     "vec<Vec3>" => VecStorage<usize, Vec3>,
+//!     "hashmap<u64,Vec3>" => HashMapStorage<u64, Vec3>,
+//! });
+//!
+//! let handle: StorageHandle<dyn Storage> = factories.create("hashmap<u64, Vec3>")?;
+//! ```
+//!
+//! Built in names:
+//!
+//! - `vec<Item>` for [VecStorage] with usize keys
+//! - `sparse<Item>` for [SparseSetVecStorage] with usize keys
+//! - `hashmap<Key,Item>` for [HashMapStorage] with u32, u64 or usize keys
+//!
+//! where Item is one of f32, f64, i32, i64, u8, u32, u64, usize, bool or String.
+//
+// # Internal Design
+//
+// - Names are compared with whitespace removed so that hand written graph files don't have to match
+//   the registered spelling exactly.
+// - Constructors return a [StorageHandleBuilder] so that [StorageFactoryRegistry::create_builder]
+//   callers can still set a label, lock rank and so on before building.
+// - Unlike the [super::StorageTypeRegistry] this needs no serde support from the storage types, so
+//   it is available without the serde feature.
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    storage_handle::{builder, StorageHandle, StorageHandleBuilder},
+    storage_traits::{
+        ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyStorage, KeyTrait, KeyTypeIdNoSelf, Storage,
+    },
+    storage_types::{HashMapStorage, SparseSetVecStorage, VecStorage},
+    Arw, ErrorKind, SimpleResult, StorageError,
+};
+
+type Constructor = Arc<dyn Fn() -> StorageHandleBuilder + Send + Sync>;
+
+/// Maps type names to storage constructors, see the [module docs](self)
+#[derive(Clone, Default)]
+pub struct StorageFactoryRegistry
+{
+    constructors: HashMap<String, Constructor>,
+}
+
+impl StorageFactoryRegistry
+{
+    /// An empty registry
+    pub fn new() -> Self
+    {
+        <_>::default()
+    }
+
+    /// A registry with the built in storage types registered, see the [module docs](self)
+    pub fn with_builtins() -> Self
+    {
+        let mut registry = Self::new();
+
+        macro_rules! register_items {
+            ($($item:ty),*) => {
+                $(
+                    let item = stringify!($item);
+
+                    registry.register_default::<VecStorage<usize, $item>>(format!("vec<{item}>"));
+                    registry.register_default::<SparseSetVecStorage<usize, $item>>(
+                        format!("sparse<{item}>"),
+                    );
+                    registry.register_default::<HashMapStorage<u32, $item>>(
+                        format!("hashmap<u32,{item}>"),
+                    );
+                    registry.register_default::<HashMapStorage<u64, $item>>(
+                        format!("hashmap<u64,{item}>"),
+                    );
+                    registry.register_default::<HashMapStorage<usize, $item>>(
+                        format!("hashmap<usize,{item}>"),
+                    );
+                )*
+            };
+        }
+
+        register_items!(f32, f64, i32, i64, u8, u32, u64, usize, bool, String);
+
+        registry
+    }
+
+    /// Register a constructor under `name`, replacing any constructor already registered under it
+    pub fn register(
+        &mut self,
+        name: impl AsRef<str>,
+        constructor: impl Fn() -> StorageHandleBuilder + Send + Sync + 'static,
+    ) -> &mut Self
+    {
+        self.constructors
+            .insert(normalize(name.as_ref()), Arc::new(constructor));
+
+        self
+    }
+
+    /// Register a storage type that is constructed empty through [Default]
+    pub fn register_default<S>(&mut self, name: impl AsRef<str>) -> &mut Self
+    where
+        S: KeyStorage
+            + ItemStorage
+            + Default
+            + Into<Arw<dyn Storage>>
+            + KeyTypeIdNoSelf
+            + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        self.register(name, || builder(S::default()))
+    }
+
+    pub fn contains(&self, name: &str) -> bool
+    {
+        self.constructors.contains_key(&normalize(name))
+    }
+
+    /// Registered names in no particular order, with whitespace removed
+    pub fn names(&self) -> impl Iterator<Item = &str>
+    {
+        self.constructors.keys().map(String::as_str)
+    }
+
+    /// Builder of a new storage of the type registered under `name`
+    pub fn create_builder(&self, name: &str) -> SimpleResult<StorageHandleBuilder>
+    {
+        self.constructors
+            .get(&normalize(name))
+            .map(|constructor| constructor())
+            .ok_or_else(|| {
+                StorageError::new(
+                    ErrorKind::NotRegistered,
+                    format!("No storage type is registered under the name {name}"),
+                )
+            })
+    }
+
+    /// A new storage of the type registered under `name` with default handle settings
+    pub fn create(&self, name: &str) -> SimpleResult<StorageHandle<dyn Storage>>
+    {
+        Ok(self.create_builder(name)?.build())
+    }
+}
+
+fn normalize(name: &str) -> String
+{
+    name.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+/// Register storage types that are constructed through [Default] with a
+/// [StorageFactoryRegistry]:
+///
+/// ```ignore
+/// register_storage_types!(factories, {
+///     "vec<Vec3>" => VecStorage<usize, Vec3>,
+/// });
+/// ```
+#[macro_export]
+macro_rules! register_storage_types {
+    ($registry:expr, { $($name:expr => $storage:ty),* $(,)? }) => {
+        $(
+            $registry.register_default::<$storage>($name);
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::any::TypeId;
+
+    use super::StorageFactoryRegistry;
+    use crate::storage_types::{HashMapStorage, VecStorage};
+
+    #[derive(Clone, Default)]
+    struct Vec3([f32; 3]);
+
+    #[test]
+    fn factory_test()
+    {
+        let mut factories = StorageFactoryRegistry::with_builtins();
+        register_storage_types!(factories, {
+            "vec<Vec3>" => VecStorage<usize, Vec3>,
+            "hashmap<u64,Vec3>" => HashMapStorage<u64, Vec3>,
+        });
+
+        let handle = factories.create("hashmap<u64, f32>").unwrap();
+        assert_eq!(handle.key_type_id(), TypeId::of::<u64>());
+        assert_eq!(handle.item_type_id(), TypeId::of::<f32>());
+
+        let handle = factories.create(" vec< Vec3 >").unwrap();
+        assert!(handle
+            .cast_to_sized_storage::<VecStorage<usize, Vec3>>()
+            .is_ok());
+
+        let mut builder = factories.create_builder("sparse<String>").unwrap();
+        builder.label("names");
+        assert_eq!(builder.build().label(), Some("names"));
+
+        assert!(factories.create("vec<Vec4>").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Self describing JSON export and import of handles for interchange with tools outside of Rust.
+//!
+//! Exported documents carry a [StorageSchema] next to the data so that a reader knows what kind of
+//! storage it is looking at without any out of band information:
+//!
+//! ```json
+//! {
+//!   "storage": "vec_usize_f32",
+//!   "key_type": "usize",
+//!   "item_type": "f32",
+//!   "label": "weights",
+//!   "data": { "data": [0.5, 1.0] }
+//! }
+//! ```
+//!
+//! Importing checks the schema against the registered storage type so a document is always
+//! rebuilt into the storage type it was exported from.
+//!
+//! Schemas can also be exported ahead of time for every registered type with
+//! [StorageTypeRegistry::schemas], or as a JSON Schema of the documents with
+//! [StorageTypeRegistry::json_schema], so that external tools can check and interpret exports.
+//! Storages registered with [StorageTypeRegistry::register_pod] carry the layout of their items
+//! which is also enough to interpret the raw bytes shared over FFI or GPU buffers.
+//
+// # Internal Design
+//
+// - Key and item type names come from [std::any::type_name] which is meant for diagnostics and may
+//   change between compiler versions. They are checked on import to catch documents that were
+//   edited by hand or exported from a different registry rather than to identify types.
+// - Going through [serde_json::Value] rather than the streaming deserializer means the fields can
+//   be in any order which matters for documents written by other tools.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    storage_handle::StorageHandle, storage_traits::Storage, ErrorKind, SimpleResult, StorageError,
+};
+
+use super::{type_registry::RegisteredType, ItemLayout, StorageTypeRegistry};
+
+/// Describes the storage held by an exported JSON document
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageSchema
+{
+    /// Registered name of the storage type, see [StorageTypeRegistry::register]
+    pub storage: String,
+    pub key_type: String,
+    pub item_type: String,
+
+    /// Only known for storages registered with [StorageTypeRegistry::register_pod]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_layout: Option<ItemLayout>,
+}
+
+impl StorageSchema
+{
+    fn of(registered: &RegisteredType) -> Self
+    {
+        Self {
+            storage: registered.name.clone(),
+            key_type: registered.key_type.into(),
+            item_type: registered.item_type.into(),
+            item_layout: registered.item_layout,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonDocument
+{
+    #[serde(flatten)]
+    schema: StorageSchema,
+    label: Option<String>,
+    data: Value,
+}
+
+impl StorageTypeRegistry
+{
+    /// Export the storage behind `handle` as a self describing JSON document
+    pub fn to_json<S>(&self, handle: &StorageHandle<S>) -> SimpleResult<String>
+    where
+        S: Storage + ?Sized,
+    {
+        let guard = handle.try_read()?;
+
+        let (registered, data) = self
+            .erase((*guard).as_any())
+            .map_err(|error| format!("Storage {}: {error}", handle.storage_id()))?;
+
+        let document = JsonDocument {
+            schema: StorageSchema::of(registered),
+            label: handle.label().map(Into::into),
+            data: serde_json::to_value(data).map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Failed to export storage data: {error}"),
+                    error,
+                )
+            })?,
+        };
+
+        serde_json::to_string(&document).map_err(|error| {
+            StorageError::with_source(ErrorKind::InvalidData, error.to_string(), error)
+        })
+    }
+
+    /// Import a document written by [StorageTypeRegistry::to_json] into a new handle
+    pub fn from_json(&self, json: &str) -> SimpleResult<StorageHandle<dyn Storage>>
+    {
+        let document: JsonDocument = serde_json::from_str(json).map_err(|error| {
+            StorageError::with_source(
+                ErrorKind::InvalidData,
+                format!("Invalid storage document: {error}"),
+                error,
+            )
+        })?;
+
+        let schema = &document.schema;
+        let registered = self.find(&schema.storage)?;
+
+        if schema.key_type != registered.key_type || schema.item_type != registered.item_type
+        {
+            return Err(StorageError::new(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Storage document of {} has key type {} and item type {} but {} was registered \
+                     with key type {} and item type {}",
+                    schema.storage,
+                    schema.key_type,
+                    schema.item_type,
+                    registered.name,
+                    registered.key_type,
+                    registered.item_type
+                ),
+            ));
+        }
+
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(document.data);
+        let mut builder = (registered.deserialize)(&mut deserializer)
+            .map_err(|error| format!("Invalid data for {}: {error}", schema.storage))?;
+
+        if let Some(label) = document.label
+        {
+            builder.label(label);
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Schema of the storage behind `handle` as it would be written by
+    /// [StorageTypeRegistry::to_json]
+    pub fn schema_of<S>(&self, handle: &StorageHandle<S>) -> SimpleResult<StorageSchema>
+    where
+        S: Storage + ?Sized,
+    {
+        let guard = handle.try_read()?;
+        let (registered, _) = self.erase((*guard).as_any())?;
+
+        Ok(StorageSchema::of(registered))
+    }
+
+    /// Schemas of every registered storage type in registration order
+    pub fn schemas(&self) -> Vec<StorageSchema>
+    {
+        self.registered_types().map(StorageSchema::of).collect()
+    }
+
+    /// A JSON Schema (draft 2020-12) that matches the documents written by
+    /// [StorageTypeRegistry::to_json] for any registered storage type. The shape of `data` is
+    /// left open as it depends on the serde implementation of each storage.
+    pub fn json_schema(&self) -> Value
+    {
+        let variants: Vec<Value> = self
+            .schemas()
+            .into_iter()
+            .map(|schema| {
+                let mut properties = json!({
+                    "storage": { "const": schema.storage },
+                    "key_type": { "const": schema.key_type },
+                    "item_type": { "const": schema.item_type }
+                });
+
+                if let Some(layout) = schema.item_layout
+                {
+                    properties["item_layout"] = json!({
+                        "const": { "size": layout.size, "align": layout.align }
+                    });
+                }
+
+                json!({ "properties": properties })
+            })
+            .collect();
+
+        let mut schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Flex storage document",
+            "type": "object",
+            "required": ["storage", "key_type", "item_type", "data"],
+            "properties": {
+                "storage": { "type": "string" },
+                "key_type": { "type": "string" },
+                "item_type": { "type": "string" },
+                "item_layout": {
+                    "type": "object",
+                    "required": ["size", "align"],
+                    "properties": {
+                        "size": { "type": "integer", "minimum": 0 },
+                        "align": { "type": "integer", "minimum": 1 }
+                    }
+                },
+                "label": { "type": ["string", "null"] },
+                "data": {}
+            }
+        });
+
+        // An empty oneOf is not a valid schema
+        if !variants.is_empty()
+        {
+            schema["oneOf"] = variants.into();
+        }
+
+        schema
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{
+        registry::{ItemLayout, StorageTypeRegistry},
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{KeyItemStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    fn registry() -> StorageTypeRegistry
+    {
+        let mut registry = StorageTypeRegistry::new();
+        registry.register::<VecStorage<usize, i32>>("vec_usize_i32");
+        registry
+    }
+
+    #[test]
+    fn json_round_trip_test()
+    {
+        let registry = registry();
+
+        let mut builder = builder(VecStorage::<usize, i32>::new_from_iter(vec![4, 5]));
+        builder.label("offsets");
+        let handle: StorageHandle<dyn Storage> = builder.build();
+
+        let json = registry.to_json(&handle).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["storage"], "vec_usize_i32");
+        assert_eq!(value["key_type"], "usize");
+        assert_eq!(value["item_type"], "i32");
+
+        let restored: StorageHandle<VecStorage<usize, i32>> = registry
+            .from_json(&json)
+            .unwrap()
+            .cast_to_sized_storage()
+            .unwrap();
+
+        assert_eq!(restored.label(), Some("offsets"));
+        let guard = restored.try_read().unwrap();
+        assert_eq!(guard.item_iter().collect::<Vec<_>>(), vec![&4, &5]);
+    }
+
+    #[test]
+    fn registry_schema_test()
+    {
+        let mut registry = registry();
+        registry.register_pod::<VecStorage<usize, f32>>("vec_usize_f32");
+
+        let schemas = registry.schemas();
+        assert_eq!(schemas.len(), 2);
+        assert_eq!(schemas[0].item_layout, None);
+        assert_eq!(schemas[1].item_layout, Some(ItemLayout { size: 4, align: 4 }));
+
+        let handle = builder(VecStorage::<usize, f32>::new()).build();
+        let json = registry.to_json(&handle).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["item_layout"]["size"], 4);
+
+        let json_schema = registry.json_schema();
+        assert_eq!(json_schema["oneOf"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            json_schema["oneOf"][0]["properties"]["storage"]["const"],
+            "vec_usize_i32"
+        );
+    }
+
+    #[test]
+    fn json_schema_mismatch_test()
+    {
+        let json = r#"{
+            "storage": "vec_usize_i32",
+            "key_type": "usize",
+            "item_type": "f32",
+            "label": null,
+            "data": { "data": [1.0] }
+        }"#;
+
+        assert!(registry().from_json(json).is_err());
+    }
+}
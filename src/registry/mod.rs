@@ -0,0 +1,14 @@
+//! Registries that map storage types and handles to names so that they can be found and rebuilt at
+//! runtime, eg when loading a saved session.
+
+mod factory;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "serde")]
+mod type_registry;
+
+pub use factory::*;
+#[cfg(feature = "json")]
+pub use json::*;
+#[cfg(feature = "serde")]
+pub use type_registry::*;
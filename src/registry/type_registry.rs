@@ -0,0 +1,460 @@
+//! Serialization of [StorageHandle]s whose concrete storage type is only known at runtime.
+//!
+//! A `StorageHandle<dyn Storage>` can't be serialized or deserialized by serde directly as the
+//! concrete storage type behind it is erased. A [StorageTypeRegistry] records a stable name for
+//! each storage type that may appear behind a handle so that:
+//!
+//! - Serializing writes the registered name of the concrete storage along with its data.
+//! - Deserializing looks the name up, deserializes the concrete storage and rebuilds a
+//!   `StorageHandle<dyn Storage>` through [crate::storage_handle::StorageHandleBuilder].
+//!
+//! ```ignore
+//! let mut registry = StorageTypeRegistry::new();
+//! registry.register::<VecStorage<usize, f32>>("vec_usize_f32");
+//!
+//! let json = serde_json::to_string(&registry.serializable(&handle))?;
+//! let handle = registry.deserialize_handle(&mut serde_json::Deserializer::from_str(&json))?;
+//! ```
+//!
+//! Only the storage data and the handle label are recorded. Other builder settings such as lock
+//! rank and policy describe how a session uses the storage rather than the data itself so they
+//! are left to the code that rebuilds the session.
+//
+// # Internal Design
+//
+// - Registered types are kept as a pair of monomorphized fn pointers so that the registry itself
+//   stays free of generics. erased_serde is used to pass serializers and deserializers through
+//   those fn pointers.
+// - The concrete storage is found by downcasting the locked storage via [Storage::as_any] so any
+//   cast of a handle can be serialized, not only `StorageHandle<dyn Storage>`.
+// - The type name has to be read before the data can be deserialized so the serialized handle is a
+//   struct with the `type` field first. Formats that don't keep field order are not supported.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+};
+
+use serde::{
+    de::{self, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeStruct},
+    Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    storage_handle::{StorageHandle, StorageHandleBuilder},
+    storage_traits::{
+        AsBytesBorrowed, ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyStorage, KeyTrait,
+        KeyTypeIdNoSelf, Storage, ZeroCopyItem,
+    },
+    Arw, ErrorKind, SimpleResult, StorageError,
+};
+
+const HANDLE_FIELDS: &[&str] = &["type", "label", "data"];
+
+type SerializeFn = for<'a> fn(&'a dyn Any) -> Option<&'a dyn erased_serde::Serialize>;
+
+type DeserializeFn = fn(
+    &mut dyn erased_serde::Deserializer<'_>,
+) -> Result<StorageHandleBuilder, erased_serde::Error>;
+
+pub(super) struct RegisteredType
+{
+    pub(super) name: String,
+    pub(super) key_type: &'static str,
+    pub(super) item_type: &'static str,
+    pub(super) item_layout: Option<ItemLayout>,
+    pub(super) serialize: SerializeFn,
+    pub(super) deserialize: DeserializeFn,
+}
+
+/// Memory layout of a plain data item type, see [StorageTypeRegistry::register_pod]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, serde::Deserialize)]
+pub struct ItemLayout
+{
+    pub size: usize,
+    pub align: usize,
+}
+
+impl ItemLayout
+{
+    pub fn of<Item>() -> Self
+    {
+        Self {
+            size: std::mem::size_of::<Item>(),
+            align: std::mem::align_of::<Item>(),
+        }
+    }
+}
+
+/// Maps concrete storage types to stable names for serializing type erased handles
+#[derive(Default)]
+pub struct StorageTypeRegistry
+{
+    types: Vec<RegisteredType>,
+    by_type_id: HashMap<TypeId, usize>,
+    by_name: HashMap<String, usize>,
+}
+
+impl StorageTypeRegistry
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Register the storage type `S` under `name`. The name is what gets written to serialized
+    /// data so it should stay the same across versions of an application.
+    ///
+    /// Panics if either `S` or `name` is already registered.
+    pub fn register<S>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        S: KeyStorage
+            + ItemStorage
+            + Into<Arw<dyn Storage>>
+            + KeyTypeIdNoSelf
+            + ItemTypeIdNoSelf
+            + Serialize
+            + DeserializeOwned,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        let name = name.into();
+
+        assert!(
+            !self.by_type_id.contains_key(&TypeId::of::<S>()),
+            "Storage type {} is already registered",
+            std::any::type_name::<S>()
+        );
+        assert!(
+            !self.by_name.contains_key(&name),
+            "Storage type name {name} is already registered"
+        );
+
+        let index = self.types.len();
+        self.by_type_id.insert(TypeId::of::<S>(), index);
+        self.by_name.insert(name.clone(), index);
+
+        self.types.push(RegisteredType {
+            name,
+            key_type: std::any::type_name::<S::Key>(),
+            item_type: std::any::type_name::<S::Item>(),
+            item_layout: None,
+            serialize: |storage| {
+                storage
+                    .downcast_ref::<S>()
+                    .map(|storage| storage as &dyn erased_serde::Serialize)
+            },
+            deserialize: |deserializer| {
+                erased_serde::deserialize::<S>(deserializer).map(StorageHandleBuilder::new)
+            },
+        });
+
+        self
+    }
+
+    /// Register the storage type `S` under `name` along with the memory layout of its items, so
+    /// that tools outside of Rust can read the raw bytes of the storage. The items must be
+    /// [ZeroCopyItem]s, ie contain no pointers or padding.
+    ///
+    /// Panics if either `S` or `name` is already registered.
+    pub fn register_pod<S>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        S: KeyStorage
+            + ItemStorage
+            + AsBytesBorrowed
+            + Into<Arw<dyn Storage>>
+            + KeyTypeIdNoSelf
+            + ItemTypeIdNoSelf
+            + Serialize
+            + DeserializeOwned,
+        S::Key: KeyTrait,
+        S::Item: ZeroCopyItem,
+    {
+        self.register::<S>(name);
+        self.types.last_mut().unwrap().item_layout = Some(ItemLayout::of::<S::Item>());
+
+        self
+    }
+
+    /// Registered name of the storage type with the given [TypeId]
+    pub fn name_of(&self, type_id: TypeId) -> Option<&str>
+    {
+        self.by_type_id
+            .get(&type_id)
+            .map(|index| self.types[*index].name.as_str())
+    }
+
+    pub fn contains_name(&self, name: &str) -> bool
+    {
+        self.by_name.contains_key(name)
+    }
+
+    /// Wrap `handle` so that it can be passed to any serde serializer
+    pub fn serializable<'a, S>(&'a self, handle: &'a StorageHandle<S>) -> SerializableHandle<'a, S>
+    where
+        S: Storage + ?Sized,
+    {
+        SerializableHandle {
+            registry: self,
+            handle,
+        }
+    }
+
+    /// Seed for deserializing handles nested in other data, eg a sequence of handles via
+    /// [SeqAccess::next_element_seed]
+    pub fn handle_seed(&self) -> HandleSeed<'_>
+    {
+        HandleSeed { registry: self }
+    }
+
+    pub fn deserialize_handle<'de, D>(
+        &self,
+        deserializer: D,
+    ) -> Result<StorageHandle<dyn Storage>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        self.handle_seed().deserialize(deserializer)
+    }
+
+    /// The registered type and serializable data of a locked storage
+    pub(super) fn erase<'a>(
+        &self,
+        storage: &'a dyn Any,
+    ) -> SimpleResult<(&RegisteredType, &'a dyn erased_serde::Serialize)>
+    {
+        let registered = self
+            .by_type_id
+            .get(&storage.type_id())
+            .map(|index| &self.types[*index])
+            .ok_or_else(|| {
+                StorageError::new(
+                    ErrorKind::NotRegistered,
+                    "Storage is not of a registered storage type",
+                )
+            })?;
+
+        let data =
+            (registered.serialize)(storage).ok_or("Registered storage type failed to downcast")?;
+
+        Ok((registered, data))
+    }
+
+    pub(super) fn registered_types(&self) -> impl Iterator<Item = &RegisteredType>
+    {
+        self.types.iter()
+    }
+
+    pub(super) fn find(&self, name: &str) -> SimpleResult<&RegisteredType>
+    {
+        self.by_name
+            .get(name)
+            .map(|index| &self.types[*index])
+            .ok_or_else(|| {
+                StorageError::new(
+                    ErrorKind::NotRegistered,
+                    format!("Storage type name {name} is not registered"),
+                )
+            })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Serialize
+////////////////////////////////////////////////////////////////////////////////
+
+/// A [StorageHandle] paired with the registry needed to serialize it
+pub struct SerializableHandle<'a, S>
+where
+    S: Storage + ?Sized,
+{
+    registry: &'a StorageTypeRegistry,
+    handle: &'a StorageHandle<S>,
+}
+
+impl<S> Serialize for SerializableHandle<'_, S>
+where
+    S: Storage + ?Sized,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+    {
+        let guard = self.handle.try_read().map_err(ser::Error::custom)?;
+        let storage = (*guard).as_any();
+
+        let (registered, data) = self.registry.erase(storage).map_err(|error| {
+            ser::Error::custom(format!("Storage {}: {error}", self.handle.storage_id()))
+        })?;
+
+        let mut state = serializer.serialize_struct("StorageHandle", HANDLE_FIELDS.len())?;
+        state.serialize_field("type", &registered.name)?;
+        state.serialize_field("label", &self.handle.label())?;
+        state.serialize_field("data", data)?;
+        state.end()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Deserialize
+////////////////////////////////////////////////////////////////////////////////
+
+/// [DeserializeSeed] that reads a handle written by [SerializableHandle]
+pub struct HandleSeed<'a>
+{
+    registry: &'a StorageTypeRegistry,
+}
+
+impl<'de> DeserializeSeed<'de> for HandleSeed<'_>
+{
+    type Value = StorageHandle<dyn Storage>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    {
+        deserializer.deserialize_struct(
+            "StorageHandle",
+            HANDLE_FIELDS,
+            HandleVisitor(self.registry),
+        )
+    }
+}
+
+/// Deserializes the data of a storage of an already known registered type
+struct DataSeed<'a>(&'a RegisteredType);
+
+impl<'de> DeserializeSeed<'de> for DataSeed<'_>
+{
+    type Value = StorageHandleBuilder;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+
+        (self.0.deserialize)(&mut deserializer).map_err(de::Error::custom)
+    }
+}
+
+struct HandleVisitor<'a>(&'a StorageTypeRegistry);
+
+impl HandleVisitor<'_>
+{
+    fn build(mut builder: StorageHandleBuilder, label: Option<String>)
+        -> StorageHandle<dyn Storage>
+    {
+        if let Some(label) = label
+        {
+            builder.label(label);
+        }
+
+        builder.build()
+    }
+}
+
+impl<'de> Visitor<'de> for HandleVisitor<'_>
+{
+    type Value = StorageHandle<dyn Storage>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result
+    {
+        formatter.write_str("a storage handle with type, label and data fields")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    {
+        let name: String = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let label: Option<String> = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        let registered = self.0.find(&name).map_err(de::Error::custom)?;
+
+        let builder = seq
+            .next_element_seed(DataSeed(registered))?
+            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+
+        Ok(Self::build(builder, label))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error>
+    {
+        let mut registered = None;
+        let mut label = None;
+        let mut builder = None;
+
+        while let Some(field) = map.next_key::<String>()?
+        {
+            match field.as_str()
+            {
+                "type" =>
+                {
+                    let name: String = map.next_value()?;
+                    registered = Some(self.0.find(&name).map_err(de::Error::custom)?);
+                }
+                "label" => label = map.next_value()?,
+                "data" =>
+                {
+                    let registered = registered.ok_or_else(|| {
+                        de::Error::custom(
+                            "The type field of a storage handle must come before data",
+                        )
+                    })?;
+
+                    builder = Some(map.next_value_seed(DataSeed(registered))?);
+                }
+                other => return Err(de::Error::unknown_field(other, HANDLE_FIELDS)),
+            }
+        }
+
+        let builder = builder.ok_or_else(|| de::Error::missing_field("data"))?;
+
+        Ok(Self::build(builder, label))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::StorageTypeRegistry;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{KeyItemStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn handle_round_trip_test()
+    {
+        let mut registry = StorageTypeRegistry::new();
+        registry.register::<VecStorage<usize, i32>>("vec_usize_i32");
+
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let mut builder = builder(storage);
+        builder.label("numbers");
+        let handle: StorageHandle<dyn Storage> = builder.build();
+
+        let json = serde_json::to_string(&registry.serializable(&handle)).unwrap();
+        let restored = registry
+            .deserialize_handle(&mut serde_json::Deserializer::from_str(&json))
+            .unwrap();
+
+        assert_eq!(restored.label(), Some("numbers"));
+
+        let restored: StorageHandle<VecStorage<usize, i32>> =
+            restored.cast_to_sized_storage().unwrap();
+        let guard = restored.try_read().unwrap();
+        assert_eq!(guard.item_iter().collect::<Vec<_>>(), vec![&1, &2, &3]);
+    }
+
+    #[test]
+    fn unregistered_type_test()
+    {
+        let registry = StorageTypeRegistry::new();
+
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1]);
+        let handle: StorageHandle<dyn Storage> = builder(storage).build();
+
+        assert!(serde_json::to_string(&registry.serializable(&handle)).is_err());
+    }
+}
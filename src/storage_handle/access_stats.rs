@@ -0,0 +1,314 @@
+//! Counts of how a storage is used through its handles, for finding dead storages and hot spots in
+//! large graphs. Enabled with the `access_stats` feature.
+//!
+//! Every handle counts the guards taken out through it and the gets, inserts and iterations made
+//! on the storage while one of its guards is held. The counts are shared by all clones and casts of
+//! a handle and are read via [StorageHandle::access_stats]:
+//!
+//! ```ignore
+//! for handle in &handles
+//! {
+//!     let stats = handle.access_stats();
+//!
+//!     if stats.is_unused()
+//!     {
+//!         println!("{:?} is never used", handle.label());
+//!     }
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - Gets, inserts and iterations are recorded by the built in storage types, which have no
+//   reference to the handle they are locked through. Each guard taken out through a handle
+//   registers the address range of the locked storage on a thread local stack and an access is
+//   attributed to the innermost guard whose range holds the address of the storage it was made on.
+//   Storages nested inline in a wrapper such as [crate::storage_types::DirtyTracked] are covered by
+//   the range of the wrapper.
+// - Storages that keep their items behind another allocation, such as
+//   [crate::storage_types::CowStorage] and the input of a view, fall outside of the range and only
+//   their guard acquisitions are counted. So are async guards as they can move between threads.
+// - Without the feature [record] is an empty inline function so the storage types pay nothing.
+
+#[cfg(feature = "access_stats")]
+use std::{
+    cell::{Cell, RefCell},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+#[cfg(feature = "access_stats")]
+use crate::storage_traits::Storage;
+
+#[cfg(feature = "access_stats")]
+use super::{HandleState, LockAccess, StorageHandle};
+
+/// Kinds of storage accesses recorded by the built in storage types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AccessKind
+{
+    /// `get` and `get_mut`
+    Get,
+
+    Insert,
+
+    /// Key and item iterators as well as item slices
+    Iteration,
+}
+
+/// Record an access to `storage`. Called by the storage types themselves.
+#[cfg(not(feature = "access_stats"))]
+#[inline(always)]
+pub(crate) fn record<T>(_storage: &T, _kind: AccessKind)
+where
+    T: ?Sized,
+{
+}
+
+/// Record an access to `storage`. Called by the storage types themselves.
+#[cfg(feature = "access_stats")]
+pub(crate) fn record<T>(storage: &T, kind: AccessKind)
+where
+    T: ?Sized,
+{
+    let address = storage as *const T as *const () as usize;
+
+    ACTIVE_SCOPES.with(|scopes| {
+        let scopes = scopes.borrow();
+
+        let Some(scope) = scopes.iter().rev().find(|scope| scope.contains(address))
+        else
+        {
+            return;
+        };
+
+        let counters = &scope.state.access_counters;
+
+        match kind
+        {
+            AccessKind::Get => counters.gets.fetch_add(1, Ordering::Relaxed),
+            AccessKind::Insert => counters.inserts.fetch_add(1, Ordering::Relaxed),
+            AccessKind::Iteration => counters.iterations.fetch_add(1, Ordering::Relaxed),
+        };
+    });
+}
+
+/// Snapshot of the access counts of a storage
+#[cfg(feature = "access_stats")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessStats
+{
+    pub read_guards: u64,
+    pub write_guards: u64,
+    pub gets: u64,
+    pub inserts: u64,
+    pub iterations: u64,
+}
+
+#[cfg(feature = "access_stats")]
+impl AccessStats
+{
+    /// No guard has been taken out since the handle was built or the stats were reset
+    pub fn is_unused(&self) -> bool
+    {
+        self.read_guards == 0 && self.write_guards == 0
+    }
+}
+
+#[cfg(feature = "access_stats")]
+#[derive(Debug, Default)]
+pub(crate) struct AccessCounters
+{
+    read_guards: AtomicU64,
+    write_guards: AtomicU64,
+    gets: AtomicU64,
+    inserts: AtomicU64,
+    iterations: AtomicU64,
+}
+
+#[cfg(feature = "access_stats")]
+impl AccessCounters
+{
+    pub(crate) fn on_acquired(&self, access: LockAccess)
+    {
+        match access
+        {
+            LockAccess::Read => self.read_guards.fetch_add(1, Ordering::Relaxed),
+            LockAccess::Write => self.write_guards.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    fn counters(&self) -> [&AtomicU64; 5]
+    {
+        [
+            &self.read_guards,
+            &self.write_guards,
+            &self.gets,
+            &self.inserts,
+            &self.iterations,
+        ]
+    }
+
+    fn snapshot(&self) -> AccessStats
+    {
+        let [read_guards, write_guards, gets, inserts, iterations] = self
+            .counters()
+            .map(|counter| counter.load(Ordering::Relaxed));
+
+        AccessStats {
+            read_guards,
+            write_guards,
+            gets,
+            inserts,
+            iterations,
+        }
+    }
+
+    fn reset(&self)
+    {
+        for counter in self.counters()
+        {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "access_stats")]
+struct ActiveScope
+{
+    id: u64,
+    start: usize,
+    len: usize,
+    state: Arc<HandleState>,
+}
+
+#[cfg(feature = "access_stats")]
+impl ActiveScope
+{
+    fn contains(&self, address: usize) -> bool
+    {
+        // Zero sized storages still claim their own address
+        address >= self.start && address < self.start + self.len.max(1)
+    }
+}
+
+#[cfg(feature = "access_stats")]
+thread_local! {
+    static ACTIVE_SCOPES: RefCell<Vec<ActiveScope>> = const { RefCell::new(Vec::new()) };
+    static NEXT_SCOPE_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Guard hook that attributes accesses to a locked storage to its handle until the guard is
+/// released
+#[cfg(feature = "access_stats")]
+pub(crate) struct AccessScope
+{
+    id: u64,
+}
+
+#[cfg(feature = "access_stats")]
+impl AccessScope
+{
+    pub(crate) fn enter<S>(state: &Arc<HandleState>, storage: &S) -> Self
+    where
+        S: ?Sized,
+    {
+        let id = NEXT_SCOPE_ID.with(|next_id| next_id.replace(next_id.get() + 1));
+
+        ACTIVE_SCOPES.with(|scopes| {
+            scopes.borrow_mut().push(ActiveScope {
+                id,
+                start: storage as *const S as *const () as usize,
+                len: std::mem::size_of_val(storage),
+                state: state.clone(),
+            })
+        });
+
+        Self { id }
+    }
+}
+
+#[cfg(feature = "access_stats")]
+impl Drop for AccessScope
+{
+    fn drop(&mut self)
+    {
+        // Guards are usually but not always released in reverse order
+        ACTIVE_SCOPES.with(|scopes| {
+            let mut scopes = scopes.borrow_mut();
+
+            if let Some(index) = scopes.iter().rposition(|scope| scope.id == self.id)
+            {
+                scopes.remove(index);
+            }
+        });
+    }
+}
+
+#[cfg(feature = "access_stats")]
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    pub fn access_stats(&self) -> AccessStats
+    {
+        self.state.access_counters.snapshot()
+    }
+
+    pub fn reset_access_stats(&self)
+    {
+        self.state.access_counters.reset();
+    }
+}
+
+#[cfg(all(test, feature = "access_stats"))]
+mod tests
+{
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::{DirtyTracked, HashMapStorage, VecStorage},
+    };
+
+    #[test]
+    fn access_stats_test()
+    {
+        let vec_handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3])).build();
+        let map_handle: StorageHandle<dyn Storage> =
+            builder(DirtyTracked::new(HashMapStorage::<u32, i32>::new())).build();
+        let unused: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::new_from_iter(vec![])).build();
+
+        let vec_handle = vec_handle.cast_to_getitem_storage::<usize, i32>().unwrap();
+        let map_handle = map_handle
+            .cast_to_mut_getitem_storage::<u32, i32>()
+            .unwrap();
+
+        {
+            let source = vec_handle.try_read().unwrap();
+            let mut target = map_handle.try_write().unwrap();
+
+            for (key, item) in source.key_item_iter()
+            {
+                target.insert(key as u32, *item);
+            }
+
+            assert_eq!(source.get(1), Some(&2));
+        }
+
+        let stats = vec_handle.access_stats();
+        assert_eq!((stats.read_guards, stats.gets, stats.iterations), (1, 1, 1));
+
+        // The inserts land on the hashmap nested inside the dirty tracking wrapper
+        let stats = map_handle.access_stats();
+        assert_eq!((stats.write_guards, stats.inserts), (1, 3));
+
+        assert!(unused.access_stats().is_unused());
+
+        map_handle.reset_access_stats();
+        assert!(map_handle.access_stats().is_unused());
+    }
+}
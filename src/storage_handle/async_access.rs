@@ -0,0 +1,681 @@
+//! Async access to storages for hosts that run on an async executor.
+//!
+//! Provides async read / write on [StorageHandle], async versions of the [ViewStorageController]
+//! operations, and owned guards that can be held across await points.
+//
+// # Internal Design
+//
+// ## Executor agnostic
+//
+// No particular async runtime is assumed. Every async operation is a retry loop over the
+// existing non blocking try_* operations. When the lock is contended the future yields back to the
+// executor so that other tasks (including the one holding the lock) can make progress. This keeps
+// the locks the same std locks that the sync API uses so sync and async consumers can share the
+// same storages.
+//
+// Locks are released without a notification to wait on, so the first retries reschedule straight
+// away and later ones are woken by a shared timer thread after a growing delay, the async
+// counterpart of the backoff in [super::blocking]. With the `local` feature there may be no threads
+// to run a timer on, so retries always reschedule straight away.
+//
+// Guards are taken in the same try_* call that checks for contention, so a future never blocks
+// its executor thread on a lock, and a poisoned lock fails the future rather than retrying.
+//
+// ## Send guards
+//
+// The returned guards own a guardian lock on the storage (just like [KeyItemViewStorage] does
+// with its input storage) so that they are not tied to the lifetime of the handle and can live
+// inside a future that is moved between executor threads. Guardian guards are !Send for the same
+// reason std guards are, so the same reasoning that justifies SendOption in
+// [crate::storage_types::KeyItemViewStorage] applies here. See [SendGuardian] for details.
+
+use std::{
+    future::{poll_fn, Future},
+    ops::{Deref, DerefMut},
+    sync::{TryLockError, TryLockResult},
+    task::{Poll, Waker},
+};
+
+#[cfg(not(feature = "local"))]
+use std::{
+    sync::{Condvar, Mutex, Once, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    casting::cast_to_dyn_getkeyitemviewstorage,
+    error::lock_error,
+    storage_traits::{ItemTrait, KeyTrait, Storage, ViewStorageSetup},
+    sync::{
+        try_read_storage_arc, try_write_storage_arc, ArcStorageLockReadGuard,
+        ArcStorageLockWriteGuard, RwLockReadGuard,
+    },
+    Arw, ErrorKind, SimpleResult,
+};
+
+#[cfg(feature = "access_stats")]
+use super::access_stats::AccessScope;
+use super::{GuardHooks, InputStorageLockStatus, LockAccess, StorageHandle, ViewStorageController};
+
+/// Resolves once `attempt` returns Some, yielding back to the executor after each failed attempt.
+///
+/// The first retries are rescheduled straight away and later ones after a growing delay of up to a
+/// millisecond, so a task that waits on a long held lock doesn't keep its executor busy.
+pub fn retry_until<T>(mut attempt: impl FnMut() -> Option<T>) -> impl Future<Output = T>
+{
+    let mut failed_attempts: u32 = 0;
+
+    poll_fn(move |cx| match attempt()
+    {
+        Some(value) => Poll::Ready(value),
+        None =>
+        {
+            failed_attempts = failed_attempts.saturating_add(1);
+            wake_for_retry(cx.waker(), failed_attempts);
+
+            Poll::Pending
+        }
+    })
+}
+
+#[cfg(not(feature = "local"))]
+const YIELD_ATTEMPTS: u32 = 16;
+
+#[cfg(not(feature = "local"))]
+const MIN_BACKOFF_DELAY: Duration = Duration::from_micros(50);
+
+#[cfg(not(feature = "local"))]
+const MAX_BACKOFF_DELAY: Duration = Duration::from_millis(1);
+
+fn wake_for_retry(waker: &Waker, failed_attempts: u32)
+{
+    #[cfg(not(feature = "local"))]
+    if failed_attempts > YIELD_ATTEMPTS
+    {
+        let doublings = (failed_attempts - YIELD_ATTEMPTS).min(8);
+        let delay = (MIN_BACKOFF_DELAY * 2u32.pow(doublings)).min(MAX_BACKOFF_DELAY);
+
+        return BACKOFF_TIMER.wake_at(Instant::now() + delay, waker);
+    }
+
+    #[cfg(feature = "local")]
+    let _ = failed_attempts;
+
+    waker.wake_by_ref();
+}
+
+/// Wakes retrying futures once their delay is up, from a thread that is started on first use
+#[cfg(not(feature = "local"))]
+struct BackoffTimer
+{
+    wakes: Mutex<Vec<(Instant, Waker)>>,
+    condvar: Condvar,
+    started: Once,
+}
+
+#[cfg(not(feature = "local"))]
+static BACKOFF_TIMER: BackoffTimer = BackoffTimer {
+    wakes: Mutex::new(Vec::new()),
+    condvar: Condvar::new(),
+    started: Once::new(),
+};
+
+#[cfg(not(feature = "local"))]
+impl BackoffTimer
+{
+    fn wake_at(&'static self, at: Instant, waker: &Waker)
+    {
+        self.started.call_once(|| {
+            thread::Builder::new()
+                .name("flex-storage-backoff".into())
+                .spawn(|| self.run())
+                .expect("Failed to spawn the async backoff timer thread");
+        });
+
+        // The wake list is only ever pushed to and drained so poisoning can be ignored
+        let mut wakes = self.wakes.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // A task that was polled again before its wake only needs the earlier of the two
+        match wakes.iter_mut().find(|(_, queued)| queued.will_wake(waker))
+        {
+            Some((queued_at, _)) => *queued_at = (*queued_at).min(at),
+            None => wakes.push((at, waker.clone())),
+        }
+
+        self.condvar.notify_one();
+    }
+
+    fn run(&self)
+    {
+        let mut wakes = self.wakes.lock().unwrap_or_else(PoisonError::into_inner);
+
+        loop
+        {
+            let now = Instant::now();
+            let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut *wakes)
+                .into_iter()
+                .partition(|(at, _)| *at <= now);
+            *wakes = pending;
+
+            if !due.is_empty()
+            {
+                // Woken without the lock so that a waker which re-polls straight away can queue
+                drop(wakes);
+                due.into_iter().for_each(|(_, waker)| waker.wake());
+                wakes = self.wakes.lock().unwrap_or_else(PoisonError::into_inner);
+
+                continue;
+            }
+
+            wakes = match wakes.iter().map(|(at, _)| *at).min()
+            {
+                Some(next) =>
+                {
+                    self.condvar
+                        .wait_timeout(wakes, next.saturating_duration_since(now))
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .condvar
+                    .wait(wakes)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+}
+
+// Safety: std lock guards are !Send because unlocking from a thread other than the one that
+// locked is undefined behavior for some lock implementations (eg pthreads). The std RwLock is
+// futex or queue based on all currently supported std targets, where release from another thread
+// is sound. This is the same assumption that [crate::storage_types::KeyItemViewStorage] makes via
+// SendOption and the same tracking issue applies: https://github.com/rust-lang/rust/issues/93740
+//
+// With the `local` feature storages may be !Sync so the guards are left !Send.
+struct SendGuardian<G>(G);
+
+#[cfg(not(feature = "local"))]
+unsafe impl<S> Send for SendGuardian<ArcStorageLockReadGuard<S>> where
+    S: ?Sized + Send + Sync + 'static
+{
+}
+
+#[cfg(not(feature = "local"))]
+unsafe impl<S> Send for SendGuardian<ArcStorageLockWriteGuard<S>> where
+    S: ?Sized + Send + Sync + 'static
+{
+}
+
+////////////////////////////////////////////////
+// Async Read Guard
+////////////////////////////////////////////////
+
+/// An owned read guard that can be held across await points
+pub struct AsyncStorageReadGuard<S>
+where
+    S: Storage + ?Sized,
+{
+    inner_guard: SendGuardian<ArcStorageLockReadGuard<S>>,
+
+    // Declared after the inner guard so that the hooks only run once the lock is released
+    _hooks: GuardHooks,
+}
+
+impl<S> Deref for AsyncStorageReadGuard<S>
+where
+    S: Storage + ?Sized,
+{
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.inner_guard.0
+    }
+}
+
+////////////////////////////////////////////////
+// Async Write Guard
+////////////////////////////////////////////////
+
+/// An owned write guard that can be held across await points
+pub struct AsyncStorageWriteGuard<S>
+where
+    S: Storage + ?Sized,
+{
+    inner_guard: SendGuardian<ArcStorageLockWriteGuard<S>>,
+
+    // Declared after the inner guard so that the hooks only run once the lock is released
+    _hooks: GuardHooks,
+}
+
+impl<S> Deref for AsyncStorageWriteGuard<S>
+where
+    S: Storage + ?Sized,
+{
+    type Target = S;
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.inner_guard.0
+    }
+}
+
+impl<S> DerefMut for AsyncStorageWriteGuard<S>
+where
+    S: Storage + ?Sized,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target
+    {
+        &mut self.inner_guard.0
+    }
+}
+
+////////////////////////////////////////////////
+// StorageHandle
+////////////////////////////////////////////////
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Async version of [StorageHandle::try_read] that waits for the lock instead of failing
+    /// under contention.
+    ///
+    /// Fails early with the same error as [StorageHandle::try_read] if this is a view storage
+    /// handle whose view has not been created yet, as waiting would never succeed.
+    pub async fn read_async(&self) -> SimpleResult<AsyncStorageReadGuard<S>>
+    {
+        let mut hooks = self.before_acquire(LockAccess::Read)?;
+        let storage: Arw<S> = self.storage.clone();
+
+        let (guard, _status_guard) = retry_until(|| {
+            // Held until the storage guard has been acquired so that the view can't be cleared in
+            // between, as in [StorageHandle::try_read]
+            let status_guard = match self.try_lock_created_view("read")
+            {
+                Ok(status_guard) => status_guard,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return None,
+                Err(error) => return Some(Err(error)),
+            };
+
+            let attempt = match self.state.lock_policy.admit(LockAccess::Read)
+            {
+                Ok(()) => try_read_storage_arc(storage.clone()),
+                Err(_) => Err(TryLockError::WouldBlock),
+            };
+
+            match attempt
+            {
+                Ok(guard) => Some(Ok((guard, status_guard))),
+                Err(TryLockError::WouldBlock) =>
+                {
+                    self.on_acquire_failed(LockAccess::Read);
+
+                    None
+                }
+                Err(error) => Some(Err(lock_error(&error, "Failed to aquire read guard"))),
+            }
+        })
+        .await?;
+
+        self.after_acquire(&mut hooks, LockAccess::Read);
+
+        #[cfg(feature = "access_stats")]
+        {
+            hooks.access_scope = Some(AccessScope::enter(&self.state, &*guard));
+        }
+
+        Ok(AsyncStorageReadGuard {
+            inner_guard: SendGuardian(guard),
+            _hooks: hooks,
+        })
+    }
+
+    /// Async version of [StorageHandle::try_write] that waits for the lock instead of failing
+    /// under contention.
+    pub async fn write_async(&self) -> SimpleResult<AsyncStorageWriteGuard<S>>
+    {
+        let mut hooks = self.before_acquire(LockAccess::Write)?;
+        let storage: Arw<S> = self.storage.clone();
+
+        let (guard, _status_guard) = retry_until(|| {
+            // Held until the storage guard has been acquired so that the view can't be cleared in
+            // between, as in [StorageHandle::try_write]
+            let status_guard = match self.try_lock_created_view("write")
+            {
+                Ok(status_guard) => status_guard,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return None,
+                Err(error) => return Some(Err(error)),
+            };
+
+            let attempt = match self.state.lock_policy.admit(LockAccess::Write)
+            {
+                Ok(()) => try_write_storage_arc(storage.clone()),
+                Err(_) => Err(TryLockError::WouldBlock),
+            };
+
+            match attempt
+            {
+                Ok(guard) => Some(Ok((guard, status_guard))),
+                Err(TryLockError::WouldBlock) =>
+                {
+                    self.on_acquire_failed(LockAccess::Write);
+
+                    None
+                }
+                Err(error) => Some(Err(lock_error(&error, "Failed to aquire write guard"))),
+            }
+        })
+        .await?;
+
+        self.after_acquire(&mut hooks, LockAccess::Write);
+        self.state.checkpoints.before_write(guard.as_any());
+
+        #[cfg(feature = "access_stats")]
+        {
+            hooks.access_scope = Some(AccessScope::enter(&self.state, &*guard));
+        }
+
+        Ok(AsyncStorageWriteGuard {
+            inner_guard: SendGuardian(guard),
+            _hooks: hooks,
+        })
+    }
+
+    /// Status guard of a view storage handle, see [ViewStorageController::lock_created_view]
+    fn try_lock_created_view(
+        &self,
+        access: &str,
+    ) -> SimpleResult<Option<RwLockReadGuard<'_, InputStorageLockStatus>>>
+    {
+        match &self.view_storage_controller
+        {
+            Some(view_controller) => Ok(Some(view_controller.lock_created_view(access)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+////////////////////////////////////////////////
+// ViewStorageController
+////////////////////////////////////////////////
+
+// Each async operation probes every lock that the matching sync operation needs and only runs
+// the sync operation once they are all available in the same poll. A lock that is grabbed by
+// another thread between the probe and the sync operation surfaces as the usual sync error.
+impl ViewStorageController
+{
+    pub async fn clear_view_async<Key, Item>(&mut self) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        retry_until(|| {
+            if !self.can_lock_view_storage()
+            {
+                return None;
+            }
+
+            Some(self.clear_view::<Key, Item>())
+        })
+        .await
+    }
+
+    pub async fn set_input_async<Key, Item>(
+        &mut self,
+        input_storage: StorageHandle<dyn Storage>,
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let mut input_storage = Some(input_storage);
+
+        retry_until(|| {
+            if !self.can_lock_view_storage()
+            {
+                return None;
+            }
+
+            let input_storage = input_storage.take()?;
+            Some(self.set_input::<Key, Item>(input_storage))
+        })
+        .await
+    }
+
+    pub async fn create_read_view_async<Key, Item>(
+        &mut self,
+        keys: impl IntoIterator<Item = Key> + 'static,
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        // Collected up front so that the future holds no borrowed iterator across await points
+        let mut keys: Option<Vec<Key>> = Some(keys.into_iter().collect());
+
+        retry_until(|| {
+            if !self.can_lock_view_storage() || !self.can_lock_input::<Key, Item>(false)
+            {
+                return None;
+            }
+
+            let keys = keys.take()?;
+            Some(self.create_read_view::<Key, Item>(keys))
+        })
+        .await
+    }
+
+    pub async fn create_write_view_async<Key, Item>(
+        &mut self,
+        keys: impl IntoIterator<Item = Key> + 'static,
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let mut keys: Option<Vec<Key>> = Some(keys.into_iter().collect());
+
+        retry_until(|| {
+            if !self.can_lock_view_storage() || !self.can_lock_input::<Key, Item>(true)
+            {
+                return None;
+            }
+
+            let keys = keys.take()?;
+            Some(self.create_write_view::<Key, Item>(keys))
+        })
+        .await
+    }
+
+    /// Probe the controller status and view storage locks
+    fn can_lock_view_storage(&self) -> bool
+    {
+        is_free(self.status.try_write()) && is_free(self.view_storage.try_write())
+    }
+
+    /// Probe the input storage lock that a view creation would take out. A view without an input
+    /// is reported as lockable so that the sync operation can report the missing input.
+    fn can_lock_input<Key, Item>(&self, write: bool) -> bool
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let Ok(view_storage) =
+            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(&self.view_storage)
+        else
+        {
+            return true;
+        };
+
+        let input: Option<Arw<dyn Storage>> = {
+            let view_storage_guard = match view_storage.try_read()
+            {
+                Ok(guard) => guard,
+                Err(error) => return is_free(Err(error)),
+            };
+
+            ViewStorageSetup::get_input_storage(&*view_storage_guard)
+        };
+
+        let Some(input) = input
+        else
+        {
+            return true;
+        };
+
+        if write
+        {
+            is_free(input.try_write())
+        }
+        else
+        {
+            is_free(input.try_read())
+        }
+    }
+}
+
+/// Whether a probe found its lock free. Poisoned locks count as free so that the sync operation
+/// reports the poisoning rather than the future retrying forever.
+fn is_free<Guard>(probe: TryLockResult<Guard>) -> bool
+{
+    !matches!(probe, Err(TryLockError::WouldBlock))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{KeyItemStorage, MutItemSliceStorage},
+        storage_types::VecStorage,
+    };
+
+    use crate::storage_traits::Storage;
+
+    /// Minimal executor so that the tests don't depend on any particular runtime
+    fn block_on<F: Future>(future: F) -> F::Output
+    {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut future = pin!(future);
+
+        loop
+        {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx)
+            {
+                return output;
+            }
+
+            std::thread::yield_now();
+        }
+    }
+
+    #[cfg(not(feature = "local"))]
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn read_write_async_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let handle: StorageHandle<VecStorage<usize, i32>> =
+            builder(storage).build().cast_to_sized_storage().unwrap();
+
+        let write_future = handle.write_async();
+        #[cfg(not(feature = "local"))]
+        assert_send(&write_future);
+
+        {
+            let mut guard = block_on(write_future).unwrap();
+            #[cfg(not(feature = "local"))]
+            assert_send(&guard);
+
+            guard.as_mut_slice()[0] = 10;
+
+            // The sync API sees the async guard as a normal lock holder
+            assert!(handle.try_read().is_err());
+        }
+
+        let guard = block_on(handle.read_async()).unwrap();
+        assert_eq!(guard.get(0), Some(&10));
+    }
+
+    #[test]
+    #[cfg(not(feature = "local"))]
+    fn read_async_waits_for_writer_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let handle: StorageHandle<dyn Storage> = builder(storage).build();
+
+        let write_guard = block_on(handle.write_async()).unwrap();
+
+        let reader = {
+            let handle = handle.clone();
+            std::thread::spawn(move || block_on(handle.read_async()).map(|guard| guard.len()))
+        };
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        drop(write_guard);
+
+        assert_eq!(reader.join().unwrap().unwrap(), 3);
+    }
+
+    #[test]
+    fn view_async_test()
+    {
+        use crate::{storage_types::KeyItemViewStorage, ErrorKind};
+
+        let input: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0])).build();
+
+        let mut view_builder =
+            builder(KeyItemViewStorage::<VecStorage<usize, f32>, usize, f32>::new());
+        view_builder.add_view_controller();
+        let mut view = view_builder.build();
+
+        // Fails rather than waits for a view that hasn't been created
+        let error = block_on(view.read_async()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ViewNotReady);
+
+        let controller = view.view_storage_controller_mut().unwrap();
+        controller.set_input::<usize, f32>(input).unwrap();
+        controller.create_read_view::<usize, f32>(vec![1]).unwrap();
+
+        let guard = block_on(view.read_async()).unwrap();
+        assert_eq!(guard.len(), 1);
+
+        // The view can't be cleared while the async guard holds the storage
+        let controller = view.view_storage_controller_mut().unwrap();
+        assert!(controller.clear_view::<usize, f32>().is_err());
+
+        drop(guard);
+        controller.clear_view::<usize, f32>().unwrap();
+    }
+
+    // parking_lot locks aren't poisoned
+    #[test]
+    #[cfg(not(feature = "parking_lot"))]
+    fn poisoned_lock_fails_test()
+    {
+        use crate::ErrorKind;
+
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let handle: StorageHandle<VecStorage<usize, i32>> =
+            builder(storage).build().cast_to_sized_storage().unwrap();
+
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = block_on(handle.write_async()).unwrap();
+            panic!("poison the lock");
+        }));
+
+        let error = block_on(handle.read_async()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Poisoned);
+        assert!(block_on(handle.write_async()).is_err());
+    }
+}
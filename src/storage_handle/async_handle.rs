@@ -0,0 +1,343 @@
+//! A handle variant for async hosts, which holds its storage in a tokio RwLock.
+//!
+//! [AsyncStorageHandle::read] and [AsyncStorageHandle::write] wait for the lock as a future, so a
+//! task that waits for a contended storage yields to its executor instead of blocking the thread.
+//! Casts take the same Key and Item types as those of [StorageHandle] and never wait for the lock:
+//!
+//! ```ignore
+//! let handle = AsyncStorageHandle::new(VecStorage::<usize, f32>::from_vec(vec![0.0; 64]));
+//! let weights = handle.clone().cast_to_slice_storage::<usize, f32>()?;
+//!
+//! tokio::spawn(async move {
+//!     let sum: f32 = weights.read().await.as_item_slice().iter().sum();
+//! });
+//!
+//! handle.cast_to_mut_getitem_storage::<usize, f32>()?.write().await.insert(64, 1.0);
+//! ```
+//
+// # Internal Design
+//
+// - Storages are held in an [AsyncArw] rather than an [crate::Arw], so an async handle and a
+//   [StorageHandle] never share a storage. The guards are tokio's own, which can be held across
+//   await points and moved between executor threads.
+// - A tokio lock is cast with the same pointer cast as a std lock, see
+//   [casting::async_dyn_storage_into_sized]. The concrete storage type is recorded when the handle
+//   is made so that casts don't need to read it through the lock, which could mean waiting.
+// - Views, lock policies, metrics and the rest of the [StorageHandle] bookkeeping aren't supported.
+//   Views hold std guards on their input storage and the bookkeeping is tied to std guards too.
+//   Of the registered casts only those of [casting::register_pointer_cast] apply, as they record
+//   the pointer metadata instead of casting a std lock.
+//
+// [StorageHandle]: super::StorageHandle
+
+use std::{any::TypeId, sync::Arc};
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    casting,
+    storage_traits::{
+        ItemSliceStorage, ItemTrait, ItemTypeIdNoSelf, KeyItemStorage, KeyStorage, KeyTrait,
+        KeyTypeIdNoSelf, MutItemSliceStorage, MutKeyItemStorage, RemovableStorage, Storage,
+    },
+    AsyncArw, ErrorKind, SimpleResult, StorageError,
+};
+
+/// A handle to a storage that async tasks share, see the [module docs](self)
+pub struct AsyncStorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    base_storage: AsyncArw<dyn Storage>,
+    storage: AsyncArw<S>,
+
+    // The concrete type behind base_storage, which casts are checked against
+    storage_type_id: TypeId,
+    storage_type_name: &'static str,
+
+    key_type_id: TypeId,
+    item_type_id: TypeId,
+}
+
+impl<S> Clone for AsyncStorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    fn clone(&self) -> Self
+    {
+        Self {
+            base_storage: self.base_storage.clone(),
+            storage: self.storage.clone(),
+            storage_type_id: self.storage_type_id,
+            storage_type_name: self.storage_type_name,
+            key_type_id: self.key_type_id,
+            item_type_id: self.item_type_id,
+        }
+    }
+}
+
+impl AsyncStorageHandle<dyn Storage>
+{
+    pub fn new<S>(storage: S) -> Self
+    where
+        S: Storage + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    {
+        let storage_type_name = storage.type_name();
+        let base_storage: AsyncArw<dyn Storage> = Arc::new(RwLock::new(storage));
+
+        Self {
+            base_storage: base_storage.clone(),
+            storage: base_storage,
+            storage_type_id: TypeId::of::<S>(),
+            storage_type_name,
+            key_type_id: S::key_type_id(),
+            item_type_id: <S as ItemTypeIdNoSelf>::item_type_id(),
+        }
+    }
+}
+
+/// Casts [AsyncStorageHandle] to AsyncStorageHandle<TargetStorageTrait> with the async casts of
+/// [crate::casting], like the casts of [super::StorageHandle]
+macro_rules! define_cast_async_handle_to_dyn_fn {
+
+    ($fn_name:ident, $inner_fn_name:ident, $target_trait:ty) => {
+        pub fn $fn_name<Key, Item>(self) -> SimpleResult<AsyncStorageHandle<$target_trait>>
+        where
+            Key: KeyTrait,
+            Item: ItemTrait,
+        {
+            // Always from the base storage so that the cast lists aren't instantiated per source
+            // type
+            let storage: AsyncArw<$target_trait> =
+                casting::$inner_fn_name::<dyn Storage, Key, Item>(
+                    &self.base_storage,
+                    self.storage_type_id,
+                    self.storage_type_name,
+                )?;
+
+            Ok(self.with_cast_storage(storage))
+        }
+    };
+}
+
+impl<S> AsyncStorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Wait for a read guard without blocking the executor
+    pub async fn read(&self) -> RwLockReadGuard<'_, S>
+    {
+        self.storage.read().await
+    }
+
+    /// Wait for a write guard without blocking the executor
+    pub async fn write(&self) -> RwLockWriteGuard<'_, S>
+    {
+        self.storage.write().await
+    }
+
+    /// Like [Self::read] but fails with [ErrorKind::WouldBlock] instead of waiting while the
+    /// storage is write locked
+    pub fn try_read(&self) -> SimpleResult<RwLockReadGuard<'_, S>>
+    {
+        self.storage
+            .try_read()
+            .map_err(|_| StorageError::new(ErrorKind::WouldBlock, "Failed to aquire read guard"))
+    }
+
+    /// Like [Self::write] but fails with [ErrorKind::WouldBlock] instead of waiting while the
+    /// storage is locked
+    pub fn try_write(&self) -> SimpleResult<RwLockWriteGuard<'_, S>>
+    {
+        self.storage
+            .try_write()
+            .map_err(|_| StorageError::new(ErrorKind::WouldBlock, "Failed to aquire write guard"))
+    }
+
+    pub fn key_type_id(&self) -> TypeId
+    {
+        self.key_type_id
+    }
+
+    pub fn item_type_id(&self) -> TypeId
+    {
+        self.item_type_id
+    }
+
+    /// Type name of the concrete storage
+    pub fn storage_type_name(&self) -> &'static str
+    {
+        self.storage_type_name
+    }
+
+    /// Whether both handles point to the same storage, whatever they are cast to
+    pub fn ptr_eq<Other>(&self, other: &AsyncStorageHandle<Other>) -> bool
+    where
+        Other: Storage + ?Sized,
+    {
+        Arc::ptr_eq(&self.base_storage, &other.base_storage)
+    }
+
+    // -------------------------------------------------
+    // Casts
+    // -------------------------------------------------
+
+    define_cast_async_handle_to_dyn_fn!(
+        cast_to_key_storage,
+        async_cast_to_key_storage,
+        dyn KeyStorage<Key = Key>
+    );
+    define_cast_async_handle_to_dyn_fn!(
+        cast_to_getitem_storage,
+        async_cast_to_dyn_getkeyitemstorage,
+        dyn KeyItemStorage<Key = Key, Item = Item>
+    );
+    define_cast_async_handle_to_dyn_fn!(
+        cast_to_mut_getitem_storage,
+        async_cast_to_dyn_mutitemstorage,
+        dyn MutKeyItemStorage<Key = Key, Item = Item>
+    );
+    define_cast_async_handle_to_dyn_fn!(
+        cast_to_removable_storage,
+        async_cast_to_dyn_removablestorage,
+        dyn RemovableStorage<Key = Key, Item = Item>
+    );
+    define_cast_async_handle_to_dyn_fn!(
+        cast_to_slice_storage,
+        async_cast_to_dyn_sliceitemstorage,
+        dyn ItemSliceStorage<Item = Item>
+    );
+    define_cast_async_handle_to_dyn_fn!(
+        cast_to_mut_slice_storage,
+        async_cast_to_dyn_mutsliceitemstorage,
+        dyn MutItemSliceStorage<Item = Item>
+    );
+
+    /// Downcast to TargetType where Target type is Sized
+    pub fn cast_to_sized_storage<TargetType>(self) -> SimpleResult<AsyncStorageHandle<TargetType>>
+    where
+        TargetType: Storage,
+    {
+        let storage: AsyncArw<TargetType> = casting::async_dyn_storage_into_sized(
+            &self.base_storage,
+            self.storage_type_id,
+            self.storage_type_name,
+        )?;
+
+        Ok(self.with_cast_storage(storage))
+    }
+
+    fn with_cast_storage<Target>(&self, storage: AsyncArw<Target>) -> AsyncStorageHandle<Target>
+    where
+        Target: Storage + ?Sized,
+    {
+        AsyncStorageHandle {
+            base_storage: self.base_storage.clone(),
+            storage,
+            storage_type_id: self.storage_type_id,
+            storage_type_name: self.storage_type_name,
+            key_type_id: self.key_type_id,
+            item_type_id: self.item_type_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::AsyncStorageHandle;
+    use crate::{
+        storage_traits::KeyItemStorage,
+        storage_types::{HashMapStorage, VecStorage},
+        ErrorKind,
+    };
+
+    /// Minimal executor so that the tests don't depend on the tokio runtime
+    fn block_on<F: Future>(future: F) -> F::Output
+    {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut future = pin!(future);
+
+        loop
+        {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx)
+            {
+                return output;
+            }
+
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn cast_and_access_test()
+    {
+        let handle = AsyncStorageHandle::new(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3]));
+
+        let writer = handle
+            .clone()
+            .cast_to_mut_getitem_storage::<usize, i32>()
+            .unwrap();
+        let slice = handle
+            .clone()
+            .cast_to_slice_storage::<usize, i32>()
+            .unwrap();
+
+        block_on(async {
+            *writer.write().await.get_mut(1).unwrap() = 20;
+            assert_eq!(slice.read().await.as_item_slice(), &[1, 20, 3]);
+        });
+
+        let sized = handle
+            .clone()
+            .cast_to_sized_storage::<VecStorage<usize, i32>>()
+            .unwrap();
+        assert!(sized.ptr_eq(&writer));
+        assert_eq!(sized.try_read().unwrap().get(1), Some(&20));
+
+        // Wrong types fail like the casts of StorageHandle
+        let error = handle
+            .clone()
+            .cast_to_getitem_storage::<usize, f32>()
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::TypeMismatch);
+        assert!(handle
+            .cast_to_sized_storage::<HashMapStorage<usize, i32>>()
+            .is_err());
+    }
+
+    #[test]
+    fn wait_for_writer_test()
+    {
+        let handle = AsyncStorageHandle::new(VecStorage::<usize, i32>::from_vec(vec![0]))
+            .cast_to_getitem_storage::<usize, i32>()
+            .unwrap();
+
+        let guard = handle.try_write().unwrap();
+        assert_eq!(
+            handle.try_read().err().unwrap().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        // Waiting on a write locked storage is pending rather than blocking
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut read = pin!(handle.read());
+        assert!(read.as_mut().poll(&mut cx).is_pending());
+
+        drop(guard);
+
+        let Poll::Ready(guard) = read.as_mut().poll(&mut cx)
+        else
+        {
+            panic!("The read should be ready once the writer is done");
+        };
+        assert_eq!(guard.get(0), Some(&0));
+    }
+}
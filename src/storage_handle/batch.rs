@@ -0,0 +1,204 @@
+//! Many edits to one storage under a single write lock.
+//!
+//! Writing item by item through a handle locks the storage and makes a virtual call per write, and
+//! every write guard released notifies subscribers. [StorageHandle::batch] instead hands a closure
+//! a [BatchEditor] that buffers edits without locking anything. They are then applied in one pass
+//! under one write guard, so subscribers see a single write:
+//!
+//! ```ignore
+//! output.batch::<u64, f32>(|editor| {
+//!     for (key, item) in results
+//!     {
+//!         editor.insert(key, item);
+//!     }
+//!     editor.remove(stale_key);
+//! })?;
+//! ```
+//
+// # Internal Design
+//
+// - Edits are applied in the order they were made. Inserts use the same replace-or-insert as staged
+//   writes so once the write lock is held the batch can't fail midway.
+// - Removals need a [RemovableStorage]. The removable cast is only required when the batch has
+//   removals so that vec like storages still take batches of inserts. Casts are made before locking
+//   so a batch that can't be applied leaves the storage untouched.
+
+use crate::{
+    storage_traits::{ItemTrait, KeyTrait, MutKeyItemStorage, Storage},
+    SimpleResult,
+};
+
+use super::{transaction::apply_staged, StorageHandle};
+
+enum Edit<Key, Item>
+{
+    Insert(Key, Item),
+    Remove(Key),
+    Clear,
+}
+
+/// Buffers the edits of a [StorageHandle::batch]
+pub struct BatchEditor<Key, Item>
+{
+    edits: Vec<Edit<Key, Item>>,
+    has_removals: bool,
+}
+
+impl<Key, Item> BatchEditor<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Replace the item at `key` or insert it if there is none
+    pub fn insert(&mut self, key: Key, item: Item)
+    {
+        self.edits.push(Edit::Insert(key, item));
+    }
+
+    /// Remove the item at `key`. Makes the batch fail unless the storage is removable.
+    pub fn remove(&mut self, key: Key)
+    {
+        self.edits.push(Edit::Remove(key));
+        self.has_removals = true;
+    }
+
+    /// Remove every item, including those inserted earlier in the batch
+    pub fn clear(&mut self)
+    {
+        self.edits.push(Edit::Clear);
+    }
+
+    /// Number of buffered edits
+    pub fn len(&self) -> usize
+    {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.edits.is_empty()
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Buffer the edits made by `edit` and apply them under a single write lock. Nothing is
+    /// applied if the storage can't be cast with the given Key and Item, can't remove items while
+    /// the batch has removals or can't be locked.
+    pub fn batch<Key, Item>(
+        &self,
+        edit: impl FnOnce(&mut BatchEditor<Key, Item>),
+    ) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let mut editor = BatchEditor {
+            edits: Vec::new(),
+            has_removals: false,
+        };
+
+        edit(&mut editor);
+
+        if editor.edits.is_empty()
+        {
+            return Ok(());
+        }
+
+        if editor.has_removals
+        {
+            let handle = self.clone().cast_to_removable_storage::<Key, Item>()?;
+            let mut guard = handle.try_write()?;
+
+            apply_edits(&mut *guard, editor.edits, |storage, key| {
+                storage.remove(key);
+            });
+        }
+        else
+        {
+            let handle = self.clone().cast_to_mut_getitem_storage::<Key, Item>()?;
+            let mut guard = handle.try_write()?;
+
+            apply_edits(&mut *guard, editor.edits, |_, _| {
+                unreachable!("Batches with removals are applied to removable storages")
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn apply_edits<T, Key, Item>(
+    storage: &mut T,
+    edits: Vec<Edit<Key, Item>>,
+    mut remove: impl FnMut(&mut T, Key),
+) where
+    T: MutKeyItemStorage<Key = Key, Item = Item> + ?Sized,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    for edit in edits
+    {
+        match edit
+        {
+            Edit::Insert(key, item) => apply_staged(storage, std::iter::once((key, item))),
+            Edit::Remove(key) => remove(storage, key),
+            Edit::Clear => storage.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::{HashMapStorage, VecStorage},
+    };
+
+    #[test]
+    fn batch_test()
+    {
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3])).build();
+        let reader = handle
+            .clone()
+            .cast_to_getitem_storage::<usize, i32>()
+            .unwrap();
+        let items = || -> Vec<i32> { reader.try_read().unwrap().item_iter().copied().collect() };
+
+        handle
+            .batch::<usize, i32>(|editor| {
+                editor.insert(0, 10);
+                editor.insert(3, 4);
+            })
+            .unwrap();
+        assert_eq!(items(), vec![10, 2, 3, 4]);
+        assert_eq!(handle.write_version(), 1);
+
+        // A vec removes by shifting the items after the key down
+        handle
+            .batch::<usize, i32>(|editor| {
+                editor.remove(0);
+                editor.insert(3, 40);
+            })
+            .unwrap();
+        assert_eq!(items(), vec![2, 3, 4, 40]);
+
+        let handle: StorageHandle<dyn Storage> = builder(HashMapStorage::<u32, i32>::new()).build();
+        handle
+            .batch::<u32, i32>(|editor| {
+                editor.insert(1, 1);
+                editor.insert(2, 2);
+                editor.remove(1);
+            })
+            .unwrap();
+
+        let reader = handle.cast_to_getitem_storage::<u32, i32>().unwrap();
+        let guard = reader.try_read().unwrap();
+        assert_eq!((guard.get(1), guard.get(2)), (None, Some(&2)));
+    }
+}
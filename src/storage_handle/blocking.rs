@@ -0,0 +1,185 @@
+//! Blocking reads and writes, for threads that would rather wait for a contended lock than retry
+//! later themselves.
+//!
+//! [StorageHandle::read] and [StorageHandle::write] wait until the lock is free while
+//! [StorageHandle::read_timeout] and [StorageHandle::write_timeout] give up at a deadline with an
+//! error of [ErrorKind::WouldBlock]:
+//!
+//! ```ignore
+//! let positions = positions.read()?;
+//!
+//! match output.write_timeout(Duration::from_millis(5))
+//! {
+//!     Ok(mut guard) => guard.insert(key, item),
+//!     Err(error) if error.kind() == ErrorKind::WouldBlock => skip_this_frame(),
+//!     Err(error) => return Err(error),
+//! }
+//! ```
+//!
+//! Errors other than contention, such as a poisoned lock or a view that hasn't been created, are
+//! returned straight away. Waiting for a lock that the same thread holds never succeeds, so
+//! [StorageHandle::read] and [StorageHandle::write] block forever in that case.
+//
+// # Internal Design
+//
+// - Waits retry [StorageHandle::try_read] and [StorageHandle::try_write] rather than calling the
+//   blocking std lock methods, so that every acquisition still goes through the lock policy, lock
+//   ordering, metrics and the other guard bookkeeping. Each failed attempt also refreshes the
+//   thread's intent with the [super::lock_policy::LockPolicy], which lets a waiting writer get its
+//   turn.
+// - Locks are released without a notification to wait on, so waits back off from spinning to
+//   yielding to short sleeps, which keeps short waits fast and long waits cheap.
+
+use std::{
+    ops::{Deref, DerefMut},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{storage_traits::Storage, ErrorKind, SimpleResult};
+
+use super::StorageHandle;
+
+/// Longest sleep between attempts, which bounds how late a waiter notices a released lock
+const MAX_BACKOFF_SLEEP: Duration = Duration::from_millis(1);
+
+const SPIN_ATTEMPTS: u32 = 16;
+const YIELD_ATTEMPTS: u32 = 32;
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Like [Self::try_read] but waits while the storage is write locked
+    pub fn read(&self) -> SimpleResult<impl Deref<Target = S> + '_>
+    {
+        retry_until(None, || self.try_read())
+    }
+
+    /// Like [Self::try_write] but waits while the storage is locked
+    pub fn write(&self) -> SimpleResult<impl DerefMut<Target = S> + '_>
+    {
+        retry_until(None, || self.try_write())
+    }
+
+    /// Like [Self::read] but fails with [ErrorKind::WouldBlock] once `timeout` has passed
+    pub fn read_timeout(&self, timeout: Duration) -> SimpleResult<impl Deref<Target = S> + '_>
+    {
+        retry_until(Some(timeout), || self.try_read())
+    }
+
+    /// Like [Self::write] but fails with [ErrorKind::WouldBlock] once `timeout` has passed
+    pub fn write_timeout(&self, timeout: Duration) -> SimpleResult<impl DerefMut<Target = S> + '_>
+    {
+        retry_until(Some(timeout), || self.try_write())
+    }
+}
+
+/// Repeat `attempt` while it fails with [ErrorKind::WouldBlock], until `timeout` if there is one
+fn retry_until<Guard>(
+    timeout: Option<Duration>,
+    mut attempt: impl FnMut() -> SimpleResult<Guard>,
+) -> SimpleResult<Guard>
+{
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut attempts: u32 = 0;
+
+    loop
+    {
+        let error = match attempt()
+        {
+            Err(error) if error.kind() == ErrorKind::WouldBlock => error,
+            result => return result,
+        };
+
+        let remaining = match deadline
+        {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => MAX_BACKOFF_SLEEP,
+        };
+
+        if remaining.is_zero()
+        {
+            let timeout = timeout.unwrap_or_default();
+            return Err(error.context(format!("Timed out after {timeout:?} waiting for the lock")));
+        }
+
+        attempts = attempts.saturating_add(1);
+
+        if attempts <= SPIN_ATTEMPTS
+        {
+            std::hint::spin_loop();
+        }
+        else if attempts <= SPIN_ATTEMPTS + YIELD_ATTEMPTS
+        {
+            thread::yield_now();
+        }
+        else
+        {
+            thread::sleep(remaining.min(MAX_BACKOFF_SLEEP));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::time::Duration;
+
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{KeyItemStorage, MutKeyItemStorage, Storage},
+        storage_types::VecStorage,
+        ErrorKind,
+    };
+
+    #[test]
+    fn timeout_test()
+    {
+        let handle: StorageHandle<VecStorage<usize, i32>> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2]))
+                .build()
+                .cast_to_sized_storage()
+                .unwrap();
+
+        let guard = handle.try_write().unwrap();
+
+        let error = handle.read_timeout(Duration::from_millis(5)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::WouldBlock);
+        assert!(handle.write_timeout(Duration::ZERO).is_err());
+
+        drop(guard);
+
+        handle.write().unwrap().insert(2, 3);
+        assert_eq!(handle.read().unwrap().get(2), Some(&3));
+        assert_eq!(
+            handle.read_timeout(Duration::from_millis(5)).unwrap().len(),
+            3
+        );
+    }
+
+    #[cfg(not(feature = "local"))]
+    #[test]
+    fn wait_for_writer_test()
+    {
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![0])).build();
+        let writer = handle
+            .clone()
+            .cast_to_mut_getitem_storage::<usize, i32>()
+            .unwrap();
+        let reader = handle.cast_to_getitem_storage::<usize, i32>().unwrap();
+
+        let mut guard = writer.try_write().unwrap();
+
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| *reader.read().unwrap().get(0).unwrap());
+
+            std::thread::sleep(Duration::from_millis(10));
+            guard.insert(0, 7);
+            drop(guard);
+
+            assert_eq!(reader.join().unwrap(), 7);
+        });
+    }
+}
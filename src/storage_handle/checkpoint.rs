@@ -0,0 +1,240 @@
+//! Restore a storage to an earlier state, eg the outputs of a node evaluation that failed midway.
+//!
+//! [StorageHandle::checkpoint] marks the current state of a cloneable storage and
+//! [StorageHandle::rollback] restores it:
+//!
+//! ```ignore
+//! let checkpoint = outputs.checkpoint()?;
+//!
+//! if let Err(error) = evaluate(&inputs, &outputs)
+//! {
+//!     outputs.rollback(checkpoint)?;
+//! }
+//!
+//! outputs.release_checkpoint(checkpoint);
+//! ```
+//
+// # Internal Design
+//
+// - Checkpoints are copy on write. Taking one only records an id and the storage is copied when the
+//   next write guard is acquired through any clone or cast of the handle. A storage that is never
+//   written after a checkpoint is never copied, and checkpoints taken without a write in between
+//   share one copy.
+// - The copy is taken through [Storage]'s downcast support with a function monomorphized for the
+//   concrete storage type when the checkpoint was taken, as the writer may only hold a trait object
+//   handle.
+// - Like [StorageHandle::write_version], writes made to an input storage through a write view don't
+//   acquire a write guard from the input's handle so they are not seen by checkpoints.
+
+use std::{
+    any::Any,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use crate::{storage_traits::Storage, ErrorKind, SimpleResult, StorageError};
+
+use super::StorageHandle;
+
+type CopyFn = fn(&dyn Any) -> Option<Arc<dyn Storage>>;
+
+/// Identifies a checkpoint taken with [StorageHandle::checkpoint]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CheckpointId(u64);
+
+#[derive(Default)]
+pub(crate) struct Checkpoints
+{
+    /// Whether a checkpoint is waiting for its copy, so that writes without checkpoints don't lock
+    pending: AtomicBool,
+
+    list: Mutex<CheckpointList>,
+}
+
+#[derive(Default)]
+struct CheckpointList
+{
+    next_id: u64,
+    copy: Option<CopyFn>,
+
+    /// Oldest first. The copy is None until the storage is written after the checkpoint.
+    entries: Vec<(CheckpointId, Option<Arc<dyn Storage>>)>,
+}
+
+impl Checkpoints
+{
+    fn lock(&self) -> MutexGuard<'_, CheckpointList>
+    {
+        // The list is never left half updated so a poisoned lock is still valid
+        self.list
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Copy the storage for pending checkpoints. Called with the write lock held, before the
+    /// writer can change the storage.
+    pub(crate) fn before_write(&self, storage: &dyn Any)
+    {
+        if !self.pending.load(Ordering::Acquire)
+        {
+            return;
+        }
+
+        let mut list = self.lock();
+        self.pending.store(false, Ordering::Release);
+
+        let Some(copy) = list.copy
+        else
+        {
+            return;
+        };
+
+        if list.entries.iter().all(|(_, copy)| copy.is_some())
+        {
+            return;
+        }
+
+        let copy = copy(storage);
+
+        for (_, entry) in list.entries.iter_mut().filter(|(_, copy)| copy.is_none())
+        {
+            entry.clone_from(&copy);
+        }
+    }
+}
+
+fn copy_storage<S>(storage: &dyn Any) -> Option<Arc<dyn Storage>>
+where
+    S: Storage + Clone,
+{
+    let storage: Arc<dyn Storage> = Arc::new(storage.downcast_ref::<S>()?.clone());
+    Some(storage)
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + Clone,
+{
+    /// Mark the current state of the storage so that it can be restored with
+    /// [StorageHandle::rollback]. Briefly takes a read lock so that no write is in progress.
+    pub fn checkpoint(&self) -> SimpleResult<CheckpointId>
+    {
+        let _guard = self.try_read()?;
+        let mut list = self.state.checkpoints.lock();
+
+        let id = CheckpointId(list.next_id);
+        list.next_id += 1;
+        list.copy = Some(copy_storage::<S>);
+        list.entries.push((id, None));
+
+        self.state
+            .checkpoints
+            .pending
+            .store(true, Ordering::Release);
+
+        Ok(id)
+    }
+
+    /// Restore the storage to its state when `checkpoint` was taken. Checkpoints taken after it
+    /// are released while `checkpoint` stays valid so it can be rolled back to again.
+    pub fn rollback(&self, checkpoint: CheckpointId) -> SimpleResult<()>
+    {
+        let copy = {
+            // Keeps writers out while the checkpoint list is inspected
+            let _guard = self.try_read()?;
+            let mut list = self.state.checkpoints.lock();
+
+            let index = list
+                .entries
+                .iter()
+                .position(|(id, _)| *id == checkpoint)
+                .ok_or_else(|| {
+                    StorageError::new(
+                        ErrorKind::NotRegistered,
+                        format!("Checkpoint {checkpoint:?} does not exist"),
+                    )
+                })?;
+
+            list.entries.truncate(index + 1);
+
+            match &list.entries[index].1
+            {
+                Some(copy) => copy.clone(),
+
+                // Not written since the checkpoint
+                None => return Ok(()),
+            }
+        };
+
+        let copy = copy
+            .downcast_ref::<S>()
+            .ok_or("Checkpoint copy is not of the handle's storage type")?
+            .clone();
+
+        *self.try_write()? = copy;
+
+        Ok(())
+    }
+
+    /// Release a checkpoint and its copy if no other checkpoint shares it
+    pub fn release_checkpoint(&self, checkpoint: CheckpointId)
+    {
+        self.state
+            .checkpoints
+            .lock()
+            .entries
+            .retain(|(id, _)| *id != checkpoint);
+    }
+
+    /// Number of checkpoints that have not been released
+    pub fn checkpoint_count(&self) -> usize
+    {
+        self.state.checkpoints.lock().entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{ItemSliceStorage, MutItemSliceStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn checkpoint_rollback_test()
+    {
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2])).build();
+        let sized: StorageHandle<VecStorage<usize, i32>> =
+            handle.clone().cast_to_sized_storage().unwrap();
+        let writer = handle.cast_to_mut_getitem_storage::<usize, i32>().unwrap();
+
+        let first = sized.checkpoint().unwrap();
+        // Shares the copy of the first checkpoint
+        let shared = sized.checkpoint().unwrap();
+        assert!(sized.state.checkpoints.lock().entries[0].1.is_none());
+
+        // Copied on the first write, even through a trait object handle
+        writer.try_write().unwrap().insert(2, 3);
+        assert!(sized.state.checkpoints.lock().entries[1].1.is_some());
+
+        let second = sized.checkpoint().unwrap();
+        sized.try_write().unwrap().as_mut_slice()[0] = 10;
+
+        sized.rollback(second).unwrap();
+        assert_eq!(sized.try_read().unwrap().as_item_slice(), &[1, 2, 3]);
+
+        sized.rollback(shared).unwrap();
+        assert_eq!(sized.try_read().unwrap().as_item_slice(), &[1, 2]);
+        assert_eq!(sized.checkpoint_count(), 2);
+        assert!(sized.rollback(second).is_err());
+
+        sized.release_checkpoint(first);
+        sized.release_checkpoint(shared);
+        assert_eq!(sized.checkpoint_count(), 0);
+    }
+}
@@ -0,0 +1,265 @@
+//! Consistent reads of several storages that are updated together, without locking them all.
+//!
+//! A node that writes several output storages leaves readers able to see some outputs from its
+//! latest evaluation and some from the one before. A [ConsistencyGroup] tags those outputs as one
+//! group. The writer wraps each coordinated update in [ConsistencyGroup::begin_update] and
+//! [EpochUpdate::complete], which stamps the group with a new epoch. Readers then read through
+//! [ConsistencyGroup::read_consistent], which retries until every member was read at the same
+//! epoch:
+//!
+//! ```ignore
+//! let outputs = ConsistencyGroup::new(vec![positions.clone(), velocities.clone()]);
+//!
+//! // Writer
+//! let update = outputs.begin_update()?;
+//! write_positions(&positions)?;
+//! write_velocities(&velocities)?;
+//! update.complete();
+//!
+//! // Reader
+//! let (positions, velocities) = outputs.read_consistent(|members| {
+//!     Ok((read_positions(&members[0])?, read_velocities(&members[1])?))
+//! })?;
+//! ```
+//
+// # Internal Design
+//
+// - Completing an update stamps the [StorageHandle::write_version] of every member. A read is
+//   consistent when no update was in progress, the epoch didn't change while reading and every
+//   member is still at its stamped version before and after the read. Write versions are bumped
+//   when a write lock is acquired, so a write that overlaps the read is detected even if the reader
+//   got its read lock first.
+// - Writes to a member made outside of an update, or an update dropped without being completed,
+//   leave the member off its stamped version. Reads keep failing until the next completed update
+//   rather than returning a mix of epochs.
+// - Readers take their own read locks one member at a time, so a read never blocks the writer and
+//   the writer never has to hold every lock at once. The cost is that readers retry.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex, MutexGuard,
+};
+
+use crate::{storage_traits::Storage, SimpleResult};
+
+use super::StorageHandle;
+
+/// Attempts made by [ConsistencyGroup::read_consistent] before giving up
+pub const CONSISTENT_READ_ATTEMPTS: usize = 64;
+
+/// Handles whose storages are updated together, see the [module docs](self). Clones share the same
+/// group.
+#[derive(Clone)]
+pub struct ConsistencyGroup
+{
+    state: Arc<GroupState>,
+}
+
+struct GroupState
+{
+    members: Vec<StorageHandle<dyn Storage>>,
+
+    /// Number of completed updates
+    epoch: AtomicU64,
+
+    updating: AtomicBool,
+
+    /// Write version of each member when the last update was completed
+    stamped_versions: Mutex<Vec<u64>>,
+}
+
+impl ConsistencyGroup
+{
+    /// A group at epoch 0 with the current contents of `members` taken as consistent
+    pub fn new(members: Vec<StorageHandle<dyn Storage>>) -> Self
+    {
+        let stamped_versions = members.iter().map(StorageHandle::write_version).collect();
+
+        Self {
+            state: Arc::new(GroupState {
+                members,
+                epoch: AtomicU64::new(0),
+                updating: AtomicBool::new(false),
+                stamped_versions: Mutex::new(stamped_versions),
+            }),
+        }
+    }
+
+    pub fn members(&self) -> &[StorageHandle<dyn Storage>]
+    {
+        &self.state.members
+    }
+
+    /// Number of updates completed since the group was created
+    pub fn epoch(&self) -> u64
+    {
+        self.state.epoch.load(Ordering::Acquire)
+    }
+
+    /// Whether no update is in progress and no member has been written since the last update was
+    /// completed
+    pub fn is_consistent(&self) -> bool
+    {
+        !self.state.updating.load(Ordering::Acquire) && self.state.members_at_stamped_versions()
+    }
+
+    /// Start a coordinated update of the members. Fails if another update is in progress.
+    pub fn begin_update(&self) -> SimpleResult<EpochUpdate<'_>>
+    {
+        self.state
+            .updating
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .map_err(|_| "Cannot begin an update while another is in progress".to_string())?;
+
+        Ok(EpochUpdate { group: self })
+    }
+
+    /// Call `read` with the members until it has read all of them at the same epoch, returning
+    /// the epoch along with what it read. `read` is called again whenever the members changed
+    /// while it was reading, so it should only read. Errors from `read` are returned straight
+    /// away. Fails after [CONSISTENT_READ_ATTEMPTS] inconsistent attempts.
+    pub fn read_consistent<T>(
+        &self,
+        mut read: impl FnMut(&[StorageHandle<dyn Storage>]) -> SimpleResult<T>,
+    ) -> SimpleResult<(u64, T)>
+    {
+        for _ in 0..CONSISTENT_READ_ATTEMPTS
+        {
+            let epoch = self.epoch();
+
+            if self.is_consistent()
+            {
+                let value = read(&self.state.members)?;
+
+                if self.is_consistent() && self.epoch() == epoch
+                {
+                    return Ok((epoch, value));
+                }
+            }
+
+            std::thread::yield_now();
+        }
+
+        Err(format!(
+            "Could not read the members of the consistency group at the same epoch after \
+             {CONSISTENT_READ_ATTEMPTS} attempts"
+        )
+        .into())
+    }
+}
+
+impl GroupState
+{
+    fn lock_stamped_versions(&self) -> MutexGuard<'_, Vec<u64>>
+    {
+        // The stamps are plain versions that are replaced as a whole so poisoning can be ignored
+        self.stamped_versions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn members_at_stamped_versions(&self) -> bool
+    {
+        self.members
+            .iter()
+            .zip(self.lock_stamped_versions().iter())
+            .all(|(member, stamped_version)| member.write_version() == *stamped_version)
+    }
+}
+
+/// An update of a [ConsistencyGroup] in progress. Readers of the group retry until it is
+/// completed. Dropping it without completing it ends the update without stamping a new epoch.
+pub struct EpochUpdate<'a>
+{
+    group: &'a ConsistencyGroup,
+}
+
+impl EpochUpdate<'_>
+{
+    /// Stamp the current state of the members as a new epoch, returning the epoch
+    pub fn complete(self) -> u64
+    {
+        let state = &self.group.state;
+
+        *state.lock_stamped_versions() = state
+            .members
+            .iter()
+            .map(StorageHandle::write_version)
+            .collect();
+
+        state.epoch.fetch_add(1, Ordering::AcqRel) + 1
+    }
+}
+
+impl Drop for EpochUpdate<'_>
+{
+    fn drop(&mut self)
+    {
+        // An update that wasn't completed keeps its stamps from the last completed update
+        self.group.state.updating.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::ConsistencyGroup;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::VecStorage,
+    };
+
+    fn set(handle: &StorageHandle<dyn Storage>, item: i32)
+    {
+        let handle = handle
+            .clone()
+            .cast_to_mut_getitem_storage::<usize, i32>()
+            .unwrap();
+        *handle.try_write().unwrap().get_mut(0).unwrap() = item;
+    }
+
+    fn get(handle: &StorageHandle<dyn Storage>) -> i32
+    {
+        let handle = handle
+            .clone()
+            .cast_to_getitem_storage::<usize, i32>()
+            .unwrap();
+        let item = *handle.try_read().unwrap().get(0).unwrap();
+        item
+    }
+
+    #[test]
+    fn consistency_group_test()
+    {
+        let a: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![0])).build();
+        let b: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![0])).build();
+        let group = ConsistencyGroup::new(vec![a.clone(), b.clone()]);
+        let read_both =
+            |members: &[StorageHandle<dyn Storage>]| Ok((get(&members[0]), get(&members[1])));
+
+        assert_eq!(group.read_consistent(read_both).unwrap(), (0, (0, 0)));
+
+        // Halfway through an update the members can't be read together
+        let update = group.begin_update().unwrap();
+        assert!(group.begin_update().is_err());
+        set(&a, 1);
+        assert!(group.read_consistent(read_both).is_err());
+        set(&b, 1);
+        assert_eq!(update.complete(), 1);
+
+        assert_eq!(group.read_consistent(read_both).unwrap(), (1, (1, 1)));
+
+        // A write outside of an update breaks consistency until the next update
+        set(&a, 2);
+        assert!(!group.is_consistent());
+        assert!(group.read_consistent(read_both).is_err());
+
+        let update = group.begin_update().unwrap();
+        set(&b, 2);
+        update.complete();
+        assert_eq!(group.read_consistent(read_both).unwrap(), (2, (2, 2)));
+    }
+}
@@ -1,18 +1,65 @@
 //! Storage guards to provide RAII read and write access to storage types.
 //!
 //! # Internal Design 
-//! - They are currently light weight wrapper guards around std [RwLockReadGuard] and [RwLockWriteGuard] 
+//! - They are currently light weight wrapper guards around the [StorageLockReadGuard] and 
+//!   [StorageLockWriteGuard], see [crate::sync::StorageLock]
 //! - They serve as future proofing architecture in case custom code needs to be run when a guard is taken 
 //!   out or dropped or for runtime tracking or debugging purposes.
+//! - With the `parking_lot` feature guards can be narrowed to part of their storage with
+//!   [StorageReadGuard::map] and [StorageWriteGuard::map]. The mapped guards keep the bookkeeping
+//!   of the guard they were mapped from so it is still released along with the lock.
 
-use std::{sync::{RwLockReadGuard, RwLockWriteGuard}, ops::{Deref, DerefMut}};
-use crate::storage_traits::Storage;
+use std::ops::{Deref, DerefMut};
+use crate::{storage_traits::Storage, sync::{StorageLockReadGuard, StorageLockWriteGuard}};
+
+#[cfg(feature = "deadlock_detection")]
+use crate::diagnostics::deadlock::LockRecord;
+
+#[cfg(debug_assertions)]
+use super::lock_order::RankRecord;
+
+use super::{metrics::HoldRecord, write_signal::WriteRelease};
+
+#[cfg(feature = "access_stats")]
+use super::access_stats::AccessScope;
+
+#[cfg(feature = "tracing")]
+use crate::diagnostics::trace::ReleaseRecord;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockAccess
+{
+    Read,
+    Write,
+}
+
+/// Bookkeeping attached to a guard that is released when the guard is dropped. Each record
+/// undoes its own registration in its Drop impl.
+#[derive(Default)]
+pub(crate) struct GuardHooks
+{
+    #[cfg(feature = "deadlock_detection")]
+    pub(crate) lock_record: Option<LockRecord>,
+
+    #[cfg(debug_assertions)]
+    pub(crate) rank_record: Option<RankRecord>,
+
+    pub(crate) write_release: Option<WriteRelease>,
+
+    pub(crate) hold_record: Option<HoldRecord>,
+
+    #[cfg(feature = "access_stats")]
+    pub(crate) access_scope: Option<AccessScope>,
+
+    #[cfg(feature = "tracing")]
+    pub(crate) release_record: Option<ReleaseRecord>,
+}
 
 ////////////////////////////////////////////////
 // Storage Read Guard
 ////////////////////////////////////////////////
 
-/// A Wrapper guard around [std::sync::RwLockReadGuard] so that 
+/// A Wrapper guard around [StorageLockReadGuard] so that 
 /// a custom drop function can be called to trigger the release of 
 /// indirectly borrowed / locked resources. 
 ///
@@ -22,14 +69,24 @@ pub struct StorageReadGuard<'a, S>
 where
 S: Storage + ?Sized + 'a,
 {
-    inner_guard: RwLockReadGuard<'a, S>
+    inner_guard: StorageLockReadGuard<'a, S>,
+
+    // Declared after the inner guard so that the hooks only run once the lock is released
+    hooks: GuardHooks,
 }
 
 impl<'a, S> StorageReadGuard<'a, S> 
 where
 S: Storage + ?Sized + 'a,
 {
-    pub fn new(inner_guard: RwLockReadGuard<'a, S>) -> Self { Self { inner_guard } }
+    pub fn new(inner_guard: StorageLockReadGuard<'a, S>) -> Self { 
+        Self { inner_guard, hooks: <_>::default() } 
+    }
+
+    pub(crate) fn with_hooks(mut self, hooks: GuardHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
 }
 
 impl<'a, S> Deref for StorageReadGuard<'a, S> 
@@ -43,6 +100,49 @@ S: Storage + ?Sized,
     }
 }
 
+#[cfg(feature = "parking_lot")]
+impl<'a, S> StorageReadGuard<'a, S> 
+where
+S: Storage + ?Sized + 'a,
+{
+    /// Narrow the guard to a part of the storage, such as one of its items. An associated function
+    /// so that it doesn't shadow a `map` of the storage.
+    pub fn map<T, F>(guard: Self, f: F) -> MappedStorageReadGuard<'a, T>
+    where
+        T: ?Sized,
+        F: FnOnce(&S) -> &T,
+    {
+        MappedStorageReadGuard {
+            inner_guard: parking_lot::RwLockReadGuard::map(guard.inner_guard.inner, f),
+            _hooks: guard.hooks,
+        }
+    }
+}
+
+/// A [StorageReadGuard] narrowed to part of its storage by [StorageReadGuard::map]
+#[cfg(feature = "parking_lot")]
+pub struct MappedStorageReadGuard<'a, T> 
+where
+T: ?Sized + 'a,
+{
+    inner_guard: parking_lot::MappedRwLockReadGuard<'a, T>,
+
+    // Declared after the inner guard so that the hooks only run once the lock is released
+    _hooks: GuardHooks,
+}
+
+#[cfg(feature = "parking_lot")]
+impl<'a, T> Deref for MappedStorageReadGuard<'a, T> 
+where
+T: ?Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner_guard
+    }
+}
+
 ////////////////////////////////////////////////
 // Storage Write Guard
 ////////////////////////////////////////////////
@@ -52,14 +152,24 @@ pub struct StorageWriteGuard<'a, S>
 where
 S: Storage + ?Sized + 'a,
 {
-    inner_guard: RwLockWriteGuard<'a, S>
+    inner_guard: StorageLockWriteGuard<'a, S>,
+
+    // Declared after the inner guard so that the hooks only run once the lock is released
+    hooks: GuardHooks,
 }
 
 impl<'a, S> StorageWriteGuard<'a, S> 
 where
 S: Storage + ?Sized + 'a,
 {
-    pub fn new(inner_guard: RwLockWriteGuard<'a, S>) -> Self { Self { inner_guard } }
+    pub fn new(inner_guard: StorageLockWriteGuard<'a, S>) -> Self { 
+        Self { inner_guard, hooks: <_>::default() } 
+    }
+
+    pub(crate) fn with_hooks(mut self, hooks: GuardHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
 }
 
 impl<'a, S> Deref for StorageWriteGuard<'a, S> 
@@ -83,3 +193,55 @@ S: Storage + ?Sized,
     }
 }
 
+#[cfg(feature = "parking_lot")]
+impl<'a, S> StorageWriteGuard<'a, S> 
+where
+S: Storage + ?Sized + 'a,
+{
+    /// Narrow the guard to a part of the storage, see [StorageReadGuard::map]
+    pub fn map<T, F>(guard: Self, f: F) -> MappedStorageWriteGuard<'a, T>
+    where
+        T: ?Sized,
+        F: FnOnce(&mut S) -> &mut T,
+    {
+        MappedStorageWriteGuard {
+            inner_guard: parking_lot::RwLockWriteGuard::map(guard.inner_guard.inner, f),
+            _hooks: guard.hooks,
+        }
+    }
+}
+
+/// A [StorageWriteGuard] narrowed to part of its storage by [StorageWriteGuard::map]
+#[cfg(feature = "parking_lot")]
+pub struct MappedStorageWriteGuard<'a, T> 
+where
+T: ?Sized + 'a,
+{
+    inner_guard: parking_lot::MappedRwLockWriteGuard<'a, T>,
+
+    // Declared after the inner guard so that the hooks only run once the lock is released
+    _hooks: GuardHooks,
+}
+
+#[cfg(feature = "parking_lot")]
+impl<'a, T> Deref for MappedStorageWriteGuard<'a, T> 
+where
+T: ?Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner_guard
+    }
+}
+
+#[cfg(feature = "parking_lot")]
+impl<'a, T> DerefMut for MappedStorageWriteGuard<'a, T> 
+where
+T: ?Sized,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner_guard
+    }
+}
+
@@ -1,19 +1,47 @@
 use std::{
-    any::TypeId,
-    ops::{Deref, DerefMut},
-    sync::{Arc, RwLock},
+    any::{type_name, TypeId},
+    sync::Arc,
+    time::Instant,
 };
 
 use crate::{
-    casting,
+    casting, error::lock_error, sync,
     storage_traits::{
-        ItemSliceStorage, ItemTrait, KeyItemStorage, KeyStorage, KeyTrait, MutKeyItemStorage,
-        Storage, ViewStorageSetup, KeyTypeIdNoSelf, ItemTypeIdNoSelf,
+        AllocKeyStorage, ExtendStorage, ForEachStorage, ItemSliceStorage, ItemStorage, ItemTrait,
+        KeyItemStorage, KeyStorage,
+        KeyTrait, MutKeyItemStorage,
+        RemovableStorage, Storage, ViewStorageSetup, KeyTypeIdNoSelf, ItemTypeIdNoSelf,
     },
-    Arw, SimpleResult, storage_types::VecStorage,
+    Arw, ErrorDetail, ErrorKind, Hint, Rw, SimpleResult, StorageError,
+    storage_types::{
+        HashMapStorage, KeyItemViewStorage, ShardedHashMapStorage, SparseSetVecStorage, ValStorage,
+        VecStorage,
+    },
+};
+
+use super::{
+    checkpoint::Checkpoints,
+    info::TypeInfo,
+    lock_policy::{LockPolicy, PolicyState},
+    metrics::{HoldRecord, MetricsState},
+    read_cache::ReadCacheId,
+    subscription::Subscribers,
+    write_signal::{WriteRelease, WriteSignal},
+    GuardHooks, InputStorageLockStatus, LockAccess, StorageReadGuard, StorageWriteGuard,
+    ViewStorageController,
 };
 
-use super::{InputStorageLockStatus, ViewStorageController};
+#[cfg(feature = "deadlock_detection")]
+use crate::diagnostics::deadlock;
+
+#[cfg(feature = "tracing")]
+use crate::diagnostics::trace;
+
+#[cfg(feature = "access_stats")]
+use super::access_stats::{AccessCounters, AccessScope};
+
+#[cfg(debug_assertions)]
+use super::lock_order;
 
 /// A Smart Pointer to any Storage type that implements [crate::storage_traits::Storage].
 ///
@@ -107,14 +135,69 @@ where
 
     // A flexible internal pointer that can contain either a sized storage type or
     // a storage trait object such as a storage supertrait
-    storage: Arw<S>,
+    pub(super) storage: Arw<S>,
 
-    view_storage_controller: Option<ViewStorageController>,
+    pub(super) view_storage_controller: Option<ViewStorageController>,
+
+    // Shared by all clones and casts of this handle
+    pub(super) state: Arc<HandleState>,
 
     key_type_id: TypeId,
     item_type_id: TypeId,
 }
 
+/// Identifies the storage that a [StorageHandle] points to. All clones and casts of a handle share
+/// the same id as it is derived from the address of the shared storage allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StorageId(usize);
+
+impl StorageId
+{
+    pub fn of<S>(storage: &Arw<S>) -> Self
+    where
+        S: Storage + ?Sized,
+    {
+        Self(Arc::as_ptr(storage) as *const () as usize)
+    }
+}
+
+impl std::fmt::Display for StorageId
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+/// State that is shared between all clones and casts of a [StorageHandle]
+#[derive(Default)]
+pub(crate) struct HandleState
+{
+    pub(crate) label: Option<String>,
+    pub(crate) lock_rank: Option<u32>,
+    pub(crate) lock_policy: PolicyState,
+
+    /// Some when the thread local read cache is enabled
+    pub(crate) read_cache: Option<ReadCacheId>,
+
+    /// Some when lock metrics are enabled
+    pub(crate) metrics: Option<MetricsState>,
+
+    pub(crate) write_signal: WriteSignal,
+    pub(crate) subscribers: Subscribers,
+    pub(crate) checkpoints: Checkpoints,
+
+    /// Some when built through a [StorageHandleBuilder]
+    pub(crate) type_info: Option<TypeInfo>,
+
+    /// True when this state is the only one counting writes to the storage, which is the case when
+    /// built through a [StorageHandleBuilder] as it moves the storage into a fresh lock
+    pub(crate) sole_write_count: bool,
+
+    #[cfg(feature = "access_stats")]
+    pub(crate) access_counters: AccessCounters,
+}
+
 impl<S> Clone for StorageHandle<S>
 where
     S: Storage + ?Sized,
@@ -125,16 +208,39 @@ where
             base_storage: self.base_storage.clone(),
             storage: self.storage.clone(),
             view_storage_controller: self.view_storage_controller.clone(),
+            state: self.state.clone(),
             key_type_id: self.key_type_id,
             item_type_id: self.item_type_id,
         }
     }
 }
 
+impl<S> std::fmt::Debug for StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        let mut debug = f.debug_struct("StorageHandle");
+
+        debug
+            .field("storage_id", &format_args!("{}", self.storage_id()))
+            .field("label", &self.label())
+            .field("write_version", &self.write_version())
+            .field("metrics", &self.state.metrics.as_ref().map(|_| self.metrics()));
+
+        #[cfg(feature = "access_stats")]
+        debug.field("access_stats", &self.access_stats());
+
+        debug.finish_non_exhaustive()
+    }
+}
+
 /// Casts [StorageHandle<SourceStorage>] to StorageHandle<TargetStorageTrait>
 /// This produces cast functions with the same purpose as the lower level
 /// [crate::casting] functions but introduces StorageHandle specifics into
-/// the equation so that the casting can be done directly with [StorageHandle]
+/// the equation so that the casting can be done directly with [StorageHandle]. Like the lower
+/// level functions, a cast fails while a writer holds the storage lock.
 macro_rules! define_cast_storage_ptr_to_dyn_fn {
 
     ($fn_name:ident, $inner_fn_name:ident, $target_trait:ty) => {
@@ -146,28 +252,54 @@ macro_rules! define_cast_storage_ptr_to_dyn_fn {
             // Check that we are dealing with the same item type
             if TypeId::of::<Item>() != self.item_type_id()
             {
-                return Err("Invalid cast due to unexpected item type id".into());
+                return Err(item_type_mismatch_error(
+                    self.state.type_info.as_ref(),
+                    type_name::<$target_trait>(),
+                ));
             }
 
-            // Takes advantage of our casting modules lower level casting function
-            let key_item_storage: Arc<RwLock<$target_trait>> =
-                casting::$inner_fn_name::<S, Key, Item>(self.storage.clone())?;
+            // Takes advantage of our casting modules lower level casting function. Always from
+            // the base storage so that the cast lists aren't instantiated per source type.
+            let key_item_storage: Arw<$target_trait> =
+                casting::$inner_fn_name::<dyn Storage, Key, Item>(&self.base_storage)?;
 
             // And then we wrap that cast into a new appropriately typed
             // StorageHandle
-            let storage_ptr = StorageHandle::<$target_trait> {
-                base_storage: self.base_storage.clone(),
-                storage: key_item_storage.clone(),
-                view_storage_controller: self.view_storage_controller.clone(),
-                key_type_id: self.key_type_id,
-                item_type_id: self.item_type_id,
-            };
-
-            Ok(storage_ptr)
+            Ok(self.with_cast_storage(key_item_storage))
         }
     };
 }
 
+/// Shared by every cast rather than generated into each one
+#[cold]
+#[inline(never)]
+fn item_type_mismatch_error(
+    type_info: Option<&TypeInfo>,
+    target_type_name: &'static str,
+) -> StorageError
+{
+    let fix = match type_info
+    {
+        Some(info) => format!("Cast with the storage's Item type '{}'", info.item_type),
+        None => "Cast with the Item type that the storage was built with".to_string(),
+    };
+
+    let error = StorageError::new(
+        ErrorKind::TypeMismatch,
+        "Invalid cast due to unexpected item type id",
+    )
+    .with_hint(Hint::CastTypeMismatch, fix);
+
+    match type_info
+    {
+        Some(info) => error.with_detail(ErrorDetail::InvalidCast {
+            from: info.storage_type,
+            to: target_type_name,
+        }),
+        None => error,
+    }
+}
+
 pub struct StorageHandleBuilder
 {
     base_storage: Arw<dyn Storage>,
@@ -176,24 +308,44 @@ pub struct StorageHandleBuilder
     // Having these as TypeID instead of phantoms saves on some compile time
     key_type_id: TypeId,
     item_type_id: TypeId,
+    type_info: TypeInfo,
 
     // Items below are optionally built
     // --------------------------------
 
     view_storage_controller: Option<ViewStorageController>,
+    label: Option<String>,
+    lock_rank: Option<u32>,
+    lock_policy: LockPolicy,
+    read_cache: bool,
+    metrics: bool,
 }
 
 impl StorageHandleBuilder
 {
     pub fn new<S>(storage: S) -> Self
     where
-        S: Storage + Into<Arw< dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+        S: KeyStorage
+            + ItemStorage
+            + Into<Arw<dyn Storage>>
+            + KeyTypeIdNoSelf
+            + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
     {
+        let base_storage: Arw<dyn Storage> = storage.into();
+
         Self {
-            base_storage: storage.into(),
+            type_info: TypeInfo::probe::<S, S::Key, S::Item>(&base_storage),
+            base_storage,
             key_type_id: S::key_type_id(),
-            item_type_id: S::item_type_id(),
+            item_type_id: <S as ItemTypeIdNoSelf>::item_type_id(),
             view_storage_controller: None,
+            label: None,
+            lock_rank: None,
+            lock_policy: <_>::default(),
+            read_cache: false,
+            metrics: false,
         }
     }
 
@@ -201,21 +353,88 @@ impl StorageHandleBuilder
     {
         self.view_storage_controller = Some(ViewStorageController::new(
             self.base_storage.clone(),
-            Arc::new(RwLock::new(InputStorageLockStatus::None)),
+            sync::Arc::new(sync::RwLock::new(InputStorageLockStatus::None)),
         ));
 
         self
     }
 
+    /// A human readable name for the storage that is used by diagnostics
+    pub fn label(&mut self, label: impl Into<String>) -> &mut Self
+    {
+        self.label = Some(label.into());
+
+        self
+    }
+
+    /// Rank used to enforce lock acquisition order in debug builds. A thread may only lock a ranked
+    /// handle while every ranked handle it already holds has a strictly lower rank. See
+    /// [super::lock_order] for details.
+    pub fn lock_rank(&mut self, rank: u32) -> &mut Self
+    {
+        self.lock_rank = Some(rank);
+
+        self
+    }
+
+    /// How waiting readers and writers are prioritised. See [LockPolicy] for details.
+    pub fn lock_policy(&mut self, policy: LockPolicy) -> &mut Self
+    {
+        self.lock_policy = policy;
+
+        self
+    }
+
+    /// Enable the thread local cache used by [StorageHandle::get_cached]
+    pub fn read_cache(&mut self) -> &mut Self
+    {
+        self.read_cache = true;
+
+        self
+    }
+
+    /// Record lock contention metrics, see [StorageHandle::metrics]
+    pub fn metrics(&mut self) -> &mut Self
+    {
+        self.metrics = true;
+
+        self
+    }
+
     pub fn build(self) -> StorageHandle<dyn Storage>
     {
-        StorageHandle::<dyn Storage> {
+        let state = HandleState {
+            label: self.label,
+            lock_rank: self.lock_rank,
+            lock_policy: PolicyState::new(self.lock_policy),
+            read_cache: self.read_cache.then(ReadCacheId::new),
+            metrics: self.metrics.then(<_>::default),
+            type_info: Some(self.type_info),
+            sole_write_count: true,
+            ..Default::default()
+        };
+
+        // The storage was only just moved into its lock so it can't be locked by anyone else
+        if let Ok(mut storage) = self.base_storage.try_write()
+        {
+            storage.attach_write_version(state.write_signal.version.clone());
+        }
+
+        #[cfg(feature = "tracing")]
+        trace::on_handle_built(StorageId::of(&self.base_storage), &state);
+
+        let handle = StorageHandle::<dyn Storage> {
             base_storage: self.base_storage.clone(),
             storage: self.base_storage.clone(),
             view_storage_controller: self.view_storage_controller,
+            state: Arc::new(state),
             key_type_id: self.key_type_id,
             item_type_id: self.item_type_id,
-        }
+        };
+
+        handle.check_invariants();
+
+        handle
     }
 }
 
@@ -223,7 +442,9 @@ impl StorageHandleBuilder
 /// needing to refer to the longer [StorageHandleBuilder] name
 pub fn builder<S>(storage: S) -> StorageHandleBuilder
 where
-    S: Storage + Into<Arw< dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    S: KeyStorage + ItemStorage + Into<Arw<dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    S::Key: KeyTrait,
+    S::Item: ItemTrait,
 {
     StorageHandleBuilder::new::<S>(storage)
 }
@@ -239,10 +460,16 @@ where
         item_type_id: TypeId,
     ) -> Self
     {
+        let state = HandleState::default();
+
+        #[cfg(feature = "tracing")]
+        trace::on_handle_built(StorageId::of(&base_storage), &state);
+
         Self {
             base_storage,
             storage,
             view_storage_controller: None,
+            state: Arc::new(state),
             key_type_id,
             item_type_id,
         }
@@ -258,13 +485,19 @@ where
     {
         let view_controller: Option<ViewStorageController> = Some(ViewStorageController::new(
             base_storage.clone(),
-            Arc::new(RwLock::new(InputStorageLockStatus::None)),
+            sync::Arc::new(sync::RwLock::new(InputStorageLockStatus::None)),
         ));
 
+        let state = HandleState::default();
+
+        #[cfg(feature = "tracing")]
+        trace::on_handle_built(StorageId::of(&base_storage), &state);
+
         Self {
             base_storage,
             storage,
             view_storage_controller: view_controller,
+            state: Arc::new(state),
             key_type_id,
             item_type_id,
         }
@@ -290,6 +523,53 @@ where
         self.item_type_id
     }
 
+    pub fn storage_id(&self) -> StorageId
+    {
+        StorageId::of(&self.base_storage)
+    }
+
+    pub(crate) fn base_storage(&self) -> &Arw<dyn Storage>
+    {
+        &self.base_storage
+    }
+
+    /// Whether this is the only handle to the storage: no clone or cast of it is alive and nothing
+    /// else, such as a view using the storage as its input, holds on to the storage
+    pub fn is_unique(&self) -> bool
+    {
+        // The handle holds the storage twice, as its base storage and as its cast, and once more
+        // through its view controller if it has one
+        let own_references = 2 + usize::from(self.view_storage_controller.is_some());
+
+        Arc::strong_count(&self.state) == 1
+            && Arc::strong_count(&self.base_storage) == own_references
+    }
+
+    pub fn label(&self) -> Option<&str>
+    {
+        self.state.label.as_deref()
+    }
+
+    pub fn lock_rank(&self) -> Option<u32>
+    {
+        self.state.lock_rank
+    }
+
+    pub fn lock_policy(&self) -> LockPolicy
+    {
+        self.state.lock_policy.policy
+    }
+
+    /// Number of write locks taken out through this handle, its clones and its casts, plus the
+    /// writes that storages such as [crate::storage_types::ShardedHashMapStorage] take through
+    /// `&self` when the handle was built with [builder]. Writes made to an input storage through a
+    /// write view are not counted, and neither are writes through other handles made with
+    /// [StorageHandle::new] over the same storage.
+    pub fn write_version(&self) -> u64
+    {
+        self.state.write_signal.current_version()
+    }
+
     // Relevance of [ViewStorageController] in try_read and try_write blocks
     // ----------------------------------------------------------------------
     // The try_read and try_write methods employ an important guard
@@ -299,46 +579,192 @@ where
     // blocks users from interacting with the ViewStorage API prior to
     // its view being created / setup correctly.
 
-    pub fn try_read(&self) -> SimpleResult<impl Deref<Target = S> + '_>
+    pub fn try_read(&self) -> SimpleResult<StorageReadGuard<'_, S>>
     {
-        // If there is a view controller, ensure that the view has been created
-        if let Some(view_controller) = &self.view_storage_controller
+        self.check_invariants();
+
+        // If there is a view controller, ensure that the view has been created. The status guard is
+        // held until the storage guard has been acquired so that the view can't be cleared in
+        // between
+        let _status_guard = match &self.view_storage_controller
         {
-            if view_controller.status()? == InputStorageLockStatus::None
+            Some(view_controller) => Some(view_controller.lock_created_view("read")?),
+            None => None,
+        };
+
+        if let Err(error) = self.state.lock_policy.admit(LockAccess::Read)
+        {
+            // Turned away threads still register their intent so that they keep their place
+            self.on_acquire_failed(LockAccess::Read);
+
+            return Err(error);
+        }
+
+        let mut hooks = self.before_acquire(LockAccess::Read)?;
+
+        match self.storage.try_read()
+        {
+            Ok(guard) =>
+            {
+                self.after_acquire(&mut hooks, LockAccess::Read);
+
+                #[cfg(feature = "access_stats")]
+                {
+                    hooks.access_scope = Some(AccessScope::enter(&self.state, &*guard));
+                }
+
+                Ok(StorageReadGuard::new(guard).with_hooks(hooks))
+            }
+            Err(error) =>
             {
-                return Err("Cannot aquire a read lock on the ViewStorage as ViewController::status == None. A View must be created first using the ViewController".into());
+                self.on_acquire_failed(LockAccess::Read);
+
+                Err(lock_error(&error, "Failed to aquire read guard"))
             }
         }
+    }
+
+    pub fn try_write(&self) -> SimpleResult<StorageWriteGuard<'_, S>>
+    {
+        let guard = self.try_write_if(|| true)?;
+
+        Ok(guard.expect("The write check always passes"))
+    }
+
+    /// [Self::try_write] that runs `check` once the storage lock is held but before the write is
+    /// counted. If the check fails the lock is released again as though it was never taken, without
+    /// bumping the write version or notifying anyone, and `None` is returned.
+    fn try_write_if(
+        &self,
+        check: impl FnOnce() -> bool,
+    ) -> SimpleResult<Option<StorageWriteGuard<'_, S>>>
+    {
+        self.check_invariants();
+
+        // If there is a view controller, ensure that the view has been created. The status guard is
+        // held until the storage guard has been acquired so that the view can't be cleared in
+        // between
+        let _status_guard = match &self.view_storage_controller
+        {
+            Some(view_controller) => Some(view_controller.lock_created_view("write")?),
+            None => None,
+        };
+
+        if let Err(error) = self.state.lock_policy.admit(LockAccess::Write)
+        {
+            // Turned away threads still register their intent so that they keep their place
+            self.on_acquire_failed(LockAccess::Write);
+
+            return Err(error);
+        }
+
+        let mut hooks = self.before_acquire(LockAccess::Write)?;
 
-        if let Ok(guard) = self.storage.try_read()
+        match self.storage.try_write()
         {
-            Ok(guard)
+            Ok(guard) if !check() =>
+            {
+                // The lock was acquired so the thread's intent is settled
+                self.state.lock_policy.on_acquired(LockAccess::Write);
+                drop(guard);
+
+                Ok(None)
+            }
+            Ok(guard) =>
+            {
+                self.after_acquire(&mut hooks, LockAccess::Write);
+                self.state.checkpoints.before_write(guard.as_any());
+
+                #[cfg(feature = "access_stats")]
+                {
+                    hooks.access_scope = Some(AccessScope::enter(&self.state, &*guard));
+                }
+
+                Ok(Some(StorageWriteGuard::new(guard).with_hooks(hooks)))
+            }
+            Err(error) =>
+            {
+                self.on_acquire_failed(LockAccess::Write);
+
+                Err(lock_error(&error, "Failed to aquire write guard"))
+            }
         }
-        else
+    }
+
+    // Guard bookkeeping shared by every way of acquiring a lock through the handle
+    // ----------------------------------------------------------------------------
+
+    /// Runs before a lock is attempted and can veto the attempt
+    pub(super) fn before_acquire(&self, _access: LockAccess) -> SimpleResult<GuardHooks>
+    {
+        #[allow(unused_mut)]
+        let mut hooks = GuardHooks::default();
+
+        #[cfg(debug_assertions)]
         {
-            Err("Failed to aquire read guard".into())
+            hooks.rank_record = lock_order::check_and_record(self.state.lock_rank, self.label())?;
         }
+
+        Ok(hooks)
     }
 
-    pub fn try_write(&self) -> SimpleResult<impl DerefMut<Target = S> + '_>
+    pub(super) fn after_acquire(&self, hooks: &mut GuardHooks, access: LockAccess)
     {
-        // If there is a view controller, ensure that the view has been created
-        if let Some(view_controller) = &self.view_storage_controller
+        self.state.lock_policy.on_acquired(access);
+
+        if let Some(metrics) = &self.state.metrics
         {
-            if view_controller.status()? == InputStorageLockStatus::None
-            {
-                return Err("Cannot aquire a write lock on the ViewStorage as ViewController::status == None. A View must be created first using the ViewController".into());
-            }
+            metrics.on_acquired(access);
+
+            hooks.hold_record = Some(HoldRecord {
+                state: self.state.clone(),
+                access,
+                acquired: Instant::now(),
+            });
+        }
+
+        // Bumped while the write lock is held so that anyone who observed the previous version
+        // under a read lock can tell that a write has happened since
+        if access == LockAccess::Write
+        {
+            let version = self.state.write_signal.next_version();
+
+            hooks.write_release = Some(WriteRelease {
+                state: self.state.clone(),
+                storage_id: self.storage_id(),
+                version,
+            });
+        }
+
+        #[cfg(feature = "access_stats")]
+        self.state.access_counters.on_acquired(access);
+
+        #[cfg(feature = "deadlock_detection")]
+        {
+            hooks.lock_record =
+                Some(deadlock::on_acquired(self.storage_id(), self.label(), access));
         }
 
-        if let Ok(guard) = self.storage.try_write()
+        #[cfg(feature = "tracing")]
         {
-            Ok(guard)
+            hooks.release_record = Some(trace::on_acquired(self.storage_id(), &self.state, access));
         }
-        else
+    }
+
+    pub(super) fn on_acquire_failed(&self, access: LockAccess)
+    {
+        self.state.lock_policy.on_failed(access);
+
+        if let Some(metrics) = &self.state.metrics
         {
-            Err("Failed to aquire write guard".into())
+            metrics.on_failed(access);
         }
+
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::on_try_failed(self.storage_id(), self.label(), access);
+
+        #[cfg(feature = "tracing")]
+        trace::on_try_failed(self.storage_id(), &self.state, access);
     }
 
     // ----------------------------------------------------------
@@ -367,29 +793,230 @@ where
         cast_to_dyn_mutitemstorage,
         dyn MutKeyItemStorage<Key = Key, Item = Item>
     );
+    define_cast_storage_ptr_to_dyn_fn!(
+        cast_to_removable_storage,
+        cast_to_dyn_removablestorage,
+        dyn RemovableStorage<Key = Key, Item = Item>
+    );
     define_cast_storage_ptr_to_dyn_fn!(
         cast_to_slice_storage,
         cast_to_dyn_sliceitemstorage,
         dyn ItemSliceStorage<Item = Item>
     );
 
-    /// Downcast to TargetType where Target type is Sized
+    /// Downcast to TargetType where Target type is Sized. Fails while a writer holds the storage
+    /// lock, see [casting::dyn_storage_into_sized].
     pub fn cast_to_sized_storage<TargetType>(self) -> SimpleResult<StorageHandle<TargetType>>
     where
         TargetType: Storage + Sized,
     {
-        let target_type: Arc<RwLock<TargetType>> =
-            casting::dyn_storage_into_sized::<S, TargetType>(self.storage.clone())?;
+        let target_type: Arw<TargetType> =
+            casting::dyn_storage_into_sized::<dyn Storage, TargetType>(&self.base_storage)?;
 
-        let storage_ptr = StorageHandle::<TargetType> {
+        Ok(self.with_cast_storage(target_type))
+    }
+
+    /// A handle that shares this handle's base storage and state, to the result of a cast
+    fn with_cast_storage<Target>(&self, storage: Arw<Target>) -> StorageHandle<Target>
+    where
+        Target: Storage + ?Sized,
+    {
+        let storage_ptr = StorageHandle::<Target> {
             base_storage: self.base_storage.clone(),
-            storage: target_type,
+            storage,
             view_storage_controller: self.view_storage_controller.clone(),
+            state: self.state.clone(),
             key_type_id: self.key_type_id,
             item_type_id: self.item_type_id,
         };
 
-        Ok(storage_ptr)
+        storage_ptr.check_invariants();
+
+        storage_ptr
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage,
+{
+    /// Replace the storage with a modified copy built from the current one (RCU style).
+    ///
+    /// `update` runs under a read lock so other readers carry on while an expensive copy is
+    /// built. The write lock is then only held long enough to swap the new storage in. If another
+    /// write happened while `update` was running it is called again on the newer storage so no
+    /// write is lost.
+    ///
+    /// A retry doesn't count as a write, so the write version only moves on and write waiters and
+    /// subscribers are only notified once the new storage is swapped in. Sharded writes, see
+    /// [crate::storage_types::ShardedHashMapStorage], made while `update` is running also cause a
+    /// retry as they bump the write version.
+    ///
+    /// Like [StorageHandle::try_write] this fails rather than waits if a lock is contended. Fails
+    /// for handles that weren't built with [builder], see below.
+    //
+    // Lost writes are detected with the write version, which is counted per handle state. Only a
+    // built handle is sure to be the one state of its storage, while handles made with
+    // [StorageHandle::new] may share their storage with handles that count writes separately.
+    //
+    // The version is compared while the storage lock is held, before try_write_if counts the
+    // write. Sharded writes only happen under a read lock so they can't move it in between.
+    pub fn update(&self, mut update: impl FnMut(&S) -> S) -> SimpleResult<()>
+    {
+        if !self.state.sole_write_count
+        {
+            return Err(StorageError::new(
+                ErrorKind::Other,
+                "Can't update a storage through a handle that wasn't built with builder as writes \
+                 through other handles to the same storage would be lost",
+            ));
+        }
+
+        loop
+        {
+            let (seen_version, mut new_storage) = {
+                let guard = self.try_read()?;
+                (self.write_version(), update(&guard))
+            };
+
+            // Peeked first so a known conflict doesn't take the write lock at all
+            if self.write_version() != seen_version
+            {
+                continue;
+            }
+
+            if let Some(mut guard) = self.try_write_if(|| self.write_version() == seen_version)?
+            {
+                new_storage.attach_write_version(self.state.write_signal.version.clone());
+                *guard = new_storage;
+
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: ForEachStorage,
+{
+    /// Call `f` on every item under a read lock. With a handle to a concrete storage, see
+    /// [StorageHandle::cast_to_sized_storage], the loop is compiled for that storage so it avoids
+    /// the boxed iterators of [KeyItemStorage::item_iter] and can be inlined like a slice loop.
+    ///
+    /// Like [StorageHandle::try_read] this fails rather than waits if the lock is contended.
+    pub fn for_each_item(&self, f: impl FnMut(&S::Item)) -> SimpleResult<()>
+    {
+        self.try_read()?.for_each_item(f);
+
+        Ok(())
+    }
+
+    /// Call `f` on every key and item under a read lock, see [Self::for_each_item]
+    pub fn for_each_key_item(&self, f: impl FnMut(S::Key, &S::Item)) -> SimpleResult<()>
+    {
+        self.try_read()?.for_each_key_item(f);
+
+        Ok(())
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: KeyItemStorage + ?Sized,
+    S::Item: Clone,
+{
+    /// Clones of the items at `keys` in the same order, with None for keys that aren't in the
+    /// storage. The read lock is taken once for all of the keys, see [KeyItemStorage::get_refs] to
+    /// borrow the items from a guard instead.
+    ///
+    /// Like [StorageHandle::try_read] this fails rather than waits if the lock is contended.
+    pub fn get_many(
+        &self,
+        keys: impl IntoIterator<Item = S::Key>,
+    ) -> SimpleResult<Vec<Option<S::Item>>>
+    {
+        let guard = self.try_read()?;
+
+        Ok(keys.into_iter().map(|key| guard.get(key).cloned()).collect())
+    }
+
+    /// Copy the items into a new storage of type `Target` at the same keys and return a handle to
+    /// it, eg to read a map through a [VecStorage] slice. Like [StorageHandle::fork] the new handle
+    /// is independent, but it is built with default settings. See [ExtendStorage::collect_from].
+    ///
+    /// Fails rather than waits if the lock is contended, or if a key can't be used with `Target`.
+    pub fn collect_into<Target>(&self) -> SimpleResult<StorageHandle<dyn Storage>>
+    where
+        Target: ExtendStorage<Key = S::Key, Item = S::Item>
+            + Default
+            + Into<Arw<dyn Storage>>
+            + KeyTypeIdNoSelf
+            + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        let storage = Target::collect_from(&*self.try_read()?)?;
+
+        Ok(builder(storage).build())
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: AllocKeyStorage + ?Sized,
+{
+    /// Insert `item` at a free key chosen under the write lock and return the key, see
+    /// [AllocKeyStorage]. Fails rather than waits if the lock is contended.
+    pub fn insert_alloc(&self, item: S::Item) -> SimpleResult<S::Key>
+    {
+        self.try_write()?.try_insert_alloc(item)
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: KeyStorage
+        + ItemStorage
+        + Clone
+        + Into<Arw<dyn Storage>>
+        + KeyTypeIdNoSelf
+        + ItemTypeIdNoSelf,
+    S::Key: KeyTrait,
+    S::Item: ItemTrait,
+{
+    /// Create an independent handle to a copy of the storage, built with the same label, lock
+    /// rank, lock policy, read cache and metrics settings. The fork has its own [StorageId] and
+    /// write version and no subscribers or checkpoints.
+    ///
+    /// The copy is a clone of the storage taken under a read lock. Wrap large storages in a
+    /// [crate::storage_types::CowStorage] to make the clone cheap and defer copying until either
+    /// side writes.
+    pub fn fork(&self) -> SimpleResult<StorageHandle<dyn Storage>>
+    {
+        let storage = self.try_read()?.clone();
+
+        let mut builder = builder(storage);
+        builder.lock_policy(self.lock_policy());
+
+        if let Some(label) = self.label()
+        {
+            builder.label(label);
+        }
+        if let Some(rank) = self.lock_rank()
+        {
+            builder.lock_rank(rank);
+        }
+        if self.state.read_cache.is_some()
+        {
+            builder.read_cache();
+        }
+        if self.state.metrics.is_some()
+        {
+            builder.metrics();
+        }
+
+        Ok(builder.build())
     }
 }
 
@@ -411,14 +1038,16 @@ pub fn storage_ptr_into_base<StorageType>(
 where
     StorageType: Storage + ?Sized,
 {
-    let storage_ptr: StorageHandle<dyn Storage> = StorageHandle::new(
+    let mut base_ptr: StorageHandle<dyn Storage> = StorageHandle::new(
         storage_ptr.base_storage.clone(),
         storage_ptr.base_storage.clone(),
         storage_ptr.key_type_id,
         storage_ptr.item_type_id,
     );
 
-    Ok(storage_ptr)
+    base_ptr.state = storage_ptr.state.clone();
+
+    Ok(base_ptr)
 }
 
 impl <Key, Item> From<VecStorage<Key, Item>> for Arw<dyn Storage> 
@@ -428,7 +1057,73 @@ where
 {
     fn from(value: VecStorage<Key, Item>) -> Self {
 
-        let storage = Arc::new(RwLock::new(value));
+        let storage = Arc::new(Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+impl <Key, Item> From<SparseSetVecStorage<Key, Item>> for Arw<dyn Storage> 
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: SparseSetVecStorage<Key, Item>) -> Self {
+
+        let storage = Arc::new(Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+impl <Key, Item> From<HashMapStorage<Key, Item>> for Arw<dyn Storage> 
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: HashMapStorage<Key, Item>) -> Self {
+
+        let storage = Arc::new(Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+impl <Key, Item> From<ShardedHashMapStorage<Key, Item>> for Arw<dyn Storage> 
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: ShardedHashMapStorage<Key, Item>) -> Self {
+
+        let storage = Arc::new(Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+impl <Key, Item> From<ValStorage<Key, Item>> for Arw<dyn Storage> 
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: ValStorage<Key, Item>) -> Self {
+
+        let storage = Arc::new(Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+impl <InputStorage, Key, Item> From<KeyItemViewStorage<InputStorage, Key, Item>> for Arw<dyn Storage> 
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+    InputStorage: KeyItemStorage<Key = Key, Item = Item>,
+{
+    fn from(value: KeyItemViewStorage<InputStorage, Key, Item>) -> Self {
+
+        let storage = Arc::new(Rw::new(value));
         let storage: Arw<dyn Storage> = storage;
         storage
     }
@@ -437,16 +1132,13 @@ where
 #[cfg(test)]
 pub mod tests
 {
-    use std::{
-        any::TypeId,
-        sync::{Arc, RwLock},
-    };
+    use std::{any::TypeId, sync::Arc};
 
     use crate::{
         // storage_ptr::builder_from_arw,
         storage_types::VecStorage,
         storage_traits::{ItemSliceStorage, KeyItemStorage, Storage},
-        Arw, storage_handle::builder,
+        Arw, Rw, storage_handle::builder,
     };
 
     use super::{storage_ptr_into_base, StorageHandle};
@@ -477,7 +1169,7 @@ pub mod tests
     {
         let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
 
-        let storage = Arc::new(RwLock::new(storage));
+        let storage = Arc::new(Rw::new(storage));
         let storage: Arw<dyn Storage> = storage;
 
         let storage_ptr: StorageHandle<dyn Storage> = StorageHandle::new(
@@ -564,7 +1256,7 @@ pub mod tests
     fn into_base_storage_test()
     {
         let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
-        let storage = Arc::new(RwLock::new(storage));
+        let storage = Arc::new(Rw::new(storage));
         let storage: Arw<dyn Storage> = storage;
 
         let storage_ptr: StorageHandle<dyn Storage> = StorageHandle::new(
@@ -579,4 +1271,127 @@ pub mod tests
 
         let _ = storage_ptr_into_base(storage_ptr);
     }
+
+    #[test]
+    fn update_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let handle: StorageHandle<VecStorage<usize, i32>> =
+            builder(storage).build().cast_to_sized_storage().unwrap();
+
+        let version = handle.write_version();
+
+        handle
+            .update(|old| VecStorage::new_from_iter(old.as_item_slice().iter().map(|i| i * 10)))
+            .unwrap();
+
+        assert_eq!(handle.try_read().unwrap().as_item_slice(), &[10, 20, 30]);
+        assert_eq!(handle.write_version(), version + 1);
+    }
+
+    #[cfg(feature = "parking_lot")]
+    #[test]
+    fn mapped_guard_test()
+    {
+        use crate::{
+            storage_handle::{StorageReadGuard, StorageWriteGuard},
+            storage_traits::MutKeyItemStorage,
+        };
+
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let handle: StorageHandle<VecStorage<usize, i32>> =
+            builder(storage).build().cast_to_sized_storage().unwrap();
+        let version = handle.write_version();
+
+        {
+            let mut item = StorageWriteGuard::map(handle.try_write().unwrap(), |storage| {
+                storage.get_mut(1).unwrap()
+            });
+            *item = 20;
+
+            // The mapped guard still holds the lock
+            assert!(handle.try_read().is_err());
+        }
+
+        let item = StorageReadGuard::map(handle.try_read().unwrap(), |storage| {
+            storage.get(1).unwrap()
+        });
+        assert_eq!(*item, 20);
+        assert_eq!(handle.write_version(), version + 1);
+    }
+
+    #[test]
+    fn update_unbuilt_handle_test()
+    {
+        let storage: Arw<VecStorage<usize, i32>> =
+            Arc::new(Rw::new(VecStorage::new_from_iter(vec![1, 2, 3])));
+        let base_storage: Arw<dyn Storage> = storage.clone();
+
+        // Another handle over the same storage would count its writes separately
+        let handle = StorageHandle::new(
+            storage,
+            base_storage,
+            TypeId::of::<usize>(),
+            TypeId::of::<i32>(),
+        );
+
+        assert!(handle.update(|old| old.clone()).is_err());
+    }
+
+    #[test]
+    fn for_each_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let handle: StorageHandle<VecStorage<usize, i32>> =
+            builder(storage).build().cast_to_sized_storage().unwrap();
+
+        let mut sum = 0;
+        handle.for_each_item(|item| sum += item).unwrap();
+        assert_eq!(sum, 6);
+
+        let mut pairs = Vec::new();
+        handle.for_each_key_item(|key, item| pairs.push((key, *item))).unwrap();
+        assert_eq!(pairs, vec![(0, 1), (1, 2), (2, 3)]);
+
+        // Fails instead of waiting while written
+        let _guard = handle.try_write().unwrap();
+        assert!(handle.for_each_item(|_| ()).is_err());
+    }
+
+    #[test]
+    fn get_many_test()
+    {
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<u32, String>::from_vec(vec!["a".into(), "b".into()])).build();
+        let handle = handle.cast_to_getitem_storage::<u32, String>().unwrap();
+
+        let items = handle.get_many([1, 5, 0]).unwrap();
+        assert_eq!(items, vec![Some("b".to_string()), None, Some("a".to_string())]);
+
+        let guard = handle.try_read().unwrap();
+        assert_eq!(guard.get_refs(&[0, 2]), vec![Some(&"a".to_string()), None]);
+    }
+
+    #[test]
+    fn collect_into_test()
+    {
+        use crate::storage_traits::MutKeyItemStorage;
+        use crate::storage_types::{HashMapStorage, SparseSetVecStorage};
+
+        let mut weights = HashMapStorage::<usize, f32>::new();
+        weights.insert(2, 0.5);
+        weights.insert(0, 0.25);
+
+        let handle: StorageHandle<dyn Storage> = builder(weights).build();
+        let handle = handle.cast_to_getitem_storage::<usize, f32>().unwrap();
+
+        let collected = handle.collect_into::<VecStorage<usize, f32>>().unwrap();
+        let collected = collected.cast_to_slice_storage::<usize, f32>().unwrap();
+        assert_eq!(collected.try_read().unwrap().as_item_slice(), &[0.25, 0.0, 0.5]);
+
+        let sparse = handle.collect_into::<SparseSetVecStorage<usize, f32>>().unwrap();
+        let sparse = sparse.cast_to_getitem_storage::<usize, f32>().unwrap();
+        assert_eq!(sparse.try_read().unwrap().len(), 2);
+        assert_eq!(sparse.get_many([2]).unwrap(), vec![Some(0.5)]);
+    }
 }
@@ -0,0 +1,225 @@
+//! Describe the storage behind a handle without knowing its concrete type, eg for UI layers that
+//! list storages generically.
+//!
+//! ```ignore
+//! let info = handle.info()?;
+//! println!("{} {}<{:?}, {:?}> len {}", info.storage_id, info.storage_type, info.key_type,
+//!     info.item_type, info.len);
+//!
+//! if info.capabilities.is_some_and(|capabilities| capabilities.sliceable)
+//! {
+//!     // Offer a table view
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - Capabilities are found by running the [crate::casting] functions once when the handle is
+//   built, where the Key and Item types are still known and the storage can't be locked by anyone
+//   else. The cast lists are what decide which casts a handle supports so they are the only
+//   reliable source, and trying the casts again on every call would need the Key and Item types.
+// - Handles created through [StorageHandle::new] rather than a builder have no recorded type info
+//   so their key, item and capabilities are None.
+
+use std::any::TypeId;
+
+use crate::{
+    casting,
+    error::lock_error,
+    storage_traits::{ItemStorage, ItemTrait, KeyStorage, KeyTrait, Storage},
+    Arw, SimpleResult,
+};
+
+use super::{LockAccess, StorageHandle, StorageId};
+
+/// What a storage can be cast to through its handle
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageCapabilities
+{
+    /// [StorageHandle::cast_to_getitem_storage]
+    pub key_item: bool,
+
+    /// [StorageHandle::cast_to_mut_getitem_storage]
+    pub mutable: bool,
+
+    /// Implements [crate::storage_traits::ClearableStorage], as mutable and view storages do
+    pub clearable: bool,
+
+    /// [StorageHandle::cast_to_slice_storage]
+    pub sliceable: bool,
+
+    /// [StorageHandle::cast_to_keyitemview_storage]
+    pub view: bool,
+}
+
+type ViewInputAccessFn = fn(&Arw<dyn Storage>) -> SimpleResult<Option<LockAccess>>;
+
+/// Type info recorded when a handle is built
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TypeInfo
+{
+    pub(crate) storage_type: &'static str,
+    key_type: &'static str,
+    pub(crate) item_type: &'static str,
+    capabilities: StorageCapabilities,
+
+    // Checked against the handle by [StorageHandle::validate]
+    pub(crate) storage_type_id: TypeId,
+    pub(crate) key_type_id: TypeId,
+    pub(crate) item_type_id: TypeId,
+
+    /// Reads which guard a view storage holds on its input. None for storages that aren't views.
+    pub(crate) view_input_access: Option<ViewInputAccessFn>,
+}
+
+impl TypeInfo
+{
+    /// Probe the casts supported by an unlocked storage of type S
+    pub(crate) fn probe<S, Key, Item>(storage: &Arw<dyn Storage>) -> Self
+    where
+        S: KeyStorage<Key = Key> + ItemStorage<Item = Item> + 'static,
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let mutable =
+            casting::cast_to_dyn_mutitemstorage::<_, Key, Item>(storage).is_ok();
+        let view =
+            casting::cast_to_dyn_getkeyitemviewstorage::<_, Key, Item>(storage).is_ok();
+
+        Self {
+            // Handles are built from unlocked storages so the type name can be read straight away
+            storage_type: storage.try_read().map_or("", |storage| storage.type_name()),
+            key_type: std::any::type_name::<Key>(),
+            item_type: std::any::type_name::<Item>(),
+            capabilities: StorageCapabilities {
+                key_item: casting::cast_to_dyn_getkeyitemstorage::<_, Key, Item>(storage)
+                    .is_ok(),
+                mutable,
+                clearable: mutable || view,
+                sliceable: casting::cast_to_dyn_sliceitemstorage::<_, Key, Item>(storage)
+                    .is_ok(),
+                view,
+            },
+            storage_type_id: TypeId::of::<S>(),
+            key_type_id: TypeId::of::<Key>(),
+            item_type_id: TypeId::of::<Item>(),
+            view_input_access: view.then_some(view_input_access::<Key, Item> as ViewInputAccessFn),
+        }
+    }
+}
+
+fn view_input_access<Key, Item>(storage: &Arw<dyn Storage>) -> SimpleResult<Option<LockAccess>>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    let view = casting::cast_to_dyn_getkeyitemviewstorage::<_, Key, Item>(storage)?;
+    let guard = view
+        .try_read()
+        .map_err(|error| lock_error(&error, "Failed to aquire view storage read guard"))?;
+
+    Ok(guard.input_access())
+}
+
+/// A description of the storage behind a handle, see [StorageHandle::info]
+#[derive(Clone, Debug)]
+pub struct StorageInfo
+{
+    pub storage_id: StorageId,
+    pub label: Option<String>,
+
+    /// Type name of the concrete storage
+    pub storage_type: &'static str,
+
+    pub key_type: Option<&'static str>,
+    pub item_type: Option<&'static str>,
+
+    pub len: usize,
+    pub write_version: u64,
+    pub capabilities: Option<StorageCapabilities>,
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Describe the storage. Briefly takes a read lock for its length and type name, without
+    /// requiring a view to have been created.
+    pub fn info(&self) -> SimpleResult<StorageInfo>
+    {
+        let (storage_type, len) = {
+            let guard = self
+                .base_storage
+                .try_read()
+                .map_err(|error| lock_error(&error, "Failed to aquire read guard"))?;
+
+            (guard.type_name(), guard.len())
+        };
+
+        let type_info = self.state.type_info;
+
+        Ok(StorageInfo {
+            storage_id: self.storage_id(),
+            label: self.label().map(str::to_owned),
+            storage_type,
+            key_type: type_info.map(|info| info.key_type),
+            item_type: type_info.map(|info| info.item_type),
+            len,
+            write_version: self.write_version(),
+            capabilities: type_info.map(|info| info.capabilities),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{any::TypeId, sync::Arc};
+
+    use super::StorageCapabilities;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::{ValStorage, VecStorage},
+        Arw, Rw,
+    };
+
+    #[test]
+    fn info_test()
+    {
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0])).build();
+        let info = handle.info().unwrap();
+
+        assert_eq!(info.len, 2);
+        assert_eq!(info.key_type, Some("usize"));
+        assert_eq!(info.item_type, Some("f32"));
+        assert!(info.storage_type.contains("VecStorage"));
+        assert_eq!(
+            info.capabilities,
+            Some(StorageCapabilities {
+                key_item: true,
+                mutable: true,
+                clearable: true,
+                sliceable: true,
+                view: false,
+            })
+        );
+
+        // Casts keep the info of the handle they were cast from
+        let sized: StorageHandle<VecStorage<usize, f32>> = handle.cast_to_sized_storage().unwrap();
+        assert_eq!(sized.info().unwrap().item_type, Some("f32"));
+
+        // Without a builder nothing is known about the types
+        let storage: Arw<dyn Storage> = Arc::new(Rw::new(ValStorage::<usize, f32>::new(1.0)));
+        let handle = StorageHandle::new(
+            storage.clone(),
+            storage,
+            TypeId::of::<usize>(),
+            TypeId::of::<f32>(),
+        );
+        let info = handle.info().unwrap();
+        assert!(info.key_type.is_none());
+        assert!(info.capabilities.is_none());
+    }
+}
@@ -0,0 +1,230 @@
+//! Debug build enforcement of a global lock acquisition order.
+//!
+//! Handles can be given a rank via [super::StorageHandleBuilder::lock_rank]. In debug builds a
+//! thread that already holds a guard on a ranked handle may only lock handles with a strictly
+//! higher rank. Acquiring out of order is reported according to the [LockOrderViolationPolicy]
+//! so that potential deadlocks are caught in tests instead of in production.
+//!
+//! Unranked handles are never checked and never constrain other handles. In release builds the
+//! ranks are kept on the handle but no checks are made.
+//
+// # Internal Design
+//
+// - Held ranks are tracked per thread in a global table rather than a thread local so that a guard
+//   that is released on a different thread (eg an async guard) still removes its entry.
+// - The check happens before the lock is attempted so that a violation never leaves the thread
+//   holding the lock it was not allowed to take.
+
+#[cfg(debug_assertions)]
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, MutexGuard},
+    thread::ThreadId,
+};
+
+use std::sync::Mutex;
+
+#[cfg(debug_assertions)]
+use crate::SimpleResult;
+
+/// What to do when a thread acquires a ranked handle out of order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockOrderViolationPolicy
+{
+    #[default]
+    Panic,
+
+    /// Fail the acquisition with an Err from try_read / try_write instead
+    Error,
+}
+
+static VIOLATION_POLICY: Mutex<LockOrderViolationPolicy> =
+    Mutex::new(LockOrderViolationPolicy::Panic);
+
+pub fn set_violation_policy(policy: LockOrderViolationPolicy)
+{
+    *VIOLATION_POLICY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = policy;
+}
+
+pub fn violation_policy() -> LockOrderViolationPolicy
+{
+    *VIOLATION_POLICY
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+////////////////////////////////////////////////
+// Rank Record
+////////////////////////////////////////////////
+
+/// RAII record of a held ranked lock. Removed from the rank table on drop.
+#[cfg(debug_assertions)]
+pub(crate) struct RankRecord
+{
+    thread: ThreadId,
+    id: u64,
+}
+
+#[cfg(debug_assertions)]
+impl Drop for RankRecord
+{
+    fn drop(&mut self)
+    {
+        let mut table = rank_table();
+
+        if let Some(held) = table.held.get_mut(&self.thread)
+        {
+            held.retain(|held_rank| held_rank.id != self.id);
+
+            if held.is_empty()
+            {
+                table.held.remove(&self.thread);
+            }
+        }
+    }
+}
+
+/// Checks `rank` against the ranks already held by the current thread and records it if the
+/// order is valid. Unranked handles are neither checked nor recorded.
+#[cfg(debug_assertions)]
+pub(crate) fn check_and_record(
+    rank: Option<u32>,
+    label: Option<&str>,
+) -> SimpleResult<Option<RankRecord>>
+{
+    let Some(rank) = rank
+    else
+    {
+        return Ok(None);
+    };
+
+    let thread = std::thread::current().id();
+    let mut table = rank_table();
+
+    let highest_held = table
+        .held
+        .get(&thread)
+        .and_then(|held| held.iter().max_by_key(|held_rank| held_rank.rank))
+        .cloned();
+
+    if let Some(highest_held) = highest_held
+    {
+        if rank <= highest_held.rank
+        {
+            // Release the table before panicking so that other threads are not poisoned
+            drop(table);
+
+            let message = format!(
+                "Lock order violation: acquiring {} with rank {rank} while holding {} with rank {}. \
+                Ranked handles must be locked in strictly increasing rank order",
+                describe(label),
+                describe(highest_held.label.as_deref()),
+                highest_held.rank
+            );
+
+            return match violation_policy()
+            {
+                LockOrderViolationPolicy::Panic => panic!("{message}"),
+                LockOrderViolationPolicy::Error => Err(message.into()),
+            };
+        }
+    }
+
+    let id = table.next_record_id;
+    table.next_record_id += 1;
+
+    table.held.entry(thread).or_default().push(HeldRank {
+        id,
+        rank,
+        label: label.map(str::to_string),
+    });
+
+    Ok(Some(RankRecord { thread, id }))
+}
+
+#[cfg(debug_assertions)]
+fn describe(label: Option<&str>) -> String
+{
+    match label
+    {
+        Some(label) => format!("'{label}'"),
+        None => "an unlabelled handle".to_string(),
+    }
+}
+
+////////////////////////////////////////////////
+// Rank Table
+////////////////////////////////////////////////
+
+#[cfg(debug_assertions)]
+#[derive(Clone)]
+struct HeldRank
+{
+    id: u64,
+    rank: u32,
+    label: Option<String>,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct RankTable
+{
+    next_record_id: u64,
+    held: HashMap<ThreadId, Vec<HeldRank>>,
+}
+
+#[cfg(debug_assertions)]
+static RANK_TABLE: LazyLock<Mutex<RankTable>> = LazyLock::new(<_>::default);
+
+#[cfg(debug_assertions)]
+fn rank_table() -> MutexGuard<'static, RankTable>
+{
+    RANK_TABLE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests
+{
+    use super::{set_violation_policy, LockOrderViolationPolicy};
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::VecStorage,
+    };
+
+    fn ranked_handle(label: &str, rank: u32) -> StorageHandle<dyn Storage>
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let mut builder = builder(storage);
+        builder.label(label).lock_rank(rank);
+        builder.build()
+    }
+
+    #[test]
+    fn lock_order_test()
+    {
+        set_violation_policy(LockOrderViolationPolicy::Error);
+
+        let low = ranked_handle("low", 1);
+        let high = ranked_handle("high", 2);
+
+        {
+            let _low_guard = low.try_write().unwrap();
+            let _high_guard = high.try_write().unwrap();
+        }
+
+        {
+            let _high_guard = high.try_read().unwrap();
+
+            let error = low.try_read().err().expect("Out of order lock should fail");
+            assert!(error.message().contains("'low'") && error.message().contains("'high'"));
+        }
+
+        // Once the higher ranked guard is released the lower rank can be taken again
+        assert!(low.try_read().is_ok());
+    }
+}
@@ -0,0 +1,251 @@
+//! Configurable fairness between readers and writers of a handle.
+//!
+//! The handle API is try based so a thread that fails to acquire a lock retries later. Without a
+//! policy a steady stream of overlapping readers means a retrying writer never finds the lock free.
+//! A [LockPolicy] lets threads that have failed to acquire register an intent so that the handle
+//! can turn away newcomers of the other access kind until the waiting thread has had its turn.
+//!
+//! The policy is set per storage via [super::StorageHandleBuilder::lock_policy] and is shared by
+//! all clones and casts of the handle.
+//
+// # Internal Design
+//
+// - Intents are kept per thread and per access kind along with when the thread first failed. A
+//   thread that gives up retrying can't tell the handle, so intents that haven't been refreshed by
+//   a failed attempt within [STALE_INTENT_AFTER] are ignored and eventually pruned.
+// - The policy only gates acquisitions made through the handle. The lock itself is still the std
+//   RwLock so whatever preference the platform implementation has still applies underneath.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    thread::ThreadId,
+    time::{Duration, Instant},
+};
+
+use crate::{ErrorKind, SimpleResult, StorageError};
+
+use super::LockAccess;
+
+/// Intents not refreshed by a failed attempt within this duration are treated as abandoned
+pub const STALE_INTENT_AFTER: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockPolicy
+{
+    /// Readers are never held back. This is the cheapest policy and matches handles without a
+    /// policy.
+    #[default]
+    ReaderPreference,
+
+    /// New readers are turned away while any writer is waiting
+    WriterPreference,
+
+    /// Waiting threads are served in the order they started waiting, alternating between batches
+    /// of readers and single writers
+    Fair,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PolicyState
+{
+    pub(crate) policy: LockPolicy,
+    intents: Mutex<Intents>,
+}
+
+#[derive(Debug, Default)]
+struct Intents
+{
+    readers: HashMap<ThreadId, Intent>,
+    writers: HashMap<ThreadId, Intent>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Intent
+{
+    since: Instant,
+    last_attempt: Instant,
+}
+
+impl Intent
+{
+    fn is_stale(&self, now: Instant) -> bool
+    {
+        now.duration_since(self.last_attempt) > STALE_INTENT_AFTER
+    }
+}
+
+impl PolicyState
+{
+    pub(crate) fn new(policy: LockPolicy) -> Self
+    {
+        Self {
+            policy,
+            intents: <_>::default(),
+        }
+    }
+
+    fn intents(&self) -> MutexGuard<'_, Intents>
+    {
+        self.intents
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether the current thread may attempt to acquire with `access` right now
+    pub(crate) fn admit(&self, access: LockAccess) -> SimpleResult<()>
+    {
+        if self.policy == LockPolicy::ReaderPreference
+        {
+            return Ok(());
+        }
+
+        let thread = std::thread::current().id();
+        let now = Instant::now();
+        let intents = self.intents();
+
+        let (own, others) = match access
+        {
+            LockAccess::Read => (&intents.readers, &intents.writers),
+            LockAccess::Write => (&intents.writers, &intents.readers),
+        };
+
+        let own_since = own.get(&thread).map_or(now, |intent| intent.since);
+
+        let waiting_first = others.values().any(|other| {
+            !other.is_stale(now)
+                && match (self.policy, access)
+                {
+                    (LockPolicy::WriterPreference, LockAccess::Read) => true,
+                    (LockPolicy::WriterPreference, LockAccess::Write) => false,
+                    (_, _) => other.since < own_since,
+                }
+        });
+
+        if waiting_first
+        {
+            let waiting = match access
+            {
+                LockAccess::Read => "writer",
+                LockAccess::Write => "reader",
+            };
+
+            return Err(StorageError::new(
+                ErrorKind::WouldBlock,
+                format!(
+                    "Lock not attempted as a {waiting} has priority under the {:?} lock policy",
+                    self.policy
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn on_acquired(&self, access: LockAccess)
+    {
+        if self.policy == LockPolicy::ReaderPreference
+        {
+            return;
+        }
+
+        let thread = std::thread::current().id();
+        let mut intents = self.intents();
+
+        match access
+        {
+            LockAccess::Read => intents.readers.remove(&thread),
+            LockAccess::Write => intents.writers.remove(&thread),
+        };
+    }
+
+    pub(crate) fn on_failed(&self, access: LockAccess)
+    {
+        if self.policy == LockPolicy::ReaderPreference
+        {
+            return;
+        }
+
+        let thread = std::thread::current().id();
+        let now = Instant::now();
+        let mut intents = self.intents();
+
+        let own = match access
+        {
+            LockAccess::Read => &mut intents.readers,
+            LockAccess::Write => &mut intents.writers,
+        };
+
+        own.retain(|_, intent| !intent.is_stale(now));
+
+        own.entry(thread)
+            .and_modify(|intent| intent.last_attempt = now)
+            .or_insert(Intent {
+                since: now,
+                last_attempt: now,
+            });
+    }
+}
+
+// Every test here needs handles that can be sent between threads
+#[cfg(all(test, not(feature = "local")))]
+mod tests
+{
+    use std::sync::{Arc, Barrier};
+
+    use super::LockPolicy;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::VecStorage,
+    };
+
+    fn handle(policy: LockPolicy) -> StorageHandle<dyn Storage>
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let mut builder = builder(storage);
+        builder.lock_policy(policy);
+        builder.build()
+    }
+
+    /// Runs a reader that holds the lock while a writer fails once and then returns whether a
+    /// new reader is admitted
+    fn new_reader_admitted_while_writer_waits(policy: LockPolicy) -> bool
+    {
+        let handle = handle(policy);
+        let barrier = Arc::new(Barrier::new(2));
+
+        let writer = {
+            let handle = handle.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                assert!(handle.try_write().is_err());
+                barrier.wait();
+            })
+        };
+
+        let admitted = {
+            let _first_reader = handle.try_read().unwrap();
+            barrier.wait();
+            barrier.wait();
+
+            std::thread::scope(|scope| scope.spawn(|| handle.try_read().is_ok()).join().unwrap())
+        };
+
+        writer.join().unwrap();
+        admitted
+    }
+
+    #[test]
+    fn lock_policy_test()
+    {
+        assert!(new_reader_admitted_while_writer_waits(
+            LockPolicy::ReaderPreference
+        ));
+        assert!(!new_reader_admitted_while_writer_waits(
+            LockPolicy::WriterPreference
+        ));
+        assert!(!new_reader_admitted_while_writer_waits(LockPolicy::Fair));
+    }
+}
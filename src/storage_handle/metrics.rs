@@ -0,0 +1,215 @@
+//! Opt-in lock contention metrics per storage.
+//!
+//! Enabled via [super::StorageHandleBuilder::metrics] and read via [StorageHandle::metrics]. The
+//! counters are shared by all clones and casts of a handle and cover every lock taken through the
+//! handle API, including async guards.
+//
+// # Internal Design
+//
+// - Counters are atomics so that recording never takes a lock on the hot path. Only the wait start
+//   of threads that are currently failing to acquire is kept behind a mutex.
+// - Wait time is measured from a thread's first failed attempt to its next successful acquisition
+//   of the same storage since the try based API has no other notion of waiting.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::ThreadId,
+    time::{Duration, Instant},
+};
+
+use crate::storage_traits::Storage;
+
+use super::{HandleState, LockAccess, StorageHandle};
+
+/// Snapshot of the lock metrics of a storage
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LockMetrics
+{
+    pub reads: u64,
+    pub writes: u64,
+    pub failed_reads: u64,
+    pub failed_writes: u64,
+
+    /// Total time read guards have been held for. Overlapping read guards each count.
+    pub read_hold_time: Duration,
+    pub write_hold_time: Duration,
+
+    /// Longest time a thread kept failing to acquire before it succeeded
+    pub max_wait: Duration,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MetricsState
+{
+    reads: AtomicU64,
+    writes: AtomicU64,
+    failed_reads: AtomicU64,
+    failed_writes: AtomicU64,
+    read_hold_nanos: AtomicU64,
+    write_hold_nanos: AtomicU64,
+    max_wait_nanos: AtomicU64,
+    wait_starts: Mutex<HashMap<ThreadId, Instant>>,
+}
+
+fn as_nanos(duration: Duration) -> u64
+{
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+impl MetricsState
+{
+    fn wait_starts(&self) -> MutexGuard<'_, HashMap<ThreadId, Instant>>
+    {
+        self.wait_starts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn on_acquired(&self, access: LockAccess)
+    {
+        match access
+        {
+            LockAccess::Read => self.reads.fetch_add(1, Ordering::Relaxed),
+            LockAccess::Write => self.writes.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let wait_start = self.wait_starts().remove(&std::thread::current().id());
+
+        if let Some(wait_start) = wait_start
+        {
+            self.max_wait_nanos
+                .fetch_max(as_nanos(wait_start.elapsed()), Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn on_failed(&self, access: LockAccess)
+    {
+        match access
+        {
+            LockAccess::Read => self.failed_reads.fetch_add(1, Ordering::Relaxed),
+            LockAccess::Write => self.failed_writes.fetch_add(1, Ordering::Relaxed),
+        };
+
+        self.wait_starts()
+            .entry(std::thread::current().id())
+            .or_insert_with(Instant::now);
+    }
+
+    fn snapshot(&self) -> LockMetrics
+    {
+        LockMetrics {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            failed_reads: self.failed_reads.load(Ordering::Relaxed),
+            failed_writes: self.failed_writes.load(Ordering::Relaxed),
+            read_hold_time: Duration::from_nanos(self.read_hold_nanos.load(Ordering::Relaxed)),
+            write_hold_time: Duration::from_nanos(self.write_hold_nanos.load(Ordering::Relaxed)),
+            max_wait: Duration::from_nanos(self.max_wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn reset(&self)
+    {
+        for counter in [
+            &self.reads,
+            &self.writes,
+            &self.failed_reads,
+            &self.failed_writes,
+            &self.read_hold_nanos,
+            &self.write_hold_nanos,
+            &self.max_wait_nanos,
+        ]
+        {
+            counter.store(0, Ordering::Relaxed);
+        }
+
+        self.wait_starts().clear();
+    }
+}
+
+/// Guard hook that adds the hold time of a guard to the metrics once it is released
+pub(crate) struct HoldRecord
+{
+    pub(crate) state: Arc<HandleState>,
+    pub(crate) access: LockAccess,
+    pub(crate) acquired: Instant,
+}
+
+impl Drop for HoldRecord
+{
+    fn drop(&mut self)
+    {
+        let Some(metrics) = &self.state.metrics
+        else
+        {
+            return;
+        };
+
+        let held = as_nanos(self.acquired.elapsed());
+
+        match self.access
+        {
+            LockAccess::Read => metrics.read_hold_nanos.fetch_add(held, Ordering::Relaxed),
+            LockAccess::Write => metrics.write_hold_nanos.fetch_add(held, Ordering::Relaxed),
+        };
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Lock metrics of the storage or None if the handle was built without metrics
+    pub fn metrics(&self) -> Option<LockMetrics>
+    {
+        self.state.metrics.as_ref().map(MetricsState::snapshot)
+    }
+
+    pub fn reset_metrics(&self)
+    {
+        if let Some(metrics) = &self.state.metrics
+        {
+            metrics.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn metrics_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let mut builder = builder(storage);
+        builder.metrics();
+        let handle: StorageHandle<dyn Storage> = builder.build();
+
+        {
+            let _read_guard = handle.try_read().unwrap();
+            assert!(handle.try_write().is_err());
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        assert!(handle.try_write().is_ok());
+
+        let metrics = handle.metrics().unwrap();
+        assert_eq!((metrics.reads, metrics.writes), (1, 1));
+        assert_eq!((metrics.failed_reads, metrics.failed_writes), (0, 1));
+        assert!(metrics.read_hold_time >= std::time::Duration::from_millis(2));
+        assert!(metrics.max_wait >= std::time::Duration::from_millis(2));
+
+        handle.reset_metrics();
+        assert_eq!(handle.metrics().unwrap().reads, 0);
+    }
+}
@@ -2,9 +2,58 @@
 //! See [StorageHandle] for details
 
 pub mod handle;
+pub(crate) mod access_stats;
+mod batch;
+mod blocking;
+mod checkpoint;
+mod consistency;
 mod guards;
+mod info;
+pub mod lock_policy;
+pub mod lock_order;
+mod metrics;
+mod read_cache;
+mod seqlock;
+mod staging;
+mod subscription;
+mod transaction;
+mod validation;
 mod view_storage_controller;
+mod write_signal;
+
+#[cfg(feature = "async")]
+mod async_access;
+
+#[cfg(feature = "tokio")]
+mod async_handle;
+
+#[cfg(feature = "read_mostly")]
+mod read_mostly;
+
+// Watches are subscriber callbacks which need handles that can be sent between threads
+#[cfg(not(feature = "local"))]
+mod watch;
 
 pub use handle::*;
+#[cfg(feature = "access_stats")]
+pub use access_stats::AccessStats;
+pub use batch::BatchEditor;
+pub use checkpoint::CheckpointId;
+pub use consistency::{ConsistencyGroup, EpochUpdate, CONSISTENT_READ_ATTEMPTS};
 pub use guards::*;
+pub use info::{StorageCapabilities, StorageInfo};
+pub use metrics::LockMetrics;
+pub use seqlock::{SeqLockHandle, SeqLockItem};
+pub use staging::StagingGuard;
+pub use subscription::{Subscription, WriteNotification};
+pub use transaction::*;
 pub use view_storage_controller::*;
+
+#[cfg(feature = "async")]
+pub use async_access::*;
+
+#[cfg(feature = "tokio")]
+pub use async_handle::AsyncStorageHandle;
+
+#[cfg(feature = "read_mostly")]
+pub use read_mostly::*;
@@ -0,0 +1,236 @@
+//! Opt-in thread local cache for repeated reads of the same item.
+//!
+//! When enabled via [super::StorageHandleBuilder::read_cache], each thread remembers the last item
+//! it read through [StorageHandle::get_cached] along with the handle's write version at the time.
+//! Repeated reads of the same key are served from the cache without locking for as long as no
+//! write lock has been taken on the storage.
+//!
+//! Writes made to an input storage through a write view don't change the handle's write version,
+//! so storages that are written that way should not use the cache.
+//
+// # Internal Design
+//
+// - Only a single (key, item) is kept per handle per thread. The target use case is a parameter
+//   looked up thousands of times per frame rather than a general purpose cache.
+// - Entries are keyed by a cache id that is unique for the lifetime of the process rather than by
+//   [super::StorageId] as storage addresses can be reused once a storage is dropped.
+// - A thread can't reach into the caches of other threads, so dropping a handle only bumps a global
+//   epoch. Each thread drops the entries of dropped handles on its next cached read after the epoch
+//   changed, which keeps long lived worker threads from collecting an entry per storage they ever
+//   read.
+// - The write version is read while holding the read lock so a cached item can never be paired with
+//   a version from after a write started.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+};
+
+use crate::{
+    storage_traits::{ItemTrait, KeyItemStorage, KeyTrait},
+    SimpleResult,
+};
+
+use super::StorageHandle;
+
+static NEXT_CACHE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Bumped whenever a [ReadCacheId] is dropped
+static DROPPED_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies the cache entries of a handle, held in the state shared by its clones and casts
+pub(crate) struct ReadCacheId
+{
+    id: u64,
+
+    /// Entries hold a weak reference so that threads can tell that the handle is gone
+    alive: Arc<()>,
+}
+
+impl ReadCacheId
+{
+    pub(crate) fn new() -> Self
+    {
+        Self {
+            id: NEXT_CACHE_ID.fetch_add(1, Ordering::Relaxed),
+            alive: Arc::new(()),
+        }
+    }
+}
+
+impl Drop for ReadCacheId
+{
+    fn drop(&mut self)
+    {
+        // Release the entries' owner before bumping the epoch so that a thread which sees the new
+        // epoch also sees the entries as dead
+        self.alive = Arc::new(());
+        DROPPED_EPOCH.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+struct CachedRead<Key, Item>
+{
+    key: Key,
+    item: Option<Item>,
+    version: u64,
+}
+
+struct CacheEntry
+{
+    owner: Weak<()>,
+    read: Box<dyn Any>,
+}
+
+#[derive(Default)]
+struct ReadCache
+{
+    entries: HashMap<u64, CacheEntry>,
+
+    /// [DROPPED_EPOCH] when the entries of dropped handles were last removed
+    pruned_epoch: u64,
+}
+
+impl ReadCache
+{
+    fn prune_dropped(&mut self)
+    {
+        let epoch = DROPPED_EPOCH.load(Ordering::Acquire);
+
+        if epoch != self.pruned_epoch
+        {
+            self.entries
+                .retain(|_, entry| entry.owner.strong_count() > 0);
+            self.pruned_epoch = epoch;
+        }
+    }
+}
+
+thread_local! {
+    static READ_CACHE: RefCell<ReadCache> = RefCell::new(ReadCache::default());
+}
+
+impl<S> StorageHandle<S>
+where
+    S: KeyItemStorage + ?Sized,
+    S::Key: KeyTrait,
+    S::Item: ItemTrait,
+{
+    /// Clone of the item at `key`, served from this thread's cache when the same key was the last
+    /// one read on this thread and the storage has not been write locked since.
+    ///
+    /// Falls back to a normal locked read when the handle was built without a read cache.
+    pub fn get_cached(&self, key: S::Key) -> SimpleResult<Option<S::Item>>
+    {
+        let Some(cache_id) = &self.state.read_cache
+        else
+        {
+            return Ok(self.try_read()?.get(key).cloned());
+        };
+
+        let version = self.write_version();
+
+        let hit = READ_CACHE.with_borrow_mut(|cache| {
+            cache.prune_dropped();
+
+            cache
+                .entries
+                .get(&cache_id.id)
+                .and_then(|entry| entry.read.downcast_ref::<CachedRead<S::Key, S::Item>>())
+                .filter(|entry| entry.key == key && entry.version == version)
+                .map(|entry| entry.item.clone())
+        });
+
+        if let Some(item) = hit
+        {
+            return Ok(item);
+        }
+
+        let (item, version) = {
+            let guard = self.try_read()?;
+            (guard.get(key).cloned(), self.write_version())
+        };
+
+        READ_CACHE.with_borrow_mut(|cache| {
+            cache.entries.insert(
+                cache_id.id,
+                CacheEntry {
+                    owner: Arc::downgrade(&cache_id.alive),
+                    read: Box::new(CachedRead {
+                        key,
+                        item: item.clone(),
+                        version,
+                    }),
+                },
+            )
+        });
+
+        Ok(item)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::READ_CACHE;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::KeyItemStorage,
+        storage_types::VecStorage,
+    };
+
+    fn cached_handle(items: Vec<i32>)
+        -> StorageHandle<dyn KeyItemStorage<Key = usize, Item = i32>>
+    {
+        let mut builder = builder(VecStorage::<usize, i32>::new_from_iter(items));
+        builder.read_cache();
+        builder.build().cast_to_getitem_storage().unwrap()
+    }
+
+    #[test]
+    fn get_cached_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let mut builder = builder(storage);
+        builder.read_cache();
+        let handle: StorageHandle<dyn KeyItemStorage<Key = usize, Item = i32>> =
+            builder.build().cast_to_getitem_storage().unwrap();
+
+        assert_eq!(handle.get_cached(1).unwrap(), Some(2));
+
+        {
+            let sized: StorageHandle<VecStorage<usize, i32>> =
+                handle.clone().cast_to_sized_storage().unwrap();
+            let mut guard = sized.try_write().unwrap();
+
+            // Taking the write lock invalidates the cache so the read has to lock
+            assert!(handle.get_cached(1).is_err());
+
+            guard.set(1, 20);
+        }
+
+        assert_eq!(handle.get_cached(1).unwrap(), Some(20));
+    }
+
+    #[test]
+    fn dropped_handle_evicted_test()
+    {
+        let dropped = cached_handle(vec![1]);
+        assert_eq!(dropped.get_cached(0).unwrap(), Some(1));
+
+        let kept = cached_handle(vec![2]);
+        assert_eq!(kept.get_cached(0).unwrap(), Some(2));
+
+        let cached_ids = || READ_CACHE.with_borrow(|cache| cache.entries.len());
+        let before = cached_ids();
+
+        drop(dropped);
+        assert_eq!(kept.get_cached(0).unwrap(), Some(2));
+        assert_eq!(cached_ids(), before - 1);
+    }
+}
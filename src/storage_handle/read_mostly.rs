@@ -0,0 +1,212 @@
+//! A handle variant for storages that are read far more often than they are written.
+//!
+//! Readers of a [ReadMostlyHandle] load an immutable snapshot of the storage without taking any
+//! lock. Writers publish a whole new snapshot which readers pick up the next time they load.
+//! Readers that still hold the previous snapshot keep using it until they drop it.
+//
+// # Internal Design
+//
+// - Snapshots are held in an [ArcSwap] so loading is wait free. This means the storage is an Arc<S>
+//   rather than an Arw<S> and so it can't share the RwLock based casting functions in
+//   [crate::casting]. Since S is always sized here, snapshots coerce to any storage trait object
+//   with plain unsized coercion instead, eg: `let dyn_snapshot: Arc<dyn KeyItemStorage<..>> =
+//   handle.load();`
+// - Conversion to and from [StorageHandle] goes through [StorageHandle::cast_to_sized_storage] so a
+//   read mostly handle can be made from any storage handle whose storage can be cast to S.
+
+use std::{any::TypeId, sync::Arc};
+
+use arc_swap::ArcSwap;
+
+use crate::{
+    storage_traits::{
+        ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyStorage, KeyTrait, KeyTypeIdNoSelf, Storage,
+    },
+    Arw, SimpleResult,
+};
+
+use super::{builder, HandleState, StorageHandle};
+
+pub struct ReadMostlyHandle<S>
+where
+    S: Storage,
+{
+    current: Arc<ArcSwap<S>>,
+    state: Arc<HandleState>,
+    key_type_id: TypeId,
+    item_type_id: TypeId,
+}
+
+impl<S> Clone for ReadMostlyHandle<S>
+where
+    S: Storage,
+{
+    fn clone(&self) -> Self
+    {
+        Self {
+            current: self.current.clone(),
+            state: self.state.clone(),
+            key_type_id: self.key_type_id,
+            item_type_id: self.item_type_id,
+        }
+    }
+}
+
+impl<S> ReadMostlyHandle<S>
+where
+    S: Storage + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+{
+    pub fn new(storage: S) -> Self
+    {
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(storage)),
+            state: <_>::default(),
+            key_type_id: S::key_type_id(),
+            item_type_id: S::item_type_id(),
+        }
+    }
+}
+
+impl<S> ReadMostlyHandle<S>
+where
+    S: Storage,
+{
+    /// Snapshot the storage held by `handle`. Later writes through `handle` are not seen by the
+    /// returned handle and vice versa.
+    pub fn from_storage_handle(handle: &StorageHandle<dyn Storage>) -> SimpleResult<Self>
+    where
+        S: Clone,
+    {
+        let sized_handle: StorageHandle<S> = handle.clone().cast_to_sized_storage()?;
+        let storage: S = sized_handle.try_read()?.clone();
+
+        Ok(Self {
+            current: Arc::new(ArcSwap::from_pointee(storage)),
+            state: handle.state.clone(),
+            key_type_id: handle.key_type_id(),
+            item_type_id: handle.item_type_id(),
+        })
+    }
+
+    /// Copy the current snapshot into a new lock based [StorageHandle]
+    pub fn to_storage_handle(&self) -> StorageHandle<dyn Storage>
+    where
+        S: KeyStorage
+            + ItemStorage
+            + Clone
+            + Into<Arw<dyn Storage>>
+            + KeyTypeIdNoSelf
+            + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        let mut builder = builder(S::clone(&self.load()));
+
+        if let Some(label) = self.label()
+        {
+            builder.label(label);
+        }
+
+        if let Some(rank) = self.state.lock_rank
+        {
+            builder.lock_rank(rank);
+        }
+
+        builder.lock_policy(self.state.lock_policy.policy);
+
+        builder.build()
+    }
+
+    /// Wait free load of the current snapshot
+    pub fn load(&self) -> Arc<S>
+    {
+        self.current.load_full()
+    }
+
+    /// The current snapshot as a base storage trait object
+    pub fn load_dyn(&self) -> Arc<dyn Storage>
+    {
+        self.load()
+    }
+
+    /// Publish a new snapshot. Readers holding the previous snapshot are unaffected.
+    pub fn store(&self, storage: S)
+    {
+        self.current.store(Arc::new(storage));
+    }
+
+    /// Publish a modified copy of the current snapshot (RCU style). Readers carry on with the
+    /// snapshot they already loaded while `update` runs. If another snapshot was published in
+    /// the meantime `update` is called again on the newer one so no write is lost.
+    pub fn update(&self, mut update: impl FnMut(&S) -> S)
+    {
+        self.current.rcu(|current| update(current));
+    }
+
+    pub fn key_type_id(&self) -> TypeId
+    {
+        self.key_type_id
+    }
+
+    pub fn item_type_id(&self) -> TypeId
+    {
+        self.item_type_id
+    }
+
+    pub fn label(&self) -> Option<&str>
+    {
+        self.state.label.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use super::ReadMostlyHandle;
+    use crate::{
+        storage_handle::builder,
+        storage_traits::{ItemSliceStorage, KeyItemStorage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn load_store_test()
+    {
+        let handle = ReadMostlyHandle::new(VecStorage::<usize, i32>::new_from_iter(vec![1, 2, 3]));
+
+        let old_snapshot = handle.load();
+
+        handle.store(VecStorage::new_from_iter(vec![4, 5]));
+
+        // Existing readers keep the snapshot they loaded
+        assert_eq!(old_snapshot.as_item_slice(), &[1, 2, 3]);
+        assert_eq!(handle.load().as_item_slice(), &[4, 5]);
+
+        let dyn_snapshot: Arc<dyn KeyItemStorage<Key = usize, Item = i32>> = handle.load();
+        assert_eq!(dyn_snapshot.get(1), Some(&5));
+
+        handle.update(|old| VecStorage::new_from_iter(old.as_item_slice().iter().map(|i| i * 2)));
+        assert_eq!(handle.load().as_item_slice(), &[8, 10]);
+    }
+
+    #[test]
+    fn storage_handle_round_trip_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let mut builder = builder(storage);
+        builder.label("params");
+        let handle = builder.build();
+
+        let read_mostly: ReadMostlyHandle<VecStorage<usize, i32>> =
+            ReadMostlyHandle::from_storage_handle(&handle).unwrap();
+
+        assert_eq!(read_mostly.label(), Some("params"));
+        assert_eq!(read_mostly.load_dyn().len(), 3);
+
+        let handle = read_mostly.to_storage_handle();
+        assert_eq!(handle.label(), Some("params"));
+        assert_eq!(handle.try_read().unwrap().len(), 3);
+    }
+}
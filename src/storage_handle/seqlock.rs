@@ -0,0 +1,571 @@
+//! A handle variant for small storages of plain data items, eg scalar or vector parameters, that
+//! are read far more often than they are written.
+//!
+//! Readers of a [SeqLockHandle] copy items out without taking any lock. A sequence counter that
+//! writers bump before and after each write lets a reader detect that a write overlapped its copy,
+//! in which case it copies again. Writers are serialized with each other by a mutex that readers
+//! never touch:
+//!
+//! ```ignore
+//! let params: SeqLockHandle<usize, [f32; 3]> = SeqLockHandle::from_storage_handle(&handle)?;
+//!
+//! // Evaluation threads
+//! let position = params.get(0).unwrap();
+//!
+//! // Editor thread
+//! params.set(0, [1.0, 2.0, 0.5])?;
+//! ```
+//
+// # Internal Design
+//
+// - Items are stored as atomic u64 words rather than behind an UnsafeCell so that torn reads are
+//   only ever a logical inconsistency that the sequence check discards, never a data race. Items
+//   describe how they map to words through [SeqLockItem] without any unsafe code, at the cost of a
+//   whole word per scalar.
+// - The number of items is fixed when the handle is created. Growing would need to reallocate the
+//   words while readers may be copying from them.
+// - The sequence counter follows the usual seqlock fences: writers make it odd, issue a release
+//   fence, store the words and make it even with a release store. Readers load it with acquire,
+//   load the words, issue an acquire fence and check it is unchanged.
+// - Readers spin while a write is in progress. Writes are short copies done under the writer mutex,
+//   which writers only take with try_lock, so a reader never waits on anything but a copy.
+// - A write that panics, eg in a user [SeqLockItem::to_words], still makes the sequence even on
+//   unwind so readers don't spin forever, and the writer mutex it poisoned is taken over by later
+//   writes. Readers may then see the items it wrote so far.
+
+use std::{any::TypeId, marker::PhantomData, sync::TryLockError};
+
+use crate::{
+    error::lock_error,
+    storage_traits::{ItemTrait, KeyTrait, Storage},
+    storage_types::{try_key_to_index, VecStorage},
+    sync::{
+        atomic::{fence, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    ErrorKind, SimpleResult, StorageError,
+};
+
+use super::StorageHandle;
+
+/// Plain data items that a [SeqLockHandle] can copy in and out of u64 words
+pub trait SeqLockItem: ItemTrait + Copy
+{
+    /// Number of words that one item occupies
+    const WORDS: usize;
+
+    /// Write the item into `words`, which is [Self::WORDS] long
+    fn to_words(&self, words: &mut [u64]);
+
+    /// Read an item from `words`, which is [Self::WORDS] long
+    fn from_words(words: &[u64]) -> Self;
+}
+
+macro_rules! impl_seqlock_item_int {
+    ($($item:ty),*) => {
+        $(
+            impl SeqLockItem for $item
+            {
+                const WORDS: usize = 1;
+
+                fn to_words(&self, words: &mut [u64])
+                {
+                    words[0] = *self as u64;
+                }
+
+                fn from_words(words: &[u64]) -> Self
+                {
+                    words[0] as $item
+                }
+            }
+        )*
+    };
+}
+
+impl_seqlock_item_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl SeqLockItem for f32
+{
+    const WORDS: usize = 1;
+
+    fn to_words(&self, words: &mut [u64])
+    {
+        words[0] = self.to_bits() as u64;
+    }
+
+    fn from_words(words: &[u64]) -> Self
+    {
+        f32::from_bits(words[0] as u32)
+    }
+}
+
+impl SeqLockItem for f64
+{
+    const WORDS: usize = 1;
+
+    fn to_words(&self, words: &mut [u64])
+    {
+        words[0] = self.to_bits();
+    }
+
+    fn from_words(words: &[u64]) -> Self
+    {
+        f64::from_bits(words[0])
+    }
+}
+
+impl SeqLockItem for bool
+{
+    const WORDS: usize = 1;
+
+    fn to_words(&self, words: &mut [u64])
+    {
+        words[0] = *self as u64;
+    }
+
+    fn from_words(words: &[u64]) -> Self
+    {
+        words[0] != 0
+    }
+}
+
+impl<T, const N: usize> SeqLockItem for [T; N]
+where
+    T: SeqLockItem,
+    [T; N]: ItemTrait,
+{
+    const WORDS: usize = T::WORDS * N;
+
+    fn to_words(&self, words: &mut [u64])
+    {
+        for (item, words) in self.iter().zip(words.chunks_exact_mut(T::WORDS))
+        {
+            item.to_words(words);
+        }
+    }
+
+    fn from_words(words: &[u64]) -> Self
+    {
+        std::array::from_fn(|index| T::from_words(&words[index * T::WORDS..]))
+    }
+}
+
+struct SeqLockState
+{
+    sequence: AtomicU64,
+    words: Box<[AtomicU64]>,
+    writer: Mutex<()>,
+}
+
+pub struct SeqLockHandle<Key, Item>
+where
+    Item: SeqLockItem,
+{
+    state: Arc<SeqLockState>,
+    len: usize,
+    label: Option<Arc<str>>,
+    phantom: PhantomData<fn() -> (Key, Item)>,
+}
+
+impl<Key, Item> Clone for SeqLockHandle<Key, Item>
+where
+    Item: SeqLockItem,
+{
+    fn clone(&self) -> Self
+    {
+        Self {
+            state: self.state.clone(),
+            len: self.len,
+            label: self.label.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Key, Item> SeqLockHandle<Key, Item>
+where
+    Key: KeyTrait,
+    Item: SeqLockItem,
+{
+    /// A handle holding a copy of `items`
+    pub fn new(items: &[Item]) -> Self
+    {
+        let words = (0..items.len() * Item::WORDS)
+            .map(|_| AtomicU64::new(0))
+            .collect();
+
+        let handle = Self {
+            state: Arc::new(SeqLockState {
+                sequence: AtomicU64::new(0),
+                words,
+                writer: Mutex::new(()),
+            }),
+            len: items.len(),
+            label: None,
+            phantom: PhantomData,
+        };
+
+        let mut buffer = vec![0; Item::WORDS];
+        for (index, item) in items.iter().enumerate()
+        {
+            item.to_words(&mut buffer);
+            handle.store_words(index, &buffer);
+        }
+
+        handle
+    }
+
+    /// Copy the items of the slice storage held by `handle`. Later writes through `handle` are not
+    /// seen by the returned handle and vice versa.
+    pub fn from_storage_handle(handle: &StorageHandle<dyn Storage>) -> SimpleResult<Self>
+    {
+        let slice_handle = handle.clone().cast_to_slice_storage::<Key, Item>()?;
+        let guard = slice_handle.try_read()?;
+
+        let mut seqlock_handle = Self::new(guard.as_item_slice());
+        seqlock_handle.label = handle.label().map(Arc::from);
+
+        Ok(seqlock_handle)
+    }
+
+    /// Copy the current items into a new lock based [StorageHandle] of a [VecStorage]
+    pub fn to_storage_handle(&self) -> StorageHandle<dyn Storage>
+    {
+        let mut builder = super::builder(VecStorage::<Key, Item>::from_vec(self.to_vec()));
+
+        if let Some(label) = self.label()
+        {
+            builder.label(label);
+        }
+
+        builder.build()
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    pub fn label(&self) -> Option<&str>
+    {
+        self.label.as_deref()
+    }
+
+    pub fn key_type_id(&self) -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+
+    pub fn item_type_id(&self) -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+
+    /// Lock free copy of the item at `key`. None if the key is out of range.
+    pub fn get(&self, key: Key) -> Option<Item>
+    {
+        let index = try_key_to_index(key)
+            .ok()
+            .filter(|index| *index < self.len)?;
+
+        let (mut stack, mut heap) = ([0; STACK_WORDS], Vec::new());
+        let buffer = item_buffer::<Item>(&mut stack, &mut heap);
+
+        self.read_consistent(|words| {
+            load_words(&words[index * Item::WORDS..], buffer);
+        });
+
+        Some(Item::from_words(buffer))
+    }
+
+    /// Lock free copy of every item, all from the same version of the storage
+    pub fn to_vec(&self) -> Vec<Item>
+    {
+        let mut items = Vec::with_capacity(self.len);
+        self.read_into(&mut items);
+
+        items
+    }
+
+    /// Like [Self::to_vec] but reuses the allocation of `items`, which is cleared first
+    pub fn read_into(&self, items: &mut Vec<Item>)
+    {
+        let mut buffer = vec![0; self.state.words.len()];
+
+        self.read_consistent(|words| load_words(words, &mut buffer));
+
+        items.clear();
+        items.extend((0..self.len).map(|index| Item::from_words(&buffer[index * Item::WORDS..])));
+    }
+
+    /// Overwrite the item at `key`. Fails if the key is out of range or another write is in
+    /// progress.
+    pub fn set(&self, key: Key, item: Item) -> SimpleResult<()>
+    {
+        let index = try_key_to_index(key)?;
+
+        if index >= self.len
+        {
+            return Err(StorageError::new(
+                ErrorKind::KeyInvalid,
+                format!(
+                    "Key {key:?} is out of range of a seqlock handle of {} items",
+                    self.len
+                ),
+            ));
+        }
+
+        let (mut stack, mut heap) = ([0; STACK_WORDS], Vec::new());
+        let buffer = item_buffer::<Item>(&mut stack, &mut heap);
+        item.to_words(buffer);
+
+        self.write(|handle| handle.store_words(index, buffer))
+    }
+
+    /// Overwrite every item. Fails if `items` is of another length or another write is in
+    /// progress.
+    pub fn store(&self, items: &[Item]) -> SimpleResult<()>
+    {
+        if items.len() != self.len
+        {
+            return Err(StorageError::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Failed to store {} items into a seqlock handle of {} items",
+                    items.len(),
+                    self.len
+                ),
+            ));
+        }
+
+        let mut buffer = vec![0; Item::WORDS];
+
+        self.write(|handle| {
+            for (index, item) in items.iter().enumerate()
+            {
+                item.to_words(&mut buffer);
+                handle.store_words(index, &buffer);
+            }
+        })
+    }
+
+    fn write(&self, write: impl FnOnce(&Self)) -> SimpleResult<()>
+    {
+        let _writer = match self.state.writer.try_lock()
+        {
+            Ok(guard) => guard,
+            // The writer mutex guards no data and a panicking write still ended its write
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(error) => return Err(lock_error(&error, "Failed to aquire seqlock writer lock")),
+        };
+
+        let sequence = self.state.sequence.load(Ordering::Relaxed);
+        self.state.sequence.store(sequence + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        // Ends the write on drop so that a panicking write doesn't leave readers spinning forever
+        let _end = WriteEnd {
+            sequence: &self.state.sequence,
+            next: sequence + 2,
+        };
+
+        write(self);
+
+        Ok(())
+    }
+
+    fn store_words(&self, index: usize, words: &[u64])
+    {
+        let start = index * Item::WORDS;
+
+        for (target, word) in self.state.words[start..start + Item::WORDS]
+            .iter()
+            .zip(words)
+        {
+            target.store(*word, Ordering::Relaxed);
+        }
+    }
+
+    /// Run `read` until it ran without a write overlapping it
+    fn read_consistent(&self, mut read: impl FnMut(&[AtomicU64]))
+    {
+        loop
+        {
+            let before = self.state.sequence.load(Ordering::Acquire);
+
+            if before & 1 == 1
+            {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            read(&self.state.words);
+
+            fence(Ordering::Acquire);
+            if self.state.sequence.load(Ordering::Relaxed) == before
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Makes the sequence even again once a write is done or has panicked
+struct WriteEnd<'a>
+{
+    sequence: &'a AtomicU64,
+    next: u64,
+}
+
+impl Drop for WriteEnd<'_>
+{
+    fn drop(&mut self)
+    {
+        self.sequence.store(self.next, Ordering::Release);
+    }
+}
+
+/// Items of up to this many words are copied through a stack buffer by single item reads and writes
+const STACK_WORDS: usize = 16;
+
+/// A buffer of [SeqLockItem::WORDS] words, on the stack if it fits so that reads don't allocate
+fn item_buffer<'a, Item: SeqLockItem>(
+    stack: &'a mut [u64; STACK_WORDS],
+    heap: &'a mut Vec<u64>,
+) -> &'a mut [u64]
+{
+    if Item::WORDS <= STACK_WORDS
+    {
+        return &mut stack[..Item::WORDS];
+    }
+
+    heap.resize(Item::WORDS, 0);
+    heap
+}
+
+fn load_words(words: &[AtomicU64], buffer: &mut [u64])
+{
+    for (word, target) in words.iter().zip(buffer.iter_mut())
+    {
+        *target = word.load(Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{SeqLockHandle, SeqLockItem};
+    use crate::{storage_handle::builder, storage_types::VecStorage, ErrorKind};
+
+    #[test]
+    fn get_set_test()
+    {
+        let params: SeqLockHandle<usize, [f32; 3]> =
+            SeqLockHandle::new(&[[0.0, 1.0, 2.0], [3.0, 4.0, 5.0]]);
+
+        assert_eq!(params.get(1), Some([3.0, 4.0, 5.0]));
+        assert_eq!(params.get(2), None);
+
+        params.set(0, [-1.0, 0.5, 8.0]).unwrap();
+        assert_eq!(params.to_vec(), vec![[-1.0, 0.5, 8.0], [3.0, 4.0, 5.0]]);
+
+        assert_eq!(
+            params.set(2, [0.0; 3]).unwrap_err().kind(),
+            ErrorKind::KeyInvalid
+        );
+        assert!(params.store(&[[0.0; 3]]).is_err());
+
+        let ints: SeqLockHandle<u8, i16> = SeqLockHandle::new(&[-3, 7]);
+        assert_eq!(ints.get(0), Some(-3));
+    }
+
+    #[test]
+    fn storage_handle_round_trip_test()
+    {
+        let mut builder = builder(VecStorage::<usize, f64>::from_vec(vec![1.5, 2.5]));
+        builder.label("params");
+        let handle = builder.build();
+
+        let params: SeqLockHandle<usize, f64> =
+            SeqLockHandle::from_storage_handle(&handle).unwrap();
+        assert_eq!(params.label(), Some("params"));
+        assert_eq!(params.to_vec(), vec![1.5, 2.5]);
+
+        params.store(&[3.0, 4.0]).unwrap();
+
+        let handle = params.to_storage_handle();
+        assert_eq!(handle.label(), Some("params"));
+        assert_eq!(handle.try_read().unwrap().len(), 2);
+    }
+
+    /// Readers racing a writer only ever see whole items
+    #[cfg(not(feature = "local"))]
+    #[test]
+    fn no_torn_reads_test()
+    {
+        let params: SeqLockHandle<usize, [u64; 4]> = SeqLockHandle::new(&[[0; 4]]);
+
+        let writer = {
+            let params = params.clone();
+            std::thread::spawn(move || {
+                for value in 1..2000
+                {
+                    while params.set(0, [value; 4]).is_err()
+                    {}
+                }
+            })
+        };
+
+        for _ in 0..2000
+        {
+            let [a, b, c, d] = params.get(0).unwrap();
+            assert!(a == b && b == c && c == d);
+        }
+
+        writer.join().unwrap();
+        assert_eq!(params.get(0), Some([1999; 4]));
+    }
+
+    #[derive(Clone, Copy, Default, Debug, PartialEq)]
+    struct Fragile(u64);
+
+    impl SeqLockItem for Fragile
+    {
+        const WORDS: usize = 1;
+
+        fn to_words(&self, words: &mut [u64])
+        {
+            assert!(self.0 != 0, "Fragile item of 0");
+            words[0] = self.0;
+        }
+
+        fn from_words(words: &[u64]) -> Self
+        {
+            Fragile(words[0])
+        }
+    }
+
+    /// A write that panics part way doesn't leave readers spinning
+    #[test]
+    fn panicking_write_test()
+    {
+        let params: SeqLockHandle<usize, Fragile> = SeqLockHandle::new(&[Fragile(1), Fragile(2)]);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            params.store(&[Fragile(3), Fragile(0)])
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(params.to_vec(), vec![Fragile(3), Fragile(2)]);
+
+        // Later writes aren't refused by the writer mutex the panic poisoned
+        params.store(&[Fragile(4), Fragile(5)]).unwrap();
+        params.set(0, Fragile(6)).unwrap();
+        assert_eq!(params.to_vec(), vec![Fragile(6), Fragile(5)]);
+    }
+}
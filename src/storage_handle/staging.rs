@@ -0,0 +1,190 @@
+//! Atomic writes to a single storage.
+//!
+//! [StorageHandle::begin] returns a [StagingGuard] that buffers writes without locking the
+//! storage. They are applied under one write lock on [StagingGuard::commit] and discarded if the
+//! guard is dropped, so a node that fails midway leaves its output untouched:
+//!
+//! ```ignore
+//! let mut staged = output.begin::<usize, f32>()?;
+//! for (key, item) in results
+//! {
+//!     staged.insert(key, item?);
+//! }
+//! staged.commit()?;
+//! ```
+//!
+//! For writes spanning several storages see [super::Transaction].
+//
+// # Internal Design
+//
+// - Staged writes are checked and then applied with the same replace-or-insert as a transaction
+//   commit, so once the write lock is held the commit can't fail midway.
+
+use std::collections::HashMap;
+
+use crate::{
+    storage_traits::{ItemTrait, KeyTrait, MutKeyItemStorage, Storage},
+    SimpleResult,
+};
+
+use super::{
+    transaction::{apply_staged, check_staged},
+    StorageHandle,
+};
+
+/// Writes to one storage buffered until commit, see [StorageHandle::begin]
+pub struct StagingGuard<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    handle: StorageHandle<dyn MutKeyItemStorage<Key = Key, Item = Item>>,
+    staged: HashMap<Key, Item>,
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Start buffering writes to the storage. Nothing is locked until [StagingGuard::commit].
+    /// Fails if the storage can't be cast to a [MutKeyItemStorage] with the given Key and Item.
+    pub fn begin<Key, Item>(&self) -> SimpleResult<StagingGuard<Key, Item>>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        Ok(StagingGuard {
+            handle: self.clone().cast_to_mut_getitem_storage::<Key, Item>()?,
+            staged: HashMap::new(),
+        })
+    }
+}
+
+impl<Key, Item> StagingGuard<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn insert(&mut self, key: Key, item: Item)
+    {
+        self.staged.insert(key, item);
+    }
+
+    /// Read an item as it will be after commit: staged writes first, then the storage. Briefly
+    /// takes a read lock on the storage if the key has not been staged.
+    pub fn get(&self, key: Key) -> SimpleResult<Option<Item>>
+    {
+        if let Some(item) = self.staged.get(&key)
+        {
+            return Ok(Some(item.clone()));
+        }
+
+        Ok(self.handle.try_read()?.get(key).cloned())
+    }
+
+    /// Stage a modification of an existing item. Fails if the key has neither been staged nor
+    /// exists in the storage.
+    pub fn modify(&mut self, key: Key, modify: impl FnOnce(&mut Item)) -> SimpleResult<()>
+    {
+        let Some(mut item) = self.get(key)?
+        else
+        {
+            return Err(format!(
+                "Cannot modify key {key:?} as it is not in the storage"
+            )
+            .into());
+        };
+
+        modify(&mut item);
+        self.insert(key, item);
+
+        Ok(())
+    }
+
+    /// Number of staged writes
+    pub fn len(&self) -> usize
+    {
+        self.staged.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.staged.is_empty()
+    }
+
+    /// Apply every staged write under one write lock. On error nothing has been applied and the
+    /// writes are discarded.
+    pub fn commit(self) -> SimpleResult<()>
+    {
+        if self.staged.is_empty()
+        {
+            return Ok(());
+        }
+
+        let mut guard = self.handle.try_write().map_err(|error| {
+            format!("Staged writes discarded as the storage could not be locked: {error}")
+        })?;
+
+        check_staged(&*guard, self.staged.keys().copied()).map_err(|error| {
+            format!("Staged writes discarded as one can't be applied to the storage: {error}")
+        })?;
+
+        apply_staged(&mut *guard, self.staged.into_iter());
+
+        Ok(())
+    }
+
+    /// Discard every staged write. Equivalent to dropping the guard.
+    pub fn abort(self) {}
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn staging_test()
+    {
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3])).build();
+        let reader = handle
+            .clone()
+            .cast_to_getitem_storage::<usize, i32>()
+            .unwrap();
+        let items = || -> Vec<i32> { reader.try_read().unwrap().item_iter().copied().collect() };
+
+        let mut staged = handle.begin::<usize, i32>().unwrap();
+        staged.insert(0, 10);
+        staged.modify(2, |item| *item += 1).unwrap();
+        assert!(staged.modify(7, |item| *item += 1).is_err());
+        assert_eq!(staged.get(0).unwrap(), Some(10));
+        assert_eq!(items(), vec![1, 2, 3]);
+
+        staged.commit().unwrap();
+        assert_eq!(items(), vec![10, 2, 4]);
+
+        // Dropped without commit
+        handle.begin::<usize, i32>().unwrap().insert(1, 20);
+        assert_eq!(items(), vec![10, 2, 4]);
+
+        let mut staged = handle.begin::<usize, i32>().unwrap();
+        staged.insert(1, 20);
+        {
+            let _guard = handle.try_read().unwrap();
+            assert!(staged.commit().is_err());
+        }
+        assert_eq!(items(), vec![10, 2, 4]);
+
+        // A write that can't be applied discards the others
+        let mut staged = handle.begin::<usize, i32>().unwrap();
+        staged.insert(1, 20);
+        staged.insert(usize::MAX, 30);
+        assert!(staged.commit().is_err());
+        assert_eq!(items(), vec![10, 2, 4]);
+    }
+}
@@ -0,0 +1,254 @@
+//! Push notifications of writes to a storage for reactive consumers that would otherwise poll
+//! [StorageHandle::write_version] every frame.
+//!
+//! A subscriber is notified with a [WriteNotification] every time a write guard on the storage is
+//! released, either through a callback or a channel. Several handles can feed one channel so a
+//! consumer can watch a whole set of storages from one place:
+//!
+//! ```ignore
+//! let (sender, receiver) = std::sync::mpsc::channel();
+//! let _subscriptions: Vec<Subscription> = handles
+//!     .iter()
+//!     .map(|handle| handle.subscribe_with_sender(sender.clone()))
+//!     .collect();
+//!
+//! for notification in receiver.try_iter()
+//! {
+//!     mark_dirty(notification.storage_id);
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - Subscribers live in the state shared by all clones and casts of a handle, like the
+//   [super::write_signal] they piggy back on, so a subscription sees writes made through any of
+//   them.
+// - Callbacks run on the writing thread once its lock has been released, so they may read the
+//   storage but should be quick as the writer is held up until they return. The subscriber list
+//   is copied out before callbacks run so that a callback may subscribe or unsubscribe.
+// - A [Subscription] only holds a weak reference to the handle state so that a forgotten
+//   subscription doesn't keep the storage state alive.
+
+use std::{
+    any::Any,
+    sync::{mpsc::Sender, Arc, Mutex, MutexGuard, Weak},
+};
+
+use crate::storage_traits::{KeyTrait, Storage};
+
+use super::{HandleState, StorageHandle, StorageId};
+
+type Callback = Arc<dyn Fn(&WriteNotification) + Send + Sync>;
+
+/// Sent to subscribers when a storage has been written to
+#[derive(Clone, Debug)]
+pub struct WriteNotification
+{
+    pub storage_id: StorageId,
+
+    /// [StorageHandle::write_version] of the write
+    pub version: u64,
+
+    dirty_keys: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl WriteNotification
+{
+    pub(crate) fn new(storage_id: StorageId, version: u64) -> Self
+    {
+        Self {
+            storage_id,
+            version,
+            dirty_keys: None,
+        }
+    }
+
+    /// Keys that were changed by the write if the writer published them, see
+    /// [StorageHandle::notify_subscribers]. None if they are unknown or if `Key` isn't the key
+    /// type they were published with.
+    pub fn dirty_keys<Key>(&self) -> Option<&[Key]>
+    where
+        Key: KeyTrait,
+    {
+        self.dirty_keys
+            .as_ref()?
+            .downcast_ref::<Vec<Key>>()
+            .map(Vec::as_slice)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Subscribers
+{
+    state: Mutex<SubscriberList>,
+}
+
+#[derive(Default)]
+struct SubscriberList
+{
+    next_id: u64,
+    callbacks: Vec<(u64, Callback)>,
+}
+
+impl Subscribers
+{
+    fn lock(&self) -> MutexGuard<'_, SubscriberList>
+    {
+        // The list is always valid as callbacks are never run while it is locked
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn notify(&self, notification: &WriteNotification)
+    {
+        let callbacks: Vec<Callback> = {
+            let list = self.lock();
+
+            if list.callbacks.is_empty()
+            {
+                return;
+            }
+
+            list.callbacks
+                .iter()
+                .map(|(_, callback)| callback.clone())
+                .collect()
+        };
+
+        for callback in callbacks
+        {
+            callback(notification);
+        }
+    }
+}
+
+/// Keeps a subscription alive. The subscriber is removed when this is dropped.
+#[must_use = "the subscription ends when this is dropped"]
+pub struct Subscription
+{
+    state: Weak<HandleState>,
+    id: u64,
+}
+
+impl Drop for Subscription
+{
+    fn drop(&mut self)
+    {
+        if let Some(state) = self.state.upgrade()
+        {
+            state
+                .subscribers
+                .lock()
+                .callbacks
+                .retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Call `callback` on the writing thread after every write to the storage until the returned
+    /// [Subscription] is dropped
+    pub fn subscribe(
+        &self,
+        callback: impl Fn(&WriteNotification) + Send + Sync + 'static,
+    ) -> Subscription
+    {
+        let mut list = self.state.subscribers.lock();
+
+        let id = list.next_id;
+        list.next_id += 1;
+        list.callbacks.push((id, Arc::new(callback)));
+
+        Subscription {
+            state: Arc::downgrade(&self.state),
+            id,
+        }
+    }
+
+    /// Send a [WriteNotification] to `sender` after every write to the storage until the returned
+    /// [Subscription] is dropped or the receiver is disconnected
+    pub fn subscribe_with_sender(&self, sender: Sender<WriteNotification>) -> Subscription
+    {
+        self.subscribe(move |notification| {
+            let _ = sender.send(notification.clone());
+        })
+    }
+
+    /// Notify subscribers of a write along with the keys that it changed. Released write guards
+    /// already notify without keys so this is for writers that track their changes or that
+    /// changed the storage without a write guard from its handle.
+    pub fn notify_subscribers<Key>(&self, dirty_keys: Option<Vec<Key>>)
+    where
+        Key: KeyTrait + Send + Sync,
+    {
+        self.state.subscribers.notify(&WriteNotification {
+            storage_id: self.storage_id(),
+            version: self.write_version(),
+            dirty_keys: dirty_keys.map(|keys| Arc::new(keys) as Arc<dyn Any + Send + Sync>),
+        });
+    }
+}
+
+// Subscribers need handles that can be sent between threads
+#[cfg(all(test, not(feature = "local")))]
+mod tests
+{
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    };
+
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{MutItemSliceStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn subscribe_test()
+    {
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2])).build();
+        let sized: StorageHandle<VecStorage<usize, i32>> =
+            handle.clone().cast_to_sized_storage().unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let subscription = {
+            let calls = calls.clone();
+            let reader = handle.clone();
+
+            // Runs after the lock is released so the storage can be read
+            handle.subscribe(move |_| {
+                assert!(reader.try_read().is_ok());
+                calls.fetch_add(1, Ordering::Relaxed);
+            })
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let _channel = handle.subscribe_with_sender(sender);
+
+        sized.try_write().unwrap().as_mut_slice()[0] = 5;
+        let _ = handle.try_read().unwrap();
+
+        let notification = receiver.try_recv().unwrap();
+        assert_eq!(notification.storage_id, handle.storage_id());
+        assert_eq!(notification.version, 1);
+        assert_eq!(notification.dirty_keys::<usize>(), None);
+        assert!(receiver.try_recv().is_err());
+
+        sized.notify_subscribers(Some(vec![1usize]));
+        let notification = receiver.try_recv().unwrap();
+        assert_eq!(notification.dirty_keys::<usize>(), Some(&[1][..]));
+        assert_eq!(notification.dirty_keys::<u32>(), None);
+
+        drop(subscription);
+        let _ = sized.try_write().unwrap();
+
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+}
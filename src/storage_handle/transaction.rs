@@ -0,0 +1,431 @@
+//! Atomic writes spanning several storages.
+//!
+//! A [Transaction] stages item writes against any number of handles in overlay buffers without
+//! locking anything. On [Transaction::commit] every involved storage is write locked up front and
+//! the staged writes are only applied once all locks are held. If any lock can't be acquired, or
+//! any staged write can't be applied to its storage, no storage is touched, so related storages are
+//! never left partially updated.
+//!
+//! ```ignore
+//! let mut transaction = Transaction::new();
+//! transaction.insert(&positions, node, position)?;
+//! transaction.insert(&velocities, node, velocity)?;
+//! transaction.commit()?;
+//! ```
+//
+// # Internal Design
+//
+// - Locks are taken in a canonical order: lock rank first (see [super::lock_order]) and then
+//   [StorageId]. Two transactions over overlapping storages therefore always contend on the same
+//   storage first rather than each grabbing half of the locks.
+// - Once all locks are held every staged write is checked with [MutKeyItemStorage::check_insert]
+//   before any is applied, so keys an insert would panic on, such as stale sparse set keys or keys
+//   missing from a view, fail the commit instead. With the storages locked the checks still hold
+//   when the writes are applied, which is what makes all or nothing possible without undo logs.
+//   Only running out of memory while a storage grows can still fail midway.
+// - Each storage gets one overlay of its concrete Key / Item types. Overlays are kept type erased
+//   in one list and downcast back when more writes are staged against the same storage.
+
+use std::collections::HashMap;
+
+use downcast_rs::{impl_downcast, Downcast};
+
+use crate::{
+    storage_traits::{ItemTrait, KeyTrait, MutKeyItemStorage, Storage},
+    SimpleResult,
+};
+
+use super::{StorageHandle, StorageId};
+
+#[derive(Default)]
+pub struct Transaction
+{
+    overlays: Vec<Box<dyn StagedWrites>>,
+}
+
+impl Transaction
+{
+    pub fn new() -> Self
+    {
+        <_>::default()
+    }
+
+    /// Stage an insert. Fails if the handle's storage can't be cast to a
+    /// [MutKeyItemStorage] with the given Key and Item.
+    pub fn insert<S, Key, Item>(
+        &mut self,
+        handle: &StorageHandle<S>,
+        key: Key,
+        item: Item,
+    ) -> SimpleResult<()>
+    where
+        S: Storage + ?Sized,
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        self.overlay::<S, Key, Item>(handle)?
+            .staged
+            .insert(key, item);
+
+        Ok(())
+    }
+
+    /// Read an item as the transaction currently sees it: staged writes first, then the storage.
+    /// Briefly takes a read lock on the storage if the key has not been staged.
+    pub fn get<S, Key, Item>(
+        &mut self,
+        handle: &StorageHandle<S>,
+        key: Key,
+    ) -> SimpleResult<Option<Item>>
+    where
+        S: Storage + ?Sized,
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let overlay = self.overlay::<S, Key, Item>(handle)?;
+
+        if let Some(item) = overlay.staged.get(&key)
+        {
+            return Ok(Some(item.clone()));
+        }
+
+        Ok(overlay.handle.try_read()?.get(key).cloned())
+    }
+
+    /// Stage a modification of an existing item. Fails if the key has neither been staged nor
+    /// exists in the storage.
+    pub fn modify<S, Key, Item>(
+        &mut self,
+        handle: &StorageHandle<S>,
+        key: Key,
+        modify: impl FnOnce(&mut Item),
+    ) -> SimpleResult<()>
+    where
+        S: Storage + ?Sized,
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let Some(mut item) = self.get::<S, Key, Item>(handle, key)?
+        else
+        {
+            return Err(format!(
+                "Cannot modify key {key:?} as it is not in the storage"
+            )
+            .into());
+        };
+
+        modify(&mut item);
+
+        self.insert(handle, key, item)
+    }
+
+    /// Number of storages with staged writes
+    pub fn storage_count(&self) -> usize
+    {
+        self.overlays.len()
+    }
+
+    /// Apply every staged write atomically.
+    ///
+    /// On error nothing has been applied and all locks taken by the commit are released.
+    pub fn commit(mut self) -> SimpleResult<()>
+    {
+        self.overlays
+            .sort_by_key(|overlay| (overlay.lock_rank(), overlay.storage_id()));
+
+        let mut locked: Vec<Box<dyn LockedWrites + '_>> = Vec::with_capacity(self.overlays.len());
+
+        for overlay in &mut self.overlays
+        {
+            let storage_id = overlay.storage_id();
+
+            // Returning drops the locks already held
+            locked.push(overlay.lock().map_err(|error| {
+                format!(
+                    "Transaction commit aborted without changes as storage {storage_id} could not \
+                    be locked: {error}"
+                )
+            })?);
+        }
+
+        for writes in &locked
+        {
+            writes.check().map_err(|error| {
+                format!(
+                    "Transaction commit aborted without changes as a write staged for storage {} \
+                    can't be applied: {error}",
+                    writes.storage_id()
+                )
+            })?;
+        }
+
+        for writes in locked
+        {
+            writes.apply();
+        }
+
+        Ok(())
+    }
+
+    /// Discard every staged write. Equivalent to dropping the transaction.
+    pub fn rollback(self) {}
+
+    fn overlay<S, Key, Item>(
+        &mut self,
+        handle: &StorageHandle<S>,
+    ) -> SimpleResult<&mut KeyItemOverlay<Key, Item>>
+    where
+        S: Storage + ?Sized,
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let storage_id = handle.storage_id();
+
+        let position = match self
+            .overlays
+            .iter()
+            .position(|overlay| overlay.storage_id() == storage_id)
+        {
+            Some(position) => position,
+            None =>
+            {
+                let handle = handle.clone().cast_to_mut_getitem_storage::<Key, Item>()?;
+
+                self.overlays.push(Box::new(KeyItemOverlay {
+                    handle,
+                    staged: <_>::default(),
+                }));
+
+                self.overlays.len() - 1
+            }
+        };
+
+        self.overlays[position]
+            .downcast_mut::<KeyItemOverlay<Key, Item>>()
+            .ok_or_else(|| {
+                format!(
+                    "Storage {storage_id} already has writes staged with a different Key or Item \
+                    type"
+                )
+                .into()
+            })
+    }
+}
+
+////////////////////////////////////////////////
+// Overlays
+////////////////////////////////////////////////
+
+trait StagedWrites: Downcast
+{
+    fn storage_id(&self) -> StorageId;
+
+    fn lock_rank(&self) -> Option<u32>;
+
+    fn lock(&mut self) -> SimpleResult<Box<dyn LockedWrites + '_>>;
+}
+
+impl_downcast!(StagedWrites);
+
+/// Staged writes whose storage is write locked and ready to apply
+trait LockedWrites
+{
+    fn storage_id(&self) -> StorageId;
+
+    /// Fails if any of the writes can't be applied, see [check_staged]
+    fn check(&self) -> SimpleResult<()>;
+
+    fn apply(self: Box<Self>);
+}
+
+struct KeyItemOverlay<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    handle: StorageHandle<dyn MutKeyItemStorage<Key = Key, Item = Item>>,
+    staged: HashMap<Key, Item>,
+}
+
+impl<Key, Item> StagedWrites for KeyItemOverlay<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn storage_id(&self) -> StorageId
+    {
+        self.handle.storage_id()
+    }
+
+    fn lock_rank(&self) -> Option<u32>
+    {
+        self.handle.lock_rank()
+    }
+
+    fn lock(&mut self) -> SimpleResult<Box<dyn LockedWrites + '_>>
+    {
+        let guard = self.handle.try_write()?;
+
+        Ok(Box::new(LockedKeyItemOverlay {
+            storage_id: self.handle.storage_id(),
+            guard,
+            staged: &mut self.staged,
+        }))
+    }
+}
+
+struct LockedKeyItemOverlay<'a, G, Key, Item>
+{
+    storage_id: StorageId,
+    guard: G,
+    staged: &'a mut HashMap<Key, Item>,
+}
+
+impl<G, Key, Item> LockedWrites for LockedKeyItemOverlay<'_, G, Key, Item>
+where
+    G: std::ops::DerefMut<Target = dyn MutKeyItemStorage<Key = Key, Item = Item>>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn storage_id(&self) -> StorageId
+    {
+        self.storage_id
+    }
+
+    fn check(&self) -> SimpleResult<()>
+    {
+        check_staged(&*self.guard, self.staged.keys().copied())
+    }
+
+    fn apply(mut self: Box<Self>)
+    {
+        let Self { guard, staged, .. } = &mut *self;
+        apply_staged(&mut **guard, staged.drain());
+    }
+}
+
+/// Fails if any of the staged keys can't be written to the locked storage by [apply_staged]
+pub(super) fn check_staged<Key, Item>(
+    storage: &(impl MutKeyItemStorage<Key = Key, Item = Item> + ?Sized),
+    keys: impl Iterator<Item = Key>,
+) -> SimpleResult<()>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    for key in keys
+    {
+        // Items that are already in the storage are replaced in place
+        if storage.get(key).is_none()
+        {
+            storage.check_insert(key)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write staged items to a locked storage. The writes should have passed [check_staged].
+pub(super) fn apply_staged<Key, Item>(
+    storage: &mut (impl MutKeyItemStorage<Key = Key, Item = Item> + ?Sized),
+    staged: impl Iterator<Item = (Key, Item)>,
+) where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    for (key, item) in staged
+    {
+        // Existing items are replaced in place as insert shifts items in vec like storages
+        match storage.get_mut(key)
+        {
+            Some(existing) => *existing = item,
+            None => storage.insert(key, item),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::Transaction;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::VecStorage,
+    };
+
+    fn handle(items: Vec<i32>) -> StorageHandle<dyn Storage>
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(items);
+        builder(storage).build()
+    }
+
+    fn items(handle: &StorageHandle<dyn Storage>) -> Vec<i32>
+    {
+        let handle = handle
+            .clone()
+            .cast_to_getitem_storage::<usize, i32>()
+            .unwrap();
+        let guard = handle.try_read().unwrap();
+        guard.item_iter().cloned().collect()
+    }
+
+    #[test]
+    fn commit_test()
+    {
+        let handle_a = handle(vec![1, 2, 3]);
+        let handle_b = handle(vec![4, 5, 6]);
+
+        let mut transaction = Transaction::new();
+        transaction.insert(&handle_a, 0_usize, 10).unwrap();
+        transaction
+            .modify(&handle_b, 2_usize, |item: &mut i32| *item += 1)
+            .unwrap();
+
+        // Reads see staged writes while the storages are untouched
+        assert_eq!(transaction.get(&handle_a, 0_usize).unwrap(), Some(10));
+        assert_eq!(items(&handle_a), vec![1, 2, 3]);
+
+        transaction.commit().unwrap();
+
+        assert_eq!(items(&handle_a), vec![10, 2, 3]);
+        assert_eq!(items(&handle_b), vec![4, 5, 7]);
+    }
+
+    #[test]
+    fn commit_is_all_or_nothing_test()
+    {
+        let handle_a = handle(vec![1, 2, 3]);
+        let handle_b = handle(vec![4, 5, 6]);
+
+        let mut transaction = Transaction::new();
+        transaction.insert(&handle_a, 0_usize, 10).unwrap();
+        transaction.insert(&handle_b, 0_usize, 40).unwrap();
+
+        {
+            // Another user holds one of the storages so the commit can't lock it
+            let _guard = handle_b.try_read().unwrap();
+            assert!(transaction.commit().is_err());
+        }
+
+        assert_eq!(items(&handle_a), vec![1, 2, 3]);
+        assert_eq!(items(&handle_b), vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn invalid_write_aborts_commit_test()
+    {
+        let handle_a = handle(vec![1, 2, 3]);
+        let handle_b = handle(vec![4, 5, 6]);
+
+        let mut transaction = Transaction::new();
+        transaction.insert(&handle_a, 0_usize, 10).unwrap();
+        // No VecStorage can grow to this key so inserting it would panic
+        transaction.insert(&handle_b, usize::MAX, 40).unwrap();
+
+        assert!(transaction.commit().is_err());
+
+        assert_eq!(items(&handle_a), vec![1, 2, 3]);
+        assert_eq!(items(&handle_b), vec![4, 5, 6]);
+    }
+}
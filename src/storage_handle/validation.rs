@@ -0,0 +1,200 @@
+//! Internal consistency checks of a [StorageHandle].
+//!
+//! A handle carries metadata next to its storage pointers that the casts rely on without being
+//! able to check it: the Key and Item TypeIds, the base storage that every cast points back to and
+//! the status of its view controller. [StorageHandle::validate] checks that:
+//!
+//! - The cast storage, the base storage and the view controller's storage are one allocation
+//! - The Key, Item and concrete storage TypeIds match the ones recorded when the handle was built
+//! - The view status matches the guard that the view holds on its input storage
+//!
+//! The same checks run automatically whenever a handle is built, cast or locked when debug
+//! assertions are on or the `validation` feature is enabled, panicking on the first violation.
+//! The `no_panic` feature turns the debug assertion checks off but not the `validation` ones:
+//!
+//! ```ignore
+//! let handle = builder(VecStorage::<usize, f32>::default()).build();
+//! handle.validate()?;
+//! ```
+//
+// # Internal Design
+//
+// - Checks that need a lock use try locks and are skipped when the lock is taken, so validating
+//   never blocks and never fails because of contention.
+// - Handles created through [StorageHandle::new] rather than a builder have no recorded type info.
+//   Only their aliasing is checked.
+// - Views are only known by type at build time, so the type info stores a function, monomorphized
+//   over the Key and Item, that reads which guard the view holds on its input.
+// - The view status is read locked while the view's guard is read. Creating and clearing a view
+//   both hold the status write lock across the whole transition so no half way state is observed.
+
+use std::sync::Arc;
+
+use crate::{storage_traits::Storage, SimpleResult};
+
+use super::{InputStorageLockStatus, LockAccess, StorageHandle};
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Check the handle's metadata against its storage, see the [module docs](self)
+    pub fn validate(&self) -> SimpleResult<()>
+    {
+        self.validate_aliasing()?;
+        self.validate_type_ids()?;
+        self.validate_view_status()
+    }
+
+    /// Panic if [Self::validate] fails. A no-op unless the `validation` feature is enabled, or
+    /// debug assertions are on without the `no_panic` feature.
+    #[inline]
+    pub(crate) fn check_invariants(&self)
+    {
+        #[cfg(any(feature = "validation", all(debug_assertions, not(feature = "no_panic"))))]
+        if let Err(error) = self.validate()
+        {
+            panic!("StorageHandle invariant violated: {error}");
+        }
+    }
+
+    fn validate_aliasing(&self) -> SimpleResult<()>
+    {
+        let base = Arc::as_ptr(&self.base_storage).cast::<()>();
+
+        if Arc::as_ptr(&self.storage).cast::<()>() != base
+        {
+            return Err("The cast storage and the base storage are different allocations".into());
+        }
+
+        if let Some(view_controller) = &self.view_storage_controller
+        {
+            if Arc::as_ptr(&view_controller.view_storage).cast::<()>() != base
+            {
+                return Err(
+                    "The view controller's storage and the base storage are different allocations"
+                        .into(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_type_ids(&self) -> SimpleResult<()>
+    {
+        let Some(type_info) = self.state.type_info
+        else
+        {
+            return Ok(());
+        };
+
+        if self.key_type_id() != type_info.key_type_id
+        {
+            return Err("The key type id doesn't match the key type of the storage".into());
+        }
+
+        if self.item_type_id() != type_info.item_type_id
+        {
+            return Err("The item type id doesn't match the item type of the storage".into());
+        }
+
+        if let Ok(guard) = self.base_storage.try_read()
+        {
+            if guard.as_any().type_id() != type_info.storage_type_id
+            {
+                return Err(format!(
+                    "The storage is a {} rather than the type the handle was built with",
+                    guard.type_name()
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_view_status(&self) -> SimpleResult<()>
+    {
+        let (Some(view_controller), Some(view_input_access)) = (
+            &self.view_storage_controller,
+            self.state.type_info.and_then(|info| info.view_input_access),
+        )
+        else
+        {
+            return Ok(());
+        };
+
+        // Held while the view is read so that the view can't be created or cleared in between
+        let Ok(status) = view_controller.status.try_read()
+        else
+        {
+            return Ok(());
+        };
+
+        let Ok(access) = view_input_access(&self.base_storage)
+        else
+        {
+            return Ok(());
+        };
+
+        let expected = match *status
+        {
+            InputStorageLockStatus::None => None,
+            InputStorageLockStatus::Readable => Some(LockAccess::Read),
+            InputStorageLockStatus::Writable => Some(LockAccess::Write),
+        };
+
+        if access != expected
+        {
+            return Err(format!(
+                "The view status is {:?} but the view holds {access:?} on its input",
+                *status
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{any::TypeId, sync::Arc};
+
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::Storage,
+        storage_types::{KeyItemViewStorage, VecStorage},
+        Arw, Rw,
+    };
+
+    #[test]
+    fn validate_test()
+    {
+        let input: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0])).build();
+        assert!(input.validate().is_ok());
+
+        let mut view_builder =
+            builder(KeyItemViewStorage::<VecStorage<usize, f32>, usize, f32>::new());
+        view_builder.add_view_controller();
+        let mut view = view_builder.build();
+
+        let controller = view.view_storage_controller_mut().unwrap();
+        controller.set_input::<usize, f32>(input.clone()).unwrap();
+        controller.create_read_view::<usize, f32>(vec![1]).unwrap();
+        assert!(view.validate().is_ok());
+
+        let controller = view.view_storage_controller_mut().unwrap();
+        controller.clear_view::<usize, f32>().unwrap();
+        assert!(view.validate().is_ok());
+
+        // A handle whose storage isn't its base storage
+        let storage: Arw<dyn Storage> = Arc::new(Rw::new(VecStorage::<usize, f32>::default()));
+        let other: Arw<dyn Storage> = Arc::new(Rw::new(VecStorage::<usize, f32>::default()));
+        let handle = StorageHandle::new(storage, other, TypeId::of::<usize>(), TypeId::of::<f32>());
+        assert!(handle.validate().is_err());
+    }
+}
@@ -1,15 +1,33 @@
+use std::sync::Arc;
+
 use crate::{
     casting::cast_to_dyn_getkeyitemviewstorage,
+    error::lock_error,
     storage_traits::{ViewStorageSetup, KeyTrait, Storage, ItemTrait},
-    Arw, SimpleResult, storage_handle::StorageHandle,
+    Arw, ErrorKind, Hint, SimpleResult, StorageError, storage_handle::{StorageHandle, StorageId},
+    sync::{self, RwLockReadGuard},
 };
 
+#[cfg(feature = "deadlock_detection")]
+use crate::diagnostics::deadlock::{self, LockAccess};
+
+#[cfg(feature = "tracing")]
+use crate::diagnostics::trace;
+
+// Suggested fixes of the view misuse errors, see [Hint]
+pub(crate) const CREATE_VIEW_FIX: &str =
+    "Call create_read_view or create_write_view on the view's controller before locking the view";
+pub(crate) const CLEAR_VIEW_FIX: &str =
+    "Call clear_view on the view's controller before setting its input or creating another view";
+pub(crate) const SET_INPUT_FIX: &str =
+    "Call set_input on the view's controller before creating a view";
+
 pub struct ViewStorageController
 {
     // Design: Even though only view storages should go in here.
     // Having this as dyn Storage as opposed to a 
     // generic type reduces complexity of casting code. 
-    view_storage: Arw<dyn Storage>,
+    pub(super) view_storage: Arw<dyn Storage>,
 
     // Arw Justification
     // -----------------------------------------------------------
@@ -20,14 +38,15 @@ pub struct ViewStorageController
     // a smart pointer around the whole StorageHandle that owns this type
     // Which would impose two layers of interior mutability on other fields 
     // of StorageHandle. Thats too much of an ergonomic hit.
-    pub(super) status: Arw<InputStorageLockStatus>,
+    // The status lock comes from [crate::sync] so that it can be model checked with loom
+    pub(super) status: sync::StateArw<InputStorageLockStatus>,
 }
 
 impl ViewStorageController
 {
     pub fn new(
         base_storage: Arw<dyn Storage>,
-        status: Arw<InputStorageLockStatus>,
+        status: sync::StateArw<InputStorageLockStatus>,
     ) -> Self {
         Self {
             view_storage: base_storage,
@@ -42,18 +61,29 @@ impl ViewStorageController
     {
         // Cast from Arw<dyn Storage> -> Arw<dyn KeyItemViewStorage>
         let storage: Arw<dyn ViewStorageSetup<Key = Key>> =
-            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(self.view_storage.clone())?;
+            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(&self.view_storage)?;
 
-        let Ok(mut guard) = storage.try_write() 
-        else {
-            return Err("Failed to aquire view storage write guard".into());
-        };
+        // The status is locked first so that the view is never seen cleared while still marked as
+        // created
+        let mut status_guard = self.status.try_write().map_err(|error| {
+            lock_error(&error, "Failed to aquire write guard for ViewController's status")
+        })?;
+
+        let mut guard = storage
+            .try_write()
+            .map_err(|error| lock_error(&error, "Failed to aquire view storage write guard"))?;
 
         guard.clear_view();
-        
-        let Ok(mut status_guard) = self.status.try_write() else {
-            return Err("Failed to aquire write guard for ViewController's status".into());
-        };
+
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::on_view_cleared(StorageId::of(&self.view_storage));
+
+        #[cfg(feature = "tracing")]
+        trace::on_view_status_changed(
+            StorageId::of(&self.view_storage),
+            *status_guard,
+            InputStorageLockStatus::None,
+        );
 
         *status_guard = InputStorageLockStatus::None;
 
@@ -65,28 +95,37 @@ impl ViewStorageController
         Key: KeyTrait,
         Item: ItemTrait,
     {
-        let Ok(status_guard) = self.status.try_read() else {
-            return Err("Failed to aquire read guard for ViewController's status".into());
-        };
+        let status_guard = self.status.try_read().map_err(|error| {
+            lock_error(&error, "Failed to aquire read guard for ViewController's status")
+        })?;
 
         if *status_guard != InputStorageLockStatus::None {
-            return Err("Failed to set input. A read or write guard has already been aquired on the view. You must call clear before changing input".into());
+            #[cfg(feature = "tracing")]
+            trace::on_view_misuse(StorageId::of(&self.view_storage), *status_guard, "set_input");
+
+            return Err(StorageError::new(ErrorKind::ViewNotReady, "Failed to set input. A read or write guard has already been aquired on the view. You must call clear before changing input")
+                .with_hint(Hint::ViewInUse, CLEAR_VIEW_FIX));
         }
 
         // Cast from Arw<dyn Storage> -> Arw<dyn KeyItemViewStorage>
         let view_storage: Arw<dyn ViewStorageSetup<Key = Key>> =
-            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(self.view_storage.clone())?;
+            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(&self.view_storage)?;
 
-        let Ok(mut view_storage_guard) = view_storage.try_write() 
-        else {
-            return Err("Failed to aquire view storage write guard".into());
-        };
+        let mut view_storage_guard = view_storage
+            .try_write()
+            .map_err(|error| lock_error(&error, "Failed to aquire view storage write guard"))?;
 
-        let view_storage: Arw<dyn Storage> = input_storage.base_storage.clone();
+        #[cfg(feature = "deadlock_detection")]
+        deadlock::register_label(input_storage.storage_id(), input_storage.label());
 
-        view_storage_guard.set_input_storage(view_storage);
+        #[cfg(feature = "tracing")]
+        trace::on_view_input_set(
+            StorageId::of(&self.view_storage),
+            input_storage.storage_id(),
+            input_storage.label(),
+        );
 
-        Ok(())
+        view_storage_guard.set_input_storage(&input_storage.base_storage)
     }
 
     pub fn create_read_view<Key, Item>(&mut self, keys: impl IntoIterator<Item = Key> + 'static) -> SimpleResult<()>
@@ -94,26 +133,53 @@ impl ViewStorageController
         Key: KeyTrait,
         Item: ItemTrait,
     {
-        let Ok(mut status_guard) = self.status.try_write() else {
-            return Err("Failed to aquire write guard for ViewController's status".into());
-        };
+        self.create_read_view_shared::<Key, Item>(keys.into_iter().collect())
+    }
+
+    /// [Self::create_read_view] with keys that are shared rather than copied, so that the same
+    /// selection can back many views or be reused when the view is created again
+    pub fn create_read_view_shared<Key, Item>(&mut self, keys: Arc<[Key]>) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let mut status_guard = self.status.try_write().map_err(|error| {
+            lock_error(&error, "Failed to aquire write guard for ViewController's status")
+        })?;
 
         if *status_guard != InputStorageLockStatus::None {
-            return Err("Failed to create view. A read or write guard has already been aquired on the view. You must call clear before changing input".into());
+            #[cfg(feature = "tracing")]
+            trace::on_view_misuse(
+                StorageId::of(&self.view_storage),
+                *status_guard,
+                "create_read_view",
+            );
+
+            return Err(StorageError::new(ErrorKind::ViewNotReady, "Failed to create view. A read or write guard has already been aquired on the view. You must call clear before changing input")
+                .with_hint(Hint::ViewInUse, CLEAR_VIEW_FIX));
         }
 
         // Cast from Arw<dyn Storage> -> Arw<dyn KeyItemViewStorage>
         let view_storage_ptr: Arw<dyn ViewStorageSetup<Key = Key>> =
-            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(self.view_storage.clone())?;
+            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(&self.view_storage)?;
+
+        let mut view_storage_guard = view_storage_ptr
+            .try_write()
+            .map_err(|error| lock_error(&error, "Failed to aquire view storage write guard"))?;
 
-        let Ok(mut view_storage_guard) = view_storage_ptr.try_write() 
-        else {
-            return Err("Failed to aquire view storage write guard".into());
-        };
+        view_storage_guard.create_read_view(keys)?;
 
-        view_storage_guard.create_read_view(Box::new(keys.into_iter()))?;
+        #[cfg(feature = "deadlock_detection")]
+        self.record_view_lock(view_storage_guard.get_input_storage(), LockAccess::Read);
 
         // Setting as Readable allows StorageHandle to take out try_read references to storage view
+        #[cfg(feature = "tracing")]
+        trace::on_view_status_changed(
+            StorageId::of(&self.view_storage),
+            InputStorageLockStatus::None,
+            InputStorageLockStatus::Readable,
+        );
+
         *status_guard = InputStorageLockStatus::Readable;
 
         Ok(())
@@ -124,39 +190,136 @@ impl ViewStorageController
         Key: KeyTrait,
         Item: ItemTrait,
     {
-        let Ok(mut status_guard) = self.status.try_write() else {
-            return Err("Failed to aquire write guard for ViewController's status".into());
-        };
+        self.create_write_view_shared::<Key, Item>(keys.into_iter().collect())
+    }
+
+    /// [Self::create_write_view] with keys that are shared rather than copied, so that the same
+    /// selection can back many views or be reused when the view is created again
+    pub fn create_write_view_shared<Key, Item>(&mut self, keys: Arc<[Key]>) -> SimpleResult<()>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        let mut status_guard = self.status.try_write().map_err(|error| {
+            lock_error(&error, "Failed to aquire write guard for ViewController's status")
+        })?;
 
         if *status_guard != InputStorageLockStatus::None {
-            return Err("Failed to create view. A read or write guard has already been aquired on the view. You must call clear before changing input".into());
+            #[cfg(feature = "tracing")]
+            trace::on_view_misuse(
+                StorageId::of(&self.view_storage),
+                *status_guard,
+                "create_write_view",
+            );
+
+            return Err(StorageError::new(ErrorKind::ViewNotReady, "Failed to create view. A read or write guard has already been aquired on the view. You must call clear before changing input")
+                .with_hint(Hint::ViewInUse, CLEAR_VIEW_FIX));
         }
 
         // Cast from Arw<dyn Storage> -> Arw<dyn KeyItemViewStorage>
         let view_storage_ptr: Arw<dyn ViewStorageSetup<Key = Key>> =
-            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(self.view_storage.clone())?;
+            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(&self.view_storage)?;
 
-        let Ok(mut view_storage_guard) = view_storage_ptr.try_write() 
-        else {
-            return Err("Failed to aquire view storage write guard".into());
-        };
+        let mut view_storage_guard = view_storage_ptr
+            .try_write()
+            .map_err(|error| lock_error(&error, "Failed to aquire view storage write guard"))?;
 
-        view_storage_guard.create_write_view(Box::new(keys.into_iter()))?;
+        view_storage_guard.create_write_view(keys)?;
+
+        #[cfg(feature = "deadlock_detection")]
+        self.record_view_lock(view_storage_guard.get_input_storage(), LockAccess::Write);
 
         // Setting as Writable allows StorageHandle to take out try_write references to storage view
+        #[cfg(feature = "tracing")]
+        trace::on_view_status_changed(
+            StorageId::of(&self.view_storage),
+            InputStorageLockStatus::None,
+            InputStorageLockStatus::Writable,
+        );
+
         *status_guard = InputStorageLockStatus::Writable;
 
         Ok(())
     }
 
+    /// Id of the storage that was given to [Self::set_input], if any
+    pub fn input_storage_id<Key, Item>(&self) -> SimpleResult<Option<StorageId>>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        // Cast from Arw<dyn Storage> -> Arw<dyn KeyItemViewStorage>
+        let view_storage: Arw<dyn ViewStorageSetup<Key = Key>> =
+            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(&self.view_storage)?;
+
+        let view_storage_guard = view_storage
+            .try_read()
+            .map_err(|error| lock_error(&error, "Failed to aquire view storage read guard"))?;
+
+        Ok(view_storage_guard.get_input_storage().map(|input| StorageId::of(&input)))
+    }
+
+    /// The keys of the current view, for creating other views of the same selection without a copy.
+    /// Empty if no view has been created.
+    pub fn view_keys<Key, Item>(&self) -> SimpleResult<Arc<[Key]>>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait,
+    {
+        // Cast from Arw<dyn Storage> -> Arw<dyn KeyItemViewStorage>
+        let view_storage: Arw<dyn ViewStorageSetup<Key = Key>> =
+            cast_to_dyn_getkeyitemviewstorage::<dyn Storage, Key, Item>(&self.view_storage)?;
+
+        let view_storage_guard = view_storage
+            .try_read()
+            .map_err(|error| lock_error(&error, "Failed to aquire view storage read guard"))?;
+
+        Ok(view_storage_guard.view_keys())
+    }
+
     pub fn status(&self) -> SimpleResult<InputStorageLockStatus> {
 
-        let Ok(status_guard) = self.status.try_read() else {
-            return Err("Failed to aquire read guard for ViewController's status".into());
-        };
+        let status_guard = self.status.try_read().map_err(|error| {
+            lock_error(&error, "Failed to aquire read guard for ViewController's status")
+        })?;
 
         Ok(*status_guard)
     }
+
+    /// Read lock the status, failing if no view has been created. Holding the returned guard
+    /// prevents the view from being created or cleared.
+    pub(super) fn lock_created_view(
+        &self,
+        access: &str,
+    ) -> SimpleResult<RwLockReadGuard<'_, InputStorageLockStatus>> {
+
+        let status_guard = self.status.try_read().map_err(|error| {
+            lock_error(&error, "Failed to aquire read guard for ViewController's status")
+        })?;
+
+        if *status_guard == InputStorageLockStatus::None {
+            #[cfg(feature = "tracing")]
+            trace::on_view_misuse(StorageId::of(&self.view_storage), *status_guard, access);
+
+            return Err(StorageError::new(ErrorKind::ViewNotReady, format!("Cannot aquire a {access} lock on the ViewStorage as ViewController::status == None. A View must be created first using the ViewController"))
+                .with_hint(Hint::ViewNotCreated, CREATE_VIEW_FIX));
+        }
+
+        Ok(status_guard)
+    }
+
+    #[cfg(feature = "deadlock_detection")]
+    fn record_view_lock(&self, input: Option<Arw<dyn Storage>>, access: LockAccess)
+    {
+        if let Some(input) = input
+        {
+            deadlock::on_view_created(
+                StorageId::of(&self.view_storage),
+                StorageId::of(&input),
+                access,
+            );
+        }
+    }
 }
 
 impl Clone for ViewStorageController
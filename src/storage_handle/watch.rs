@@ -0,0 +1,144 @@
+//! Notifications of changes to a single key, for UI bindings that show one item of a large
+//! storage.
+//!
+//! [StorageHandle::watch] calls back with the new item whenever a write to the storage changes the
+//! item at the watched key, and [StorageHandle::watch_with_sender] sends it down a channel:
+//!
+//! ```ignore
+//! let (sender, receiver) = std::sync::mpsc::channel();
+//! let _watch = positions.watch_with_sender::<u64, Vec3>(selected_node, sender)?;
+//!
+//! // In the UI loop
+//! if let Some(position) = receiver.try_iter().last()
+//! {
+//!     position_field.set(position);
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - Watches are built on [super::subscription]. After each write the watched item is read and
+//   compared with the item seen last, so a change is detected however the storage was written. A
+//   notification with dirty keys that don't include the watched key is skipped without reading.
+// - The read can only fail if another writer took the lock straight after the write was released,
+//   and that writer's release triggers another check, so no change is missed.
+// - The callback holds a clone of the handle until the [Subscription] is dropped.
+
+use std::sync::{mpsc::Sender, Mutex};
+
+use crate::{
+    storage_traits::{ItemTrait, KeyTrait, Storage},
+    SimpleResult,
+};
+
+use super::{StorageHandle, Subscription};
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Call `callback` with the item at `key` whenever a write changes it, with None when it is
+    /// removed, until the returned [Subscription] is dropped. Fails if the storage can't be cast
+    /// to a key item storage with the given Key and Item or if it can't be read to find the
+    /// current item.
+    pub fn watch<Key, Item>(
+        &self,
+        key: Key,
+        callback: impl Fn(Option<&Item>) + Send + Sync + 'static,
+    ) -> SimpleResult<Subscription>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait + PartialEq,
+    {
+        let reader = self.clone().cast_to_getitem_storage::<Key, Item>()?;
+        let last_seen = Mutex::new(reader.try_read()?.get(key).cloned());
+
+        Ok(self.subscribe(move |notification| {
+            if let Some(dirty_keys) = notification.dirty_keys::<Key>()
+            {
+                if !dirty_keys.contains(&key)
+                {
+                    return;
+                }
+            }
+
+            let Ok(guard) = reader.try_read()
+            else
+            {
+                return;
+            };
+
+            let current = guard.get(key);
+
+            // Only ever locked by this callback which can't panic while holding it
+            let mut last_seen = last_seen
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+            if last_seen.as_ref() != current
+            {
+                *last_seen = current.cloned();
+                callback(current);
+            }
+        }))
+    }
+
+    /// Send the item at `key` to `sender` whenever a write changes it, see [Self::watch]
+    pub fn watch_with_sender<Key, Item>(
+        &self,
+        key: Key,
+        sender: Sender<Option<Item>>,
+    ) -> SimpleResult<Subscription>
+    where
+        Key: KeyTrait,
+        Item: ItemTrait + PartialEq,
+    {
+        self.watch(key, move |item: Option<&Item>| {
+            let _ = sender.send(item.cloned());
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::mpsc;
+
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{MutKeyItemStorage, Storage},
+        storage_types::HashMapStorage,
+    };
+
+    #[test]
+    fn watch_test()
+    {
+        let mut storage = HashMapStorage::<u32, i32>::new();
+        storage.insert(1, 10);
+        storage.insert(2, 20);
+
+        let handle: StorageHandle<dyn Storage> = builder(storage).build();
+        let writer = handle
+            .clone()
+            .cast_to_removable_storage::<u32, i32>()
+            .unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let watch = handle.watch_with_sender::<u32, i32>(1, sender).unwrap();
+
+        // Writes to other keys and writes that don't change the item are not sent
+        writer.try_write().unwrap().insert(2, 21);
+        *writer.try_write().unwrap().get_mut(1).unwrap() = 10;
+        assert!(receiver.try_recv().is_err());
+
+        writer.try_write().unwrap().insert(1, 11);
+        assert_eq!(receiver.try_recv().unwrap(), Some(11));
+
+        writer.try_write().unwrap().remove(1);
+        assert_eq!(receiver.try_recv().unwrap(), None);
+
+        drop(watch);
+        writer.try_write().unwrap().insert(1, 12);
+        assert!(receiver.try_recv().is_err());
+    }
+}
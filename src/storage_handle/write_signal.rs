@@ -0,0 +1,225 @@
+//! Lets consumer threads sleep until a producer has finished writing a storage instead of polling
+//! try_read in a loop.
+//!
+//! Every write guard handed out by a [StorageHandle] signals waiting threads once it is released.
+//! Producers that change a storage without a handle write guard (eg through a write view) can
+//! signal explicitly via [StorageHandle::notify_readers].
+//
+// # Internal Design
+//
+// - The signal lives in the state shared by all clones and casts of a handle so a consumer can wait
+//   on a different clone of the handle to the one the producer writes through.
+// - The release hook is stored in the guard's [GuardHooks] which are dropped after the lock itself
+//   so a woken consumer can take its read lock straight away.
+// - Writes are identified by the write version they were acquired with (see
+//   [StorageHandle::write_version]). Since write locks are exclusive, versions are released in
+//   increasing order which is what allows [StorageHandle::wait_for_write_since] to detect a write
+//   that completed before the wait started.
+// - Storages written through `&self` bump the same version, see [Storage::attach_write_version].
+//   They only write under a read lock on the storage so they never bump it during a handle write,
+//   but they don't signal, so their writers call [StorageHandle::notify_readers] to wake waiters.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    storage_traits::Storage,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+};
+
+use super::{subscription::WriteNotification, HandleState, StorageHandle, StorageId};
+
+pub(crate) struct WriteSignal
+{
+    /// Incremented every time a write lock is acquired through the handle, and by storages that are
+    /// written without one, see [Storage::attach_write_version]
+    pub(crate) version: Arc<AtomicU64>,
+
+    state: Mutex<SignalState>,
+    condvar: Condvar,
+}
+
+impl Default for WriteSignal
+{
+    fn default() -> Self
+    {
+        Self {
+            version: Arc::new(AtomicU64::new(0)),
+            state: Mutex::new(<_>::default()),
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SignalState
+{
+    /// Write version of the most recently released write guard
+    released_version: u64,
+
+    /// Incremented by every release and explicit notification
+    generation: u64,
+}
+
+impl WriteSignal
+{
+    /// Bump the version for a newly acquired write lock, returning the new version
+    pub(crate) fn next_version(&self) -> u64
+    {
+        self.version.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    pub(crate) fn current_version(&self) -> u64
+    {
+        self.version.load(Ordering::Acquire)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SignalState>
+    {
+        // The signal state is two counters which are always valid so poisoning can be ignored
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn signal(&self, released_version: Option<u64>)
+    {
+        let mut state = self.lock();
+
+        if let Some(released_version) = released_version
+        {
+            state.released_version = state.released_version.max(released_version);
+        }
+
+        state.generation += 1;
+
+        self.condvar.notify_all();
+    }
+
+    /// Wait until `done` holds or `timeout` has elapsed. Returns whether `done` held.
+    fn wait_until(&self, timeout: Duration, done: impl Fn(&SignalState) -> bool) -> bool
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+
+        while !done(&state)
+        {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero()
+            {
+                return false;
+            }
+
+            state = self
+                .condvar
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+
+        true
+    }
+}
+
+/// Guard hook that signals waiting consumers and notifies subscribers once a write guard is
+/// released
+pub(crate) struct WriteRelease
+{
+    pub(crate) state: Arc<HandleState>,
+    pub(crate) storage_id: StorageId,
+    pub(crate) version: u64,
+}
+
+impl Drop for WriteRelease
+{
+    fn drop(&mut self)
+    {
+        self.state.write_signal.signal(Some(self.version));
+
+        self.state
+            .subscribers
+            .notify(&WriteNotification::new(self.storage_id, self.version));
+    }
+}
+
+impl<S> StorageHandle<S>
+where
+    S: Storage + ?Sized,
+{
+    /// Block the current thread until a write guard on this storage is released or
+    /// [StorageHandle::notify_readers] is called, or until `timeout` elapses. Only signals sent
+    /// after this call starts count. Returns false on timeout.
+    ///
+    /// Use [StorageHandle::wait_for_write_since] to also catch a write that completed just
+    /// before waiting.
+    pub fn wait_for_write(&self, timeout: Duration) -> bool
+    {
+        let signal = &self.state.write_signal;
+        let start_generation = signal.lock().generation;
+
+        signal.wait_until(timeout, |state| state.generation != start_generation)
+    }
+
+    /// Like [StorageHandle::wait_for_write] but returns straight away if a write newer than
+    /// `version` has already been released. `version` is usually a [StorageHandle::write_version]
+    /// taken while reading the storage.
+    pub fn wait_for_write_since(&self, version: u64, timeout: Duration) -> bool
+    {
+        let signal = &self.state.write_signal;
+        let start_generation = signal.lock().generation;
+
+        signal.wait_until(timeout, |state| {
+            state.released_version > version || state.generation != start_generation
+        })
+    }
+
+    /// Wake every thread waiting in [StorageHandle::wait_for_write]. Only needed when a storage
+    /// was changed without a write guard from its handle as released write guards already notify.
+    pub fn notify_readers(&self)
+    {
+        self.state.write_signal.signal(None);
+    }
+}
+
+// Needs handles that can be sent between threads
+#[cfg(all(test, not(feature = "local")))]
+mod tests
+{
+    use std::time::Duration;
+
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{MutItemSliceStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn wait_for_write_test()
+    {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+        let handle: StorageHandle<dyn Storage> = builder(storage).build();
+
+        assert!(!handle.wait_for_write(Duration::from_millis(1)));
+
+        let version = handle.write_version();
+
+        let producer = {
+            let handle: StorageHandle<VecStorage<usize, i32>> =
+                handle.clone().cast_to_sized_storage().unwrap();
+
+            std::thread::spawn(move || {
+                handle.try_write().unwrap().as_mut_slice()[0] = 10;
+            })
+        };
+
+        assert!(handle.wait_for_write_since(version, Duration::from_secs(10)));
+        assert!(handle.try_read().is_ok());
+
+        producer.join().unwrap();
+    }
+}
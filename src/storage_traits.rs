@@ -3,6 +3,8 @@
 //! # Design
 //! - Must be [Send] and [Sync] so that they are compatible with threading and the DowncastSync base
 //!   trait from downcast_rs
+//!   - Unless the `local` feature is enabled, which drops the [Send] and [Sync] requirements for
+//!     single threaded targets. See [MaybeSendSync]
 //! - Must be 'static due to [Storage]: [DowncastSync] which is ultimately bound to: 'static
 //! - Associated types or generics if used must not be bound in the trait definitions themselves as
 //!   we wish to leave these up to implementors. Eg: VecStorage needs its key to have +
@@ -20,22 +22,31 @@
 //!   this issue and I may be able to bring back the two trait approach if I think that the Semantic
 //!   win is justifies it.
 
-use crate::{Arw, SimpleResult};
-use downcast_rs::{impl_downcast, DowncastSync};
-use std::any::TypeId;
+use crate::{
+    cursor::StorageCursor, storage_handle::LockAccess, sync::atomic::AtomicU64, Arw, SimpleResult,
+};
+#[cfg(not(feature = "local"))]
+use downcast_rs::DowncastSync;
+#[cfg(feature = "local")]
+use downcast_rs::Downcast;
+use downcast_rs::impl_downcast;
+use std::{any::TypeId, cmp::Ordering, sync::Arc};
 
 /// Implements [KeyTrait] for the list of given types
 //
 // # Design
 // A macro is used here to take place of Blanket impl. Blanket impl isn't feasible to use here as
 // we need to implement it differently for two different sets of types. The two different sets are
-// types that support being used as indices such as u18,16,usize, and types that don't such as u64
+// types that support being used as indices such as u18,16,usize, and types that don't such as i64
 // and u128. Blanket implement has issues when trying to discriminate between these two sets. Some
 // form of impl specialization may work in future but for now I couldn't get min_specialization to
 // like one of the trait bounds (I think it was TryInto or possibly Into)
+//
+// Whether u32 and u64 fit depends on the pointer width of the target, so the second argument is an
+// expression that can be a cfg! of the target rather than a plain true or false.
 macro_rules! impl_key_trait {
 
-    ( [$($t:ty),*], $supports_index:ident ) => {
+    ( [$($t:ty),*], $supports_index:expr ) => {
 
         $( impl KeyTrait for $t
         {
@@ -54,13 +65,15 @@ macro_rules! impl_key_trait {
 
 /// Represents storage Keys that are able to be used on either Mappable or Indexable Storages
 /// Only keys that can be converted into usize without losing any precision are indexable.
-/// These keys will be no-op conversions to usize. Keys such as u128 can't serve this purpose but
+/// These keys will be no-op conversions to usize. u32 keys are indexable on 32 and 64 bit targets
+/// and u64 keys on 64 bit targets. Keys such as u128 can't serve this purpose but
 /// can still be used for keys in Mappable storages.
 /// # Trait Bounds
-/// * [Sync] + [Send] to be maximally compatible with threading
+/// * [MaybeSendSync] to be maximally compatible with threading
 /// * [Copy] because certain internal storage collections such as [Vec] and [xsparseset]
 ///   require keys to be [Copy]
-/// * [Ord] so that we can sort keys when needed
+/// * [Hash](std::hash::Hash) and [Eq] so that keys can address map storages. Functionality that
+///   sorts keys requires a [SortableKey] instead, so that keys without an order can still be used.
 /// * [`TryInto<usize>`] and [`TryFrom<usize>`] are required to convert to and from indices for storages
 ///   that use indices as keys These are Try because not all Keys can be converted into a sensible
 ///   index. eg. [u128] cant be used to lookup an index in a [crate::storage_types::VecStorage]
@@ -68,47 +81,146 @@ macro_rules! impl_key_trait {
 pub trait KeyTrait:
     Clone
     + Copy
-    + Sync
-    + Send
+    + MaybeSendSync
     + TryInto<usize>
     + TryFrom<usize>
     + std::hash::Hash
     + Eq
-    + Ord
     + std::fmt::Debug
     + 'static
 {
     fn supports_index() -> bool;
+
+    /// The index the key addresses in index storages, see
+    /// [crate::storage_types::try_key_to_index]. Defaults to the key's `TryInto<usize>`, and is
+    /// overridden by keys whose std conversions can't be changed, such as
+    /// [std::num::NonZeroUsize].
+    fn try_to_index(self) -> Option<usize>
+    {
+        self.try_into().ok()
+    }
+
+    /// The key that addresses `index`, the inverse of [Self::try_to_index]. Defaults to the key's
+    /// `TryFrom<usize>`.
+    fn try_from_index(index: usize) -> Option<Self>
+    {
+        Self::try_from(index).ok()
+    }
+
+    /// The generation of a generational key such as [crate::keys::GenKey], 0 for other keys.
+    /// Storages that keep their keys, such as [crate::storage_types::SparseSetVecStorage], treat a
+    /// key as absent when another generation of it is stored and reject keys older than the one
+    /// stored.
+    fn generation(&self) -> u32
+    {
+        0
+    }
 }
 
+/// A [KeyTrait] key with an order, for functionality that sorts keys such as
+/// [crate::storage_types::SparseSetVecStorage::sort_dense]. Implemented for every ordered key.
+pub trait SortableKey: KeyTrait + Ord {}
+impl<T> SortableKey for T where T: KeyTrait + Ord {}
+
 // Impl KeyTrait for types that can be converted to usize without precision loss
 impl_key_trait!([u8, u16, usize], true);
 
+// Impl KeyTrait for types that can be converted to usize without precision loss on targets with a
+// wide enough usize
+impl_key_trait!([u32], cfg!(any(target_pointer_width = "32", target_pointer_width = "64")));
+impl_key_trait!([u64], cfg!(target_pointer_width = "64"));
+
 // Impl KeyTrait for types that cannot be converted to our index type (usize) without precision loss
-impl_key_trait!([i8, i16, i32, i64, i128, u32, u64, u128], false);
+impl_key_trait!([i8, i16, i32, i64, i128, u128], false);
 
 /// # Trait Bounds
-/// * [Sync] + [Send] to be maximally compatible with threading
+/// * [MaybeSendSync] to be maximally compatible with threading
 /// * [Default] so that storages like [crate::storage_types::VecStorage] can have default entries
 ///   populated if keys are inserted at sparse locations. The decision to introduce default was a
 ///   little difficult because I didn't want to impose too many requirements on what Items end users
 ///   could ultimately use but in exchange, VecStorage can now participate as a first class citizen
 ///   with the other storage types in terms of item insertion. And additionally, this crate
 ///   prioritizes maximal sharing of traits between storage types to allow for maximum storage type
-///   interchangeability
-pub trait ItemTrait: Sync + Send + Default + Clone + 'static {}
-impl<T> ItemTrait for T where T: Sync + Send + Default + Clone + 'static {}
+///   interchangeability. Trait objects, which are neither Default nor Clone, are stored through
+///   [crate::items::DynItem]
+pub trait ItemTrait: MaybeSendSync + Default + Clone + 'static {}
+impl<T> ItemTrait for T where T: MaybeSendSync + Default + Clone + 'static {}
+
+/// Supplies the items that fill gaps when a storage grows to a key past its end, in place of
+/// [Default], so that fill values can depend on the key. Implemented for closures of the key, see
+/// [crate::storage_types::VecStorage::set_default_factory].
+pub trait DefaultFactory<Key, Item>: MaybeSendSync
+{
+    fn default_item(&self, key: Key) -> Item;
+}
+
+impl<Key, Item, F> DefaultFactory<Key, Item> for F
+where
+    F: Fn(Key) -> Item + MaybeSendSync,
+{
+    fn default_item(&self, key: Key) -> Item
+    {
+        self(key)
+    }
+}
 
-pub trait Storage: DowncastSync
+/// [Send] + [Sync], unless the `local` feature is enabled in which case it is implemented for every
+/// type. The `local` feature is meant for single threaded targets such as wasm32-unknown-unknown
+/// where keys, items and storages have no need to cross threads.
+#[cfg(not(feature = "local"))]
+pub trait MaybeSendSync: Send + Sync {}
+#[cfg(not(feature = "local"))]
+impl<T> MaybeSendSync for T where T: Send + Sync + ?Sized {}
+
+#[cfg(feature = "local")]
+pub trait MaybeSendSync {}
+#[cfg(feature = "local")]
+impl<T> MaybeSendSync for T where T: ?Sized {}
+
+/// The downcast_rs base of [Storage]: [DowncastSync], or [Downcast] with the `local` feature
+#[cfg(not(feature = "local"))]
+pub trait StorageDowncast: DowncastSync {}
+#[cfg(not(feature = "local"))]
+impl<T> StorageDowncast for T where T: DowncastSync {}
+
+#[cfg(feature = "local")]
+pub trait StorageDowncast: Downcast {}
+#[cfg(feature = "local")]
+impl<T> StorageDowncast for T where T: Downcast {}
+
+pub trait Storage: StorageDowncast
 {
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool
     {
         self.len() == 0
     }
+
+    /// Name of the concrete storage type which is also available through trait objects
+    fn type_name(&self) -> &'static str
+    {
+        std::any::type_name::<Self>()
+    }
+
+    /// Approximate number of bytes the storage occupies, including the buffers it allocates but
+    /// not memory owned by the items themselves. Defaults to the size of the storage value alone.
+    fn memory_size(&self) -> usize
+    {
+        std::mem::size_of_val(self)
+    }
+
+    /// Called by [crate::storage_handle::StorageHandleBuilder::build] and
+    /// [crate::storage_handle::StorageHandle::update] with the counter behind the handle's
+    /// [crate::storage_handle::StorageHandle::write_version]. Storages that can be written through
+    /// `&self`, such as [crate::storage_types::ShardedHashMapStorage], bump it after each such
+    /// write. Other storages ignore it.
+    fn attach_write_version(&mut self, _version: Arc<AtomicU64>) {}
 }
 
+#[cfg(not(feature = "local"))]
 impl_downcast!(sync Storage);
+#[cfg(feature = "local")]
+impl_downcast!(Storage);
 
 pub trait ClearableStorage: Storage
 {
@@ -158,29 +270,203 @@ pub trait KeyItemStorage: KeyStorage + ItemStorage
     // cannot be returned by reference. This pushes the requirement onto all storages that
     // implement this method to maintain a common interface.
     fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>;
+
+    /// The items at `keys` in the same order, with None for keys that aren't in the storage. For
+    /// fetching a handful of items under one guard, see also
+    /// [crate::storage_handle::StorageHandle::get_many].
+    fn get_refs(&self, keys: &[Self::Key]) -> Vec<Option<&Self::Item>>
+    where
+        Self::Key: Clone,
+    {
+        keys.iter().map(|key| self.get(key.clone())).collect()
+    }
+}
+
+/// Iteration through statically dispatched closures rather than boxed iterators, for code that
+/// knows the concrete storage type such as a handle cast to a sized storage. See
+/// [crate::storage_handle::StorageHandle::for_each_item].
+pub trait ForEachStorage: KeyItemStorage + Sized
+{
+    fn for_each_item(&self, f: impl FnMut(&Self::Item));
+
+    fn for_each_key_item(&self, f: impl FnMut(Self::Key, &Self::Item));
 }
 
 pub trait MutKeyItemStorage: KeyItemStorage + ClearableStorage
 {
     fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>;
 
-    // TODO: This needs to return a SimpleResult in the case of an unmatched key
+    /// Panics if the storage can't take the key, such as a key that can't be converted to an
+    /// index or one that isn't part of a view. See [Self::try_insert] for keys from user input.
     fn insert(&mut self, key: Self::Key, item: Self::Item);
 
+    /// Insert that fails instead of panicking if the key can't be used with this storage
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        self.insert(key, item);
+
+        Ok(())
+    }
+
+    /// Fails if [Self::try_insert] would fail for `key` in the storage's current state, without
+    /// inserting anything. Storages that override try_insert override this too.
+    fn check_insert(&self, _key: Self::Key) -> SimpleResult<()>
+    {
+        Ok(())
+    }
+
     // TODO: Need to implement a mutable iterator here
     // fn key_item_iter_mut(&mut self) -> Box<dyn Iterator<Item = (Self::Key, &mut Self::Item)> +
     // '_>;
 }
 
+/// Write many items at once so that storages can allocate for all of them up front
+pub trait ExtendStorage: MutKeyItemStorage + Sized
+{
+    /// Set the item of every (key, item) pair, replacing the items at keys that are already in the
+    /// storage. Unlike [MutKeyItemStorage::insert] this never moves the items at other keys, which
+    /// makes a difference for storages whose insert shifts items, such as
+    /// [crate::storage_types::VecStorage]. The default sets them one by one, inserting through
+    /// [MutKeyItemStorage::try_insert] at new keys, and stops at the first key that fails.
+    fn set_bulk(
+        &mut self,
+        items: impl IntoIterator<Item = (Self::Key, Self::Item)>,
+    ) -> SimpleResult<()>
+    where
+        Self::Key: Clone,
+    {
+        items
+            .into_iter()
+            .try_for_each(|(key, item)| match self.get_mut(key.clone())
+            {
+                Some(existing) =>
+                {
+                    *existing = item;
+
+                    Ok(())
+                }
+                None => self.try_insert(key, item),
+            })
+    }
+
+    /// A new storage of this type with clones of the items of `source` at the same keys, for
+    /// switching layouts such as from a map to a [crate::storage_types::VecStorage] for slice
+    /// access. Fails if a key of `source` can't be used with this storage. See
+    /// [crate::storage_handle::StorageHandle::collect_into] to collect into a new handle.
+    fn collect_from<Source>(source: &Source) -> SimpleResult<Self>
+    where
+        Self: Default,
+        Self::Key: Clone,
+        Self::Item: Clone,
+        Source: KeyItemStorage<Key = Self::Key, Item = Self::Item> + ?Sized,
+    {
+        let mut storage = Self::default();
+        storage.set_bulk(source.key_item_iter().map(|(key, item)| (key, item.clone())))?;
+
+        Ok(storage)
+    }
+}
+
+/// Storages that choose the key of an inserted item, for producers that don't care which keys
+/// their items get. Allocating and inserting happen under one write guard, so concurrent producers
+/// can't race on a free key computed outside of the lock, see
+/// [crate::storage_handle::StorageHandle::insert_alloc].
+pub trait AllocKeyStorage: MutKeyItemStorage
+{
+    /// Insert `item` at a key that isn't in use and return the key. Fails if no free key fits the
+    /// Key type.
+    fn try_insert_alloc(&mut self, item: Self::Item) -> SimpleResult<Self::Key>;
+
+    /// Like [Self::try_insert_alloc] but panics if no free key fits the Key type
+    fn insert_alloc(&mut self, item: Self::Item) -> Self::Key
+    {
+        self.try_insert_alloc(item)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+}
+
+/// Storage that items can be removed from by key. Map like and sparse storages leave the other keys
+/// unchanged, see [StableRemovableStorage], while index backed storages such as
+/// [crate::storage_types::VecStorage] move the items after the key down by one.
+pub trait RemovableStorage: MutKeyItemStorage
+{
+    fn remove(&mut self, key: Self::Key) -> Option<Self::Item>;
+}
+
+/// A [RemovableStorage] whose inserts and removes leave every other key unchanged, so that an edit
+/// only changes the item at its own key. Needed where edits are undone per key, as by
+/// [crate::storage_types::UndoableStorage].
+pub trait StableRemovableStorage: RemovableStorage {}
+
 /// Provides common read only functionality for a map
 pub trait ItemSliceStorage: ItemStorage
 {
     fn as_item_slice(&self) -> &[Self::Item];
 }
 
+/// Mutable access to the contiguous items of a storage, including sorting them in place.
+///
+/// Sorting moves items between the positions of the slice. Keys that are positions, as in
+/// [crate::storage_types::VecStorage], then address the sorted items. Storages with stored keys
+/// such as [crate::storage_types::SparseSetVecStorage] have their own sort that keeps each key with
+/// its item.
 pub trait MutItemSliceStorage: ItemSliceStorage
 {
     fn as_mut_slice(&mut self) -> &mut [Self::Item];
+
+    /// Stable sort with a comparator, also callable on trait objects
+    fn sort_by(&mut self, compare: &mut dyn FnMut(&Self::Item, &Self::Item) -> Ordering)
+    {
+        self.as_mut_slice().sort_by(compare);
+    }
+
+    /// Unstable sort with a comparator, also callable on trait objects
+    fn sort_unstable_by(&mut self, compare: &mut dyn FnMut(&Self::Item, &Self::Item) -> Ordering)
+    {
+        self.as_mut_slice().sort_unstable_by(compare);
+    }
+
+    /// Unstable sort by a key extracted from each item. Use [Self::sort_unstable_by] on trait
+    /// objects.
+    fn sort_unstable_by_key<SortKey: Ord>(&mut self, key: impl FnMut(&Self::Item) -> SortKey)
+    where
+        Self: Sized,
+    {
+        self.as_mut_slice().sort_unstable_by_key(key);
+    }
+}
+
+/// Storages that keep their keys and items in aligned dense arrays, such as
+/// [crate::storage_types::SparseSetVecStorage]. The key at each position of the key slice is the
+/// key of the item at the same position, so both can be walked together by index without the boxed
+/// iterator of [KeyItemStorage::key_item_iter]:
+///
+/// ```ignore
+/// let (keys, items) = storage.key_item_slices();
+/// for i in 0..keys.len()
+/// {
+///     output.insert(keys[i], items[i] * 2.0);
+/// }
+/// ```
+pub trait KeyItemSliceStorage: KeyItemStorage + ItemSliceStorage
+{
+    fn key_item_slices(&self) -> (&[Self::Key], &[Self::Item]);
+}
+
+/// Mutable items alongside their keys. The keys are a copy as changing them would detach them
+/// from the storage's lookup, and the storage can't lend them out while its items are mutably
+/// borrowed.
+pub trait MutKeyItemSliceStorage: KeyItemSliceStorage + MutItemSliceStorage
+{
+    fn key_item_slices_mut(&mut self) -> (Vec<Self::Key>, &mut [Self::Item]);
+}
+
+/// Storages whose items can be walked in ascending key order with a [StorageCursor], see
+/// [crate::cursor]
+pub trait OrderedStorage: KeyItemStorage
+{
+    /// A cursor before the first item
+    fn cursor(&self) -> StorageCursor<'_, Self::Key, Self::Item>;
 }
 
 /// This trait is deliberately narrow in scope as this is only intended to be used by StorageHandle
@@ -189,13 +475,21 @@ pub trait ViewStorageSetup: KeyStorage + ClearableStorage
 {
     fn clear_view(&mut self);
 
-    fn set_input_storage(&mut self, input: Arw<dyn Storage>);
+    /// Fails if `input` isn't of the view's input storage type
+    fn set_input_storage(&mut self, input: &Arw<dyn Storage>) -> SimpleResult<()>;
 
     fn get_input_storage(&self) -> Option<Arw<dyn Storage>>;
 
-    fn create_read_view(&mut self, keys: Box<dyn Iterator<Item = Self::Key>>) -> SimpleResult<()>;
+    /// The view keeps `keys` as they are, so the same keys can back many views without a copy
+    fn create_read_view(&mut self, keys: Arc<[Self::Key]>) -> SimpleResult<()>;
+
+    fn create_write_view(&mut self, keys: Arc<[Self::Key]>) -> SimpleResult<()>;
+
+    /// The keys of the current view, shared rather than copied. Empty if no view has been created.
+    fn view_keys(&self) -> Arc<[Self::Key]>;
 
-    fn create_write_view(&mut self, keys: Box<dyn Iterator<Item = Self::Key>>) -> SimpleResult<()>;
+    /// The kind of guard held on the input storage, None if no view has been created
+    fn input_access(&self) -> Option<LockAccess>;
 }
 
 // There are two traits offer alternate techniques for converting to more primitive data The
@@ -204,11 +498,33 @@ pub trait ViewStorageSetup: KeyStorage + ClearableStorage
 // reasonable because buffers could get pretty big. Types such as Vector3 use [`AsVec`] trait
 // because they copy their data in to the WebGL API in calls such as uniformf4v
 
+/// The items of the storage as raw bytes. Only implemented for storages of [ZeroCopyItem]s, so
+/// that the bytes never contain pointers, such as those of [String] or [Arc] items.
 pub trait AsBytesBorrowed
 {
     fn byte_slice(&self) -> &[u8];
 }
 
+/// Items whose bytes are their whole value, so that the byte and GPU paths such as
+/// [AsBytesBorrowed] can hand them out without a copy.
+///
+/// # Safety
+///
+/// Every byte of the type must be initialized, ie it has no padding, and it must hold no pointers
+/// or references. `#[repr(C)]` structs of ZeroCopyItem fields without padding qualify.
+pub unsafe trait ZeroCopyItem: ItemTrait + Copy {}
+
+macro_rules! impl_zero_copy_item {
+    ($($t:ty),*) => {
+        $( unsafe impl ZeroCopyItem for $t {} )*
+    }
+}
+
+impl_zero_copy_item!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+// Arrays have no padding between their elements
+unsafe impl<T: ZeroCopyItem, const N: usize> ZeroCopyItem for [T; N] where [T; N]: Default {}
+
 pub trait AsFloatVec
 {
     fn as_float_vec(&self) -> Vec<f32>;
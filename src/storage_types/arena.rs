@@ -0,0 +1,447 @@
+//! Reuse of item heap allocations across clears.
+//!
+//! Storages of items with heap internals, such as [String] or [Vec] items, free every item's
+//! buffer when they are cleared and allocate them all again when they are refilled. [ArenaStorage]
+//! wraps a mutable storage and keeps the items it clears in an arena of reset items instead. Items
+//! for the next fill are taken from the arena with their buffers still allocated, and the arena is
+//! freed all at once with [ArenaStorage::release] or when the storage is dropped:
+//!
+//! ```ignore
+//! let mut labels = ArenaStorage::new(VecStorage::<usize, String>::new());
+//!
+//! // Each tick
+//! labels.clear();
+//! for (key, node) in nodes.iter().enumerate()
+//! {
+//!     labels.insert_with(key, |label| write!(label, "{}", node.name).unwrap())?;
+//! }
+//! ```
+//
+// # Internal Design
+//
+// - A bump arena that item buffers are allocated from would need the unstable allocator_api and
+//   items that borrow the arena, which can't be 'static items. Recycling whole items gives the same
+//   clear and refill pattern without either.
+// - Items are reset by a function pointer chosen when wrapping rather than through a bound on the
+//   struct, so that the wrapper fits the casting lists which are generic over any [ItemTrait].
+//   [Recycle] provides the function for common item types.
+// - Items are moved out on clear with [std::mem::take], so the wrapped storage drops default items
+//   that own no heap memory. The keys are gathered first into a buffer that is kept for the next
+//   clear.
+// - Only clear recycles. Items that are removed or overwritten belong to the caller or are dropped,
+//   though they can be handed back with [ArenaStorage::recycle].
+
+use std::{any::TypeId, collections::HashMap, hash::Hash};
+
+use crate::{
+    error::panic_or_skip,
+    storage_traits::{
+        ClearableStorage, ItemSliceStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf,
+        KeyItemStorage, KeyStorage, KeyTrait, KeyTypeIdNoSelf, MutItemSliceStorage,
+        MutKeyItemStorage, RemovableStorage, StableRemovableStorage, Storage,
+    },
+    Arw, SimpleResult,
+};
+
+/// Items whose heap allocations can be kept while they are reset to an empty value
+pub trait Recycle: ItemTrait
+{
+    /// Reset to the empty value while keeping any heap allocations, eg [String::clear]
+    fn recycle(&mut self);
+}
+
+impl Recycle for String
+{
+    fn recycle(&mut self)
+    {
+        self.clear();
+    }
+}
+
+impl<T> Recycle for Vec<T>
+where
+    T: ItemTrait,
+{
+    fn recycle(&mut self)
+    {
+        self.clear();
+    }
+}
+
+impl<K, V> Recycle for HashMap<K, V>
+where
+    K: ItemTrait + Hash + Eq,
+    V: ItemTrait,
+{
+    fn recycle(&mut self)
+    {
+        self.clear();
+    }
+}
+
+/// A storage wrapper that reuses the items it clears, see the [module docs](self)
+#[derive(Clone, Debug)]
+pub struct ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    storage: S,
+
+    /// Reset items ready to be filled again
+    arena: Vec<Item>,
+
+    reset: fn(&mut Item),
+
+    /// Keys of the storage while it is cleared, kept for its capacity
+    clear_keys: Vec<Key>,
+}
+
+impl<S, Key, Item> ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: Recycle,
+{
+    pub fn new(storage: S) -> Self
+    {
+        Self::with_reset(storage, Item::recycle)
+    }
+}
+
+impl<S, Key, Item> ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Wrap a storage of items that don't implement [Recycle], resetting them with `reset`
+    pub fn with_reset(storage: S, reset: fn(&mut Item)) -> Self
+    {
+        Self {
+            storage,
+            arena: Vec::new(),
+            reset,
+            clear_keys: Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &S
+    {
+        &self.storage
+    }
+
+    /// Unwrap the storage, freeing the arena
+    pub fn into_inner(self) -> S
+    {
+        self.storage
+    }
+
+    /// Number of reset items waiting in the arena
+    pub fn arena_len(&self) -> usize
+    {
+        self.arena.len()
+    }
+
+    /// An item from the arena, or a default item if the arena is empty
+    pub fn take_item(&mut self) -> Item
+    {
+        self.arena.pop().unwrap_or_default()
+    }
+
+    /// Reset `item` and keep it in the arena for a later fill
+    pub fn recycle(&mut self, mut item: Item)
+    {
+        (self.reset)(&mut item);
+        self.arena.push(item);
+    }
+
+    /// Insert an item from the arena after filling it with `fill`
+    pub fn insert_with(&mut self, key: Key, fill: impl FnOnce(&mut Item)) -> SimpleResult<()>
+    {
+        let mut item = self.take_item();
+        fill(&mut item);
+
+        self.storage.try_insert(key, item)
+    }
+
+    /// Free every item in the arena at once
+    pub fn release(&mut self)
+    {
+        self.arena = Vec::new();
+    }
+}
+
+impl<S, Key, Item> From<ArenaStorage<S, Key, Item>> for Arw<dyn Storage>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: ArenaStorage<S, Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<S, Key, Item> Storage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.storage.len()
+    }
+}
+
+impl<S, Key, Item> KeyTypeIdNoSelf for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<S, Key, Item> ItemTypeIdNoSelf for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<S, Key, Item> KeyStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.storage.contains(key)
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        self.storage.keys_iter()
+    }
+}
+
+impl<S, Key, Item> ItemStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<S, Key, Item> KeyItemStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        self.storage.get(key)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        self.storage.item_iter()
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        self.storage.key_item_iter()
+    }
+}
+
+impl<S, Key, Item> MutKeyItemStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>
+    {
+        self.storage.get_mut(key)
+    }
+
+    fn insert(&mut self, key: Self::Key, item: Self::Item)
+    {
+        panic_or_skip(self.try_insert(key, item));
+    }
+
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        self.storage.try_insert(key, item)
+    }
+
+    fn check_insert(&self, key: Self::Key) -> SimpleResult<()>
+    {
+        self.storage.check_insert(key)
+    }
+}
+
+impl<S, Key, Item> ClearableStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Clear the storage, moving its items into the arena
+    fn clear(&mut self)
+    {
+        let mut keys = std::mem::take(&mut self.clear_keys);
+        keys.extend(self.storage.keys_iter());
+
+        for key in keys.drain(..)
+        {
+            if let Some(item) = self.storage.get_mut(key)
+            {
+                let item = std::mem::take(item);
+                self.recycle(item);
+            }
+        }
+
+        self.clear_keys = keys;
+        self.storage.clear();
+    }
+}
+
+impl<S, Key, Item> RemovableStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item> + RemovableStorage,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Self::Key) -> Option<Self::Item>
+    {
+        self.storage.remove(key)
+    }
+}
+
+impl<S, Key, Item> StableRemovableStorage for ArenaStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
+impl<S, Key, Item> ItemSliceStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item> + ItemSliceStorage,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_item_slice(&self) -> &[Self::Item]
+    {
+        self.storage.as_item_slice()
+    }
+}
+
+impl<S, Key, Item> MutItemSliceStorage for ArenaStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item> + MutItemSliceStorage,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_mut_slice(&mut self) -> &mut [Self::Item]
+    {
+        self.storage.as_mut_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::ArenaStorage;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{ClearableStorage, KeyItemStorage, Storage},
+        storage_types::{HashMapStorage, VecStorage},
+    };
+
+    #[test]
+    fn clear_and_refill_test()
+    {
+        let mut storage = ArenaStorage::new(HashMapStorage::<u32, String>::new());
+        storage
+            .insert_with(1, |label| label.push_str("first"))
+            .unwrap();
+        storage
+            .insert_with(2, |label| label.push_str("second"))
+            .unwrap();
+
+        let buffer = storage.get(2).unwrap().as_ptr();
+
+        // Cleared items wait in the arena, reset but still allocated
+        storage.clear();
+        assert_eq!(storage.len(), 0);
+        assert_eq!(storage.arena_len(), 2);
+
+        let item = storage.take_item();
+        assert!(item.is_empty());
+        assert!(item.capacity() >= "first".len());
+
+        storage.recycle(item);
+        storage
+            .insert_with(5, |label| label.push_str("again"))
+            .unwrap();
+        storage
+            .insert_with(6, |label| label.push_str("more"))
+            .unwrap();
+        assert_eq!(storage.get(5).map(String::as_str), Some("again"));
+        assert_eq!(storage.arena_len(), 0);
+
+        // Both buffers were reused, in some order
+        let buffers = [
+            storage.get(5).unwrap().as_ptr(),
+            storage.get(6).unwrap().as_ptr(),
+        ];
+        assert!(buffers.contains(&buffer));
+
+        storage.clear();
+        storage.release();
+        assert_eq!(storage.arena_len(), 0);
+    }
+
+    #[test]
+    fn with_reset_test()
+    {
+        let storage =
+            ArenaStorage::with_reset(VecStorage::<usize, Option<Box<i32>>>::new(), |item| {
+                *item = None
+            });
+        let handle: StorageHandle<dyn Storage> = builder(storage).build();
+
+        let handle = handle
+            .cast_to_mut_getitem_storage::<usize, Option<Box<i32>>>()
+            .unwrap();
+        handle.try_write().unwrap().insert(0, Some(Box::new(1)));
+        handle.try_write().unwrap().clear();
+        assert_eq!(handle.try_read().unwrap().len(), 0);
+    }
+}
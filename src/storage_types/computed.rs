@@ -0,0 +1,323 @@
+//! A storage whose items are derived from other storages, like a spreadsheet cell.
+//!
+//! A [ComputedStorage] owns handles to its inputs and a closure that computes its items from them.
+//! It remembers the [StorageHandle::write_version] of every input it last computed from, and is
+//! recomputed on [ComputedStorage::refresh] only if one of them has been written since. The items
+//! are read through the usual read traits so a computed storage can be the input of any node:
+//!
+//! ```ignore
+//! let total = ComputedStorage::new(vec![prices.clone(), quantities.clone()], |inputs| {
+//!     let prices = inputs[0].clone().cast_to_slice_storage::<usize, f32>()?;
+//!     let quantities = inputs[1].clone().cast_to_slice_storage::<usize, f32>()?;
+//!     let (prices, quantities) = (prices.try_read()?, quantities.try_read()?);
+//!
+//!     let totals = prices.as_item_slice().iter().zip(quantities.as_item_slice());
+//!
+//!     Ok(totals.map(|(price, quantity)| price * quantity).collect())
+//! });
+//!
+//! let total: StorageHandle<ComputedStorage<usize, f32>> =
+//!     builder(total).build().cast_to_sized_storage()?;
+//!
+//! // Recomputes only if prices or quantities were written since the last read
+//! let guard = total.try_read_fresh()?;
+//! ```
+//
+// # Internal Design
+//
+// - Staleness is a comparison of write versions rather than a subscription to the inputs so that a
+//   computed storage holds no callbacks in its inputs and costs nothing until it is read.
+// - Reads through the read traits can't recompute as they only have `&self`, so recomputing is an
+//   explicit step that needs the write lock. [StorageHandle::try_read_fresh] does both steps.
+// - Computed storages that take other computed storages as inputs don't refresh them. The write
+//   version of an upstream computed storage only changes when it is refreshed, so upstream storages
+//   have to be refreshed first, eg in the topological order a graph evaluates in.
+
+use std::{any::TypeId, ops::Deref};
+
+use crate::{
+    storage_handle::StorageHandle,
+    storage_traits::{
+        ItemSliceStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyItemStorage, KeyStorage,
+        KeyTrait, KeyTypeIdNoSelf, Storage,
+    },
+    Arw, SimpleResult,
+};
+
+use super::VecStorage;
+
+type Compute<Item> =
+    Box<dyn Fn(&[StorageHandle<dyn Storage>]) -> SimpleResult<Vec<Item>> + Send + Sync>;
+
+/// Items computed from input storages, see the [module docs](self)
+pub struct ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    inputs: Vec<StorageHandle<dyn Storage>>,
+
+    /// Write versions of the inputs when the items were last computed. None until first computed.
+    seen_versions: Option<Vec<u64>>,
+
+    compute: Compute<Item>,
+    items: VecStorage<Key, Item>,
+}
+
+impl<Key, Item> ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// A computed storage that is empty until first refreshed. `compute` is given the inputs in the
+    /// order they were passed in and returns the items in key order.
+    pub fn new(
+        inputs: Vec<StorageHandle<dyn Storage>>,
+        compute: impl Fn(&[StorageHandle<dyn Storage>]) -> SimpleResult<Vec<Item>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self
+    {
+        Self {
+            inputs,
+            seen_versions: None,
+            compute: Box::new(compute),
+            items: VecStorage::from_vec(Vec::new()),
+        }
+    }
+
+    pub fn inputs(&self) -> &[StorageHandle<dyn Storage>]
+    {
+        &self.inputs
+    }
+
+    /// Whether an input has been written since the items were last computed or they never were
+    pub fn is_stale(&self) -> bool
+    {
+        match &self.seen_versions
+        {
+            Some(seen_versions) => self
+                .inputs
+                .iter()
+                .zip(seen_versions)
+                .any(|(input, seen_version)| input.write_version() != *seen_version),
+            None => true,
+        }
+    }
+
+    /// Recompute the items if they are stale. Returns whether they were recomputed. On error the
+    /// previous items are kept and the storage stays stale.
+    pub fn refresh(&mut self) -> SimpleResult<bool>
+    {
+        if !self.is_stale()
+        {
+            return Ok(false);
+        }
+
+        self.recompute()?;
+
+        Ok(true)
+    }
+
+    /// Recompute the items whether or not they are stale
+    pub fn recompute(&mut self) -> SimpleResult<()>
+    {
+        // Versions are read before computing so that a write made while computing leaves the
+        // storage stale rather than being missed
+        let versions = self
+            .inputs
+            .iter()
+            .map(StorageHandle::write_version)
+            .collect();
+
+        self.items = VecStorage::from_vec((self.compute)(&self.inputs)?);
+        self.seen_versions = Some(versions);
+
+        Ok(())
+    }
+}
+
+impl<Key, Item> StorageHandle<ComputedStorage<Key, Item>>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Read the storage after refreshing it if any of its inputs have been written since it was
+    /// last computed. Refreshing takes the write lock so this fails if the storage is being read
+    /// while it is stale.
+    pub fn try_read_fresh(
+        &self,
+    ) -> SimpleResult<impl Deref<Target = ComputedStorage<Key, Item>> + '_>
+    {
+        let stale = self.try_read()?.is_stale();
+
+        if stale
+        {
+            self.try_write()?.refresh()?;
+        }
+
+        self.try_read()
+    }
+}
+
+impl<Key, Item> From<ComputedStorage<Key, Item>> for Arw<dyn Storage>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: ComputedStorage<Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<Key, Item> Storage for ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.items.len()
+    }
+}
+
+impl<Key, Item> KeyTypeIdNoSelf for ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<Key, Item> ItemTypeIdNoSelf for ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<Key, Item> KeyStorage for ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.items.contains(key)
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        self.items.keys_iter()
+    }
+}
+
+impl<Key, Item> ItemStorage for ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<Key, Item> KeyItemStorage for ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        self.items.get(key)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        self.items.item_iter()
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        self.items.key_item_iter()
+    }
+}
+
+impl<Key, Item> ItemSliceStorage for ComputedStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_item_slice(&self) -> &[Self::Item]
+    {
+        self.items.as_item_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::ComputedStorage;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{ItemSliceStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn computed_test()
+    {
+        let input: StorageHandle<dyn Storage> =
+            builder(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3])).build();
+
+        let doubled = ComputedStorage::<usize, i32>::new(vec![input.clone()], |inputs| {
+            let input = inputs[0].clone().cast_to_slice_storage::<usize, i32>()?;
+            let guard = input.try_read()?;
+
+            Ok(guard.as_item_slice().iter().map(|item| item * 2).collect())
+        });
+
+        let doubled: StorageHandle<ComputedStorage<usize, i32>> =
+            builder(doubled).build().cast_to_sized_storage().unwrap();
+
+        assert!(doubled.try_read().unwrap().is_empty());
+        assert_eq!(
+            doubled.try_read_fresh().unwrap().as_item_slice(),
+            &[2, 4, 6]
+        );
+        assert!(!doubled.try_write().unwrap().refresh().unwrap());
+
+        {
+            let input = input
+                .clone()
+                .cast_to_mut_getitem_storage::<usize, i32>()
+                .unwrap();
+            *input.try_write().unwrap().get_mut(1).unwrap() = 10;
+        }
+
+        assert!(doubled.try_read().unwrap().is_stale());
+        assert_eq!(
+            doubled.try_read_fresh().unwrap().as_item_slice(),
+            &[2, 20, 6]
+        );
+
+        // Readable through trait objects like any other storage
+        let reader = doubled.cast_to_getitem_storage::<usize, i32>().unwrap();
+        assert_eq!(reader.try_read().unwrap().get(1), Some(&20));
+    }
+}
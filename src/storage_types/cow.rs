@@ -0,0 +1,304 @@
+//! Cheap copy on write branches of a storage.
+//!
+//! [CowStorage] keeps its storage behind an [Arc] so cloning it only clones the pointer. The clones
+//! share the data until one of them writes, at which point the writer gets a copy of its own. This
+//! suits preview branches of large storages:
+//!
+//! ```ignore
+//! let handle = builder(CowStorage::new(VecStorage::<usize, Mesh>::from_vec(meshes))).build();
+//! let sized: StorageHandle<CowStorage<VecStorage<usize, Mesh>, usize, Mesh>> =
+//!     handle.cast_to_sized_storage()?;
+//!
+//! // Shares the meshes until either handle is written to
+//! let preview = sized.fork()?;
+//! ```
+//
+// # Internal Design
+//
+// - Writes go through [Arc::make_mut] so a storage that isn't shared is written in place without
+//   any extra cost beyond the reference count check.
+// - Any `&mut self` access counts as a write since there is no telling whether the caller changes
+//   anything through the returned reference. A `get_mut` on a shared storage copies it even if the
+//   key doesn't exist.
+
+use std::{any::TypeId, sync::Arc};
+
+use crate::{
+    storage_traits::{
+        ClearableStorage, ItemSliceStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf,
+        KeyItemStorage, KeyStorage, KeyTrait, KeyTypeIdNoSelf, MutItemSliceStorage,
+        MutKeyItemStorage, RemovableStorage, StableRemovableStorage, Storage,
+    },
+    Arw, SimpleResult,
+};
+
+/// A storage wrapper whose clones share data until written, see the [module docs](self)
+#[derive(Clone, Debug)]
+pub struct CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    storage: Arc<S>,
+}
+
+impl<S, Key, Item> CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn new(storage: S) -> Self
+    {
+        Self {
+            storage: Arc::new(storage),
+        }
+    }
+
+    pub fn inner(&self) -> &S
+    {
+        &self.storage
+    }
+
+    /// Mutable access to the storage, copying it first if it is shared
+    pub fn inner_mut(&mut self) -> &mut S
+    {
+        Arc::make_mut(&mut self.storage)
+    }
+
+    /// Unwrap the storage, copying it if it is shared
+    pub fn into_inner(self) -> S
+    {
+        Arc::unwrap_or_clone(self.storage)
+    }
+
+    /// Whether the data is currently shared with another clone
+    pub fn is_shared(&self) -> bool
+    {
+        Arc::strong_count(&self.storage) > 1
+    }
+}
+
+impl<S, Key, Item> From<CowStorage<S, Key, Item>> for Arw<dyn Storage>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: CowStorage<S, Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<S, Key, Item> Storage for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.storage.len()
+    }
+}
+
+impl<S, Key, Item> KeyTypeIdNoSelf for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<S, Key, Item> ItemTypeIdNoSelf for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<S, Key, Item> KeyStorage for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.storage.contains(key)
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        self.storage.keys_iter()
+    }
+}
+
+impl<S, Key, Item> ItemStorage for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<S, Key, Item> KeyItemStorage for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        self.storage.get(key)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        self.storage.item_iter()
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        self.storage.key_item_iter()
+    }
+}
+
+impl<S, Key, Item> ClearableStorage for CowStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn clear(&mut self)
+    {
+        self.inner_mut().clear();
+    }
+}
+
+impl<S, Key, Item> MutKeyItemStorage for CowStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>
+    {
+        self.inner_mut().get_mut(key)
+    }
+
+    fn insert(&mut self, key: Self::Key, item: Self::Item)
+    {
+        self.inner_mut().insert(key, item);
+    }
+
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        self.inner_mut().try_insert(key, item)
+    }
+
+    fn check_insert(&self, key: Self::Key) -> SimpleResult<()>
+    {
+        self.inner().check_insert(key)
+    }
+}
+
+impl<S, Key, Item> RemovableStorage for CowStorage<S, Key, Item>
+where
+    S: RemovableStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Self::Key) -> Option<Self::Item>
+    {
+        self.inner_mut().remove(key)
+    }
+}
+
+impl<S, Key, Item> StableRemovableStorage for CowStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item> + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
+impl<S, Key, Item> ItemSliceStorage for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + ItemSliceStorage + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_item_slice(&self) -> &[Self::Item]
+    {
+        self.storage.as_item_slice()
+    }
+}
+
+impl<S, Key, Item> MutItemSliceStorage for CowStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Key = Key, Item = Item> + MutItemSliceStorage + Clone,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_mut_slice(&mut self) -> &mut [Self::Item]
+    {
+        self.inner_mut().as_mut_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::CowStorage;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{ItemSliceStorage, MutItemSliceStorage, Storage},
+        storage_types::VecStorage,
+    };
+
+    #[test]
+    fn fork_test()
+    {
+        let storage = CowStorage::new(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3]));
+        let mut builder = builder(storage);
+        builder.label("meshes");
+        let handle: StorageHandle<dyn Storage> = builder.build();
+        let handle: StorageHandle<CowStorage<VecStorage<usize, i32>, usize, i32>> =
+            handle.cast_to_sized_storage().unwrap();
+
+        let fork = handle.fork().unwrap();
+        assert_ne!(fork.storage_id(), handle.storage_id());
+        assert_eq!(fork.label(), Some("meshes"));
+
+        let fork: StorageHandle<CowStorage<VecStorage<usize, i32>, usize, i32>> =
+            fork.cast_to_sized_storage().unwrap();
+        assert!(handle.try_read().unwrap().is_shared());
+
+        fork.try_write().unwrap().as_mut_slice()[0] = 10;
+        assert!(!handle.try_read().unwrap().is_shared());
+
+        assert_eq!(handle.try_read().unwrap().as_item_slice(), &[1, 2, 3]);
+        assert_eq!(fork.try_read().unwrap().as_item_slice(), &[10, 2, 3]);
+    }
+}
@@ -0,0 +1,433 @@
+//! Key level change tracking for incremental recomputation.
+//!
+//! [DirtyTracked] wraps a mutable storage and records the keys touched by `insert`, `get_mut`,
+//! `remove` and `clear` into a change buffer. A consumer takes the changes once per evaluation
+//! tick and only recomputes what depends on those keys:
+//!
+//! ```ignore
+//! let handle = builder(DirtyTracked::new(VecStorage::<usize, f32>::new())).build();
+//!
+//! // Each tick
+//! let changes = sized_handle.try_write()?.take_changes();
+//! if changes.cleared() { recompute_all() } else { recompute(changes.keys()) }
+//! ```
+//!
+//! The keys can also be handed to subscribers of the handle with
+//! [crate::storage_handle::StorageHandle::notify_subscribers].
+//
+// # Internal Design
+//
+// - `get_mut` marks the key whether or not the caller ends up writing through the reference as
+//   there is no way to tell. Keys are recorded once per tick in the order they were first touched.
+// - VecStorage shifts the items after an inserted key, so an insert into one marks every key from
+//   the inserted key to the end. It is detected by TypeId when wrapping as there is no trait that
+//   describes how a storage inserts.
+// - Only read access to item slices is passed through. Mutable slices would bypass the tracking.
+
+use std::{any::TypeId, collections::HashSet};
+
+use crate::{
+    error::panic_or_skip,
+    storage_traits::{
+        ClearableStorage, ItemSliceStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf,
+        KeyItemStorage, KeyStorage, KeyTrait, KeyTypeIdNoSelf, MutKeyItemStorage, RemovableStorage,
+        StableRemovableStorage, Storage,
+    },
+    Arw, SimpleResult,
+};
+
+use super::{key_to_index, try_index_to_key, VecStorage};
+
+/// Keys changed since the change buffer was last taken or reset
+#[derive(Clone, Debug)]
+pub struct Changes<Key>
+{
+    keys: Vec<Key>,
+    seen: HashSet<Key>,
+    cleared: bool,
+}
+
+impl<Key> Default for Changes<Key>
+{
+    fn default() -> Self
+    {
+        Self {
+            keys: Vec::new(),
+            seen: HashSet::new(),
+            cleared: false,
+        }
+    }
+}
+
+impl<Key> Changes<Key>
+where
+    Key: KeyTrait,
+{
+    /// Changed keys in the order they were first changed. After a clear only the keys changed
+    /// since the clear are listed.
+    pub fn keys(&self) -> &[Key]
+    {
+        &self.keys
+    }
+
+    /// Whether the storage was cleared, in which case every key that existed before may have
+    /// changed
+    pub fn cleared(&self) -> bool
+    {
+        self.cleared
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.keys.is_empty() && !self.cleared
+    }
+
+    pub fn into_keys(self) -> Vec<Key>
+    {
+        self.keys
+    }
+
+    fn mark(&mut self, key: Key)
+    {
+        if self.seen.insert(key)
+        {
+            self.keys.push(key);
+        }
+    }
+
+    fn mark_cleared(&mut self)
+    {
+        self.keys.clear();
+        self.seen.clear();
+        self.cleared = true;
+    }
+}
+
+/// A storage wrapper that records changed keys, see the [module docs](self)
+#[derive(Clone, Debug)]
+pub struct DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    storage: S,
+    changes: Changes<Key>,
+
+    /// Whether an insert shifts the items after it
+    shifts_on_insert: bool,
+}
+
+impl<S, Key, Item> DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn new(storage: S) -> Self
+    {
+        Self {
+            storage,
+            changes: <_>::default(),
+            shifts_on_insert: TypeId::of::<S>() == TypeId::of::<VecStorage<Key, Item>>(),
+        }
+    }
+
+    pub fn inner(&self) -> &S
+    {
+        &self.storage
+    }
+
+    /// Unwrap the storage, dropping the changes
+    pub fn into_inner(self) -> S
+    {
+        self.storage
+    }
+
+    pub fn changes(&self) -> &Changes<Key>
+    {
+        &self.changes
+    }
+
+    /// Take the changes recorded so far, leaving an empty change buffer for the next tick
+    pub fn take_changes(&mut self) -> Changes<Key>
+    {
+        std::mem::take(&mut self.changes)
+    }
+
+    pub fn reset_changes(&mut self)
+    {
+        self.changes = <_>::default();
+    }
+
+    fn mark_insert(&mut self, key: Key, old_len: usize)
+    {
+        if self.shifts_on_insert
+        {
+            // Gaps filled with default items are marked too. The key was just inserted into a vec
+            // so it converts, but items shifted past the range of the Key type have no key.
+            let shifted = key_to_index(key).min(old_len)..self.storage.len();
+
+            for key in shifted.map_while(|index| try_index_to_key(index).ok())
+            {
+                self.changes.mark(key);
+            }
+        }
+        else
+        {
+            self.changes.mark(key);
+        }
+    }
+}
+
+impl<S, Key, Item> From<DirtyTracked<S, Key, Item>> for Arw<dyn Storage>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: DirtyTracked<S, Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<S, Key, Item> Storage for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.storage.len()
+    }
+}
+
+impl<S, Key, Item> KeyTypeIdNoSelf for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<S, Key, Item> ItemTypeIdNoSelf for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<S, Key, Item> KeyStorage for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.storage.contains(key)
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        self.storage.keys_iter()
+    }
+}
+
+impl<S, Key, Item> ItemStorage for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<S, Key, Item> KeyItemStorage for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        self.storage.get(key)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        self.storage.item_iter()
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        self.storage.key_item_iter()
+    }
+}
+
+impl<S, Key, Item> MutKeyItemStorage for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>
+    {
+        let item = self.storage.get_mut(key);
+
+        if item.is_some()
+        {
+            self.changes.mark(key);
+        }
+
+        item
+    }
+
+    fn insert(&mut self, key: Self::Key, item: Self::Item)
+    {
+        panic_or_skip(self.try_insert(key, item));
+    }
+
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        let old_len = self.storage.len();
+        self.storage.try_insert(key, item)?;
+        self.mark_insert(key, old_len);
+
+        Ok(())
+    }
+
+    fn check_insert(&self, key: Self::Key) -> SimpleResult<()>
+    {
+        self.storage.check_insert(key)
+    }
+}
+
+impl<S, Key, Item> ClearableStorage for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn clear(&mut self)
+    {
+        self.storage.clear();
+        self.changes.mark_cleared();
+    }
+}
+
+impl<S, Key, Item> RemovableStorage for DirtyTracked<S, Key, Item>
+where
+    S: RemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Self::Key) -> Option<Self::Item>
+    {
+        let item = self.storage.remove(key);
+
+        if item.is_some()
+        {
+            self.changes.mark(key);
+        }
+
+        item
+    }
+}
+
+impl<S, Key, Item> StableRemovableStorage for DirtyTracked<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
+impl<S, Key, Item> ItemSliceStorage for DirtyTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item> + ItemSliceStorage,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_item_slice(&self) -> &[Self::Item]
+    {
+        self.storage.as_item_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::DirtyTracked;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{
+            ClearableStorage, KeyItemStorage, MutKeyItemStorage, RemovableStorage, Storage,
+        },
+        storage_types::{HashMapStorage, VecStorage},
+    };
+
+    #[test]
+    fn dirty_tracked_test()
+    {
+        let mut storage = DirtyTracked::new(HashMapStorage::<u32, f32>::new());
+        storage.insert(4, 1.0);
+        storage.insert(9, 2.0);
+        *storage.get_mut(4).unwrap() = 3.0;
+        assert!(storage.get_mut(5).is_none());
+
+        let changes = storage.take_changes();
+        assert_eq!(changes.keys(), &[4, 9]);
+        assert!(!changes.cleared());
+        assert!(storage.changes().is_empty());
+
+        storage.remove(9);
+        storage.clear();
+        storage.insert(1, 0.5);
+        assert_eq!(storage.changes().keys(), &[1]);
+        assert!(storage.changes().cleared());
+
+        storage.reset_changes();
+        assert!(storage.changes().is_empty());
+    }
+
+    #[test]
+    fn dirty_tracked_vec_insert_test()
+    {
+        let storage = DirtyTracked::new(VecStorage::<usize, i32>::from_vec(vec![1, 2, 3]));
+        let handle: StorageHandle<dyn Storage> = builder(storage).build();
+
+        let handle = handle.cast_to_mut_getitem_storage::<usize, i32>().unwrap();
+        let mut guard = handle.try_write().unwrap();
+
+        // Shifts the items at 1 and 2 along
+        guard.insert(1, 10);
+        drop(guard);
+
+        let handle: StorageHandle<DirtyTracked<VecStorage<usize, i32>, usize, i32>> =
+            handle.cast_to_sized_storage().unwrap();
+        let mut guard = handle.try_write().unwrap();
+
+        assert_eq!(guard.get(3), Some(&3));
+        assert_eq!(guard.take_changes().keys(), &[1, 2, 3]);
+    }
+}
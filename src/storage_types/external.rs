@@ -0,0 +1,312 @@
+//! Read only storages over items owned by the application.
+//!
+//! [ExternalVecStorage] shares a buffer that is owned elsewhere, such as an `Arc<Vec<Item>>` held
+//! by an asset cache or a `&'static [Item]` table, and exposes it to handles and casts like a
+//! [super::VecStorage] without moving or copying the items:
+//!
+//! ```ignore
+//! let samples: Arc<Vec<f32>> = audio_cache.load("kick.wav");
+//!
+//! let handle: StorageHandle<dyn Storage> =
+//!     builder(ExternalVecStorage::<usize, f32>::new(samples.clone())).build();
+//! let reader = handle.cast_to_slice_storage::<usize, f32>()?;
+//! ```
+//
+// # Internal Design
+//
+// - Storages must be 'static to be downcast (see [crate::storage_traits]), so a storage can't
+//   borrow a Vec for a shorter lifetime. Buffers are shared through an Arc instead, and borrowed
+//   only when they are 'static.
+// - The source is type erased behind [SliceSource] so the storage type only names the key and item
+//   types, which keeps it listable in the [crate::casting] tables for any buffer type.
+// - The storage is read only as the owner may share the buffer with other readers. Updates are made
+//   by the owner and published with [ExternalVecStorage::set_source].
+
+use std::{any::TypeId, fmt, marker::PhantomData, sync::Arc};
+
+use crate::{
+    storage_handle::access_stats::{self, AccessKind},
+    storage_traits::{
+        ForEachStorage, ItemSliceStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyItemStorage,
+        KeyStorage, KeyTrait, KeyTypeIdNoSelf, MaybeSendSync, Storage,
+    },
+    Arw, SimpleResult,
+};
+
+use super::{assert_index_key, check_index_key, try_index_to_key, try_key_to_index, KeyRange};
+
+/// A buffer of items owned outside of the storage, implemented for every [`AsRef<[Item]>`] type
+/// such as [Vec], boxed slices and `&'static [Item]`
+pub trait SliceSource<Item>: MaybeSendSync
+{
+    fn items(&self) -> &[Item];
+}
+
+impl<Item, T> SliceSource<Item> for T
+where
+    T: AsRef<[Item]> + MaybeSendSync + ?Sized,
+{
+    fn items(&self) -> &[Item]
+    {
+        self.as_ref()
+    }
+}
+
+/// Items owned elsewhere with their indices as keys, see the [module docs](self)
+pub struct ExternalVecStorage<Key, Item>
+{
+    source: Arc<dyn SliceSource<Item>>,
+    key_phantom: PhantomData<Key>,
+}
+
+impl<Key, Item> ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn new(source: Arc<impl SliceSource<Item> + 'static>) -> Self
+    {
+        assert_index_key::<Key>();
+
+        Self {
+            source,
+            key_phantom: PhantomData,
+        }
+    }
+
+    /// Like [Self::new] but fails instead of panicking if the Key type can't be used as an index
+    pub fn try_new(source: Arc<impl SliceSource<Item> + 'static>) -> SimpleResult<Self>
+    {
+        check_index_key::<Key>()?;
+
+        Ok(Self::new(source))
+    }
+
+    /// Borrow a buffer that lives for the rest of the program, such as a static table
+    pub fn from_static(items: &'static [Item]) -> Self
+    {
+        Self::new(Arc::new(items))
+    }
+
+    /// Replace the buffer, such as with a newer version published by its owner
+    pub fn set_source(&mut self, source: Arc<impl SliceSource<Item> + 'static>)
+    {
+        self.source = source;
+    }
+
+    pub fn source(&self) -> &Arc<dyn SliceSource<Item>>
+    {
+        &self.source
+    }
+
+    /// The keys of all items. Unlike [KeyStorage::keys_iter] this doesn't allocate.
+    pub fn keys(&self) -> KeyRange<Key>
+    {
+        access_stats::record(self, AccessKind::Iteration);
+        KeyRange::new(0..self.source.items().len())
+    }
+}
+
+/// Shares the buffer rather than copying it
+impl<Key, Item> Clone for ExternalVecStorage<Key, Item>
+{
+    fn clone(&self) -> Self
+    {
+        Self {
+            source: self.source.clone(),
+            key_phantom: PhantomData,
+        }
+    }
+}
+
+impl<Key, Item: fmt::Debug> fmt::Debug for ExternalVecStorage<Key, Item>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("ExternalVecStorage")
+            .field("items", &self.source.items())
+            .finish()
+    }
+}
+
+impl<Key, Item> From<ExternalVecStorage<Key, Item>> for Arw<dyn Storage>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: ExternalVecStorage<Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<Key, Item> Storage for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.source.items().len()
+    }
+}
+
+impl<Key, Item> KeyTypeIdNoSelf for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<Key, Item> ItemTypeIdNoSelf for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<Key, Item> ItemStorage for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<Key, Item> KeyStorage for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        try_key_to_index(key).is_ok_and(|index| index < self.len())
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        Box::new(self.keys())
+    }
+}
+
+impl<Key, Item> KeyItemStorage for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        access_stats::record(self, AccessKind::Get);
+        self.source.items().get(try_key_to_index(key).ok()?)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        access_stats::record(self, AccessKind::Iteration);
+        Box::new(self.source.items().iter())
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        access_stats::record(self, AccessKind::Iteration);
+
+        let iter = self
+            .source
+            .items()
+            .iter()
+            .enumerate()
+            .map_while(|(index, item)| Some((try_index_to_key(index).ok()?, item)));
+
+        Box::new(iter)
+    }
+}
+
+impl<Key, Item> ForEachStorage for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn for_each_item(&self, f: impl FnMut(&Self::Item))
+    {
+        access_stats::record(self, AccessKind::Iteration);
+        self.source.items().iter().for_each(f);
+    }
+
+    fn for_each_key_item(&self, mut f: impl FnMut(Self::Key, &Self::Item))
+    {
+        access_stats::record(self, AccessKind::Iteration);
+
+        for (key, item) in self.keys().zip(self.source.items())
+        {
+            f(key, item);
+        }
+    }
+}
+
+impl<Key, Item> ItemSliceStorage for ExternalVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_item_slice(&self) -> &[Item]
+    {
+        access_stats::record(self, AccessKind::Iteration);
+        self.source.items()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use super::ExternalVecStorage;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{ItemSliceStorage, KeyItemStorage, KeyStorage, Storage},
+    };
+
+    static WEIGHTS: [f32; 3] = [0.25, 0.5, 0.25];
+
+    #[test]
+    fn external_vec_test()
+    {
+        let samples = Arc::new(vec![1, 2, 3]);
+        let mut storage = ExternalVecStorage::<u16, i32>::new(samples.clone());
+
+        assert_eq!(storage.get(1), Some(&2));
+        assert!(!storage.contains(3));
+        assert!(std::ptr::eq(storage.as_item_slice(), samples.as_slice()));
+
+        // The owner publishes a new version
+        storage.set_source(Arc::new(vec![4, 5, 6, 7]));
+        assert_eq!(storage.keys().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        let handle: StorageHandle<dyn Storage> =
+            builder(ExternalVecStorage::<usize, f32>::from_static(&WEIGHTS)).build();
+        let reader = handle
+            .clone()
+            .cast_to_slice_storage::<usize, f32>()
+            .unwrap();
+        assert_eq!(reader.try_read().unwrap().as_item_slice(), &WEIGHTS);
+
+        let reader = handle.cast_to_getitem_storage::<usize, f32>().unwrap();
+        assert_eq!(reader.try_read().unwrap().get(1), Some(&0.5));
+    }
+}
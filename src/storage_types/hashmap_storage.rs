@@ -3,9 +3,12 @@ use std::collections::hash_map::Iter;
 use std::{collections::HashMap, fmt::Debug};
 
 use crate::storage_traits::{
-    ClearableStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyItemStorage, KeyStorage,
-    KeyTrait, KeyTypeIdNoSelf, MutKeyItemStorage, Storage,
+    ClearableStorage, ExtendStorage, ForEachStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf,
+    KeyItemStorage, KeyStorage, KeyTrait, KeyTypeIdNoSelf, MutKeyItemStorage, RemovableStorage,
+    StableRemovableStorage, Storage,
 };
+use crate::storage_handle::access_stats::{self, AccessKind};
+use crate::SimpleResult;
 
 /// Sparse Storage that uses a vec to store the Sparse Keys
 /// #DESIGN
@@ -14,6 +17,14 @@ use crate::storage_traits::{
 /// [`Into<usize>`] and also implement Copy as that is also a constraint of
 /// the interior [xsparseset::SparseSetVec]
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "Key: KeyTrait + serde::Serialize, Item: serde::Serialize",
+        deserialize = "Key: KeyTrait + serde::Deserialize<'de>, Item: serde::Deserialize<'de>"
+    ))
+)]
 pub struct HashMapStorage<Key, Item>
 {
     data: HashMap<Key, Item>,
@@ -73,6 +84,13 @@ where
     {
         self.data.len()
     }
+
+    fn memory_size(&self) -> usize
+    {
+        // Ignores the control bytes of the table
+        std::mem::size_of::<Self>()
+            + self.data.capacity() * (std::mem::size_of::<Key>() + std::mem::size_of::<Item>())
+    }
 }
 
 impl<Key, Item> KeyTypeIdNoSelf for HashMapStorage<Key, Item>
@@ -111,6 +129,7 @@ where
 
     fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
     {
+        access_stats::record(self, AccessKind::Iteration);
         Box::new(self.data.keys().cloned())
     }
 }
@@ -130,11 +149,13 @@ where
 {
     fn get(&self, key: Key) -> Option<&Item>
     {
+        access_stats::record(self, AccessKind::Get);
         self.data.get(&key)
     }
 
     fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
     {
+        access_stats::record(self, AccessKind::Iteration);
         let iter = self.data.values();
 
         Box::new(iter)
@@ -142,12 +163,35 @@ where
 
     fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
     {
+        access_stats::record(self, AccessKind::Iteration);
         let iter = self.data.iter().map(|(key, item)| (*key, item));
 
         Box::new(iter)
     }
 }
 
+impl<Key, Item> ForEachStorage for HashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn for_each_item(&self, f: impl FnMut(&Self::Item))
+    {
+        access_stats::record(self, AccessKind::Iteration);
+        self.data.values().for_each(f);
+    }
+
+    fn for_each_key_item(&self, mut f: impl FnMut(Self::Key, &Self::Item))
+    {
+        access_stats::record(self, AccessKind::Iteration);
+
+        for (key, item) in &self.data
+        {
+            f(*key, item);
+        }
+    }
+}
+
 impl<Key, Item> MutKeyItemStorage for HashMapStorage<Key, Item>
 where
     Key: KeyTrait,
@@ -155,15 +199,31 @@ where
 {
     fn insert(&mut self, key: Key, item: Item)
     {
+        access_stats::record(self, AccessKind::Insert);
         self.data.insert(key, item);
     }
 
     fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>
     {
+        access_stats::record(self, AccessKind::Get);
         self.data.get_mut(&key)
     }
 }
 
+impl<Key, Item> ExtendStorage for HashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn set_bulk(&mut self, items: impl IntoIterator<Item = (Key, Item)>) -> SimpleResult<()>
+    {
+        access_stats::record(self, AccessKind::Insert);
+        self.data.extend(items);
+
+        Ok(())
+    }
+}
+
 impl<Key, Item> ClearableStorage for HashMapStorage<Key, Item>
 where
     Key: KeyTrait,
@@ -175,6 +235,24 @@ where
     }
 }
 
+impl<Key, Item> RemovableStorage for HashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Key) -> Option<Item>
+    {
+        self.data.remove(&key)
+    }
+}
+
+impl<Key, Item> StableRemovableStorage for HashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
 #[cfg(test)]
 mod tests
 {
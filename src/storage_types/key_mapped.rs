@@ -0,0 +1,467 @@
+//! Present a storage under a remapped key space without copying its items.
+//!
+//! [KeyMappedStorage] wraps a storage and translates every key that passes through it with a
+//! [KeyMap]. A sub-graph's storage with local indices can then be read and written with global
+//! keys, by an [Offset], a [Stride] or functions of the keys through [FnMap]:
+//!
+//! ```ignore
+//! // Local indices 0..n of the sub-graph are global keys 100..100 + n
+//! let outputs = KeyMappedStorage::<_, usize, f32>::new(sub_graph_outputs, Offset(100));
+//! assert_eq!(outputs.get(100), sub_graph_outputs_copy.get(0));
+//!
+//! // Interleaved channels, eg the second channel of every pixel
+//! let stride = Stride { offset: 1, stride: 3 };
+//! let green = KeyMappedStorage::<_, usize, f32>::new(green_values, stride);
+//! ```
+//
+// # Internal Design
+//
+// - Maps are expected to be bijections between the keys in use. Keys of the inner storage that a
+//   map has no outer key for are left out of iteration but still counted by [Storage::len].
+// - The map is held as a shared trait object so that the wrapper's type only names the inner
+//   storage and the key and item types. That keeps it listable in the [crate::casting] tables for
+//   inner storages with usize keys, the usual case of local indices.
+// - Items are not touched, so item slices are passed through in the inner storage's order.
+
+use std::{any::TypeId, fmt, sync::Arc};
+
+use crate::{
+    error::panic_or_skip,
+    storage_traits::{
+        ClearableStorage, ItemSliceStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf,
+        KeyItemStorage, KeyStorage, KeyTrait, KeyTypeIdNoSelf, MaybeSendSync, MutItemSliceStorage,
+        MutKeyItemStorage, RemovableStorage, StableRemovableStorage, Storage,
+    },
+    Arw, ErrorKind, SimpleResult, StorageError,
+};
+
+use super::{try_index_to_key, try_key_to_index};
+
+/// A bijection between the keys of a [KeyMappedStorage] and the keys of the storage it wraps
+pub trait KeyMap<Outer, Inner>: MaybeSendSync
+{
+    /// None if the key has no inner key
+    fn to_inner(&self, key: Outer) -> Option<Inner>;
+
+    /// None if the key has no outer key
+    fn to_outer(&self, key: Inner) -> Option<Outer>;
+}
+
+/// Outer indices are the inner indices plus the offset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Offset(pub usize);
+
+impl<Outer: KeyTrait, Inner: KeyTrait> KeyMap<Outer, Inner> for Offset
+{
+    fn to_inner(&self, key: Outer) -> Option<Inner>
+    {
+        let index = try_key_to_index(key).ok()?.checked_sub(self.0)?;
+        try_index_to_key(index).ok()
+    }
+
+    fn to_outer(&self, key: Inner) -> Option<Outer>
+    {
+        let index = try_key_to_index(key).ok()?.checked_add(self.0)?;
+        try_index_to_key(index).ok()
+    }
+}
+
+/// Outer indices are `offset + inner * stride`. Outer indices between those have no inner key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stride
+{
+    pub offset: usize,
+    pub stride: usize,
+}
+
+impl<Outer: KeyTrait, Inner: KeyTrait> KeyMap<Outer, Inner> for Stride
+{
+    fn to_inner(&self, key: Outer) -> Option<Inner>
+    {
+        let from_offset = try_key_to_index(key).ok()?.checked_sub(self.offset)?;
+
+        if from_offset.checked_rem(self.stride)? != 0
+        {
+            return None;
+        }
+
+        try_index_to_key(from_offset / self.stride).ok()
+    }
+
+    fn to_outer(&self, key: Inner) -> Option<Outer>
+    {
+        let index = try_key_to_index(key)
+            .ok()?
+            .checked_mul(self.stride)?
+            .checked_add(self.offset)?;
+
+        try_index_to_key(index).ok()
+    }
+}
+
+/// A map through a pair of functions that are inverses of each other
+#[derive(Clone, Copy, Debug)]
+pub struct FnMap<Outer, Inner>
+{
+    pub to_inner: fn(Outer) -> Option<Inner>,
+    pub to_outer: fn(Inner) -> Option<Outer>,
+}
+
+impl<Outer, Inner> KeyMap<Outer, Inner> for FnMap<Outer, Inner>
+where
+    Outer: MaybeSendSync,
+    Inner: MaybeSendSync,
+{
+    fn to_inner(&self, key: Outer) -> Option<Inner>
+    {
+        (self.to_inner)(key)
+    }
+
+    fn to_outer(&self, key: Inner) -> Option<Outer>
+    {
+        (self.to_outer)(key)
+    }
+}
+
+/// A storage presented under another key space, see the [module docs](self)
+#[derive(Clone)]
+pub struct KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    storage: S,
+    map: Arc<dyn KeyMap<Key, S::Key>>,
+}
+
+impl<S, Key, Item> KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn new(storage: S, map: impl KeyMap<Key, S::Key> + 'static) -> Self
+    {
+        Self {
+            storage,
+            map: Arc::new(map),
+        }
+    }
+
+    pub fn inner(&self) -> &S
+    {
+        &self.storage
+    }
+
+    /// Mutable access to the storage under its own keys
+    pub fn inner_mut(&mut self) -> &mut S
+    {
+        &mut self.storage
+    }
+
+    pub fn into_inner(self) -> S
+    {
+        self.storage
+    }
+
+    /// The inner key of an outer key, None if it has none
+    pub fn to_inner(&self, key: Key) -> Option<S::Key>
+    {
+        self.map.to_inner(key)
+    }
+
+    /// The outer key of an inner key, None if it has none
+    pub fn to_outer(&self, key: S::Key) -> Option<Key>
+    {
+        self.map.to_outer(key)
+    }
+}
+
+impl<S, Key, Item> fmt::Debug for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item> + fmt::Debug,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("KeyMappedStorage")
+            .field("storage", &self.storage)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Key, Item> From<KeyMappedStorage<S, Key, Item>> for Arw<dyn Storage>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: KeyMappedStorage<S, Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<S, Key, Item> Storage for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.storage.len()
+    }
+}
+
+impl<S, Key, Item> KeyTypeIdNoSelf for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<S, Key, Item> ItemTypeIdNoSelf for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<S, Key, Item> KeyStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.to_inner(key)
+            .is_some_and(|key| self.storage.contains(key))
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        Box::new(
+            self.storage
+                .keys_iter()
+                .filter_map(|key| self.map.to_outer(key)),
+        )
+    }
+}
+
+impl<S, Key, Item> ItemStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<S, Key, Item> KeyItemStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        self.storage.get(self.to_inner(key)?)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        self.storage.item_iter()
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        Box::new(
+            self.storage
+                .key_item_iter()
+                .filter_map(|(key, item)| Some((self.map.to_outer(key)?, item))),
+        )
+    }
+}
+
+impl<S, Key, Item> ClearableStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn clear(&mut self)
+    {
+        self.storage.clear();
+    }
+}
+
+impl<S, Key, Item> MutKeyItemStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: MutKeyItemStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>
+    {
+        let key = self.to_inner(key)?;
+        self.storage.get_mut(key)
+    }
+
+    fn insert(&mut self, key: Self::Key, item: Self::Item)
+    {
+        panic_or_skip(self.try_insert(key, item));
+    }
+
+    /// Fails if the key has no inner key
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        let inner_key = self.to_inner(key).ok_or_else(|| {
+            StorageError::new(
+                ErrorKind::KeyInvalid,
+                format!("Key {key:?} has no key in the mapped storage"),
+            )
+        })?;
+
+        self.storage.try_insert(inner_key, item)
+    }
+
+    fn check_insert(&self, key: Self::Key) -> SimpleResult<()>
+    {
+        let inner_key = self.to_inner(key).ok_or_else(|| {
+            StorageError::new(
+                ErrorKind::KeyInvalid,
+                format!("Key {key:?} has no key in the mapped storage"),
+            )
+        })?;
+
+        self.storage.check_insert(inner_key)
+    }
+}
+
+impl<S, Key, Item> RemovableStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: RemovableStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Self::Key) -> Option<Self::Item>
+    {
+        let key = self.to_inner(key)?;
+        self.storage.remove(key)
+    }
+}
+
+impl<S, Key, Item> StableRemovableStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
+impl<S, Key, Item> ItemSliceStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item> + ItemSliceStorage,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_item_slice(&self) -> &[Self::Item]
+    {
+        self.storage.as_item_slice()
+    }
+}
+
+impl<S, Key, Item> MutItemSliceStorage for KeyMappedStorage<S, Key, Item>
+where
+    S: KeyItemStorage<Item = Item> + MutItemSliceStorage,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_mut_slice(&mut self) -> &mut [Self::Item]
+    {
+        self.storage.as_mut_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{FnMap, KeyMappedStorage, Offset, Stride};
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{KeyItemStorage, KeyStorage, MutKeyItemStorage, Storage},
+        storage_types::{HashMapStorage, VecStorage},
+        ErrorKind,
+    };
+
+    #[test]
+    fn offset_and_stride_test()
+    {
+        let local = VecStorage::<usize, i32>::from_vec(vec![10, 11, 12]);
+        let mut global = KeyMappedStorage::<_, usize, i32>::new(local, Offset(100));
+
+        assert_eq!(global.get(101), Some(&11));
+        assert_eq!(global.get(1), None);
+        assert!(global.keys_iter().eq([100, 101, 102]));
+
+        global.insert(103, 13);
+        assert_eq!(global.inner().get(3), Some(&13));
+        let error = global.try_insert(5, 0).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::KeyInvalid);
+
+        let green = VecStorage::<usize, f32>::from_vec(vec![0.1, 0.2]);
+        let green = KeyMappedStorage::<_, u32, f32>::new(
+            green,
+            Stride {
+                offset: 1,
+                stride: 3,
+            },
+        );
+        assert!(green.keys_iter().eq([1, 4]));
+        assert_eq!(green.get(4), Some(&0.2));
+        assert!(!green.contains(3));
+    }
+
+    #[test]
+    fn fn_map_cast_test()
+    {
+        // Node ids of a sub-graph are its local indices shifted into a namespace
+        let local = HashMapStorage::<usize, &str>::new();
+        let mut global = KeyMappedStorage::<_, u64, &str>::new(
+            local,
+            FnMap {
+                to_inner: |key: u64| key.checked_sub(1 << 32).map(|key| key as usize),
+                to_outer: |key: usize| Some((1 << 32) + key as u64),
+            },
+        );
+        global.insert((1 << 32) + 7, "blur");
+        assert_eq!(global.inner().get(7), Some(&"blur"));
+
+        // Casts to the outer key type
+        let handle: StorageHandle<dyn Storage> = builder(global).build();
+        let global = handle.cast_to_getitem_storage::<u64, &str>().unwrap();
+        assert_eq!(global.try_read().unwrap().get((1 << 32) + 7), Some(&"blur"));
+    }
+}
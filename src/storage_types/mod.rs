@@ -2,32 +2,172 @@
 //! and tooling to promote richer trait based programming via either static or dynamic dispatch.
 //! For more information see crate level documentation [crate]
 
+mod arena;
+mod computed;
+mod cow;
+mod dirty_tracked;
+mod external;
 mod hashmap_storage;
+mod key_mapped;
+mod provenance;
+mod sharded_hashmap_storage;
 mod sparse_storage;
+mod undoable;
 mod val_storage;
 mod vec_storage;
 mod view;
 
+pub use arena::*;
+pub use computed::*;
+pub use cow::*;
+pub use dirty_tracked::*;
+pub use external::*;
 pub use hashmap_storage::*;
+pub use key_mapped::*;
+pub use provenance::*;
+pub use sharded_hashmap_storage::*;
 pub use sparse_storage::*;
+pub use undoable::*;
 pub use val_storage::*;
 pub use vec_storage::*;
 pub use view::*;
 
-use crate::storage_traits::KeyTrait;
+use std::{iter::FusedIterator, marker::PhantomData, ops::Range};
 
-pub fn key_to_index<Key: KeyTrait>(key: Key) -> usize {
-    if let Ok(val) = key.try_into() {
-        val
-    } else {
-        panic!("Key could not be converted to usize");
+use crate::{storage_traits::KeyTrait, ErrorDetail, ErrorKind, Hint, SimpleResult, StorageError};
+
+/// Convert a key to the index it addresses in an index based storage. Fails if the key doesn't
+/// fit in a usize.
+pub fn try_key_to_index<Key: KeyTrait>(key: Key) -> SimpleResult<usize>
+{
+    key.try_to_index().ok_or_else(|| {
+        StorageError::new(
+            ErrorKind::KeyInvalid,
+            format!(
+                "Key {key:?} of type {} could not be converted to an index",
+                std::any::type_name::<Key>()
+            ),
+        )
+        .with_detail(ErrorDetail::KeyConversion {
+            value: format!("{key:?}"),
+            key_type: std::any::type_name::<Key>(),
+        })
+    })
+}
+
+/// Convert an index to the key that addresses it in an index based storage. Fails if the index
+/// doesn't fit in the Key type.
+pub fn try_index_to_key<Key: KeyTrait>(index: usize) -> SimpleResult<Key>
+{
+    Key::try_from_index(index).ok_or_else(|| {
+        StorageError::new(
+            ErrorKind::KeyInvalid,
+            format!(
+                "Index {index} could not be converted to a key of type {}",
+                std::any::type_name::<Key>()
+            ),
+        )
+        .with_detail(ErrorDetail::KeyConversion {
+            value: index.to_string(),
+            key_type: std::any::type_name::<Key>(),
+        })
+    })
+}
+
+/// Fails if the Key type can't be used as an index, see [KeyTrait::supports_index]
+pub fn check_index_key<Key: KeyTrait>() -> SimpleResult<()>
+{
+    if Key::supports_index()
+    {
+        return Ok(());
     }
+
+    Err(StorageError::new(
+        ErrorKind::KeyInvalid,
+        format!(
+            "Key type {} can't be used as an index",
+            std::any::type_name::<Key>()
+        ),
+    )
+    .with_hint(
+        Hint::NonIndexKey,
+        "Use a Key type that converts to usize such as usize, u32 or u16, or a map storage such as \
+         HashMapStorage for other keys",
+    ))
 }
 
-pub fn index_to_key<Key: KeyTrait>(index: usize) -> Key {
-    if let Ok(val) = index.try_into() {
-        val
-    } else {
-        panic!("Key could not be converted to usize");
+/// Panic with the error and fix of [check_index_key] if the Key type can't be used as an index.
+/// A no-op with the `no_panic` feature.
+pub(crate) fn assert_index_key<Key: KeyTrait>()
+{
+    #[cfg(not(feature = "no_panic"))]
+    if let Err(error) = check_index_key::<Key>()
+    {
+        panic!("{error}. {}", error.fix().unwrap_or_default());
     }
 }
+
+/// Unchecked version of [try_key_to_index] for keys known to convert, such as keys that were
+/// already used to insert into the storage. Panics if the key doesn't fit in a usize.
+pub fn key_to_index<Key: KeyTrait>(key: Key) -> usize
+{
+    try_key_to_index(key).unwrap_or_else(|error| panic!("{error}"))
+}
+
+/// Unchecked version of [try_index_to_key] for indices known to convert, such as indices below the
+/// length of a storage that was filled through keys. Panics if the index doesn't fit in the Key
+/// type.
+pub fn index_to_key<Key: KeyTrait>(index: usize) -> Key
+{
+    try_index_to_key(index).unwrap_or_else(|error| panic!("{error}"))
+}
+
+/// The keys of a range of indices, returned by the inherent `keys` methods of index based storages
+/// such as [VecStorage::keys] so that key iteration doesn't box an iterator. Stops at the first
+/// index that doesn't fit in the Key type, like [crate::storage_traits::KeyStorage::keys_iter].
+#[derive(Clone, Debug)]
+pub struct KeyRange<Key>
+{
+    indices: Range<usize>,
+    key_phantom: PhantomData<Key>,
+}
+
+impl<Key> KeyRange<Key>
+{
+    pub fn new(indices: Range<usize>) -> Self
+    {
+        Self {
+            indices,
+            key_phantom: PhantomData,
+        }
+    }
+}
+
+impl<Key: KeyTrait> Iterator for KeyRange<Key>
+{
+    type Item = Key;
+
+    #[inline]
+    fn next(&mut self) -> Option<Key>
+    {
+        let index = self.indices.next()?;
+
+        match try_index_to_key(index)
+        {
+            Ok(key) => Some(key),
+            Err(_) =>
+            {
+                // Indices only grow so none of the rest fit either
+                self.indices = self.indices.end..self.indices.end;
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>)
+    {
+        (0, Some(self.indices.len()))
+    }
+}
+
+impl<Key: KeyTrait> FusedIterator for KeyRange<Key> {}
@@ -0,0 +1,442 @@
+//! Per key record of who last wrote an item and when, for tracking down which node wrote a bad
+//! value into a shared storage.
+//!
+//! [ProvenanceTracked] wraps a mutable storage and records a [WriteRecord] for every key written
+//! through `insert` or `get_mut`. The writer is whatever [WriterScope] is active on the writing
+//! thread, so graph evaluators can tag every write a node makes without the node knowing about
+//! provenance or the concrete storage type:
+//!
+//! ```ignore
+//! {
+//!     let _scope = WriterScope::enter("blur node");
+//!     node.evaluate(&inputs, &outputs)?;
+//! }
+//!
+//! let (item, record) = tracked.get_with_provenance(key).unwrap();
+//! println!("{item:?} written by {:?} at {}", record.unwrap().writer, record.unwrap().timestamp);
+//! ```
+//
+// # Internal Design
+//
+// - Timestamps are a logical counter per storage that increases with every recorded write, so
+//   records of the same storage can be ordered without relying on the system clock.
+// - The writer lives in a thread local rather than on the wrapper so that it reaches writes made
+//   through trait object casts of a handle.
+// - VecStorage shifts the items after an inserted key, so their records are shifted along with
+//   them. It is detected by TypeId when wrapping as for [super::DirtyTracked].
+
+use std::{any::TypeId, cell::RefCell, collections::HashMap, sync::Arc};
+
+use crate::{
+    error::panic_or_skip,
+    storage_traits::{
+        ClearableStorage, ItemSliceStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf,
+        KeyItemStorage, KeyStorage, KeyTrait, KeyTypeIdNoSelf, MutKeyItemStorage, RemovableStorage,
+        StableRemovableStorage, Storage,
+    },
+    Arw, SimpleResult,
+};
+
+use super::{try_index_to_key, try_key_to_index, VecStorage};
+
+thread_local! {
+    static CURRENT_WRITER: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// Tags writes made on this thread with a writer until dropped. Scopes nest, dropping one restores
+/// the writer of the scope it was entered in.
+#[must_use = "the writer is only set until the scope is dropped"]
+pub struct WriterScope
+{
+    previous: Option<Arc<str>>,
+}
+
+impl WriterScope
+{
+    pub fn enter(writer: impl Into<Arc<str>>) -> Self
+    {
+        let previous = CURRENT_WRITER.with(|current| current.replace(Some(writer.into())));
+
+        Self { previous }
+    }
+
+    /// The writer of the innermost active scope on this thread
+    pub fn current() -> Option<Arc<str>>
+    {
+        CURRENT_WRITER.with(|current| current.borrow().clone())
+    }
+}
+
+impl Drop for WriterScope
+{
+    fn drop(&mut self)
+    {
+        CURRENT_WRITER.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Who last wrote an item and when
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteRecord
+{
+    /// None if the write was made outside of a [WriterScope]
+    pub writer: Option<Arc<str>>,
+
+    /// Logical time of the write within its storage
+    pub timestamp: u64,
+}
+
+/// A storage wrapper that records the last writer of each key, see the [module docs](self)
+#[derive(Clone, Debug)]
+pub struct ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    storage: S,
+    records: HashMap<Key, WriteRecord>,
+    next_timestamp: u64,
+
+    /// Whether an insert shifts the items after it
+    shifts_on_insert: bool,
+}
+
+impl<S, Key, Item> ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn new(storage: S) -> Self
+    {
+        Self {
+            storage,
+            records: HashMap::new(),
+            next_timestamp: 0,
+            shifts_on_insert: TypeId::of::<S>() == TypeId::of::<VecStorage<Key, Item>>(),
+        }
+    }
+
+    pub fn inner(&self) -> &S
+    {
+        &self.storage
+    }
+
+    /// Unwrap the storage, dropping the records
+    pub fn into_inner(self) -> S
+    {
+        self.storage
+    }
+
+    /// Record of the last write to `key`. None if it hasn't been written since it was wrapped.
+    pub fn provenance(&self, key: Key) -> Option<&WriteRecord>
+    {
+        self.records.get(&key)
+    }
+
+    pub fn get_with_provenance(&self, key: Key) -> Option<(&Item, Option<&WriteRecord>)>
+    {
+        Some((self.storage.get(key)?, self.records.get(&key)))
+    }
+
+    /// Keys last written by `writer`
+    pub fn keys_written_by<'a>(&'a self, writer: &'a str) -> impl Iterator<Item = Key> + 'a
+    {
+        self.records
+            .iter()
+            .filter(move |(_, record)| record.writer.as_deref() == Some(writer))
+            .map(|(key, _)| *key)
+    }
+
+    fn record(&mut self, key: Key)
+    {
+        let record = WriteRecord {
+            writer: WriterScope::current(),
+            timestamp: self.next_timestamp,
+        };
+
+        self.next_timestamp += 1;
+        self.records.insert(key, record);
+    }
+
+    fn record_insert(&mut self, key: Key, old_len: usize)
+    {
+        if self.shifts_on_insert
+        {
+            // Keys that don't convert were rejected by the storage so nothing shifted
+            if let Ok(index) = try_key_to_index(key)
+            {
+                self.shift_records(index, old_len);
+            }
+        }
+
+        self.record(key);
+    }
+
+    /// Move the records from `index` on along by one, following the items of a shifting insert
+    fn shift_records(&mut self, index: usize, old_len: usize)
+    {
+        for index in (index..old_len).rev()
+        {
+            let Ok(key) = try_index_to_key(index)
+            else
+            {
+                continue;
+            };
+
+            // Records shifted past the range of the Key type have no key to move to
+            if let Some(record) = self.records.remove(&key)
+            {
+                if let Ok(shifted) = try_index_to_key(index + 1)
+                {
+                    self.records.insert(shifted, record);
+                }
+            }
+        }
+    }
+}
+
+impl<S, Key, Item> From<ProvenanceTracked<S, Key, Item>> for Arw<dyn Storage>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: ProvenanceTracked<S, Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<S, Key, Item> Storage for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.storage.len()
+    }
+}
+
+impl<S, Key, Item> KeyTypeIdNoSelf for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<S, Key, Item> ItemTypeIdNoSelf for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<S, Key, Item> KeyStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.storage.contains(key)
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        self.storage.keys_iter()
+    }
+}
+
+impl<S, Key, Item> ItemStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<S, Key, Item> KeyItemStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        self.storage.get(key)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        self.storage.item_iter()
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        self.storage.key_item_iter()
+    }
+}
+
+impl<S, Key, Item> MutKeyItemStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>
+    {
+        if self.storage.contains(key)
+        {
+            self.record(key);
+        }
+
+        self.storage.get_mut(key)
+    }
+
+    fn insert(&mut self, key: Self::Key, item: Self::Item)
+    {
+        panic_or_skip(self.try_insert(key, item));
+    }
+
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        let old_len = self.storage.len();
+        self.storage.try_insert(key, item)?;
+        self.record_insert(key, old_len);
+
+        Ok(())
+    }
+
+    fn check_insert(&self, key: Self::Key) -> SimpleResult<()>
+    {
+        self.storage.check_insert(key)
+    }
+}
+
+impl<S, Key, Item> ClearableStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn clear(&mut self)
+    {
+        self.storage.clear();
+        self.records.clear();
+    }
+}
+
+impl<S, Key, Item> RemovableStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: RemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Self::Key) -> Option<Self::Item>
+    {
+        self.records.remove(&key);
+        self.storage.remove(key)
+    }
+}
+
+impl<S, Key, Item> StableRemovableStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
+impl<S, Key, Item> ItemSliceStorage for ProvenanceTracked<S, Key, Item>
+where
+    S: MutKeyItemStorage<Key = Key, Item = Item> + ItemSliceStorage,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn as_item_slice(&self) -> &[Self::Item]
+    {
+        self.storage.as_item_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{ProvenanceTracked, WriterScope};
+    use crate::{
+        storage_traits::MutKeyItemStorage,
+        storage_types::{HashMapStorage, VecStorage},
+    };
+
+    #[test]
+    fn provenance_test()
+    {
+        let mut storage = ProvenanceTracked::new(HashMapStorage::<u32, f32>::new());
+        storage.insert(1, 0.5);
+
+        {
+            let _scope = WriterScope::enter("blur");
+            storage.insert(2, 1.0);
+
+            {
+                let _scope = WriterScope::enter("sharpen");
+                *storage.get_mut(1).unwrap() = 2.0;
+            }
+
+            storage.insert(3, 1.5);
+        }
+
+        let (item, record) = storage.get_with_provenance(1).unwrap();
+        assert_eq!(*item, 2.0);
+        assert_eq!(record.unwrap().writer.as_deref(), Some("sharpen"));
+        assert_eq!(record.unwrap().timestamp, 2);
+
+        let mut keys: Vec<u32> = storage.keys_written_by("blur").collect();
+        keys.sort();
+        assert_eq!(keys, vec![2, 3]);
+
+        assert!(WriterScope::current().is_none());
+    }
+
+    #[test]
+    fn provenance_vec_insert_test()
+    {
+        let mut storage = ProvenanceTracked::new(VecStorage::<usize, i32>::from_vec(vec![1, 2]));
+
+        let _scope = WriterScope::enter("a");
+        storage.insert(1, 5);
+        drop(_scope);
+
+        // The shifting insert moves the record at 1 along to 2
+        let _scope = WriterScope::enter("b");
+        storage.insert(1, 10);
+
+        assert_eq!(storage.provenance(1).unwrap().writer.as_deref(), Some("b"));
+        assert_eq!(storage.provenance(2).unwrap().writer.as_deref(), Some("a"));
+        assert!(storage.provenance(0).is_none());
+    }
+}
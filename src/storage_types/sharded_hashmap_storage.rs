@@ -0,0 +1,648 @@
+//! A map storage whose key space is split across several internally locked shards so that writes
+//! to different shards don't serialize on a single storage lock.
+//!
+//! Sharded writes only need a read lock on the storage itself, so any number of threads can
+//! share the handle and write to different shards concurrently:
+//!
+//! ```ignore
+//! let storage = handle.try_read()?;
+//! storage.write_shard(key)?.insert(key, item)?;
+//! ```
+//!
+//! Taking a write lock on the handle still gives exclusive access to every shard at once which is
+//! what whole storage operations such as [ClearableStorage::clear] use.
+//!
+//! Once the storage is in a handle made with [builder], every released sharded write bumps the
+//! handle's [StorageHandle::write_version] so autosave, computed storages and
+//! [StorageHandle::update] see it. To the lock machinery a sharded write is still a read though,
+//! so it doesn't wake the handle's write waiters and subscribers or take checkpoints. Call
+//! [StorageHandle::notify_readers] after sharded writes to wake waiting readers.
+//!
+//! [builder]: crate::storage_handle::builder
+//! [StorageHandle::write_version]: crate::storage_handle::StorageHandle::write_version
+//! [StorageHandle::update]: crate::storage_handle::StorageHandle::update
+//! [StorageHandle::notify_readers]: crate::storage_handle::StorageHandle::notify_readers
+//
+// # Internal Design
+//
+// - Items live behind the shard locks so they can't be handed out by reference from `&self`. This
+//   is why [KeyItemStorage] and [MutKeyItemStorage] are not implemented and item access goes
+//   through the shard guards instead.
+// - The write version is bumped when a shard write guard is dropped rather than when it is taken,
+//   so a reader that sees the new version also sees the write. A reader that caches between the
+//   write and the bump sees the old version and is corrected by the bump.
+// - The shard of a key is decided by hashing it with the storage's own hasher so that every thread
+//   agrees on the shard for a key without any shared bookkeeping.
+
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{
+    error::lock_error,
+    storage_traits::{
+        ClearableStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyStorage, KeyTrait,
+        KeyTypeIdNoSelf, Storage,
+    },
+    sync::atomic::{AtomicU64, Ordering},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+/// Shard count used by [ShardedHashMapStorage::new]
+pub const DEFAULT_SHARD_COUNT: usize = 16;
+
+#[derive(Debug)]
+pub struct ShardedHashMapStorage<Key, Item>
+{
+    shards: Vec<RwLock<HashMap<Key, Item>>>,
+    hasher: RandomState,
+    /// The write version of the handle holding the storage, see [Storage::attach_write_version]
+    write_version: Option<Arc<AtomicU64>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Inherent methods
+////////////////////////////////////////////////////////////////////////////////
+
+impl<Key, Item> ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn new() -> Self
+    {
+        Self::with_shard_count(DEFAULT_SHARD_COUNT)
+    }
+
+    /// Panics if `shard_count` is 0. With the `no_panic` feature one shard is used instead.
+    pub fn with_shard_count(shard_count: usize) -> Self
+    {
+        #[cfg(not(feature = "no_panic"))]
+        assert!(
+            shard_count > 0,
+            "A sharded storage needs at least one shard"
+        );
+
+        #[cfg(feature = "no_panic")]
+        let shard_count = shard_count.max(1);
+
+        Self {
+            shards: (0..shard_count).map(|_| <_>::default()).collect(),
+            hasher: <_>::default(),
+            write_version: None,
+        }
+    }
+
+    pub fn shard_count(&self) -> usize
+    {
+        self.shards.len()
+    }
+
+    /// The index of the shard that owns `key`
+    pub fn shard_index(&self, key: Key) -> usize
+    {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    /// Lock the shard owning `key` for reading, waiting for any writer of that shard
+    pub fn read_shard(&self, key: Key) -> SimpleResult<ShardReadGuard<'_, Key, Item>>
+    {
+        let shard = self.shard_index(key);
+
+        match self.shards[shard].read()
+        {
+            Ok(guard) => Ok(ShardReadGuard { shard, guard }),
+            Err(_) => Err(StorageError::new(
+                ErrorKind::Poisoned,
+                format!("Failed to aquire read lock on shard {shard} as it is poisoned"),
+            )),
+        }
+    }
+
+    /// Lock the shard owning `key` for writing, waiting for any other user of that shard.
+    ///
+    /// Bumps the write version of the handle holding the storage once the write is released, see
+    /// the [module docs](self).
+    pub fn write_shard(&self, key: Key) -> SimpleResult<ShardWriteGuard<'_, Key, Item>>
+    {
+        let shard = self.shard_index(key);
+
+        match self.shards[shard].write()
+        {
+            Ok(guard) => Ok(ShardWriteGuard {
+                storage: self,
+                shard,
+                guard,
+            }),
+            Err(_) => Err(StorageError::new(
+                ErrorKind::Poisoned,
+                format!("Failed to aquire write lock on shard {shard} as it is poisoned"),
+            )),
+        }
+    }
+
+    pub fn try_read_shard(&self, key: Key) -> SimpleResult<ShardReadGuard<'_, Key, Item>>
+    {
+        let shard = self.shard_index(key);
+
+        match self.shards[shard].try_read()
+        {
+            Ok(guard) => Ok(ShardReadGuard { shard, guard }),
+            Err(error) => Err(lock_error(
+                &error,
+                &format!("Failed to aquire read lock on shard {shard}"),
+            )),
+        }
+    }
+
+    pub fn try_write_shard(&self, key: Key) -> SimpleResult<ShardWriteGuard<'_, Key, Item>>
+    {
+        let shard = self.shard_index(key);
+
+        match self.shards[shard].try_write()
+        {
+            Ok(guard) => Ok(ShardWriteGuard {
+                storage: self,
+                shard,
+                guard,
+            }),
+            Err(error) => Err(lock_error(
+                &error,
+                &format!("Failed to aquire write lock on shard {shard}"),
+            )),
+        }
+    }
+
+    /// Clone of the item at `key`. Locks only the owning shard.
+    pub fn get_cloned(&self, key: Key) -> SimpleResult<Option<Item>>
+    {
+        Ok(self.read_shard(key)?.get(key).cloned())
+    }
+
+    /// Insert into the owning shard, returning the previous item. Locks only the owning shard.
+    ///
+    /// Bumps the write version of the handle holding the storage once the write is released, see
+    /// the [module docs](self).
+    pub fn insert_shared(&self, key: Key, item: Item) -> SimpleResult<Option<Item>>
+    {
+        self.write_shard(key)?.insert(key, item)
+    }
+
+    /// Exclusive access to the owning shard without locking as `&mut self` already guarantees
+    /// that no shard guards are alive
+    fn shard_mut(&mut self, key: Key) -> &mut HashMap<Key, Item>
+    {
+        let shard = self.shard_index(key);
+
+        // A poisoned shard is still structurally valid so it is recovered rather than lost
+        self.shards[shard]
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn insert(&mut self, key: Key, item: Item) -> Option<Item>
+    {
+        self.shard_mut(key).insert(key, item)
+    }
+
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut Item>
+    {
+        self.shard_mut(key).get_mut(&key)
+    }
+
+    pub fn remove(&mut self, key: Key) -> Option<Item>
+    {
+        self.shard_mut(key).remove(&key)
+    }
+}
+
+impl<Key, Item> Default for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl<Key, Item> FromIterator<(Key, Item)> for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from_iter<I: IntoIterator<Item = (Key, Item)>>(iter: I) -> Self
+    {
+        let mut storage = Self::new();
+
+        for (key, item) in iter
+        {
+            storage.insert(key, item);
+        }
+
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Serde impl
+////////////////////////////////////////////////////////////////////////////////
+
+// Serialized as a single map so the shard count and hasher of the writer don't leak into the data.
+// Deserializing re-shards the entries with [DEFAULT_SHARD_COUNT] shards.
+
+#[cfg(feature = "serde")]
+impl<Key, Item> serde::Serialize for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait + serde::Serialize,
+    Item: ItemTrait + serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        use serde::ser::{Error, SerializeMap};
+
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+
+        for shard in &self.shards
+        {
+            let shard = shard
+                .read()
+                .map_err(|_| S::Error::custom("Failed to aquire read lock on a shard"))?;
+
+            for (key, item) in shard.iter()
+            {
+                map.serialize_entry(key, item)?;
+            }
+        }
+
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Key, Item> serde::Deserialize<'de> for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait + serde::Deserialize<'de>,
+    Item: ItemTrait + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>
+    {
+        let entries: HashMap<Key, Item> = serde::Deserialize::deserialize(deserializer)?;
+
+        Ok(entries.into_iter().collect())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Shard guards
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ShardReadGuard<'a, Key, Item>
+{
+    shard: usize,
+    guard: RwLockReadGuard<'a, HashMap<Key, Item>>,
+}
+
+impl<Key, Item> ShardReadGuard<'_, Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn shard(&self) -> usize
+    {
+        self.shard
+    }
+
+    pub fn get(&self, key: Key) -> Option<&Item>
+    {
+        self.guard.get(&key)
+    }
+
+    pub fn contains(&self, key: Key) -> bool
+    {
+        self.guard.contains_key(&key)
+    }
+
+    /// Iterate over the entries of this shard only
+    pub fn key_item_iter(&self) -> impl Iterator<Item = (Key, &Item)>
+    {
+        self.guard.iter().map(|(key, item)| (*key, item))
+    }
+}
+
+pub struct ShardWriteGuard<'a, Key, Item>
+{
+    storage: &'a ShardedHashMapStorage<Key, Item>,
+    shard: usize,
+    guard: RwLockWriteGuard<'a, HashMap<Key, Item>>,
+}
+
+impl<Key, Item> Drop for ShardWriteGuard<'_, Key, Item>
+{
+    fn drop(&mut self)
+    {
+        if let Some(write_version) = &self.storage.write_version
+        {
+            write_version.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl<Key, Item> ShardWriteGuard<'_, Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    pub fn shard(&self) -> usize
+    {
+        self.shard
+    }
+
+    pub fn get(&self, key: Key) -> Option<&Item>
+    {
+        self.guard.get(&key)
+    }
+
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut Item>
+    {
+        self.guard.get_mut(&key)
+    }
+
+    pub fn contains(&self, key: Key) -> bool
+    {
+        self.guard.contains_key(&key)
+    }
+
+    /// Fails if `key` is owned by a different shard than the one this guard has locked
+    pub fn insert(&mut self, key: Key, item: Item) -> SimpleResult<Option<Item>>
+    {
+        let owning_shard = self.storage.shard_index(key);
+
+        if owning_shard != self.shard
+        {
+            return Err(format!(
+                "Key {key:?} belongs to shard {owning_shard} but this guard has shard {} locked",
+                self.shard
+            )
+            .into());
+        }
+
+        Ok(self.guard.insert(key, item))
+    }
+
+    pub fn remove(&mut self, key: Key) -> Option<Item>
+    {
+        self.guard.remove(&key)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+// Whole storage trait methods only take `&self` so they read lock each shard in turn. They are
+// there for interchangeability with other storages rather than for the contended hot path.
+
+impl<Key, Item> Storage for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().map(|shard| shard.len()).unwrap_or(0))
+            .sum()
+    }
+
+    fn attach_write_version(&mut self, version: Arc<AtomicU64>)
+    {
+        self.write_version = Some(version);
+    }
+}
+
+impl<Key, Item> KeyTypeIdNoSelf for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> std::any::TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<Key, Item> ItemTypeIdNoSelf for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> std::any::TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<Key, Item> KeyStorage for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.read_shard(key)
+            .map(|shard| shard.contains(key))
+            .unwrap_or(false)
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        // Collected so that no shard stays locked while the caller iterates
+        let keys: Vec<Key> = self
+            .shards
+            .iter()
+            .filter_map(|shard| shard.read().ok())
+            .flat_map(|shard| shard.keys().cloned().collect::<Vec<_>>())
+            .collect();
+
+        Box::new(keys.into_iter())
+    }
+}
+
+impl<Key, Item> ItemStorage for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<Key, Item> ClearableStorage for ShardedHashMapStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn clear(&mut self)
+    {
+        for shard in &mut self.shards
+        {
+            shard
+                .get_mut()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::ShardedHashMapStorage;
+    use crate::storage_traits::{KeyStorage, Storage};
+
+    #[cfg(not(feature = "local"))]
+    use crate::storage_handle::{builder, StorageHandle};
+
+    #[test]
+    fn test()
+    {
+        let mut storage: ShardedHashMapStorage<u64, i32> =
+            ShardedHashMapStorage::with_shard_count(4);
+
+        storage.insert(0, 10);
+        storage.insert(1, 11);
+
+        assert_eq!(storage.get_cloned(0).unwrap(), Some(10));
+        assert!(storage.contains(1));
+        assert_eq!(storage.len(), 2);
+
+        // A guard refuses keys that belong to another shard
+        let other_key = (0..100)
+            .find(|key| storage.shard_index(*key) != storage.shard_index(0))
+            .unwrap();
+        let mut shard = storage.write_shard(0).unwrap();
+        assert!(shard.insert(other_key, 1).is_err());
+
+        #[cfg(feature = "no_panic")]
+        assert_eq!(ShardedHashMapStorage::<u64, i32>::with_shard_count(0).shard_count(), 1);
+    }
+
+    /// Threads writing through a shared read lock on the handle
+    #[test]
+    #[cfg(not(feature = "local"))]
+    fn concurrent_shard_writes_test()
+    {
+        let handle: StorageHandle<ShardedHashMapStorage<u64, u64>> =
+            builder(ShardedHashMapStorage::<u64, u64>::new())
+                .build()
+                .cast_to_sized_storage()
+                .unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let handle = handle.clone();
+                std::thread::spawn(move || {
+                    let storage = handle.try_read().unwrap();
+
+                    for key in (thread * 100)..(thread * 100 + 100)
+                    {
+                        storage.insert_shared(key, key * 2).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads
+        {
+            thread.join().unwrap();
+        }
+
+        let storage = handle.try_read().unwrap();
+        assert_eq!(storage.len(), 400);
+        assert_eq!(storage.get_cloned(250).unwrap(), Some(500));
+    }
+
+    #[test]
+    #[cfg(not(feature = "local"))]
+    fn write_version_test()
+    {
+        let handle: StorageHandle<ShardedHashMapStorage<u64, u64>> =
+            builder(ShardedHashMapStorage::<u64, u64>::new())
+                .build()
+                .cast_to_sized_storage()
+                .unwrap();
+
+        let version = handle.write_version();
+
+        {
+            let storage = handle.try_read().unwrap();
+            storage.insert_shared(1, 1).unwrap();
+            storage.insert_shared(2, 2).unwrap();
+        }
+
+        assert_eq!(handle.write_version(), version + 2);
+
+        // Reads of a shard don't count
+        handle.try_read().unwrap().get_cloned(1).unwrap();
+        assert_eq!(handle.write_version(), version + 2);
+    }
+
+    /// A sharded write while the copy is built makes update retry without counting the retry
+    #[test]
+    #[cfg(not(feature = "local"))]
+    fn update_test()
+    {
+        let handle: StorageHandle<ShardedHashMapStorage<u64, u64>> =
+            builder(ShardedHashMapStorage::<u64, u64>::new())
+                .build()
+                .cast_to_sized_storage()
+                .unwrap();
+
+        let version = handle.write_version();
+        let mut calls = 0;
+
+        handle
+            .update(|old| {
+                calls += 1;
+
+                if calls == 1
+                {
+                    old.insert_shared(1, 1).unwrap();
+                }
+
+                let mut storage = ShardedHashMapStorage::new();
+                storage.insert(2, 2);
+                storage
+            })
+            .unwrap();
+
+        assert_eq!(calls, 2);
+        // The sharded write and the swap
+        assert_eq!(handle.write_version(), version + 2);
+
+        // The swapped in storage bumps the version too
+        handle.try_read().unwrap().insert_shared(3, 3).unwrap();
+        assert_eq!(handle.write_version(), version + 3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_test()
+    {
+        let storage: ShardedHashMapStorage<u64, i32> =
+            (0..100).map(|key| (key, key as i32)).collect();
+
+        let json = serde_json::to_string(&storage).unwrap();
+        let restored: ShardedHashMapStorage<u64, i32> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.len(), 100);
+        assert_eq!(restored.get_cloned(42).unwrap(), Some(42));
+    }
+}
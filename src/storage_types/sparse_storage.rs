@@ -1,13 +1,21 @@
 use std::iter;
-use std::{fmt::Debug, any::TypeId};
+use std::{cmp::Ordering, fmt::Debug, any::TypeId};
 
 use std::mem::size_of;
 use xsparseset::SparseSetVec;
 
 use crate::storage_traits::{
-    AsBytesBorrowed, ClearableStorage, ItemSliceStorage, ItemStorage, ItemTrait, KeyItemStorage,
-    KeyStorage, MutItemSliceStorage, MutKeyItemStorage, Storage, KeyTypeIdNoSelf, ItemTypeIdNoSelf, KeyTrait
+    AsBytesBorrowed, ClearableStorage, ExtendStorage, ForEachStorage, ItemSliceStorage,
+    ItemStorage, ItemTrait, KeyItemSliceStorage, KeyItemStorage,
+    KeyStorage, MutItemSliceStorage, MutKeyItemSliceStorage, MutKeyItemStorage, Storage, KeyTypeIdNoSelf, ItemTypeIdNoSelf, KeyTrait,
+    RemovableStorage, SortableKey, ZeroCopyItem, AllocKeyStorage, OrderedStorage,
+    StableRemovableStorage,
 };
+use crate::cursor::{CursorItems, CursorKeys, StorageCursor};
+use crate::storage_handle::access_stats::{self, AccessKind};
+use crate::{error::panic_or_skip, ErrorKind, SimpleResult, StorageError};
+
+use super::{assert_index_key, check_index_key, try_index_to_key};
 
 /// Sparse Storage that uses a vec to store the Sparse Keys
 /// 
@@ -35,12 +43,121 @@ where
     Item: ItemTrait,
 {
     pub fn new() -> Self {
-        assert!(Key::supports_index());
+        assert_index_key::<Key>();
 
         Self {
             data: <_>::default(),
         }
     }
+
+    /// Like [Self::new] but fails instead of panicking if the Key type can't be used as an index
+    pub fn try_new() -> SimpleResult<Self> {
+        check_index_key::<Key>()?;
+
+        Ok(Self::new())
+    }
+
+    /// Position of the key in the dense arrays, which is the position of its item in
+    /// [ItemSliceStorage::as_item_slice] and [MutItemSliceStorage::as_mut_slice]. None if the key
+    /// isn't stored, including when a key of another generation is stored at its index, see
+    /// [KeyTrait::generation]. Keys without generations are always equal to the key stored at
+    /// their index.
+    ///
+    /// Positions change when keys are removed or the dense arrays are sorted.
+    pub fn dense_index_of(&self, key: Key) -> Option<usize> {
+        let index = self.data.get_index(key)?;
+
+        (self.data.ids()[index] == key).then_some(index)
+    }
+
+    /// The key of the item at `index` in the item slice. None if `index` is out of bounds.
+    pub fn key_at_dense(&self, index: usize) -> Option<Key> {
+        self.data.ids().get(index).copied()
+    }
+
+    /// The keys in the same order as the item slice, so that `dense_keys()[i]` is the key of
+    /// `as_item_slice()[i]`
+    pub fn dense_keys(&self) -> &[Key] {
+        access_stats::record(self, AccessKind::Iteration);
+        self.data.ids()
+    }
+
+    /// Visit the items in dense order with their positions and keys, for writing results that
+    /// were computed on the item slice back by position or by key
+    pub fn for_each_dense_mut(&mut self, mut f: impl FnMut(usize, Key, &mut Item)) {
+        access_stats::record(self, AccessKind::Iteration);
+
+        for index in 0..self.data.len() {
+            let key = self.data.ids()[index];
+            f(index, key, &mut self.data.data_mut()[index]);
+        }
+    }
+
+    /// Stable sort of the dense key and item arrays by `compare`, moving each key along with its
+    /// item so that every key still addresses the same item. Unlike
+    /// [MutItemSliceStorage::sort_by], which sorts the items alone and so reassigns them to
+    /// other keys.
+    //
+    // # Internal Design
+    //
+    // The sorted order is computed over positions first and then applied by walking each cycle of
+    // the permutation with the sparse set's own swaps, which keep its key lookup up to date.
+    pub fn sort_pairs_by(
+        &mut self,
+        mut compare: impl FnMut((Key, &Item), (Key, &Item)) -> Ordering,
+    ) {
+        let ids = self.data.ids();
+        let items = self.data.data();
+
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.sort_by(|&a, &b| compare((ids[a], &items[a]), (ids[b], &items[b])));
+
+        // Position i receives the pair that was at order[i]
+        let mut placed = vec![false; order.len()];
+
+        for start in 0..order.len() {
+            let mut current = start;
+
+            while !placed[current] {
+                placed[current] = true;
+
+                let next = order[current];
+                if next == start {
+                    break;
+                }
+
+                self.data.swap_by_index(current, next);
+                current = next;
+            }
+        }
+    }
+}
+
+impl<Key, Item> SparseSetVecStorage<Key, Item>
+where
+    Key: SortableKey,
+    Item: ItemTrait,
+{
+    /// Reorder the dense arrays into ascending key order, so that iteration walks the items in the
+    /// order their keys were allocated. Restores iteration locality after heavy insert and remove
+    /// churn, see [Self::fragmentation] for when it is worth doing.
+    pub fn sort_dense(&mut self) {
+        self.sort_pairs_by(|(a, _), (b, _)| a.cmp(&b));
+    }
+
+    /// The fraction of adjacent dense entries whose keys are out of ascending order. 0.0 for a
+    /// storage in key order, around 0.5 once removals and inserts have shuffled it.
+    pub fn fragmentation(&self) -> f32 {
+        let ids = self.data.ids();
+
+        if ids.len() < 2 {
+            return 0.0;
+        }
+
+        let out_of_order = ids.windows(2).filter(|pair| pair[1] < pair[0]).count();
+
+        out_of_order as f32 / (ids.len() - 1) as f32
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -62,6 +179,42 @@ where
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// Serde impl
+////////////////////////////////////////////////////////////////////////////////
+
+// The interior [SparseSetVec] isn't serializable so the storage is written as a sequence of
+// (key, item) pairs in dense order and rebuilt by inserting them which keeps the dense order.
+
+#[cfg(feature = "serde")]
+impl<Key, Item> serde::Serialize for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait + serde::Serialize,
+    Item: ItemTrait + serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Key, Item> serde::Deserialize<'de> for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait + serde::Deserialize<'de>,
+    Item: ItemTrait + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries: Vec<(Key, Item)> = serde::Deserialize::deserialize(deserializer)?;
+
+        let mut storage = Self::new();
+        for (key, item) in entries {
+            storage.insert(key, item);
+        }
+
+        Ok(storage)
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Storage trait family impl
 ////////////////////////////////////////////////////////////////////////////////
@@ -74,6 +227,12 @@ where
     fn len(&self) -> usize {
         self.data.len()
     }
+
+    fn memory_size(&self) -> usize {
+        // Each item has a dense key and an entry in the sparse vec. The sparse vec is sized by the
+        // largest key so this undercounts storages with large gaps between keys.
+        size_of::<Self>() + self.data.len() * (2 * size_of::<Key>() + size_of::<Item>())
+    }
 }
 
 impl<Key, Item> KeyTypeIdNoSelf for SparseSetVecStorage<Key, Item>
@@ -104,10 +263,11 @@ where
     type Key = Key;
 
     fn contains(&self, key: Self::Key) -> bool {
-        self.data.contains(key)
+        self.dense_index_of(key).is_some()
     }
 
     fn keys_iter(&self) -> Box<dyn Iterator<Item=Self::Key> + '_> {
+        access_stats::record(self, AccessKind::Iteration);
         Box::new(self.data.ids().iter().cloned())
     }
 }
@@ -126,10 +286,14 @@ where
     Item: ItemTrait,
 {
     fn get(&self, key: Key) -> Option<&Item> {
-        self.data.get(key)
+        access_stats::record(self, AccessKind::Get);
+
+        let index = self.dense_index_of(key)?;
+        Some(&self.data.data()[index])
     }
 
     fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_> {
+        access_stats::record(self, AccessKind::Iteration);
 
         let iter = self.data.data().iter();
 
@@ -137,6 +301,7 @@ where
     }
 
     fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_> {
+        access_stats::record(self, AccessKind::Iteration);
 
         let ids_iter = self.data.ids().iter().cloned();
         let item_iter = self.data.data();
@@ -147,12 +312,32 @@ where
     }
 }
 
+impl<Key, Item> ForEachStorage for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn for_each_item(&self, f: impl FnMut(&Self::Item)) {
+        access_stats::record(self, AccessKind::Iteration);
+        self.data.data().iter().for_each(f);
+    }
+
+    fn for_each_key_item(&self, mut f: impl FnMut(Self::Key, &Self::Item)) {
+        access_stats::record(self, AccessKind::Iteration);
+
+        for (key, item) in iter::zip(self.data.ids(), self.data.data()) {
+            f(*key, item);
+        }
+    }
+}
+
 impl<Key, Item> ItemSliceStorage for SparseSetVecStorage<Key, Item>
 where
     Key: KeyTrait,
     Item: ItemTrait,
 {
     fn as_item_slice(&self) -> &[Item] {
+        access_stats::record(self, AccessKind::Iteration);
         self.data.data()
     }
 }
@@ -163,24 +348,116 @@ where
     Item: ItemTrait,
 {
     fn as_mut_slice(&mut self) -> &mut [Item] {
+        access_stats::record(self, AccessKind::Iteration);
         self.data.data_mut()
     }
 }
 
+impl<Key, Item> KeyItemSliceStorage for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_item_slices(&self) -> (&[Key], &[Item]) {
+        access_stats::record(self, AccessKind::Iteration);
+        (self.data.ids(), self.data.data())
+    }
+}
+
+impl<Key, Item> MutKeyItemSliceStorage for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_item_slices_mut(&mut self) -> (Vec<Key>, &mut [Item]) {
+        access_stats::record(self, AccessKind::Iteration);
+
+        (self.data.ids().to_vec(), self.data.data_mut())
+    }
+}
+
+impl<Key, Item> OrderedStorage for SparseSetVecStorage<Key, Item>
+where
+    Key: SortableKey,
+    Item: ItemTrait,
+{
+    /// Walks a copy of the dense order sorted by key unless the dense arrays are already in key
+    /// order, see [Self::sort_dense]
+    fn cursor(&self) -> StorageCursor<'_, Key, Item> {
+        access_stats::record(self, AccessKind::Iteration);
+
+        let keys = CursorKeys::Stored { keys: self.data.ids(), compare: Key::cmp };
+        StorageCursor::new(keys, CursorItems::Slice(self.data.data()), self.data.len())
+    }
+}
+
 impl<Key, Item> MutKeyItemStorage for SparseSetVecStorage<Key, Item>
 where
     Key: KeyTrait,
     Item: ItemTrait,
 {
+    /// Insert or replace the item of the key.
+    /// # Panics
+    /// If a newer generation of the key is stored, see [Self::try_insert]. With the `no_panic`
+    /// feature the insert is skipped instead.
     fn insert(&mut self, key: Key, item: Item) {
+        panic_or_skip(self.try_insert(key, item));
+    }
+
+    /// Insert or replace the item of the key. A key of a newer generation than the stored key
+    /// replaces it, and a key of an older generation fails as it is stale.
+    fn try_insert(&mut self, key: Key, item: Item) -> SimpleResult<()> {
+        access_stats::record(self, AccessKind::Insert);
+
+        self.check_insert(key)?;
+
+        if let Some(index) = self.data.get_index(key) {
+            let stored = self.data.ids()[index];
+
+            // The sparse set keeps the stored key when replacing an item
+            if stored != key {
+                self.data.remove(stored);
+            }
+        }
+
         self.data.insert(key, item);
+
+        Ok(())
+    }
+
+    /// Fails for a key of an older generation than the stored key
+    fn check_insert(&self, key: Key) -> SimpleResult<()> {
+        if let Some(index) = self.data.get_index(key) {
+            let stored = self.data.ids()[index];
+
+            if stored.generation() > key.generation() {
+                return Err(StorageError::new(
+                    ErrorKind::KeyInvalid,
+                    format!(
+                        "Failed to insert with the stale key {key:?} as {stored:?} replaced it"
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
     }
 
     fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item> {
-        self.data.get_mut(key)
+        access_stats::record(self, AccessKind::Get);
+
+        let index = self.dense_index_of(key)?;
+        Some(&mut self.data.data_mut()[index])
     }
 }
 
+impl<Key, Item> ExtendStorage for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
 impl<Key, Item> ClearableStorage for SparseSetVecStorage<Key, Item>
 where
     Key: KeyTrait,
@@ -191,10 +468,56 @@ where
     }
 }
 
-impl<Key, Item> AsBytesBorrowed for SparseSetVecStorage<Key, Item>
+impl<Key, Item> RemovableStorage for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Key) -> Option<Item> {
+        // A stale key must not remove the newer key stored at its index
+        self.dense_index_of(key)?;
+        self.data.remove(key)
+    }
+}
+
+impl<Key, Item> StableRemovableStorage for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
+impl<Key, Item> AllocKeyStorage for SparseSetVecStorage<Key, Item>
 where
     Key: KeyTrait,
     Item: ItemTrait,
+{
+    /// The lowest free key from the item count up, which is the item count unless items were
+    /// removed below keys that are still in use. A key whose index holds another generation isn't
+    /// free.
+    fn try_insert_alloc(&mut self, item: Item) -> SimpleResult<Key> {
+        // Each index that is taken holds one of the items, so this ends within len + 1 steps
+        let mut index = self.data.len();
+        let key = loop {
+            let key = try_index_to_key(index)?;
+
+            if self.data.get_index(key).is_none() {
+                break key;
+            }
+
+            index += 1;
+        };
+
+        self.try_insert(key, item)?;
+
+        Ok(key)
+    }
+}
+
+impl<Key, Item> AsBytesBorrowed for SparseSetVecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ZeroCopyItem,
 {
     fn byte_slice(&self) -> &[u8] {
         unsafe {
@@ -233,4 +556,140 @@ mod tests {
             println!("{:?}", (id, item));
         }
     }
+
+    #[test]
+    fn sort_test() {
+        use crate::storage_traits::{ItemSliceStorage, KeyStorage, MutItemSliceStorage};
+
+        let mut storage: SparseSetVecStorage<usize, i32> = SparseSetVecStorage::new();
+        for (key, item) in [(3, 30), (9, 10), (1, 50), (4, 20), (7, 40)] {
+            storage.insert(key, item);
+        }
+
+        // Keys move with their items
+        storage.sort_pairs_by(|(_, a), (_, b)| a.cmp(b));
+        assert_eq!(storage.as_item_slice(), &[10, 20, 30, 40, 50]);
+        assert_eq!(storage.keys_iter().collect::<Vec<_>>(), vec![9, 4, 3, 7, 1]);
+        assert_eq!(storage.get(1), Some(&50));
+        assert_eq!(storage.get(9), Some(&10));
+
+        // Sorting the items alone leaves the keys in place
+        storage.sort_unstable_by_key(|item| -item);
+        assert_eq!(storage.as_item_slice(), &[50, 40, 30, 20, 10]);
+        assert_eq!(storage.get(9), Some(&50));
+    }
+
+    #[test]
+    fn dense_index_test() {
+        use crate::storage_traits::{ItemSliceStorage, RemovableStorage};
+
+        let mut positions: SparseSetVecStorage<u32, f32> = SparseSetVecStorage::new();
+        for (key, item) in [(10, 1.0), (3, 2.0), (7, 3.0)] {
+            positions.insert(key, item);
+        }
+        positions.remove(10);
+
+        assert_eq!(positions.dense_index_of(7), Some(0));
+        assert_eq!(positions.dense_index_of(10), None);
+        assert_eq!(positions.key_at_dense(1), Some(3));
+        assert_eq!(positions.key_at_dense(2), None);
+        assert_eq!(positions.dense_keys(), &[7, 3]);
+
+        // Compute on the dense slice and write the results back to the same keys elsewhere
+        let doubled: Vec<f32> = positions.as_item_slice().iter().map(|item| item * 2.0).collect();
+        let mut velocities: SparseSetVecStorage<u32, f32> = SparseSetVecStorage::new();
+        for (&key, &item) in positions.dense_keys().iter().zip(&doubled) {
+            velocities.insert(key, item);
+        }
+        assert_eq!(velocities.get(3), Some(&4.0));
+
+        positions.for_each_dense_mut(|index, _, item| *item = doubled[index]);
+        assert_eq!(positions.get(7), Some(&6.0));
+    }
+
+    #[test]
+    fn key_item_slices_test() {
+        use crate::storage_traits::{KeyItemSliceStorage, MutKeyItemSliceStorage};
+
+        let mut weights: SparseSetVecStorage<u32, f32> = SparseSetVecStorage::new();
+        for (key, item) in [(4, 0.5), (1, 0.25), (9, 1.0)] {
+            weights.insert(key, item);
+        }
+
+        let (keys, items) = weights.key_item_slices();
+        assert_eq!(keys, &[4, 1, 9]);
+        assert_eq!(items, &[0.5, 0.25, 1.0]);
+
+        let (keys, items) = weights.key_item_slices_mut();
+        for i in 0..keys.len() {
+            items[i] += keys[i] as f32;
+        }
+        assert_eq!(weights.get(4), Some(&4.5));
+        assert_eq!(weights.get(9), Some(&10.0));
+    }
+
+    #[test]
+    fn sort_dense_test() {
+        use crate::storage_traits::{KeyStorage, RemovableStorage};
+
+        let mut storage: SparseSetVecStorage<usize, i32> = SparseSetVecStorage::new();
+        for key in 0..8 {
+            storage.insert(key, key as i32 * 10);
+        }
+        assert_eq!(storage.fragmentation(), 0.0);
+
+        // Removals swap the last entries into the holes
+        storage.remove(1);
+        storage.remove(4);
+        storage.insert(1, 11);
+        assert!(storage.fragmentation() > 0.0);
+
+        storage.sort_dense();
+        assert_eq!(storage.fragmentation(), 0.0);
+        assert_eq!(storage.keys_iter().collect::<Vec<_>>(), vec![0, 1, 2, 3, 5, 6, 7]);
+        assert_eq!(storage.get(1), Some(&11));
+        assert_eq!(storage.get(7), Some(&70));
+    }
+
+    #[test]
+    fn insert_alloc_test() {
+        use crate::storage_traits::{AllocKeyStorage, RemovableStorage};
+
+        let mut storage: SparseSetVecStorage<u8, i32> = SparseSetVecStorage::new();
+        assert_eq!(storage.insert_alloc(10), 0);
+        assert_eq!(storage.insert_alloc(11), 1);
+        storage.insert(3, 13);
+
+        // Starts from the item count and skips keys that are in use
+        assert_eq!(storage.insert_alloc(14), 4);
+        assert_eq!(storage.insert_alloc(15), 5);
+
+        // Freed keys are reused once the count drops to them
+        storage.remove(4);
+        storage.remove(0);
+        assert_eq!(storage.insert_alloc(16), 4);
+        assert_eq!(storage.get(4), Some(&16));
+
+        // Fails once no free key fits the Key type
+        let mut full: SparseSetVecStorage<u8, i32> = SparseSetVecStorage::new();
+        for key in 0..=u8::MAX {
+            full.insert(key, 0);
+        }
+        assert!(full.try_insert_alloc(0).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_test() {
+        let mut storage: SparseSetVecStorage<usize, i32> = SparseSetVecStorage::new();
+        storage.insert(7, 70);
+        storage.insert(2, 20);
+
+        let json = serde_json::to_string(&storage).unwrap();
+        let restored: SparseSetVecStorage<usize, i32> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.get(7), Some(&70));
+        assert_eq!(restored.get(2), Some(&20));
+        assert_eq!(restored.item_iter().collect::<Vec<_>>(), vec![&70, &20]);
+    }
 }
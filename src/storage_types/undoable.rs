@@ -0,0 +1,541 @@
+//! Bounded undo / redo history for mutable storages.
+//!
+//! [UndoableStorage] wraps a storage and records the inverse of every `insert`, `get_mut`,
+//! `remove` and `clear` so that edits can be undone and redone without snapshotting the whole
+//! storage per action:
+//!
+//! ```ignore
+//! let mut storage = UndoableStorage::new(HashMapStorage::<u32, Node>::new(), 100);
+//!
+//! storage.begin_action();
+//! storage.insert(1, node_a);
+//! storage.insert(2, node_b);
+//! storage.end_action();
+//!
+//! storage.undo(); // Removes both nodes
+//! storage.redo();
+//! ```
+//!
+//! Each storage has its own history. An editor undoing across storages keeps a list of which
+//! storages each of its actions touched and undoes those.
+//!
+//! The wrapped storage must keep every other key where it is on an insert or remove, as map like
+//! and sparse storages do, see [StableRemovableStorage]. [VecStorage](super::VecStorage) shifts the
+//! items after the key instead, so an undone edit would restore items at the wrong keys.
+//
+// # Internal Design
+//
+// - An edit records the item at a key before and after the change. The item after a `get_mut` is
+//   unknown when the reference is handed out, so it is read when the edit is undone. Every later
+//   edit has been undone by then so the current item is the one the `get_mut` left behind.
+// - Restoring "no item at this key" needs a remove, which is why the wrapped storage must be a
+//   [RemovableStorage].
+// - Edits are undone per key, which assumes that an edit only changes the item at its own key.
+//   Undoing a shifting insert or remove would need the shift undone too, so shifting storages are
+//   ruled out by the [StableRemovableStorage] bound rather than tracking it.
+// - The oldest action is dropped once the history is full, making the history bounded in actions
+//   rather than in memory. Recording a new action drops the redo history as usual.
+
+use std::{any::TypeId, collections::VecDeque};
+
+use crate::{
+    storage_traits::{
+        ClearableStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyItemStorage, KeyStorage,
+        KeyTrait, KeyTypeIdNoSelf, MutKeyItemStorage, RemovableStorage, StableRemovableStorage,
+        Storage,
+    },
+    Arw, SimpleResult,
+};
+
+#[derive(Clone, Debug)]
+enum Edit<Key, Item>
+{
+    /// The item at `key` changed from `before` to `after`, where None is no item. `after` is None
+    /// and `after_unknown` true for a `get_mut` that hasn't been undone yet.
+    Set
+    {
+        key: Key,
+        before: Option<Item>,
+        after: Option<Item>,
+        after_unknown: bool,
+    },
+
+    Clear
+    {
+        items: Vec<(Key, Item)>
+    },
+}
+
+/// A storage wrapper with undo and redo, see the [module docs](self)
+#[derive(Clone, Debug)]
+pub struct UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    storage: S,
+
+    undo_history: VecDeque<Vec<Edit<Key, Item>>>,
+    redo_history: Vec<Vec<Edit<Key, Item>>>,
+
+    /// Edits of the action started by [UndoableStorage::begin_action]
+    open_action: Option<Vec<Edit<Key, Item>>>,
+
+    max_actions: usize,
+}
+
+impl<S, Key, Item> UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Wrap `storage`, keeping the last `max_actions` actions
+    pub fn new(storage: S, max_actions: usize) -> Self
+    {
+        Self {
+            storage,
+            undo_history: VecDeque::new(),
+            redo_history: Vec::new(),
+            open_action: None,
+            max_actions,
+        }
+    }
+
+    pub fn inner(&self) -> &S
+    {
+        &self.storage
+    }
+
+    /// Unwrap the storage, dropping the history
+    pub fn into_inner(self) -> S
+    {
+        self.storage
+    }
+
+    /// Group the edits made until [UndoableStorage::end_action] into a single action. Without an
+    /// open action every edit is an action of its own.
+    pub fn begin_action(&mut self)
+    {
+        self.end_action();
+        self.open_action = Some(Vec::new());
+    }
+
+    pub fn end_action(&mut self)
+    {
+        if let Some(edits) = self.open_action.take()
+        {
+            self.push_action(edits);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool
+    {
+        !self.undo_history.is_empty() || self.open_action.as_ref().is_some_and(|a| !a.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool
+    {
+        !self.redo_history.is_empty()
+    }
+
+    /// Undo the most recent action, ending an open action first. Returns false if there was
+    /// nothing to undo.
+    pub fn undo(&mut self) -> bool
+    {
+        self.end_action();
+
+        let Some(mut edits) = self.undo_history.pop_back()
+        else
+        {
+            return false;
+        };
+
+        for edit in edits.iter_mut().rev()
+        {
+            match edit
+            {
+                Edit::Set {
+                    key,
+                    before,
+                    after,
+                    after_unknown,
+                } =>
+                {
+                    if *after_unknown
+                    {
+                        *after = self.storage.get(*key).cloned();
+                        *after_unknown = false;
+                    }
+
+                    self.set(*key, before.clone());
+                }
+                Edit::Clear { items } =>
+                {
+                    for (key, item) in items.iter()
+                    {
+                        self.storage.insert(*key, item.clone());
+                    }
+                }
+            }
+        }
+
+        self.redo_history.push(edits);
+
+        true
+    }
+
+    /// Redo the most recently undone action. Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> bool
+    {
+        self.end_action();
+
+        let Some(edits) = self.redo_history.pop()
+        else
+        {
+            return false;
+        };
+
+        for edit in &edits
+        {
+            match edit
+            {
+                Edit::Set { key, after, .. } => self.set(*key, after.clone()),
+                Edit::Clear { .. } => self.storage.clear(),
+            }
+        }
+
+        self.undo_history.push_back(edits);
+
+        true
+    }
+
+    /// Drop the undo and redo history
+    pub fn clear_history(&mut self)
+    {
+        self.undo_history.clear();
+        self.redo_history.clear();
+        self.open_action = self.open_action.as_ref().map(|_| Vec::new());
+    }
+
+    fn set(&mut self, key: Key, item: Option<Item>)
+    {
+        match item
+        {
+            Some(item) => match self.storage.get_mut(key)
+            {
+                Some(current) => *current = item,
+                None => self.storage.insert(key, item),
+            },
+            None =>
+            {
+                self.storage.remove(key);
+            }
+        }
+    }
+
+    fn record(&mut self, edit: Edit<Key, Item>)
+    {
+        self.redo_history.clear();
+
+        match &mut self.open_action
+        {
+            Some(edits) => edits.push(edit),
+            None => self.push_action(vec![edit]),
+        }
+    }
+
+    fn push_action(&mut self, edits: Vec<Edit<Key, Item>>)
+    {
+        if edits.is_empty() || self.max_actions == 0
+        {
+            return;
+        }
+
+        if self.undo_history.len() == self.max_actions
+        {
+            self.undo_history.pop_front();
+        }
+
+        self.undo_history.push_back(edits);
+    }
+}
+
+impl<S, Key, Item> From<UndoableStorage<S, Key, Item>> for Arw<dyn Storage>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn from(value: UndoableStorage<S, Key, Item>) -> Self
+    {
+        let storage = std::sync::Arc::new(crate::Rw::new(value));
+        let storage: Arw<dyn Storage> = storage;
+        storage
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Storage trait family impl
+////////////////////////////////////////////////////////////////////////////////
+
+impl<S, Key, Item> Storage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn len(&self) -> usize
+    {
+        self.storage.len()
+    }
+}
+
+impl<S, Key, Item> KeyTypeIdNoSelf for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<Key>()
+    }
+}
+
+impl<S, Key, Item> ItemTypeIdNoSelf for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<Item>()
+    }
+}
+
+impl<S, Key, Item> KeyStorage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Key = Key;
+
+    fn contains(&self, key: Self::Key) -> bool
+    {
+        self.storage.contains(key)
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_>
+    {
+        self.storage.keys_iter()
+    }
+}
+
+impl<S, Key, Item> ItemStorage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    type Item = Item;
+}
+
+impl<S, Key, Item> KeyItemStorage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get(&self, key: Self::Key) -> Option<&Self::Item>
+    {
+        self.storage.get(key)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_>
+    {
+        self.storage.item_iter()
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
+    {
+        self.storage.key_item_iter()
+    }
+}
+
+impl<S, Key, Item> MutKeyItemStorage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item>
+    {
+        let before = self.storage.get(key)?.clone();
+
+        self.record(Edit::Set {
+            key,
+            before: Some(before),
+            after: None,
+            after_unknown: true,
+        });
+
+        self.storage.get_mut(key)
+    }
+
+    fn insert(&mut self, key: Self::Key, item: Self::Item)
+    {
+        self.record(Edit::Set {
+            key,
+            before: self.storage.get(key).cloned(),
+            after: Some(item.clone()),
+            after_unknown: false,
+        });
+
+        self.storage.insert(key, item);
+    }
+
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        let before = self.storage.get(key).cloned();
+        self.storage.try_insert(key, item.clone())?;
+
+        self.record(Edit::Set {
+            key,
+            before,
+            after: Some(item),
+            after_unknown: false,
+        });
+
+        Ok(())
+    }
+
+    fn check_insert(&self, key: Self::Key) -> SimpleResult<()>
+    {
+        self.storage.check_insert(key)
+    }
+}
+
+impl<S, Key, Item> ClearableStorage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn clear(&mut self)
+    {
+        let items = self
+            .storage
+            .key_item_iter()
+            .map(|(key, item)| (key, item.clone()))
+            .collect();
+
+        self.record(Edit::Clear { items });
+        self.storage.clear();
+    }
+}
+
+impl<S, Key, Item> RemovableStorage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn remove(&mut self, key: Self::Key) -> Option<Self::Item>
+    {
+        let item = self.storage.remove(key)?;
+
+        self.record(Edit::Set {
+            key,
+            before: Some(item.clone()),
+            after: None,
+            after_unknown: false,
+        });
+
+        Some(item)
+    }
+}
+
+impl<S, Key, Item> StableRemovableStorage for UndoableStorage<S, Key, Item>
+where
+    S: StableRemovableStorage<Key = Key, Item = Item>,
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::UndoableStorage;
+    use crate::{
+        storage_traits::{
+            ClearableStorage, KeyItemStorage, MutKeyItemStorage, RemovableStorage, Storage,
+        },
+        storage_handle::builder,
+        storage_types::HashMapStorage,
+    };
+
+    #[test]
+    fn undo_redo_test()
+    {
+        let mut storage = UndoableStorage::new(HashMapStorage::<u32, i32>::new(), 10);
+
+        storage.begin_action();
+        storage.insert(1, 10);
+        storage.insert(2, 20);
+        storage.end_action();
+
+        *storage.get_mut(1).unwrap() = 11;
+        storage.remove(2);
+
+        assert!(storage.undo());
+        assert_eq!(storage.get(2), Some(&20));
+        assert!(storage.undo());
+        assert_eq!(storage.get(1), Some(&10));
+        assert!(storage.undo());
+        assert!(storage.is_empty());
+        assert!(!storage.undo());
+
+        assert!(storage.redo());
+        assert!(storage.redo());
+        assert_eq!(storage.get(1), Some(&11));
+
+        // A new edit drops the redo history
+        storage.clear();
+        assert!(!storage.redo());
+        assert!(storage.undo());
+        assert_eq!(storage.get(1), Some(&11));
+        assert_eq!(storage.get(2), Some(&20));
+    }
+
+    #[test]
+    fn bounded_history_test()
+    {
+        let mut storage = UndoableStorage::new(HashMapStorage::<u32, i32>::new(), 2);
+
+        for key in 0..3
+        {
+            storage.insert(key, 0);
+        }
+
+        assert!(storage.undo());
+        assert!(storage.undo());
+        assert!(!storage.undo());
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn cast_test()
+    {
+        let handle = builder(UndoableStorage::new(HashMapStorage::<u32, i32>::new(), 10)).build();
+
+        assert!(handle.clone().cast_to_key_storage::<u32, i32>().is_ok());
+        assert!(handle.clone().cast_to_getitem_storage::<u32, i32>().is_ok());
+        assert!(handle.clone().cast_to_mut_getitem_storage::<u32, i32>().is_ok());
+        assert!(handle.cast_to_removable_storage::<u32, i32>().is_ok());
+    }
+}
@@ -1,16 +1,25 @@
 use crate::storage_traits::{
-    ItemSliceStorage, ItemStorage, MutItemSliceStorage, ItemTypeIdNoSelf, KeyTypeIdNoSelf, ItemTrait, KeyItemStorage, KeyStorage, Storage, AsFloatVec
+    ClearableStorage, ForEachStorage, MutKeyItemStorage,
+    ItemSliceStorage, ItemStorage, MutItemSliceStorage, ItemTypeIdNoSelf, KeyTypeIdNoSelf, ItemTrait, KeyItemStorage, KeyStorage, Storage, AsFloatVec,
+    MaybeSendSync,
 };
 
 use core::slice;
 use std::any::TypeId;
 use std::{fmt::Debug, marker::PhantomData};
 
-use super::{key_to_index, index_to_key, KeyTrait};
+use super::{
+    assert_index_key, check_index_key, index_to_key, try_key_to_index, KeyRange, KeyTrait,
+};
+use crate::storage_handle::access_stats::{self, AccessKind};
+use crate::{error::panic_or_skip, ErrorKind, SimpleResult, StorageError};
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValStorage<Key, Item> {
     pub data: Item,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
     key_phantom: PhantomData<Key>,
 }
 
@@ -19,13 +28,26 @@ where
     Key: KeyTrait,
 {
     pub fn new(val: Item) -> Self {
-        assert!(Key::supports_index());
+        assert_index_key::<Key>();
 
         Self {
             data: val,
             key_phantom: <_>::default(),
         }
     }
+
+    /// Like [Self::new] but fails instead of panicking if the Key type can't be used as an index
+    pub fn try_new(val: Item) -> SimpleResult<Self> {
+        check_index_key::<Key>()?;
+
+        Ok(Self::new(val))
+    }
+
+    /// The sole key of the storage. Unlike [KeyStorage::keys_iter] this doesn't allocate.
+    pub fn keys(&self) -> KeyRange<Key> {
+        access_stats::record(self, AccessKind::Iteration);
+        KeyRange::new(0..1)
+    }
 }
 
 impl<Key, Item> Storage for ValStorage<Key, Item>
@@ -73,6 +95,7 @@ where
 {
     /// Returns the value as a single item slice
     fn as_item_slice(&self) -> &[Item] {
+        access_stats::record(self, AccessKind::Iteration);
         slice::from_ref(&self.data)
     }
 }
@@ -83,6 +106,7 @@ where
     Item: ItemTrait,
 {
     fn as_mut_slice(&mut self) -> &mut [Item] {
+        access_stats::record(self, AccessKind::Iteration);
         slice::from_mut(&mut self.data)
     }
 }
@@ -99,14 +123,12 @@ where
     type Key = Key;
 
     fn contains(&self, index: Self::Key) -> bool {
-        0 == key_to_index(index)
+        matches!(try_key_to_index(index), Ok(0))
     }
 
     fn keys_iter(&self) -> Box<dyn Iterator<Item=Self::Key> + '_> {
-
         // Returns an iterator that will return 0 for the sole key that this has and then exit
-        let range_iter = (0..1).map(|v| index_to_key(v));
-        Box::new(range_iter)
+        Box::new(self.keys())
     }
 }
 
@@ -116,9 +138,8 @@ where
     Item: ItemTrait,
 {
     fn get(&self, key: Self::Key) -> Option<&Item> {
-        let index: usize = key_to_index(key);
-
-        if index == 0 {
+        access_stats::record(self, AccessKind::Get);
+        if matches!(try_key_to_index(key), Ok(0)) {
             Some(&self.data)
         } else {
             None
@@ -126,12 +147,14 @@ where
     }
 
     fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_> {
+        access_stats::record(self, AccessKind::Iteration);
 
         let iter = self .as_item_slice().iter();
         Box::new(iter)
     }
 
     fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_> {
+        access_stats::record(self, AccessKind::Iteration);
 
         let iter = self .as_item_slice().iter()
             .enumerate()
@@ -141,12 +164,82 @@ where
     }
 }
 
+impl<Key, Item> ForEachStorage for ValStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn for_each_item(&self, mut f: impl FnMut(&Self::Item)) {
+        access_stats::record(self, AccessKind::Iteration);
+        f(&self.data);
+    }
+
+    fn for_each_key_item(&self, mut f: impl FnMut(Self::Key, &Self::Item)) {
+        access_stats::record(self, AccessKind::Iteration);
+        f(index_to_key(0), &self.data);
+    }
+}
+
+impl<Key, Item> MutKeyItemStorage for ValStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn get_mut(&mut self, key: Self::Key) -> Option<&mut Item> {
+        access_stats::record(self, AccessKind::Get);
+        if matches!(try_key_to_index(key), Ok(0)) {
+            Some(&mut self.data)
+        } else {
+            None
+        }
+    }
+
+    /// Replaces the value
+    /// # Panics
+    /// If [Self::try_insert] fails. With the `no_panic` feature the insert is skipped instead.
+    fn insert(&mut self, key: Key, item: Item) {
+        panic_or_skip(self.try_insert(key, item));
+    }
+
+    /// Replaces the value. Fails for any key other than 0 as the storage always holds one value.
+    fn try_insert(&mut self, key: Key, item: Item) -> SimpleResult<()> {
+        access_stats::record(self, AccessKind::Insert);
+
+        self.check_insert(key)?;
+        self.data = item;
+
+        Ok(())
+    }
+
+    fn check_insert(&self, key: Key) -> SimpleResult<()> {
+        if !matches!(try_key_to_index(key), Ok(0)) {
+            return Err(StorageError::new(
+                ErrorKind::KeyInvalid,
+                format!("Key {key:?} is not 0, the only key of a ValStorage"),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl<Key, Item> ClearableStorage for ValStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Resets the value to Item::default. The storage still holds one value afterwards.
+    fn clear(&mut self) {
+        self.data = Item::default();
+    }
+}
+
 ////////////////////////////////////////////////////
 
 impl<Key, Item> AsFloatVec for ValStorage<Key, Item>
 where
     Key: KeyTrait,
-    Item: 'static + AsFloatVec + Sync + Send + Debug + Copy + Into<f32>,
+    Item: 'static + AsFloatVec + MaybeSendSync + Debug + Copy + Into<f32>,
 {
     fn as_float_vec(&self) -> Vec<f32> {
         vec![self.data.into()]
@@ -166,4 +259,32 @@ mod tests {
 
         assert_eq!(val, 1);
     }
+
+    #[test]
+    fn write_through_handle_test() {
+        use crate::{
+            storage_handle::{builder, StorageHandle},
+            storage_traits::Storage,
+            ErrorKind,
+        };
+
+        let handle: StorageHandle<dyn Storage> = builder(ValStorage::<u32, f32>::new(0.5)).build();
+        let handle = handle.cast_to_mut_getitem_storage::<u32, f32>().unwrap();
+
+        {
+            let mut guard = handle.try_write().unwrap();
+            guard.insert(0, 2.0);
+            *guard.get_mut(0).unwrap() += 1.0;
+            assert!(guard.get_mut(1).is_none());
+
+            let error = guard.try_insert(1, 4.0).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::KeyInvalid);
+        }
+        assert_eq!(handle.try_read().unwrap().get(0), Some(&3.0));
+
+        handle.try_write().unwrap().clear();
+        let guard = handle.try_read().unwrap();
+        assert_eq!(guard.get(0), Some(&0.0));
+        assert_eq!(guard.len(), 1);
+    }
 }
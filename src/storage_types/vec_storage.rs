@@ -18,27 +18,61 @@
 //   contrast to this, HashMaps and their kind insert at the given key and don't affect any other
 //   keys unless that key was added before.
 //
+// - Gaps left by inserting past the end are filled with Item::default unless a [DefaultFactory] is
+//   set. The factory is shared between clones and isn't serialized or archived, so a loaded storage
+//   fills with Item::default until it is set again.
+//
 // In summary, if true Key Item based semantics are required then a map like storages should be
 // used. For example, this crate could have multiple map like storage types that all share Storage
 // Map traits so there would still be uniformity of traits in those cases which is useful for
 // interchangeability but just not here for a true vec like storage.
 
 use crate::storage_traits::{
-    AsBytesBorrowed, ClearableStorage, ItemSliceStorage, ItemStorage, ItemTrait,
-    MutItemSliceStorage, Storage, ItemTypeIdNoSelf, KeyItemStorage, KeyTypeIdNoSelf, MutKeyItemStorage, KeyStorage
+    AsBytesBorrowed, ClearableStorage, ExtendStorage, ForEachStorage, ItemSliceStorage,
+    ItemStorage, ItemTrait, MutItemSliceStorage, Storage, ItemTypeIdNoSelf, KeyItemStorage,
+    KeyTypeIdNoSelf, MutKeyItemStorage, KeyStorage, ZeroCopyItem, DefaultFactory,
+    AllocKeyStorage, OrderedStorage, RemovableStorage,
 };
+use crate::cursor::{CursorItems, CursorKeys, StorageCursor};
 
-use std::{any::TypeId, marker::PhantomData, mem::size_of};
+use std::{any::TypeId, fmt, marker::PhantomData, mem::size_of, sync::Arc};
 
-use super::{index_to_key, key_to_index, KeyTrait};
+use super::{
+    assert_index_key, check_index_key, index_to_key, try_index_to_key, try_key_to_index, KeyRange,
+    KeyTrait,
+};
+use crate::{error::panic_or_skip, ErrorKind, SimpleResult, StorageError};
+use crate::storage_handle::access_stats::{self, AccessKind};
 
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "rkyv", derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize))]
 pub struct VecStorage<Key, Item> {
     data: Vec<Item>,
 
     // #DESIGN Unlike a normal Vec - Index phantom data is required so that
     // we can make trait objects of this type related to the key type that is used.
+    #[cfg_attr(feature = "serde", serde(skip))]
     index_phantom: PhantomData<Key>,
+
+    #[cfg_attr(feature = "serde", serde(skip))]
+    #[cfg_attr(feature = "rkyv", rkyv(with = rkyv::with::Skip))]
+    default_factory: Option<SharedFactory<Key, Item>>,
+}
+
+/// Clone and Debug for the factory so that they can still be derived for the storage
+struct SharedFactory<Key, Item>(Arc<dyn DefaultFactory<Key, Item>>);
+
+impl<Key, Item> Clone for SharedFactory<Key, Item> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Key, Item> fmt::Debug for SharedFactory<Key, Item> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DefaultFactory")
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -52,16 +86,24 @@ where
     pub fn new() -> Self {
         // Prevent the construction of this type if a non index supporting
         // Key has been passed in.
-        assert!(Key::supports_index());
+        assert_index_key::<Key>();
 
         Self {
             data: <_>::default(),
             index_phantom: <_>::default(),
+            default_factory: None,
         }
     }
 
+    /// Like [Self::new] but fails instead of panicking if the Key type can't be used as an index
+    pub fn try_new() -> SimpleResult<Self> {
+        check_index_key::<Key>()?;
+
+        Ok(Self::new())
+    }
+
     pub fn new_from_iter<I: IntoIterator<Item = Item>>(iter: I) -> Self {
-        assert!(Key::supports_index());
+        assert_index_key::<Key>();
 
         let mut data: Vec<Item> = Default::default();
         data.extend(iter);
@@ -69,9 +111,51 @@ where
         VecStorage {
             data,
             index_phantom: <_>::default(),
+            default_factory: None,
+        }
+    }
+
+    /// Wrap an existing Vec without copying it
+    pub fn from_vec(data: Vec<Item>) -> Self {
+        assert_index_key::<Key>();
+
+        VecStorage {
+            data,
+            index_phantom: <_>::default(),
+            default_factory: None,
         }
     }
 
+    /// Like [Self::from_vec] but fails instead of panicking if the Key type can't be used as an
+    /// index
+    pub fn try_from_vec(data: Vec<Item>) -> SimpleResult<Self> {
+        check_index_key::<Key>()?;
+
+        Ok(Self::from_vec(data))
+    }
+
+    /// Fill the gaps left by inserting past the end with items from `factory` instead of
+    /// Item::default, eg identity transforms for some keys and zero vectors for others
+    pub fn set_default_factory(&mut self, factory: impl DefaultFactory<Key, Item> + 'static) {
+        self.default_factory = Some(SharedFactory(Arc::new(factory)));
+    }
+
+    /// Fill gaps with Item::default again
+    pub fn clear_default_factory(&mut self) {
+        self.default_factory = None;
+    }
+
+    /// Unwrap the inner Vec without copying it
+    pub fn into_vec(self) -> Vec<Item> {
+        self.data
+    }
+
+    /// The keys of all items. Unlike [KeyStorage::keys_iter] this doesn't allocate.
+    pub fn keys(&self) -> KeyRange<Key> {
+        access_stats::record(self, AccessKind::Iteration);
+        KeyRange::new(0..self.data.len())
+    }
+
     // TODO: Consider changing this to Slice syntax and removing the set
     // because Vec doesn't have a set method
     pub fn set(&mut self, index: usize, item: Item) {
@@ -97,9 +181,50 @@ where
         self.data.insert(index, item);
     }
 
+    /// A classic Vec like remove that shifts the items after `index` to the left, so their keys
+    /// move down by one. None if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Option<Item> {
+        (index < self.data.len()).then(|| self.data.remove(index))
+    }
+
+    /// Remove the item at `index` and move the last item into its place. Unlike [Self::remove]
+    /// only the key of the last item changes. None if `index` is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> Option<Item> {
+        (index < self.data.len()).then(|| self.data.swap_remove(index))
+    }
+
+    /// Remove the last item
+    pub fn pop(&mut self) -> Option<Item> {
+        self.data.pop()
+    }
+
+    /// Remove the items from `len` on. Does nothing if the storage is not longer than `len`.
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
     // ---------------------------------------------------
 }
 
+/// Read access to an archived storage without deserializing it, see [crate::interop::rkyv]
+#[cfg(feature = "rkyv")]
+impl<Key, Item> ArchivedVecStorage<Key, Item>
+where
+    Item: rkyv::Archive,
+{
+    pub fn as_item_slice(&self) -> &[rkyv::Archived<Item>] {
+        self.data.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Rust std traits impl
 ////////////////////////////////////////////////////////////////////////////////
@@ -131,6 +256,10 @@ where
     fn len(&self) -> usize {
         self.data.len()
     }
+
+    fn memory_size(&self) -> usize {
+        size_of::<Self>() + self.data.capacity() * size_of::<Item>()
+    }
 }
 
 impl<Key, Item> KeyTypeIdNoSelf for VecStorage<Key, Item>
@@ -169,17 +298,16 @@ where
     type Key = Key;
 
     fn contains(&self, key: Self::Key) -> bool {
-        let index: usize = key_to_index(key);
-        index < self.data.len()
+        try_key_to_index(key).is_ok_and(|index| index < self.data.len())
     }
 
     fn keys_iter(&self) -> Box<dyn Iterator<Item = Self::Key> + '_> {
         // Return the indices as keys by using a simple range iterator
         // Design: Keys need to be returned by value because a VecStorage
         // has no stored keys to return by reference from. Only Indices which
-        // can be converted to Keys transiently during iteration.
-        let range_iter = (0..self.data.len()).map(|v| index_to_key(v));
-        Box::new(range_iter)
+        // can be converted to Keys transiently during iteration. Items pushed past the range
+        // of the Key type have no key so iteration stops there.
+        Box::new(self.keys())
     }
 }
 
@@ -189,20 +317,23 @@ where
     Item: ItemTrait,
 {
     fn get(&self, index: Self::Key) -> Option<&Self::Item> {
-        self.data.get(key_to_index(index))
+        access_stats::record(self, AccessKind::Get);
+        self.data.get(try_key_to_index(index).ok()?)
     }
 
     fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_> {
+        access_stats::record(self, AccessKind::Iteration);
         let iter = self
             .data
             .iter()
             .enumerate()
-            .map(|(index, item)| (index_to_key(index), item));
+            .map_while(|(index, item)| Some((try_index_to_key(index).ok()?, item)));
 
         Box::new(iter)
     }
 
     fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_> {
+        access_stats::record(self, AccessKind::Iteration);
 
         let iter = self
             .data
@@ -212,6 +343,27 @@ where
     }
 }
 
+impl<Key, Item> ForEachStorage for VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn for_each_item(&self, f: impl FnMut(&Self::Item)) {
+        access_stats::record(self, AccessKind::Iteration);
+        self.data.iter().for_each(f);
+    }
+
+    fn for_each_key_item(&self, mut f: impl FnMut(Self::Key, &Self::Item)) {
+        access_stats::record(self, AccessKind::Iteration);
+
+        // Like key_item_iter, items past the range of the Key type have no key
+        for (index, item) in self.data.iter().enumerate() {
+            let Ok(key) = try_index_to_key(index) else { break };
+            f(key, item);
+        }
+    }
+}
+
 impl<Key, Item> MutKeyItemStorage for VecStorage<Key, Item>
 where
     Key: KeyTrait,
@@ -224,29 +376,154 @@ where
     /// #Design
     /// This method uses Clone + Default and is the primary reason for these two
     /// being added into [KeyTrait]
+    /// # Panics
+    /// If [Self::try_insert] fails. With the `no_panic` feature the insert is skipped instead.
     fn insert(&mut self, key: Key, item: Item) {
-        let index: usize = key_to_index(key);
+        panic_or_skip(self.try_insert(key, item));
+    }
 
-        if index > self.data.len() {
-            self.data.resize(index, Item::default());
-        }
+    /// Fails if the key can't be converted to an index or the storage can't grow to it
+    fn try_insert(&mut self, key: Key, item: Item) -> SimpleResult<()> {
+        access_stats::record(self, AccessKind::Insert);
 
+        let index = try_key_to_index(key)?;
+        try_reserve_for_index(&mut self.data, index)?;
+        fill_to(&mut self.data, index, self.default_factory.as_ref());
         self.data.insert(index, item);
+
+        Ok(())
+    }
+
+    /// Fails if the key can't be converted to an index or the storage could never grow to it.
+    /// Running out of memory while growing is only found by [Self::try_insert].
+    fn check_insert(&self, key: Key) -> SimpleResult<()> {
+        let index = try_key_to_index(key)?;
+
+        let fits = index
+            .checked_add(1)
+            .is_some_and(|len| std::alloc::Layout::array::<Item>(len).is_ok());
+
+        if !fits {
+            return Err(StorageError::new(
+                ErrorKind::KeyInvalid,
+                format!("Storage can't grow to index {index}"),
+            ));
+        }
+
+        Ok(())
     }
 
     fn get_mut(&mut self, key: Self::Key) -> Option<&mut Self::Item> {
+        access_stats::record(self, AccessKind::Get);
 
-        let index: usize = key_to_index(key);
+        let index: usize = try_key_to_index(key).ok()?;
         self.data.get_mut(index)
     }
 }
 
+impl<Key, Item> ExtendStorage for VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Resizes once to the largest key and writes the items in place, rather than growing and
+    /// shifting per item as [MutKeyItemStorage::insert] does. Items at keys that are already in
+    /// the storage are replaced and no other item moves. Fails without changing the storage if a
+    /// key can't be converted to an index or the storage can't grow to it.
+    fn set_bulk(&mut self, items: impl IntoIterator<Item = (Key, Item)>) -> SimpleResult<()> {
+        access_stats::record(self, AccessKind::Insert);
+
+        let items = items
+            .into_iter()
+            .map(|(key, item)| Ok((try_key_to_index(key)?, item)))
+            .collect::<SimpleResult<Vec<(usize, Item)>>>()?;
+
+        let Some(last) = items.iter().map(|(index, _)| *index).max() else {
+            return Ok(());
+        };
+
+        if last >= self.data.len() {
+            try_reserve_for_index(&mut self.data, last)?;
+            fill_to(&mut self.data, last + 1, self.default_factory.as_ref());
+        }
+
+        for (index, item) in items {
+            self.data[index] = item;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Key, Item> AllocKeyStorage for VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Appends the item, so its key is the item count
+    fn try_insert_alloc(&mut self, item: Item) -> SimpleResult<Key> {
+        access_stats::record(self, AccessKind::Insert);
+
+        let key = try_index_to_key(self.data.len())?;
+        self.data.push(item);
+
+        Ok(key)
+    }
+}
+
+impl<Key, Item> RemovableStorage for VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    /// Removes with Vec semantics as [VecStorage::remove] does, so the keys of the items after
+    /// `key` move down by one rather than staying as they would in a map like storage
+    fn remove(&mut self, key: Key) -> Option<Item> {
+        VecStorage::remove(self, try_key_to_index(key).ok()?)
+    }
+}
+
+/// Reserve room for an item at `index`, failing instead of panicking when the length
+/// overflows or the allocation fails
+fn try_reserve_for_index<Item>(data: &mut Vec<Item>, index: usize) -> SimpleResult<()> {
+    let additional = index.saturating_sub(data.len()).saturating_add(1);
+
+    data.try_reserve(additional).map_err(|error| {
+        StorageError::with_source(
+            ErrorKind::KeyInvalid,
+            format!("Storage can't grow to index {index}: {error}"),
+            error,
+        )
+    })
+}
+
+/// Grow `data` to `len` with items from the factory, or Item::default without one
+fn fill_to<Key: KeyTrait, Item: ItemTrait>(
+    data: &mut Vec<Item>,
+    len: usize,
+    factory: Option<&SharedFactory<Key, Item>>,
+) {
+    if len <= data.len() {
+        return;
+    }
+
+    match factory {
+        // The indices are below one that converted to a key, so they convert too
+        Some(factory) => {
+            let start = data.len();
+            data.extend((start..len).map(|index| factory.0.default_item(index_to_key(index))));
+        }
+        None => data.resize(len, Item::default()),
+    }
+}
+
 impl<Key, Item> ItemSliceStorage for VecStorage<Key, Item>
 where
     Key: KeyTrait,
     Item: ItemTrait,
 {
     fn as_item_slice(&self) -> &[Item] {
+        access_stats::record(self, AccessKind::Iteration);
         self.data.as_slice()
     }
 }
@@ -257,10 +534,22 @@ where
     Item: ItemTrait,
 {
     fn as_mut_slice(&mut self) -> &mut [Item] {
+        access_stats::record(self, AccessKind::Iteration);
         self.data.as_mut_slice()
     }
 }
 
+impl<Key, Item> OrderedStorage for VecStorage<Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+{
+    fn cursor(&self) -> StorageCursor<'_, Key, Item> {
+        access_stats::record(self, AccessKind::Iteration);
+        StorageCursor::new(CursorKeys::Indices, CursorItems::Slice(&self.data), self.data.len())
+    }
+}
+
 impl<Key, Item> ClearableStorage for VecStorage<Key, Item>
 where
     Key: KeyTrait,
@@ -278,7 +567,7 @@ where
 impl<Key, Item> AsBytesBorrowed for VecStorage<Key, Item>
 where
     Key: KeyTrait,
-    Item: ItemTrait,
+    Item: ZeroCopyItem,
 {
     fn byte_slice(&self) -> &[u8] {
         unsafe {
@@ -335,4 +624,201 @@ mod tests {
             println!("{:?}", item);
         }
     }
+
+    #[test]
+    fn unconvertible_key_test() {
+        use crate::storage_traits::{KeyStorage, MutKeyItemStorage};
+        use crate::storage_types::{try_index_to_key, try_key_to_index};
+
+        assert!(try_key_to_index(-1_i32).is_err());
+        assert!(try_index_to_key::<u8>(256).is_err());
+
+        // Items past the last u8 key can't be addressed so they are left out of key iteration
+        let mut storage: VecStorage<u8, i32> = VecStorage::from_vec(vec![0; 300]);
+        assert_eq!(storage.keys_iter().count(), 256);
+        assert_eq!(storage.key_item_iter().count(), 256);
+        assert_eq!(storage.keys().count(), 256);
+        assert_eq!(storage.keys().last(), Some(255));
+
+        assert!(storage.try_insert(255, 1).is_ok());
+        assert_eq!(storage.get(255), Some(&1));
+    }
+
+    #[test]
+    fn keys_test() {
+        use crate::storage_types::ValStorage;
+
+        let storage: VecStorage<u16, i32> = VecStorage::from_vec(vec![5, 6, 7]);
+        assert_eq!(storage.keys().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(storage.keys().size_hint(), (0, Some(3)));
+
+        let storage: ValStorage<usize, i32> = ValStorage::new(5);
+        assert_eq!(storage.keys().collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn set_bulk_test() {
+        use crate::storage_traits::{ExtendStorage, ItemSliceStorage, MutKeyItemStorage};
+
+        let mut storage: VecStorage<u16, i32> = VecStorage::from_vec(vec![1, 2]);
+        storage.set_bulk([(4, 40), (1, 10), (6, 60)]).unwrap();
+        assert_eq!(storage.as_item_slice(), &[1, 10, 0, 0, 40, 0, 60]);
+
+        // Unlike insert, which shifts the items from the key on
+        let mut storage: VecStorage<u16, i32> = VecStorage::from_vec(vec![1, 2]);
+        storage.insert(1, 10);
+        assert_eq!(storage.as_item_slice(), &[1, 10, 2]);
+
+        // Nothing is written if the storage can't grow to any of the keys
+        let mut storage: VecStorage<usize, i32> = VecStorage::from_vec(vec![1]);
+        assert!(storage.set_bulk([(0, 10), (usize::MAX, 20)]).is_err());
+        assert_eq!(storage.as_item_slice(), &[1]);
+    }
+
+    #[test]
+    fn sort_test() {
+        use crate::storage_traits::{ItemSliceStorage, MutItemSliceStorage};
+
+        let mut storage: VecStorage<usize, i32> = VecStorage::from_vec(vec![3, 1, 2]);
+
+        // Through a trait object as nodes holding dyn handles would
+        let slice: &mut dyn MutItemSliceStorage<Item = i32> = &mut storage;
+        slice.sort_by(&mut |a, b| b.cmp(a));
+        assert_eq!(storage.as_item_slice(), &[3, 2, 1]);
+
+        storage.sort_unstable_by_key(|item| *item);
+        assert_eq!(storage.get(0), Some(&1));
+    }
+
+    #[test]
+    fn adversarial_key_test() {
+        use crate::storage_traits::{MutKeyItemStorage, Storage};
+        use crate::ErrorKind;
+
+        assert!(VecStorage::<u128, i32>::try_new().is_err());
+        assert!(VecStorage::<u128, i32>::try_from_vec(vec![1]).is_err());
+
+        // Wide unsigned keys are indices where usize can hold them
+        #[cfg(target_pointer_width = "64")]
+        {
+            let mut storage: VecStorage<u64, i32> = VecStorage::try_new().unwrap();
+            storage.try_insert(2, 7).unwrap();
+            assert_eq!(storage.get(2), Some(&7));
+            assert!(VecStorage::<u32, i32>::try_new().is_ok());
+        }
+
+        // Keys past the addressable range fail instead of overflowing the allocation
+        let mut storage: VecStorage<usize, i32> = VecStorage::try_new().unwrap();
+        let error = storage.try_insert(usize::MAX, 1).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::KeyInvalid);
+        assert!(storage.is_empty());
+
+        #[cfg(feature = "no_panic")]
+        {
+            storage.insert(usize::MAX, 1);
+            assert!(storage.is_empty());
+
+            // Non index keys are accepted and each key is checked as it is used
+            let mut storage: VecStorage<i32, i32> = VecStorage::new();
+            storage.insert(-1, 1);
+            storage.insert(0, 2);
+            assert_eq!(storage.get(0), Some(&2));
+            assert_eq!(storage.len(), 1);
+        }
+    }
+
+    #[test]
+    fn default_factory_test() {
+        use crate::storage_traits::{ExtendStorage, ItemSliceStorage, MutKeyItemStorage};
+
+        // Channel 0 of every pixel is opaque
+        let mut storage: VecStorage<usize, f32> = VecStorage::new();
+        storage.set_default_factory(|key: usize| if key.is_multiple_of(2) { 1.0 } else { 0.0 });
+
+        storage.insert(3, 5.0);
+        assert_eq!(storage.as_item_slice(), &[1.0, 0.0, 1.0, 5.0]);
+
+        // Clones share the factory
+        let mut copy = storage.clone();
+        copy.set_bulk([(6, 7.0)]).unwrap();
+        assert_eq!(&copy.as_item_slice()[4..], &[1.0, 0.0, 7.0]);
+
+        storage.clear_default_factory();
+        storage.insert(5, 2.0);
+        assert_eq!(&storage.as_item_slice()[4..], &[0.0, 2.0]);
+    }
+
+    #[test]
+    fn insert_alloc_test() {
+        use crate::{
+            storage_handle::{builder, StorageHandle},
+            storage_traits::{AllocKeyStorage, ItemSliceStorage},
+        };
+
+        let handle: StorageHandle<VecStorage<u8, i32>> = builder(VecStorage::<u8, i32>::new())
+            .build()
+            .cast_to_sized_storage()
+            .unwrap();
+
+        // Producers with their own handles to the storage never get the same key
+        let producers = [handle.clone(), handle.clone()];
+        let keys: Vec<u8> = (0..4)
+            .map(|item| producers[item % 2].insert_alloc(item as i32).unwrap())
+            .collect();
+        assert_eq!(keys, [0, 1, 2, 3]);
+        assert_eq!(handle.try_read().unwrap().as_item_slice(), &[0, 1, 2, 3]);
+
+        // Fails once the next key doesn't fit the Key type
+        let mut full: VecStorage<u8, i32> = VecStorage::from_vec(vec![0; 256]);
+        assert!(full.try_insert_alloc(0).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_test() {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![1, 2, 3]);
+
+        let json = serde_json::to_string(&storage).unwrap();
+        let restored: VecStorage<usize, i32> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.item_iter().collect::<Vec<_>>(), vec![&1, &2, &3]);
+    }
+
+    #[cfg(feature = "local")]
+    #[test]
+    fn non_send_item_test() {
+        use std::rc::Rc;
+
+        use crate::storage_handle::{builder, StorageHandle};
+
+        let storage: VecStorage<usize, Rc<i32>> = VecStorage::new_from_iter(vec![Rc::new(1)]);
+        let handle: StorageHandle<VecStorage<usize, Rc<i32>>> =
+            builder(storage).build().cast_to_sized_storage().unwrap();
+
+        assert_eq!(**handle.try_read().unwrap().get(0).unwrap(), 1);
+    }
+
+    #[test]
+    fn remove_test() {
+        use crate::storage_handle::{builder, StorageHandle};
+        use crate::storage_traits::{ItemSliceStorage, Storage};
+
+        let mut storage: VecStorage<u16, i32> = VecStorage::from_vec(vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(storage.remove(1), Some(1));
+        assert_eq!(storage.swap_remove(0), Some(0));
+        assert_eq!(storage.as_item_slice(), &[5, 2, 3, 4]);
+        assert_eq!(storage.pop(), Some(4));
+        assert_eq!(storage.remove(3), None);
+        assert_eq!(storage.swap_remove(3), None);
+
+        storage.truncate(1);
+        assert_eq!(storage.as_item_slice(), &[5]);
+
+        // The trait remove shifts like the inherent one
+        let handle: StorageHandle<dyn Storage> =
+            builder(VecStorage::<u16, i32>::from_vec(vec![7, 8, 9])).build();
+        let handle = handle.cast_to_removable_storage::<u16, i32>().unwrap();
+        assert_eq!(handle.try_write().unwrap().remove(0), Some(7));
+        assert_eq!(handle.try_read().unwrap().get(0), Some(&8));
+    }
 }
@@ -1,16 +1,21 @@
 
-use std::{any::TypeId, marker::PhantomData};
+use std::{any::TypeId, marker::PhantomData, sync::Arc};
 
-use guardian::{ArcRwLockReadGuardian, ArcRwLockWriteGuardian};
+#[cfg(not(feature = "local"))]
 use sendable::SendOption;
 
 use crate::{
     casting::dyn_storage_into_sized,
     storage_traits::{
-        ClearableStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyItemStorage,
-        KeyStorage, KeyTrait, KeyTypeIdNoSelf, MutKeyItemStorage, Storage, ViewStorageSetup,
+        ClearableStorage, ForEachStorage, ItemStorage, ItemTrait, ItemTypeIdNoSelf,
+        KeyItemStorage, KeyStorage, KeyTrait, KeyTypeIdNoSelf, MutKeyItemStorage, OrderedStorage,
+        SortableKey, Storage, ViewStorageSetup,
     },
-    Arw, OArw, SimpleResult, storage_types::key_to_index,
+    cursor::{CursorItems, CursorKeys, StorageCursor},
+    storage_handle::{LockAccess, SET_INPUT_FIX},
+    error::panic_or_skip,
+    sync::{read_storage_arc, write_storage_arc, ArcStorageLockReadGuard, ArcStorageLockWriteGuard},
+    Arw, ErrorKind, Hint, OArw, SimpleResult, StorageError, storage_types::try_key_to_index,
 };
 
 /// Provides a view into any other storage that implements [KeyItemStorage]
@@ -64,6 +69,21 @@ use crate::{
 // parking lot There is a point listed under "possible later goals" which states:
 // "Allow Sending MutexGuards to other threads"
 //
+// With the `local` feature storages don't need to be Send + Sync so the guards are held in a plain
+// Option instead. See [HeldGuard].
+//
+// ## One held guard
+//
+// The read or write guard is held as a single [InputGuard] enum rather than as two options, so
+// that item access resolves the input storage with one SendOption deref and one match instead of
+// trying each guard in turn.
+//
+// ## Shared keys
+//
+// The view keys are an `Arc<[Key]>` rather than a Vec so that a selection of many keys can back
+// several views, or be given to the view again each frame, without being copied. Views never
+// modify their keys so there is no need for copy on write.
+//
 // ## Excluded Trait Implementations
 //
 // [ValSliceAccess] is deliberately not implemented for RefViewStorage.
@@ -82,11 +102,51 @@ where
     Item: ItemTrait,
     InputStorage: KeyItemStorage<Key = Key, Item = Item>,
 {
-    view_keys: Vec<Key>,
+    view_keys: Arc<[Key]>,
     input_storage: OArw<InputStorage>,
 
-    read_guard: SendOption<ArcRwLockReadGuardian<InputStorage>>,
-    write_guard: SendOption<ArcRwLockWriteGuardian<InputStorage>>,
+    input_guard: HeldGuard<InputGuard<InputStorage>>,
+}
+
+/// The guard that a view holds on its input storage while it exists
+enum InputGuard<InputStorage>
+where
+    InputStorage: Storage,
+{
+    Read(ArcStorageLockReadGuard<InputStorage>),
+    Write(ArcStorageLockWriteGuard<InputStorage>),
+}
+
+impl<InputStorage> InputGuard<InputStorage>
+where
+    InputStorage: Storage,
+{
+    fn storage(&self) -> &InputStorage
+    {
+        match self
+        {
+            InputGuard::Read(guard) => guard,
+            InputGuard::Write(guard) => guard,
+        }
+    }
+}
+
+/// Holds an input storage guard for as long as a view exists
+#[cfg(not(feature = "local"))]
+type HeldGuard<G> = SendOption<G>;
+#[cfg(feature = "local")]
+type HeldGuard<G> = Option<G>;
+
+#[cfg(not(feature = "local"))]
+fn hold_guard<G>(guard: G) -> HeldGuard<G>
+{
+    SendOption::new(Some(guard))
+}
+
+#[cfg(feature = "local")]
+fn hold_guard<G>(guard: G) -> HeldGuard<G>
+{
+    Some(guard)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -104,39 +164,59 @@ where
         Self {
             view_keys: <_>::default(),
             input_storage: <_>::default(),
-            read_guard: <_>::default(),
-            write_guard: <_>::default(),
+            input_guard: <_>::default(),
         }
     }
 
+    /// The input storage, if the view holds a guard on it
+    #[inline]
+    fn input(&self) -> Option<&InputStorage>
+    {
+        self.input_guard.as_ref().map(InputGuard::storage)
+    }
+
     fn as_keys_slice(&self) -> &[Key]
     {
-        self.view_keys.as_slice()
+        &self.view_keys
+    }
+
+    /// Key of the input storage that a view key maps to. None for keys that are out of range or
+    /// can't be converted to an index.
+    fn input_key(&self, key: Key) -> Option<Key>
+    {
+        self.view_keys.get(try_key_to_index(key).ok()?).copied()
     }
 
     /// Create an iterator returns tuples of (Key, &Item).
     fn key_item_iter_static(&self) -> KeysToItemsIter<'_, InputStorage, std::slice::Iter<'_, Key>, Item>
     {
-        // Attempt to get an iterator from any guard that is available out of the read and write guards
-        // panicking of no guards are available
-
-        if let Some(input_storage) = self.read_guard.as_ref() {
-
-            let iter: KeysToItemsIter<InputStorage, std::slice::Iter<Key>, Item> =
-                KeysToItemsIter::new(input_storage, self.view_keys.iter());
-
-            return iter;
+        let Some(iter) = self.try_key_item_iter_static()
+        else
+        {
+            panic!("Cannot create an iterator without first creating view data");
         };
 
-        if let Some(input_storage) = self.write_guard.as_ref() {
+        iter
+    }
+
+    /// Like [Self::key_item_iter_static] but None instead of panicking if there is no view data
+    fn try_key_item_iter_static(
+        &self,
+    ) -> Option<KeysToItemsIter<'_, InputStorage, std::slice::Iter<'_, Key>, Item>>
+    {
+        let input_storage = self.input()?;
 
-            let iter: KeysToItemsIter<InputStorage, std::slice::Iter<Key>, Item> =
-                KeysToItemsIter::new(input_storage, self.view_keys.iter());
+        Some(KeysToItemsIter::new(input_storage, self.view_keys.iter()))
+    }
 
-            return iter;
-        };
+    /// The view's items, or with the `no_panic` feature nothing if there is no view data
+    fn key_item_iter_or_empty(&self) -> impl Iterator<Item = (Key, &Item)>
+    {
+        #[cfg(feature = "no_panic")]
+        return self.try_key_item_iter_static().into_iter().flatten();
 
-        panic!("Cannot create an iterator without first creating view data");
+        #[cfg(not(feature = "no_panic"))]
+        self.key_item_iter_static()
     }
 }
 
@@ -163,22 +243,10 @@ where
 
     fn contains(&self, key: Self::Key) -> bool
     {
-        if let Some(input_data_guard) = &*self.read_guard
-        {
-            let entry: Option<&Key> = self.view_keys.get(key_to_index(key));
-
-            if let Some(index) = entry
-            {
-                input_data_guard.contains(*index)
-            }
-            else
-            {
-                false
-            }
-        }
-        else
+        match (self.input(), self.input_key(key))
         {
-            false
+            (Some(input_storage), Some(input_key)) => input_storage.contains(input_key),
+            _ => false,
         }
     }
 
@@ -196,61 +264,13 @@ where
 {
     fn get(&self, key: Self::Key) -> Option<&Item>
     {
-        // Orig that only worked with a read_guard and not EITHER a read or write guard as the new one does below
-        // Leaving for reference for a while but TODO: Delete soon.
-        // if let Some(input_data_guard) = &*self.read_guard
-        // {
-        //     let entry: Option<&Key> = self.view_data.get(key_to_index(key));
-        //     if let Some(index) = entry
-        //     {
-        //         input_data_guard.get(*index)
-        //     }
-        //     else
-        //     {
-        //         None
-        //     }
-        // }
-        // else
-        // {
-        //     None
-        // }
-
-        // Get using any guard that is active (either the read or write guard)
-        // otherwise return None
-
-        if let Some(input_data_guard) = self.read_guard.as_ref() {
-
-            let entry: Option<&Key> = self.view_keys.get(key_to_index(key));
-            if let Some(index) = entry
-            {
-                return input_data_guard.get(*index);
-            }
-            else
-            {
-                return None;
-            }
-        };
-
-        if let Some(input_data_guard) = self.write_guard.as_ref() {
-
-            let entry: Option<&Key> = self.view_keys.get(key_to_index(key));
-            if let Some(index) = entry
-            {
-                return input_data_guard.get(*index);
-            }
-            else
-            {
-                return None;
-            }
-
-        };
-
-        None
+        // One branch on the guard, whichever kind of view this is
+        self.input()?.get(self.input_key(key)?)
     }
 
     fn item_iter(&self) -> Box<dyn Iterator<Item = &Self::Item> + '_> {
 
-        let iter = self.key_item_iter_static()
+        let iter = self.key_item_iter_or_empty()
             .map(|(_, item)| item);
 
         Box::new(iter)
@@ -258,11 +278,48 @@ where
 
     fn key_item_iter(&self) -> Box<dyn Iterator<Item = (Self::Key, &Self::Item)> + '_>
     {
-        let iter = self.key_item_iter_static();
+        let iter = self.key_item_iter_or_empty();
         Box::new(iter)
     }
 }
 
+impl<InputStorage, Key, Item> ForEachStorage for KeyItemViewStorage<InputStorage, Key, Item>
+where
+    Key: KeyTrait,
+    Item: ItemTrait,
+    InputStorage: KeyItemStorage<Key = Key, Item = Item>,
+{
+    fn for_each_item(&self, mut f: impl FnMut(&Self::Item))
+    {
+        self.key_item_iter_or_empty().for_each(|(_, item)| f(item));
+    }
+
+    fn for_each_key_item(&self, mut f: impl FnMut(Self::Key, &Self::Item))
+    {
+        self.key_item_iter_or_empty().for_each(|(key, item)| f(key, item));
+    }
+}
+
+/// Walks the view keys in ascending order, skipping keys that are missing from the input storage
+impl<InputStorage, Key, Item> OrderedStorage for KeyItemViewStorage<InputStorage, Key, Item>
+where
+    Key: SortableKey,
+    Item: ItemTrait,
+    InputStorage: KeyItemStorage<Key = Key, Item = Item>,
+{
+    fn cursor(&self) -> StorageCursor<'_, Key, Item>
+    {
+        let keys = CursorKeys::Stored { keys: &self.view_keys, compare: Key::cmp };
+        let items = match self.input()
+        {
+            Some(input) => CursorItems::Lookup(input),
+            None => CursorItems::Empty,
+        };
+
+        StorageCursor::new(keys, items, self.view_keys.len())
+    }
+}
+
 impl<InputStorage, Key, Item> MutKeyItemStorage for KeyItemViewStorage<InputStorage, Key, Item>
 where
     Key: KeyTrait,
@@ -271,34 +328,51 @@ where
 {
     fn get_mut(&mut self, key: Self::Key) -> Option<&mut Item>
     {
-        if let Some(input_data_guard) = &mut *self.write_guard
-        {
-            let entry: Option<&Key> = self.view_keys.get(key_to_index(key));
-            if let Some(index) = entry
-            {
-                input_data_guard.get_mut(*index)
-            }
-            else
-            {
-                None
-            }
-        }
-        else
+        let input_key = self.input_key(key)?;
+
+        match self.input_guard.as_mut()?
         {
-            None
+            InputGuard::Write(guard) => guard.get_mut(input_key),
+            InputGuard::Read(_) => None,
         }
     }
 
     /// Insert the item at the key location overwriting any existing item.
     /// # Panics
-    /// This will panic if the key is not part of the view already
-    /// TODO: #LOW Return an error instead of panicking 
+    /// This will panic if the key is not part of the view already, see [Self::try_insert]. With the
+    /// `no_panic` feature the insert is skipped instead.
     fn insert(&mut self, key: Self::Key, item: Self::Item)
     {
-        let Some(existing_item) = self.get_mut(key) 
-        else {panic!("Could not insert item at key location as the view does not already contain this key")};
+        panic_or_skip(self.try_insert(key, item));
+    }
+
+    /// Overwrite the item at the key location. Fails if the key is not part of the view already or
+    /// the view has no write guard.
+    fn try_insert(&mut self, key: Self::Key, item: Self::Item) -> SimpleResult<()>
+    {
+        self.check_insert(key)?;
+
+        if let Some(existing_item) = self.get_mut(key)
+        {
+            *existing_item = item;
+        }
+
+        Ok(())
+    }
+
+    fn check_insert(&self, key: Self::Key) -> SimpleResult<()>
+    {
+        if self.get(key).is_none()
+        {
+            return Err(StorageError::new(
+                ErrorKind::KeyInvalid,
+                format!(
+                    "Could not insert item at key {key:?} as the view does not already contain this key"
+                ),
+            ));
+        }
 
-        *existing_item = item;
+        Ok(())
     }
 }
 
@@ -314,15 +388,14 @@ where
 {
     fn clear_view(&mut self) {
 
-        // Clear the view keys
-        self.view_keys.clear();
+        // Release the view keys, which may be shared with other views
+        self.view_keys = <_>::default();
 
-        // Drop the guards
-        self.read_guard = <_>::default();
-        self.write_guard = <_>::default();
+        // Drop the guard
+        self.input_guard = <_>::default();
     }
 
-    fn set_input_storage(&mut self, input: Arw<dyn Storage>)
+    fn set_input_storage(&mut self, input: &Arw<dyn Storage>) -> SimpleResult<()>
     {
         // input storage is potentially changed so we need to clear
         // this to be safe
@@ -335,9 +408,11 @@ where
         // view and know that its interior is concrete and thus good to
         // go for static dispatch
         let storage: Arw<InputStorage> =
-            dyn_storage_into_sized::<dyn Storage, InputStorage>(input).unwrap();
+            dyn_storage_into_sized::<dyn Storage, InputStorage>(input)?;
 
         self.input_storage = Some(storage);
+
+        Ok(())
     }
 
     fn get_input_storage(&self) -> Option<Arw<dyn Storage>>
@@ -351,48 +426,58 @@ where
         Some(storage)
     }
 
-    fn create_read_view(&mut self, keys: Box<dyn Iterator<Item = Key>>) -> SimpleResult<()>
+    fn create_read_view(&mut self, keys: Arc<[Key]>) -> SimpleResult<()>
     {
         let Some(input) = &self.input_storage else {
-            return Err("Input storage not set".into());
+            return Err(StorageError::new(ErrorKind::ViewNotReady, "Input storage not set")
+                .with_hint(Hint::ViewInputNotSet, SET_INPUT_FIX));
         };
 
-        let Ok(guard) = ArcRwLockReadGuardian::take(input.clone()) else {
-            return Err("Could not aquire read lock on input storage".into());
+        let Ok(guard) = read_storage_arc(input.clone()) else {
+            return Err(StorageError::new(
+                ErrorKind::Poisoned,
+                "Could not aquire read lock on input storage",
+            ));
         };
 
-        self.read_guard = SendOption::new(Some(guard));
-
-        self.view_keys.clear();
-
-        for key in keys
-        {
-            self.view_keys.push(key);
-        }
+        self.input_guard = hold_guard(InputGuard::Read(guard));
+        self.view_keys = keys;
 
         Ok(())
     }
 
-    fn create_write_view(&mut self, keys: Box<dyn Iterator<Item = Key>>) -> SimpleResult<()>
+    fn create_write_view(&mut self, keys: Arc<[Key]>) -> SimpleResult<()>
     {
         let Some(input) = &self.input_storage else {
-            return Err("Input storage not set".into());
+            return Err(StorageError::new(ErrorKind::ViewNotReady, "Input storage not set")
+                .with_hint(Hint::ViewInputNotSet, SET_INPUT_FIX));
         };
 
-        let Ok(guard) = ArcRwLockWriteGuardian::take(input.clone()) else {
-            return Err("Could not aquire write lock on input storage".into());
+        let Ok(guard) = write_storage_arc(input.clone()) else {
+            return Err(StorageError::new(
+                ErrorKind::Poisoned,
+                "Could not aquire write lock on input storage",
+            ));
         };
 
-        self.write_guard = SendOption::new(Some(guard));
+        self.input_guard = hold_guard(InputGuard::Write(guard));
+        self.view_keys = keys;
+
+        Ok(())
+    }
 
-        self.view_keys.clear();
+    fn view_keys(&self) -> Arc<[Key]>
+    {
+        self.view_keys.clone()
+    }
 
-        for key in keys
+    fn input_access(&self) -> Option<LockAccess>
+    {
+        match self.input_guard.as_ref()?
         {
-            self.view_keys.push(key);
+            InputGuard::Read(_) => Some(LockAccess::Read),
+            InputGuard::Write(_) => Some(LockAccess::Write),
         }
-
-        Ok(())
     }
 }
 
@@ -418,8 +503,11 @@ where
     // ViewController so without a major change to the structure of the storage 
     // trait family the only sensible non panicking implementation is to reset 
     // each item in the view back to its default.  
+    //
+    // With the `no_panic` feature this is a no-op until then.
     fn clear(&mut self)
     {
+        #[cfg(not(feature = "no_panic"))]
         todo!("Not implemented");
     }
 }
@@ -513,10 +601,10 @@ mod tests
 {
     use super::KeyItemViewStorage;
     use crate::{
-        storage_traits::{KeyItemStorage, ViewStorageSetup, MutKeyItemStorage, Storage},
-        Arw, storage_types::{VecStorage, SparseSetVecStorage},
+        storage_traits::{KeyItemStorage, KeyStorage, ViewStorageSetup, MutKeyItemStorage, Storage},
+        Arw, Rw, storage_types::{VecStorage, SparseSetVecStorage},
     };
-    use std::sync::{Arc, RwLock};
+    use std::sync::Arc;
 
     #[derive(Debug, Clone, Default, PartialEq, Eq)]
     struct ComponentA(i32);
@@ -531,17 +619,17 @@ mod tests
         storage.insert_and_shift(2, ComponentA(2));
         storage.insert_and_shift(3, ComponentA(3));
 
-        let input_storage_am: Arw<VecStorage<usize, ComponentA>> = Arc::new(RwLock::new(storage));
+        let input_storage_am: Arw<dyn Storage> = Arc::new(Rw::new(storage));
 
         // View ----------------------
 
         let mut view_storage: KeyItemViewStorage<VecStorage<usize, ComponentA>, usize, ComponentA> =
             KeyItemViewStorage::new();
 
-        view_storage.set_input_storage(input_storage_am.clone());
+        view_storage.set_input_storage(&input_storage_am).unwrap();
 
         let vec = vec![2, 0, 1];
-        view_storage.create_read_view(Box::new(vec.into_iter())).unwrap();
+        view_storage.create_read_view(vec.into()).unwrap();
 
         // Confirm that iter works
         println!("view_storage.iter():");
@@ -565,8 +653,7 @@ mod tests
         storage.insert(2, ComponentA(2));
         storage.insert(3, ComponentA(3));
 
-        let input_storage_am: Arw<SparseSetVecStorage<usize, ComponentA>> =
-            Arc::new(RwLock::new(storage));
+        let input_storage_am: Arw<dyn Storage> = Arc::new(Rw::new(storage));
 
         // View ----------------------
 
@@ -576,10 +663,10 @@ mod tests
             ComponentA,
         > = KeyItemViewStorage::new();
 
-        view_storage.set_input_storage(input_storage_am.clone());
+        view_storage.set_input_storage(&input_storage_am).unwrap();
 
         let vec = vec![2, 0, 1];
-        view_storage.create_read_view(Box::new(vec.into_iter())).unwrap();
+        view_storage.create_read_view(vec.into()).unwrap();
 
         // Confirm that iter works
         println!("view_storage.iter():");
@@ -601,15 +688,16 @@ mod tests
         storage.insert_and_shift(0, ComponentA(0));
         storage.insert_and_shift(1, ComponentA(1));
 
-        let input_storage_am: Arw<VecStorage<usize, ComponentA>> = Arc::new(RwLock::new(storage));
+        let input_storage_am: Arw<VecStorage<usize, ComponentA>> = Arc::new(Rw::new(storage));
 
         let mut view_storage: KeyItemViewStorage<VecStorage<usize, ComponentA>, usize, ComponentA> =
             KeyItemViewStorage::new();
 
-        view_storage.set_input_storage(input_storage_am.clone());
+        let input: Arw<dyn Storage> = input_storage_am.clone();
+        view_storage.set_input_storage(&input).unwrap();
 
         let vec = vec![0, 1];
-        view_storage.create_read_view(Box::new(vec.into_iter())).unwrap();
+        view_storage.create_read_view(vec.into()).unwrap();
 
         assert_eq!(view_storage.len(), 2);
 
@@ -645,4 +733,51 @@ mod tests
             assert!(write_guard.is_ok());
         }
     }
+
+    #[test]
+    fn try_insert_test()
+    {
+        let storage: VecStorage<usize, ComponentA> =
+            VecStorage::from_vec(vec![ComponentA(0), ComponentA(1), ComponentA(2)]);
+        let input_storage_am: Arw<VecStorage<usize, ComponentA>> = Arc::new(Rw::new(storage));
+
+        let mut view_storage: KeyItemViewStorage<VecStorage<usize, ComponentA>, usize, ComponentA> =
+            KeyItemViewStorage::new();
+
+        let input: Arw<dyn Storage> = input_storage_am.clone();
+        view_storage.set_input_storage(&input).unwrap();
+        view_storage.create_write_view(Arc::from([2])).unwrap();
+
+        // View key 0 maps to input key 2 and there is no view key 1
+        assert!(view_storage.contains(0));
+        assert!(!view_storage.contains(1));
+        assert!(view_storage.try_insert(0, ComponentA(20)).is_ok());
+        assert!(view_storage.try_insert(1, ComponentA(10)).is_err());
+        assert_eq!(view_storage.get(0), Some(&ComponentA(20)));
+
+        view_storage.clear_view();
+        assert_eq!(input_storage_am.try_read().unwrap().get(2), Some(&ComponentA(20)));
+    }
+
+    #[test]
+    fn adversarial_input_test()
+    {
+        let mut view_storage: KeyItemViewStorage<VecStorage<usize, ComponentA>, usize, ComponentA> =
+            KeyItemViewStorage::new();
+
+        // An input of another storage type is rejected
+        let wrong_input: Arw<dyn Storage> =
+            Arc::new(Rw::new(SparseSetVecStorage::<usize, ComponentA>::new()));
+        assert!(view_storage.set_input_storage(&wrong_input).is_err());
+
+        // Without view data there is nothing to iterate, insert into or clear
+        #[cfg(feature = "no_panic")]
+        {
+            use crate::storage_traits::ClearableStorage;
+
+            assert_eq!(view_storage.key_item_iter().count(), 0);
+            view_storage.insert(usize::MAX, ComponentA(1));
+            view_storage.clear();
+        }
+    }
 }
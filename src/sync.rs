@@ -0,0 +1,440 @@
+//! Synchronization primitives used by the handle, guard and view controller state machines.
+//!
+//! In normal builds these are the std primitives. When built with `RUSTFLAGS="--cfg loom"` they
+//! are swapped for [loom](https://docs.rs/loom) versions so that the state machines can be model
+//! checked. Downstream crates can use the same types in their own loom tests.
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom
+//! ```
+//!
+//! The lock that storages are held in, [StorageLock], wraps a std RwLock by default and a
+//! parking_lot RwLock with the `parking_lot` feature. Parking lot locks are never poisoned and are
+//! fairer to writers under contention. The API of [StorageLock] and its guards is the same with
+//! either backend, so turning the feature on anywhere in a dependency graph doesn't break code that
+//! locks storages.
+//
+// # Internal Design
+//
+// - Storages are held in a std Arc<StorageLock<dyn Storage>> under loom too, so in std's or
+//   parking_lot's RwLock but never in loom's. The casting functions in [crate::casting] rely on the
+//   lock layout and loom's RwLock doesn't support unsized types. Storage locks are only ever
+//   acquired with try_* so they never block a loom model, they just aren't explored as preemption
+//   points.
+// - Only primitives that take part in the state machines are routed through here. Diagnostics and
+//   metrics bookkeeping use std directly as they don't affect behavior.
+// - [StorageLock] is a transparent wrapper so that the owned guards, guardian's over std locks and
+//   parking_lot's arc_lock guards over parking_lot locks, can be taken on an Arc of the backend
+//   lock. The crate takes them through the functions at the end of this module.
+// - A parking_lot [StorageLock] reports its failures as std TryLockErrors and LockResults too, so
+//   that error handling is shared and it simply never reports a poisoned lock.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    ops::{Deref, DerefMut},
+    sync::{LockResult, TryLockError, TryLockResult},
+};
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::PoisonError;
+
+#[cfg(not(loom))]
+pub use std::sync::{
+    atomic, Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+#[cfg(loom)]
+pub use loom::sync::{
+    atomic, Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+/// Arc Read Write lock pointer built from the primitives of this module, for the state machines
+pub(crate) type StateArw<T> = Arc<RwLock<T>>;
+
+// -------------------------------------------------
+// Storage locks
+// -------------------------------------------------
+
+#[cfg(not(feature = "parking_lot"))]
+type BackendLock<T> = std::sync::RwLock<T>;
+#[cfg(not(feature = "parking_lot"))]
+type BackendReadGuard<'a, T> = std::sync::RwLockReadGuard<'a, T>;
+#[cfg(not(feature = "parking_lot"))]
+type BackendWriteGuard<'a, T> = std::sync::RwLockWriteGuard<'a, T>;
+
+#[cfg(feature = "parking_lot")]
+type BackendLock<T> = parking_lot::RwLock<T>;
+#[cfg(feature = "parking_lot")]
+type BackendReadGuard<'a, T> = parking_lot::RwLockReadGuard<'a, T>;
+#[cfg(feature = "parking_lot")]
+type BackendWriteGuard<'a, T> = parking_lot::RwLockWriteGuard<'a, T>;
+
+/// The read write lock that storages are held in, see [crate::Rw]. Its methods are those of std's
+/// RwLock with either backend, a parking_lot lock just never reports being poisoned.
+#[repr(transparent)]
+pub struct StorageLock<T>
+where
+    T: ?Sized,
+{
+    inner: BackendLock<T>,
+}
+
+impl<T> StorageLock<T>
+{
+    pub fn new(value: T) -> Self
+    {
+        Self {
+            inner: BackendLock::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T>
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        return self.inner.into_inner();
+
+        #[cfg(feature = "parking_lot")]
+        return Ok(self.inner.into_inner());
+    }
+}
+
+impl<T> StorageLock<T>
+where
+    T: ?Sized,
+{
+    pub fn read(&self) -> LockResult<StorageLockReadGuard<'_, T>>
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        return map_lock_result(self.inner.read(), StorageLockReadGuard::from_inner);
+
+        #[cfg(feature = "parking_lot")]
+        return Ok(StorageLockReadGuard::from_inner(self.inner.read()));
+    }
+
+    pub fn write(&self) -> LockResult<StorageLockWriteGuard<'_, T>>
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        return map_lock_result(self.inner.write(), StorageLockWriteGuard::from_inner);
+
+        #[cfg(feature = "parking_lot")]
+        return Ok(StorageLockWriteGuard::from_inner(self.inner.write()));
+    }
+
+    pub fn try_read(&self) -> TryLockResult<StorageLockReadGuard<'_, T>>
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        return map_try_lock_result(self.inner.try_read(), StorageLockReadGuard::from_inner);
+
+        #[cfg(feature = "parking_lot")]
+        return self
+            .inner
+            .try_read()
+            .map(StorageLockReadGuard::from_inner)
+            .ok_or(TryLockError::WouldBlock);
+    }
+
+    pub fn try_write(&self) -> TryLockResult<StorageLockWriteGuard<'_, T>>
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        return map_try_lock_result(self.inner.try_write(), StorageLockWriteGuard::from_inner);
+
+        #[cfg(feature = "parking_lot")]
+        return self
+            .inner
+            .try_write()
+            .map(StorageLockWriteGuard::from_inner)
+            .ok_or(TryLockError::WouldBlock);
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T>
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        return self.inner.get_mut();
+
+        #[cfg(feature = "parking_lot")]
+        return Ok(self.inner.get_mut());
+    }
+
+    pub fn is_poisoned(&self) -> bool
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        return self.inner.is_poisoned();
+
+        #[cfg(feature = "parking_lot")]
+        return false;
+    }
+
+    pub fn clear_poison(&self)
+    {
+        #[cfg(not(feature = "parking_lot"))]
+        self.inner.clear_poison();
+    }
+
+    /// The backend lock of a shared storage lock, for the owned guards of the backends
+    fn into_backend(lock: std::sync::Arc<Self>) -> std::sync::Arc<BackendLock<T>>
+    {
+        // Safety: StorageLock is a transparent wrapper of the backend lock
+        unsafe { std::sync::Arc::from_raw(std::sync::Arc::into_raw(lock) as *const BackendLock<T>) }
+    }
+}
+
+impl<T> Default for StorageLock<T>
+where
+    T: Default,
+{
+    fn default() -> Self
+    {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for StorageLock<T>
+{
+    fn from(value: T) -> Self
+    {
+        Self::new(value)
+    }
+}
+
+impl<T> Debug for StorageLock<T>
+where
+    T: ?Sized + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        self.inner.fmt(f)
+    }
+}
+
+/// A read guard of a [StorageLock]
+pub struct StorageLockReadGuard<'a, T>
+where
+    T: ?Sized,
+{
+    pub(crate) inner: BackendReadGuard<'a, T>,
+}
+
+impl<'a, T> StorageLockReadGuard<'a, T>
+where
+    T: ?Sized,
+{
+    fn from_inner(inner: BackendReadGuard<'a, T>) -> Self
+    {
+        Self { inner }
+    }
+}
+
+impl<T> Deref for StorageLockReadGuard<'_, T>
+where
+    T: ?Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T> Debug for StorageLockReadGuard<'_, T>
+where
+    T: ?Sized + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        (**self).fmt(f)
+    }
+}
+
+/// A write guard of a [StorageLock]
+pub struct StorageLockWriteGuard<'a, T>
+where
+    T: ?Sized,
+{
+    pub(crate) inner: BackendWriteGuard<'a, T>,
+}
+
+impl<'a, T> StorageLockWriteGuard<'a, T>
+where
+    T: ?Sized,
+{
+    fn from_inner(inner: BackendWriteGuard<'a, T>) -> Self
+    {
+        Self { inner }
+    }
+}
+
+impl<T> Deref for StorageLockWriteGuard<'_, T>
+where
+    T: ?Sized,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for StorageLockWriteGuard<'_, T>
+where
+    T: ?Sized,
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        &mut self.inner
+    }
+}
+
+impl<T> Debug for StorageLockWriteGuard<'_, T>
+where
+    T: ?Sized + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(not(feature = "parking_lot"))]
+fn map_lock_result<G, W>(result: LockResult<G>, wrap: impl FnOnce(G) -> W) -> LockResult<W>
+{
+    match result
+    {
+        Ok(guard) => Ok(wrap(guard)),
+        Err(poisoned) => Err(PoisonError::new(wrap(poisoned.into_inner()))),
+    }
+}
+
+#[cfg(not(feature = "parking_lot"))]
+fn map_try_lock_result<G, W>(
+    result: TryLockResult<G>,
+    wrap: impl FnOnce(G) -> W,
+) -> TryLockResult<W>
+{
+    match result
+    {
+        Ok(guard) => Ok(wrap(guard)),
+        Err(TryLockError::Poisoned(poisoned)) => Err(TryLockError::Poisoned(PoisonError::new(
+            wrap(poisoned.into_inner()),
+        ))),
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    }
+}
+
+/// A read guard that keeps its storage alive, such as a view holds on its input
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) type ArcStorageLockReadGuard<T> = guardian::ArcRwLockReadGuardian<T>;
+
+/// A write guard that keeps its storage alive, such as a view holds on its input
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) type ArcStorageLockWriteGuard<T> = guardian::ArcRwLockWriteGuardian<T>;
+
+/// A read guard that keeps its storage alive, such as a view holds on its input
+#[cfg(feature = "parking_lot")]
+pub(crate) type ArcStorageLockReadGuard<T> =
+    parking_lot::ArcRwLockReadGuard<parking_lot::RawRwLock, T>;
+
+/// A write guard that keeps its storage alive, such as a view holds on its input
+#[cfg(feature = "parking_lot")]
+pub(crate) type ArcStorageLockWriteGuard<T> =
+    parking_lot::ArcRwLockWriteGuard<parking_lot::RawRwLock, T>;
+
+/// Like [StorageLock::try_read] for a read guard that holds on to `lock`
+pub(crate) fn try_read_storage_arc<T>(
+    lock: std::sync::Arc<StorageLock<T>>,
+) -> TryLockResult<ArcStorageLockReadGuard<T>>
+where
+    T: ?Sized + 'static,
+{
+    let lock = StorageLock::into_backend(lock);
+
+    #[cfg(not(feature = "parking_lot"))]
+    return match guardian::ArcRwLockReadGuardian::try_take(lock)
+    {
+        Some(result) => result.map_err(Into::into),
+        None => Err(TryLockError::WouldBlock),
+    };
+
+    #[cfg(feature = "parking_lot")]
+    return lock.try_read_arc().ok_or(TryLockError::WouldBlock);
+}
+
+/// Like [StorageLock::try_write] for a write guard that holds on to `lock`
+pub(crate) fn try_write_storage_arc<T>(
+    lock: std::sync::Arc<StorageLock<T>>,
+) -> TryLockResult<ArcStorageLockWriteGuard<T>>
+where
+    T: ?Sized + 'static,
+{
+    let lock = StorageLock::into_backend(lock);
+
+    #[cfg(not(feature = "parking_lot"))]
+    return match guardian::ArcRwLockWriteGuardian::try_take(lock)
+    {
+        Some(result) => result.map_err(Into::into),
+        None => Err(TryLockError::WouldBlock),
+    };
+
+    #[cfg(feature = "parking_lot")]
+    return lock.try_write_arc().ok_or(TryLockError::WouldBlock);
+}
+
+/// Wait for a read guard on `lock` that holds on to it
+pub(crate) fn read_storage_arc<T>(
+    lock: std::sync::Arc<StorageLock<T>>,
+) -> LockResult<ArcStorageLockReadGuard<T>>
+where
+    T: ?Sized + 'static,
+{
+    let lock = StorageLock::into_backend(lock);
+
+    #[cfg(not(feature = "parking_lot"))]
+    return guardian::ArcRwLockReadGuardian::take(lock);
+
+    #[cfg(feature = "parking_lot")]
+    return Ok(lock.read_arc());
+}
+
+/// Wait for a write guard on `lock` that holds on to it
+pub(crate) fn write_storage_arc<T>(
+    lock: std::sync::Arc<StorageLock<T>>,
+) -> LockResult<ArcStorageLockWriteGuard<T>>
+where
+    T: ?Sized + 'static,
+{
+    let lock = StorageLock::into_backend(lock);
+
+    #[cfg(not(feature = "parking_lot"))]
+    return guardian::ArcRwLockWriteGuardian::take(lock);
+
+    #[cfg(feature = "parking_lot")]
+    return Ok(lock.write_arc());
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::TryLockError;
+
+    use super::StorageLock;
+
+    /// The lock has std's API whichever backend it wraps
+    #[test]
+    fn std_api_test()
+    {
+        let lock = StorageLock::new(vec![1, 2, 3]);
+
+        {
+            let mut guard = lock.write().unwrap();
+            guard.push(4);
+
+            assert!(matches!(lock.try_read(), Err(TryLockError::WouldBlock)));
+            assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+        }
+
+        assert_eq!(lock.try_read().unwrap().len(), 4);
+        assert!(!lock.is_poisoned());
+        assert_eq!(lock.into_inner().unwrap(), vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,469 @@
+//! Write-ahead logging of storage writes for crash recovery.
+//!
+//! A [WriteAheadLog] wraps a handle and appends every insert, remove and clear made through it to
+//! a file before applying it to the storage. Opening the log on startup replays the recorded
+//! writes onto the storage, so a long editing session can be recovered without saving a full
+//! snapshot on every change:
+//!
+//! ```ignore
+//! let handle: StorageHandle<Nodes> = builder(Nodes::new()).build().cast_to_sized_storage()?;
+//! handle.load_snapshot(File::open("nodes.snapshot")?)?;
+//!
+//! // Replays the writes made since the snapshot was saved
+//! let mut log = WriteAheadLog::open(handle.clone(), "nodes.wal")?;
+//! log.insert(node_id, node)?;
+//!
+//! // Later, once a new snapshot has been saved the log can start over
+//! handle.save_snapshot(File::create("nodes.snapshot")?)?;
+//! log.truncate()?;
+//! ```
+//
+// # Internal Design
+//
+// - The write lock is taken before a record is appended and held until it has been applied. The
+//   order of records in the file is therefore always the order the writes were applied in, even
+//   with several logs open on clones of the same handle.
+// - Records are appended with a single write each. A crash can at worst leave a partial record at
+//   the end of the file, which is detected on replay and cut off.
+// - Writes made to the storage without going through the log are not recorded.
+// - Logs can be opened for any [MutKeyItemStorage] while only a [RemovableStorage] can write remove
+//   records. Replay finds the remove support of the storage by downcasting to the removable storage
+//   types, the same way [crate::casting] finds trait support from a list of storage types.
+
+use std::{
+    any::TypeId,
+    fs::{File, OpenOptions},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use bincode::{DefaultOptions, Options};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    storage_handle::StorageHandle,
+    storage_traits::{ItemTrait, KeyTrait, MutKeyItemStorage, RemovableStorage},
+    storage_types::{HashMapStorage, SparseSetVecStorage, VecStorage},
+    ErrorKind, SimpleResult, StorageError,
+};
+
+const MAGIC: [u8; 4] = *b"NFWL";
+
+/// Version of the log format. Bumped whenever the header or record encoding changes.
+pub const WAL_VERSION: u32 = 1;
+
+const HEADER_LEN: u64 = 8;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WalRecord<Key, Item>
+{
+    Insert(Key, Item),
+    Remove(Key),
+    Clear,
+}
+
+pub struct WriteAheadLog<S>
+where
+    S: MutKeyItemStorage,
+{
+    handle: StorageHandle<S>,
+    log: LogFile,
+}
+
+struct LogFile
+{
+    file: File,
+    path: PathBuf,
+    sync_each_record: bool,
+}
+
+impl<S> WriteAheadLog<S>
+where
+    S: MutKeyItemStorage,
+    S::Key: KeyTrait + Serialize + DeserializeOwned,
+    S::Item: ItemTrait + Serialize + DeserializeOwned,
+{
+    /// Open or create the log at `path` and replay any writes it holds onto the storage
+    pub fn open(handle: StorageHandle<S>, path: impl AsRef<Path>) -> SimpleResult<Self>
+    {
+        let path = path.as_ref().to_owned();
+        let io_error = |error: std::io::Error| {
+            StorageError::with_source(
+                ErrorKind::Io,
+                format!("WAL {}: {error}", path.display()),
+                error,
+            )
+        };
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(io_error)?;
+
+        if bytes.is_empty()
+        {
+            file.write_all(&header()).map_err(io_error)?;
+        }
+        else
+        {
+            let valid_len = replay(&handle, &bytes)?;
+
+            // Cut off a record that was only partially written before a crash
+            if valid_len < bytes.len() as u64
+            {
+                file.set_len(valid_len).map_err(io_error)?;
+            }
+        }
+
+        file.seek(SeekFrom::End(0)).map_err(io_error)?;
+
+        Ok(Self {
+            handle,
+            log: LogFile {
+                file,
+                path,
+                sync_each_record: false,
+            },
+        })
+    }
+
+    /// Wait for every record to reach the disk before applying it. Without this records are
+    /// handed to the OS and survive a crash of the process but not of the machine.
+    pub fn sync_each_record(&mut self, sync: bool) -> &mut Self
+    {
+        self.log.sync_each_record = sync;
+
+        self
+    }
+
+    pub fn handle(&self) -> &StorageHandle<S>
+    {
+        &self.handle
+    }
+
+    pub fn insert(&mut self, key: S::Key, item: S::Item) -> SimpleResult<()>
+    {
+        let mut guard = self.handle.try_write()?;
+
+        self.log.append(&WalRecord::Insert(&key, &item))?;
+        guard.insert(key, item);
+
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> SimpleResult<()>
+    {
+        let mut guard = self.handle.try_write()?;
+
+        self.log.append(&WalRecord::<S::Key, S::Item>::Clear)?;
+        guard.clear();
+
+        Ok(())
+    }
+
+    /// Empty the log, eg after the storage has been saved to a snapshot that includes every
+    /// logged write
+    pub fn truncate(&mut self) -> SimpleResult<()>
+    {
+        // Hold a read lock so that no write is logged in between
+        let _guard = self.handle.try_read()?;
+
+        self.log
+            .file
+            .set_len(HEADER_LEN)
+            .and_then(|_| self.log.file.seek(SeekFrom::End(0)))
+            .map(|_| ())
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::Io,
+                    format!("WAL {}: {error}", self.log.path.display()),
+                    error,
+                )
+            })
+    }
+}
+
+impl LogFile
+{
+    /// Records are encoded the same whether they hold values or references
+    fn append<Key, Item>(&mut self, record: &WalRecord<Key, Item>) -> SimpleResult<()>
+    where
+        Key: Serialize,
+        Item: Serialize,
+    {
+        let bytes = DefaultOptions::new()
+            .serialize(record)
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::InvalidData,
+                    format!("Failed to encode WAL record: {error}"),
+                    error,
+                )
+            })?;
+
+        self.file
+            .write_all(&bytes)
+            .and_then(|_| match self.sync_each_record
+            {
+                true => self.file.sync_data(),
+                false => Ok(()),
+            })
+            .map_err(|error| {
+                StorageError::with_source(
+                    ErrorKind::Io,
+                    format!("WAL {}: {error}", self.path.display()),
+                    error,
+                )
+            })
+    }
+}
+
+impl<S> WriteAheadLog<S>
+where
+    S: RemovableStorage,
+    S::Key: KeyTrait + Serialize + DeserializeOwned,
+    S::Item: ItemTrait + Serialize + DeserializeOwned,
+{
+    pub fn remove(&mut self, key: S::Key) -> SimpleResult<Option<S::Item>>
+    {
+        let mut guard = self.handle.try_write()?;
+
+        self.log
+            .append(&WalRecord::<S::Key, S::Item>::Remove(key))?;
+
+        Ok(guard.remove(key))
+    }
+}
+
+fn header() -> [u8; HEADER_LEN as usize]
+{
+    let mut header = [0u8; HEADER_LEN as usize];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..].copy_from_slice(&WAL_VERSION.to_le_bytes());
+
+    header
+}
+
+/// Apply the records in `bytes` to the storage and return the length of the valid records
+/// including the header
+fn replay<S>(handle: &StorageHandle<S>, bytes: &[u8]) -> SimpleResult<u64>
+where
+    S: MutKeyItemStorage,
+    S::Key: KeyTrait + DeserializeOwned,
+    S::Item: ItemTrait + DeserializeOwned,
+{
+    if bytes.len() < HEADER_LEN as usize || bytes[..4] != MAGIC
+    {
+        return Err(StorageError::new(ErrorKind::InvalidData, "File is not a flex storage WAL"));
+    }
+
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != WAL_VERSION
+    {
+        return Err(StorageError::new(
+            ErrorKind::InvalidData,
+            format!("WAL version {version} is not supported, expected version {WAL_VERSION}"),
+        ));
+    }
+
+    // Decode everything before locking so that a corrupt log leaves the storage untouched
+    let mut cursor = Cursor::new(bytes);
+    cursor.set_position(HEADER_LEN);
+
+    let mut records = Vec::new();
+    let mut valid_len = HEADER_LEN;
+
+    while valid_len < bytes.len() as u64
+    {
+        match DefaultOptions::new().deserialize_from::<_, WalRecord<S::Key, S::Item>>(&mut cursor)
+        {
+            Ok(record) =>
+            {
+                records.push(record);
+                valid_len = cursor.position();
+            }
+            Err(error) => match *error
+            {
+                bincode::ErrorKind::Io(ref io)
+                    if io.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                _ =>
+                {
+                    return Err(StorageError::new(
+                        ErrorKind::InvalidData,
+                        format!("Corrupt WAL record at byte {valid_len}: {error}"),
+                    ))
+                }
+            },
+        }
+    }
+
+    let has_removes = records
+        .iter()
+        .any(|record| matches!(record, WalRecord::Remove(_)));
+
+    let mut guard = handle.try_write()?;
+
+    let remove = removal::<S>();
+    if has_removes && remove.is_none()
+    {
+        return Err("WAL holds remove records for a storage that doesn't support removal".into());
+    }
+
+    for record in records
+    {
+        match record
+        {
+            WalRecord::Insert(key, item) => guard.insert(key, item),
+            WalRecord::Remove(key) =>
+            {
+                if let Some(remove) = remove
+                {
+                    remove(&mut *guard, key)
+                }
+            }
+            WalRecord::Clear => guard.clear(),
+        }
+    }
+
+    Ok(valid_len)
+}
+
+/// Remove function of `S` if it is one of the [RemovableStorage] types. Remove records can only
+/// have been written for a [RemovableStorage] but replay is available for every
+/// [MutKeyItemStorage] so the storage is downcast to find its remove support.
+fn removal<S>() -> Option<fn(&mut S, S::Key)>
+where
+    S: MutKeyItemStorage,
+    S::Key: KeyTrait,
+    S::Item: ItemTrait,
+{
+    fn remove_as<S, Removable>(storage: &mut S, key: S::Key)
+    where
+        S: MutKeyItemStorage,
+        Removable: RemovableStorage<Key = S::Key>,
+    {
+        if let Some(storage) = storage.as_any_mut().downcast_mut::<Removable>()
+        {
+            storage.remove(key);
+        }
+    }
+
+    let type_id = TypeId::of::<S>();
+
+    if type_id == TypeId::of::<HashMapStorage<S::Key, S::Item>>()
+    {
+        Some(remove_as::<S, HashMapStorage<S::Key, S::Item>>)
+    }
+    else if type_id == TypeId::of::<SparseSetVecStorage<S::Key, S::Item>>()
+    {
+        Some(remove_as::<S, SparseSetVecStorage<S::Key, S::Item>>)
+    }
+    else if type_id == TypeId::of::<VecStorage<S::Key, S::Item>>()
+    {
+        Some(remove_as::<S, VecStorage<S::Key, S::Item>>)
+    }
+    else
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::{
+        any::TypeId,
+        fs::OpenOptions,
+        io::Write,
+        sync::Arc,
+    };
+
+    use super::WriteAheadLog;
+    use crate::{
+        storage_handle::{builder, StorageHandle},
+        storage_traits::{ClearableStorage, KeyItemStorage, Storage},
+        storage_types::{SparseSetVecStorage, VecStorage},
+        Rw,
+    };
+
+    type Nodes = SparseSetVecStorage<usize, i32>;
+
+    fn nodes_handle() -> StorageHandle<Nodes>
+    {
+        let storage = Arc::new(Rw::new(Nodes::new()));
+        StorageHandle::new(
+            storage.clone(),
+            storage,
+            TypeId::of::<usize>(),
+            TypeId::of::<i32>(),
+        )
+    }
+
+    #[test]
+    fn replay_test()
+    {
+        let path =
+            std::env::temp_dir().join(format!("flex_storage_wal_{}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut log = WriteAheadLog::open(nodes_handle(), &path).unwrap();
+            log.insert(1, 10).unwrap();
+            log.insert(2, 20).unwrap();
+            log.remove(1).unwrap();
+            log.insert(3, 30).unwrap();
+        }
+
+        // A record cut off by a crash
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0])
+            .unwrap();
+
+        let log = WriteAheadLog::open(nodes_handle(), &path).unwrap();
+        {
+            let guard = log.handle().try_read().unwrap();
+            assert_eq!(guard.len(), 2);
+            assert_eq!(guard.get(2), Some(&20));
+            assert_eq!(guard.get(1), None);
+        }
+        drop(log);
+
+        // Logs of storages without removal replay inserts and clears
+        let handle: StorageHandle<VecStorage<usize, i32>> =
+            builder(VecStorage::<usize, i32>::new())
+                .build()
+                .cast_to_sized_storage()
+                .unwrap();
+        assert!(WriteAheadLog::open(handle.clone(), &path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        let mut log = WriteAheadLog::open(handle.clone(), &path).unwrap();
+        log.insert(0, 5).unwrap();
+        log.clear().unwrap();
+        log.insert(0, 7).unwrap();
+        log.truncate().unwrap();
+        log.insert(1, 8).unwrap();
+        drop(log);
+
+        handle.try_write().unwrap().clear();
+        WriteAheadLog::open(handle.clone(), &path).unwrap();
+        assert_eq!(
+            handle.try_read().unwrap().item_iter().collect::<Vec<_>>(),
+            vec![&0, &8]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+//! A top level container of the storages of an application, found by name or by type.
+//!
+//! [StorageWorld] owns a `StorageHandle<dyn Storage>` per storage and casts it on the way out, so
+//! every part of an application can look up the storages it needs from one shared place:
+//!
+//! ```ignore
+//! let world = Arc::new(StorageWorld::new());
+//! world.insert("positions", VecStorage::<usize, f32>::from_vec(vec![0.0; 64]));
+//!
+//! // Anywhere else
+//! let positions = world.get::<VecStorage<usize, f32>>("positions")?;
+//! let velocities = world.get_or_insert_with("velocities", VecStorage::<usize, f32>::new)?;
+//! let weights = world.get_dyn("weights")?.cast_to_slice_storage::<usize, f32>()?;
+//! ```
+//!
+//! Storages of which there is one per application, such as settings, can be keyed by their type
+//! instead of a name with [StorageWorld::insert_resource] and [StorageWorld::resource].
+//
+// # Internal Design
+//
+// - Handles are held as `StorageHandle<dyn Storage>` as that is the one cast every other cast can
+//   be made from, like [crate::graph::StorageGraph] nodes.
+// - The map is behind its own lock so that a world can be shared through an Arc and storages can be
+//   added on first access through `&self`. The lock is only held while the map is read or changed,
+//   never while a storage is locked, except that initializers run under it so that two threads
+//   can't both initialize the same storage. Initializers must not use the world.
+// - Names and types are separate key spaces, so a resource never collides with a named storage.
+
+use std::{
+    any::{type_name, TypeId},
+    collections::HashMap,
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{
+    storage_handle::{builder, StorageHandle},
+    storage_traits::{
+        ItemStorage, ItemTrait, ItemTypeIdNoSelf, KeyStorage, KeyTrait, KeyTypeIdNoSelf, Storage,
+    },
+    Arw, ErrorKind, SimpleResult, StorageError,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Slot
+{
+    Name(String),
+    Type(TypeId),
+}
+
+type HandleMap = HashMap<Slot, StorageHandle<dyn Storage>>;
+
+/// Named and typed storage handles of an application, see the [module docs](self)
+#[derive(Default)]
+pub struct StorageWorld
+{
+    handles: RwLock<HandleMap>,
+}
+
+impl StorageWorld
+{
+    pub fn new() -> Self
+    {
+        <_>::default()
+    }
+
+    /// Build a handle to `storage` labelled with `name` and add it, replacing and returning any
+    /// handle that was held under the name
+    pub fn insert<S>(
+        &self,
+        name: impl Into<String>,
+        storage: S,
+    ) -> Option<StorageHandle<dyn Storage>>
+    where
+        S: KeyStorage + ItemStorage + Into<Arw<dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        let name = name.into();
+        let handle = build_labelled(storage, &name);
+
+        self.insert_handle(name, handle)
+    }
+
+    /// Add an existing handle, eg one built with a lock policy, replacing and returning any handle
+    /// that was held under the name
+    pub fn insert_handle(
+        &self,
+        name: impl Into<String>,
+        handle: StorageHandle<dyn Storage>,
+    ) -> Option<StorageHandle<dyn Storage>>
+    {
+        self.write_map().insert(Slot::Name(name.into()), handle)
+    }
+
+    /// The storage held under `name` as its concrete type
+    pub fn get<S>(&self, name: &str) -> SimpleResult<StorageHandle<S>>
+    where
+        S: Storage,
+    {
+        self.get_dyn(name)?.cast_to_sized_storage::<S>()
+    }
+
+    /// The storage held under `name`, to be cast to any of its traits
+    pub fn get_dyn(&self, name: &str) -> SimpleResult<StorageHandle<dyn Storage>>
+    {
+        self.read_map()
+            .get(&Slot::Name(name.to_owned()))
+            .cloned()
+            .ok_or_else(|| not_found(&format!("named {name}")))
+    }
+
+    /// The storage held under `name`, first adding the storage made by `init` if there is none.
+    /// Fails if the storage that is held isn't an S.
+    ///
+    /// `init` runs while the world is locked so it must not use the world.
+    pub fn get_or_insert_with<S>(
+        &self,
+        name: &str,
+        init: impl FnOnce() -> S,
+    ) -> SimpleResult<StorageHandle<S>>
+    where
+        S: KeyStorage + ItemStorage + Into<Arw<dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        self.get_or_insert_slot(Slot::Name(name.to_owned()), name, init)
+    }
+
+    /// Like [Self::get_or_insert_with] with an empty storage made by [Default]
+    pub fn get_or_default<S>(&self, name: &str) -> SimpleResult<StorageHandle<S>>
+    where
+        S: KeyStorage
+            + ItemStorage
+            + Default
+            + Into<Arw<dyn Storage>>
+            + KeyTypeIdNoSelf
+            + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        self.get_or_insert_with(name, S::default)
+    }
+
+    pub fn remove(&self, name: &str) -> Option<StorageHandle<dyn Storage>>
+    {
+        self.write_map().remove(&Slot::Name(name.to_owned()))
+    }
+
+    pub fn contains(&self, name: &str) -> bool
+    {
+        self.read_map().contains_key(&Slot::Name(name.to_owned()))
+    }
+
+    /// Names of the named storages in no particular order
+    pub fn names(&self) -> Vec<String>
+    {
+        self.read_map()
+            .keys()
+            .filter_map(|slot| match slot
+            {
+                Slot::Name(name) => Some(name.clone()),
+                Slot::Type(_) => None,
+            })
+            .collect()
+    }
+
+    /// Number of named storages and resources
+    pub fn len(&self) -> usize
+    {
+        self.read_map().len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+
+    // -------------------------------------------------
+    // Resources
+    // -------------------------------------------------
+
+    /// Add `storage` as the resource of its type, replacing and returning any handle that was held
+    /// for the type
+    pub fn insert_resource<S>(&self, storage: S) -> Option<StorageHandle<dyn Storage>>
+    where
+        S: KeyStorage + ItemStorage + Into<Arw<dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        let handle = build_labelled(storage, type_name::<S>());
+
+        self.write_map()
+            .insert(Slot::Type(TypeId::of::<S>()), handle)
+    }
+
+    /// The resource of type S
+    pub fn resource<S>(&self) -> SimpleResult<StorageHandle<S>>
+    where
+        S: Storage,
+    {
+        self.read_map()
+            .get(&Slot::Type(TypeId::of::<S>()))
+            .cloned()
+            .ok_or_else(|| not_found(&format!("of type {}", type_name::<S>())))?
+            .cast_to_sized_storage::<S>()
+    }
+
+    /// The resource of type S, first adding the storage made by `init` if there is none. `init`
+    /// runs while the world is locked so it must not use the world.
+    pub fn resource_or_insert_with<S>(
+        &self,
+        init: impl FnOnce() -> S,
+    ) -> SimpleResult<StorageHandle<S>>
+    where
+        S: KeyStorage + ItemStorage + Into<Arw<dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        self.get_or_insert_slot(Slot::Type(TypeId::of::<S>()), type_name::<S>(), init)
+    }
+
+    pub fn remove_resource<S>(&self) -> Option<StorageHandle<dyn Storage>>
+    where
+        S: Storage,
+    {
+        self.write_map().remove(&Slot::Type(TypeId::of::<S>()))
+    }
+
+    // -------------------------------------------------
+
+    fn get_or_insert_slot<S>(
+        &self,
+        slot: Slot,
+        label: &str,
+        init: impl FnOnce() -> S,
+    ) -> SimpleResult<StorageHandle<S>>
+    where
+        S: KeyStorage + ItemStorage + Into<Arw<dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+        S::Key: KeyTrait,
+        S::Item: ItemTrait,
+    {
+        if let Some(handle) = self.read_map().get(&slot).cloned()
+        {
+            return handle.cast_to_sized_storage::<S>();
+        }
+
+        // Another thread may have added the storage between the two locks
+        self.write_map()
+            .entry(slot)
+            .or_insert_with(|| build_labelled(init(), label))
+            .clone()
+            .cast_to_sized_storage::<S>()
+    }
+
+    // A panic while the map was locked can't leave it half changed, so poisoning is ignored
+    fn read_map(&self) -> RwLockReadGuard<'_, HandleMap>
+    {
+        self.handles.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_map(&self) -> RwLockWriteGuard<'_, HandleMap>
+    {
+        self.handles.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn build_labelled<S>(storage: S, label: &str) -> StorageHandle<dyn Storage>
+where
+    S: KeyStorage + ItemStorage + Into<Arw<dyn Storage>> + KeyTypeIdNoSelf + ItemTypeIdNoSelf,
+    S::Key: KeyTrait,
+    S::Item: ItemTrait,
+{
+    let mut builder = builder(storage);
+    builder.label(label);
+    builder.build()
+}
+
+#[cold]
+fn not_found(description: &str) -> StorageError
+{
+    StorageError::new(
+        ErrorKind::NotRegistered,
+        format!("The world holds no storage {description}"),
+    )
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::StorageWorld;
+    use crate::{
+        storage_traits::{KeyItemStorage, MutKeyItemStorage},
+        storage_types::{HashMapStorage, ValStorage, VecStorage},
+        ErrorKind,
+    };
+
+    #[test]
+    fn named_storages_test()
+    {
+        let world = StorageWorld::new();
+        world.insert(
+            "positions",
+            VecStorage::<usize, f32>::from_vec(vec![1.0, 2.0]),
+        );
+
+        let positions = world.get::<VecStorage<usize, f32>>("positions").unwrap();
+        assert_eq!(positions.label(), Some("positions"));
+        positions.try_write().unwrap().push(3.0);
+
+        let slice = world
+            .get_dyn("positions")
+            .unwrap()
+            .cast_to_slice_storage::<usize, f32>()
+            .unwrap();
+        assert_eq!(slice.try_read().unwrap().as_item_slice(), &[1.0, 2.0, 3.0]);
+
+        // Wrong types and missing names are errors
+        let error = world
+            .get::<VecStorage<usize, i32>>("positions")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TypeMismatch);
+        let error = world.get_dyn("velocities").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotRegistered);
+
+        // Initialized on first access only
+        let names = world
+            .get_or_insert_with("names", || {
+                let mut names = HashMapStorage::<u32, String>::new();
+                names.insert(1, "blur".into());
+                names
+            })
+            .unwrap();
+        let again = world
+            .get_or_default::<HashMapStorage<u32, String>>("names")
+            .unwrap();
+        assert_eq!(again.storage_id(), names.storage_id());
+        assert_eq!(
+            again.try_read().unwrap().get(1).map(String::as_str),
+            Some("blur")
+        );
+
+        let mut all = world.names();
+        all.sort();
+        assert_eq!(all, ["names", "positions"]);
+
+        assert!(world.remove("names").is_some());
+        assert!(!world.contains("names"));
+    }
+
+    #[test]
+    fn resources_test()
+    {
+        let world = StorageWorld::new();
+        let frame = world
+            .resource_or_insert_with(|| ValStorage::<usize, u64>::new(0))
+            .unwrap();
+        frame.try_write().unwrap().data += 1;
+
+        // Resources don't collide with named storages
+        world.insert("frame", ValStorage::<usize, u64>::new(7));
+
+        let frame = world.resource::<ValStorage<usize, u64>>().unwrap();
+        assert_eq!(frame.try_read().unwrap().data, 1);
+        assert_eq!(world.len(), 2);
+
+        assert!(world.remove_resource::<ValStorage<usize, u64>>().is_some());
+        assert!(world.resource::<ValStorage<usize, u64>>().is_err());
+    }
+}
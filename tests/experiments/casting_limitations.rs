@@ -74,7 +74,7 @@ mod tests {
     //     let storage: Arw<dyn KeyStorage<Key = usize>> = storage;
     //
     //     let slice_storage: Arw<dyn KeyItemStorage<Key = usize, Item = i32>> =
-    //         cast_to_dyn_getkeyitemstorage::<_, usize, i32>(storage).unwrap();
+    //         cast_to_dyn_getkeyitemstorage::<_, usize, i32>(&storage).unwrap();
     //
     //     let guard = slice_storage.try_read().unwrap();
     //     assert_eq!(guard.get(0).unwrap(), &1);
@@ -90,7 +90,7 @@ mod tests {
     //
     //     // Cast
     //     let slice_storage: Arw<dyn KeyItemStorage<Key = usize, Item = i32>> =
-    //         cast_to_dyn_getkeyitemstorage::<_, usize, i32>(storage).unwrap();
+    //         cast_to_dyn_getkeyitemstorage::<_, usize, i32>(&storage).unwrap();
     //
     //     let storage: Arw<dyn Storage> = slice_storage;
     // }
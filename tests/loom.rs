@@ -0,0 +1,79 @@
+//! Loom model checks of the handle and view controller state machines.
+//!
+//! Run with: RUSTFLAGS="--cfg loom" cargo test --release --test loom
+#![cfg(loom)]
+
+use std::{any::TypeId, sync::Arc};
+
+use ngenate_flex_storage::{
+    storage_handle::{InputStorageLockStatus, StorageHandle},
+    storage_traits::Storage,
+    storage_types::{KeyItemViewStorage, VecStorage},
+    Rw,
+};
+
+fn read_view_handle() -> StorageHandle<dyn Storage>
+{
+    let input_storage_ptr: StorageHandle<dyn Storage> = {
+        let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![0, 1, 2, 3, 4]);
+        let storage = Arc::new(Rw::new(storage));
+
+        StorageHandle::new(
+            storage.clone(),
+            storage,
+            TypeId::of::<usize>(),
+            TypeId::of::<i32>(),
+        )
+    };
+
+    let mut view_storage_ptr: StorageHandle<dyn Storage> = {
+        let storage: KeyItemViewStorage<VecStorage<usize, i32>, usize, i32> =
+            KeyItemViewStorage::new();
+        let storage = Arc::new(Rw::new(storage));
+
+        StorageHandle::new_with_view_controller(
+            storage.clone(),
+            storage,
+            TypeId::of::<usize>(),
+            TypeId::of::<i32>(),
+        )
+    };
+
+    let view_controller = view_storage_ptr.view_storage_controller_mut().unwrap();
+
+    view_controller
+        .set_input::<usize, i32>(input_storage_ptr)
+        .unwrap();
+
+    view_controller
+        .create_read_view::<usize, i32>(vec![0, 2, 4])
+        .unwrap();
+
+    view_storage_ptr
+}
+
+/// A guard on a view must never be handed out for a view that is being cleared
+#[test]
+fn read_guard_vs_clear_view_test()
+{
+    loom::model(|| {
+        let view_storage_ptr = read_view_handle();
+
+        let clearer = {
+            let mut view_controller = view_storage_ptr.view_storage_controller().unwrap().clone();
+            loom::thread::spawn(move || {
+                let _ = view_controller.clear_view::<usize, i32>();
+            })
+        };
+
+        if let Ok(guard) = view_storage_ptr.try_read()
+        {
+            let status = view_storage_ptr.view_storage_controller().unwrap().status();
+
+            assert_eq!(status, Ok(InputStorageLockStatus::Readable));
+            assert_eq!(guard.len(), 3);
+        }
+
+        clearer.join().unwrap();
+    });
+}
@@ -1,9 +1,7 @@
-use std::{
-    any::TypeId,
-    sync::{Arc, RwLock},
-};
+use std::{any::TypeId, sync::Arc};
 
 use ngenate_flex_storage::{
+    Rw,
     storage_handle::{StorageHandle, ViewStorageController},
     storage_types::{KeyItemViewStorage, VecStorage}, storage_traits::{Storage, KeyItemStorage, MutKeyItemStorage},
 };
@@ -16,7 +14,7 @@ fn view_storage_read_test()
     // Create the source storage
     let input_storage_ptr: StorageHandle<dyn Storage> = {
         let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![0, 1, 2, 3, 4]);
-        let storage = Arc::new(RwLock::new(storage));
+        let storage = Arc::new(Rw::new(storage));
 
         let storage_ptr: StorageHandle<dyn Storage> = StorageHandle::new(
             storage.clone(),
@@ -31,7 +29,7 @@ fn view_storage_read_test()
     // Create the view storage (Not the actual view yet)
     let mut view_storage_ptr_dyn_storage: StorageHandle<dyn Storage> = {
         let storage: KeyItemViewStorage<VecStorage<usize, i32>, usize, i32> = KeyItemViewStorage::new();
-        let storage = Arc::new(RwLock::new(storage));
+        let storage = Arc::new(Rw::new(storage));
 
         let storage_ptr: StorageHandle<dyn Storage> = StorageHandle::new_with_view_controller(
             storage.clone(),
@@ -114,7 +112,7 @@ fn view_storage_write_test()
     // Create the source storage
     let input_storage_ptr: StorageHandle<dyn Storage> = {
         let storage: VecStorage<usize, i32> = VecStorage::new_from_iter(vec![0, 1, 2, 3, 4]);
-        let storage = Arc::new(RwLock::new(storage));
+        let storage = Arc::new(Rw::new(storage));
 
         let storage_ptr: StorageHandle<dyn Storage> = StorageHandle::new(
             storage.clone(),
@@ -129,7 +127,7 @@ fn view_storage_write_test()
     // Create the view storage (Not the actual view yet)
     let mut view_storage_ptr_dyn_storage: StorageHandle<dyn Storage> = {
         let storage: KeyItemViewStorage<VecStorage<usize, i32>, usize, i32> = KeyItemViewStorage::new();
-        let storage = Arc::new(RwLock::new(storage));
+        let storage = Arc::new(Rw::new(storage));
 
         let storage_ptr: StorageHandle<dyn Storage> = StorageHandle::new_with_view_controller(
             storage.clone(),
@@ -193,3 +191,201 @@ fn view_storage_write_test()
         assert_eq!(guard.get(2).unwrap(), &4);
     }
 }
+
+// Two views over one input that share a single buffer of keys
+#[test]
+fn view_storage_shared_keys_test()
+{
+    use ngenate_flex_storage::storage_handle::builder;
+
+    let input: StorageHandle<dyn Storage> =
+        builder(VecStorage::<usize, i32>::new_from_iter(vec![0, 1, 2, 3, 4])).build();
+
+    let mut views: Vec<StorageHandle<dyn Storage>> = (0..2)
+        .map(|_| {
+            let mut view_builder =
+                builder(KeyItemViewStorage::<VecStorage<usize, i32>, usize, i32>::new());
+            view_builder.add_view_controller();
+            view_builder.build()
+        })
+        .collect();
+
+    let keys: Arc<[usize]> = Arc::from([4, 0]);
+
+    for view in &mut views
+    {
+        let controller = view.view_storage_controller_mut().unwrap();
+        controller.set_input::<usize, i32>(input.clone()).unwrap();
+        controller.create_read_view_shared::<usize, i32>(keys.clone()).unwrap();
+
+        // The view holds the given keys rather than a copy of them
+        assert!(Arc::ptr_eq(&controller.view_keys::<usize, i32>().unwrap(), &keys));
+
+        let view = view.clone().cast_to_getitem_storage::<usize, i32>().unwrap();
+        assert_eq!(view.try_read().unwrap().get(0), Some(&4));
+    }
+
+    // Clearing one view leaves the other's keys in place
+    let controller = views[0].view_storage_controller_mut().unwrap();
+    controller.clear_view::<usize, i32>().unwrap();
+    assert!(controller.view_keys::<usize, i32>().unwrap().is_empty());
+    assert_eq!(Arc::strong_count(&keys), 2);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct NodeId(u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct AssetId(u128);
+
+ngenate_flex_storage::impl_key_type!(NodeId(u32), AssetId(u128));
+
+#[test]
+fn user_key_type_test()
+{
+    use ngenate_flex_storage::{storage_handle::builder, storage_types::HashMapStorage};
+
+    let input: StorageHandle<dyn Storage> =
+        builder(VecStorage::<NodeId, i32>::new_from_iter(vec![0, 1, 2, 3])).build();
+
+    // Casts and views of a user key type
+    let items = input.clone().cast_to_getitem_storage::<NodeId, i32>().unwrap();
+    assert_eq!(items.try_read().unwrap().get(NodeId(2)), Some(&2));
+
+    let mut view_builder =
+        builder(KeyItemViewStorage::<VecStorage<NodeId, i32>, NodeId, i32>::new());
+    view_builder.add_view_controller();
+    let mut view = view_builder.build();
+
+    let controller = view.view_storage_controller_mut().unwrap();
+    controller.set_input::<NodeId, i32>(input.clone()).unwrap();
+    controller.create_read_view::<NodeId, i32>(vec![NodeId(3), NodeId(1)]).unwrap();
+
+    let view = view.cast_to_getitem_storage::<NodeId, i32>().unwrap();
+    assert_eq!(view.try_read().unwrap().get(NodeId(0)), Some(&3));
+
+    // Keys that aren't indices key map storages only
+    let mut assets: HashMapStorage<AssetId, i32> = HashMapStorage::new();
+    assets.insert(AssetId(u128::MAX), 7);
+    assert_eq!(assets.get(AssetId(u128::MAX)), Some(&7));
+    assert!(VecStorage::<AssetId, i32>::try_new().is_err());
+}
+
+/// A storage type defined outside of the crate, keyed by position from the newest item
+#[derive(Default)]
+struct HistoryStorage
+{
+    items: Vec<f32>,
+}
+
+impl Storage for HistoryStorage
+{
+    fn len(&self) -> usize
+    {
+        self.items.len()
+    }
+}
+
+impl ngenate_flex_storage::storage_traits::KeyTypeIdNoSelf for HistoryStorage
+{
+    fn key_type_id() -> TypeId
+    {
+        TypeId::of::<usize>()
+    }
+}
+
+impl ngenate_flex_storage::storage_traits::ItemTypeIdNoSelf for HistoryStorage
+{
+    fn item_type_id() -> TypeId
+    {
+        TypeId::of::<f32>()
+    }
+}
+
+impl ngenate_flex_storage::storage_traits::ItemStorage for HistoryStorage
+{
+    type Item = f32;
+}
+
+impl ngenate_flex_storage::storage_traits::KeyStorage for HistoryStorage
+{
+    type Key = usize;
+
+    fn contains(&self, key: usize) -> bool
+    {
+        key < self.items.len()
+    }
+
+    fn keys_iter(&self) -> Box<dyn Iterator<Item = usize> + '_>
+    {
+        Box::new(0..self.items.len())
+    }
+}
+
+impl KeyItemStorage for HistoryStorage
+{
+    fn get(&self, key: usize) -> Option<&f32>
+    {
+        self.items.iter().rev().nth(key)
+    }
+
+    fn item_iter(&self) -> Box<dyn Iterator<Item = &f32> + '_>
+    {
+        Box::new(self.items.iter().rev())
+    }
+
+    fn key_item_iter(&self) -> Box<dyn Iterator<Item = (usize, &f32)> + '_>
+    {
+        Box::new(self.items.iter().rev().enumerate())
+    }
+}
+
+impl From<HistoryStorage> for ngenate_flex_storage::Arw<dyn Storage>
+{
+    fn from(value: HistoryStorage) -> Self
+    {
+        Arc::new(Rw::new(value))
+    }
+}
+
+#[test]
+fn user_storage_type_cast_test()
+{
+    use ngenate_flex_storage::{casting::is_cast_registered, storage_handle::builder};
+
+    let history = HistoryStorage {
+        items: vec![1.0, 2.0, 3.0],
+    };
+
+    ngenate_flex_storage::register_casts!(HistoryStorage => [
+        dyn KeyItemStorage<Key = usize, Item = f32>,
+    ]);
+    assert!(is_cast_registered::<HistoryStorage, dyn KeyItemStorage<Key = usize, Item = f32>>());
+
+    let handle: StorageHandle<dyn Storage> = builder(history).build();
+    assert!(handle.info().unwrap().capabilities.unwrap().key_item);
+
+    let reader = handle.clone().cast_to_getitem_storage::<usize, f32>().unwrap();
+    assert_eq!(reader.try_read().unwrap().get(0), Some(&3.0));
+
+    // Casts that weren't registered still fail
+    assert!(handle.cast_to_mut_getitem_storage::<usize, f32>().is_err());
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn user_storage_type_async_cast_test()
+{
+    use ngenate_flex_storage::storage_handle::AsyncStorageHandle;
+
+    ngenate_flex_storage::register_casts!(HistoryStorage => [
+        dyn KeyItemStorage<Key = usize, Item = f32>,
+    ]);
+
+    let handle = AsyncStorageHandle::new(HistoryStorage {
+        items: vec![1.0, 2.0],
+    });
+
+    let reader = handle.cast_to_getitem_storage::<usize, f32>().unwrap();
+    assert_eq!(reader.try_read().unwrap().get(0), Some(&2.0));
+}